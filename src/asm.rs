@@ -0,0 +1,312 @@
+//! # Asm Module
+//!
+//! `asm` is a small two pass 6502 assembler, so tests and examples can be written as assembly rather than lists of
+//! opcode bytes. It knows the official instructions, labels and the standard addressing mode syntax, plus the
+//! `.byte` and `.word` directives for data.
+//!
+//! ```text
+//!         LDX #$05        ; comments start with a semicolon
+//! loop:   DEX
+//!         BNE loop
+//!         STA ($10),Y
+//!         .byte $01, %10, 3
+//! ```
+//!
+//! Numbers are decimal, hex with `$` or binary with `%`. An address that fits in a byte uses zero page addressing
+//! where the instruction has it, unless it is written with more than two hex digits. Labels always use absolute
+//! addressing, as their value is not known on the first pass.
+
+use crate::cpu::AddressingMode;
+use crate::opcodes::{OpCode, CPU_OPS_CODES};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Where [`assemble`] places the program, the address [`crate::cpu::CPU::load`] loads it at.
+const DEFAULT_ORIGIN : u16 = 0x8000;
+
+/// Why a program could not be assembled. `line` is the one based line number in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// The instruction or directive does not exist.
+    UnknownMnemonic { line : usize, mnemonic : String },
+    /// The operand could not be parsed.
+    InvalidOperand { line : usize, operand : String },
+    /// The instruction exists but not with the addressing mode of the operand.
+    UnsupportedAddressingMode { line : usize, mnemonic : String },
+    /// A label is used but never defined.
+    UndefinedLabel { line : usize, label : String },
+    /// A label is defined twice.
+    DuplicateLabel { line : usize, label : String },
+    /// A branch target is more than 128 bytes away, `offset` is the distance from the next instruction.
+    BranchOutOfRange { line : usize, offset : i32 },
+    /// A value does not fit in the byte it is assembled into.
+    ValueOutOfRange { line : usize, value : u16 },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => write!(f, "line {}: unknown mnemonic {}", line, mnemonic),
+            AsmError::InvalidOperand { line, operand } => write!(f, "line {}: invalid operand {}", line, operand),
+            AsmError::UnsupportedAddressingMode { line, mnemonic } => {
+                write!(f, "line {}: {} does not support this addressing mode", line, mnemonic)
+            }
+            AsmError::UndefinedLabel { line, label } => write!(f, "line {}: undefined label {}", line, label),
+            AsmError::DuplicateLabel { line, label } => write!(f, "line {}: label {} is already defined", line, label),
+            AsmError::BranchOutOfRange { line, offset } => {
+                write!(f, "line {}: branch target is {} bytes away, the limit is -128 to 127", line, offset)
+            }
+            AsmError::ValueOutOfRange { line, value } => write!(f, "line {}: ${:04X} does not fit in a byte", line, value),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A number or a label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    /// The number and whether it was written too wide for zero page addressing.
+    Number(u16, bool),
+    Label(String),
+}
+
+impl Value {
+    /// Whether the value is known to fit in the zero page on the first pass.
+    fn is_zero_page(&self) -> bool {
+        matches!(self, Value::Number(value, false) if *value <= 0xff)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    /// No operand, or `A` for the accumulator forms of the shifts and rotates.
+    None,
+    Immediate(Value),
+    Direct(Value),
+    DirectX(Value),
+    DirectY(Value),
+    Indirect(Value),
+    IndirectX(Value),
+    IndirectY(Value),
+}
+
+/// What a line assembles into.
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction { opcode : &'static OpCode, operand : Operand },
+    Bytes(Vec<Value>),
+    Words(Vec<Value>),
+}
+
+impl Statement {
+    fn len(&self) -> u16 {
+        match self {
+            Statement::Instruction { opcode, .. } => opcode.bytes as u16,
+            Statement::Bytes(values) => values.len() as u16,
+            Statement::Words(values) => 2 * values.len() as u16,
+        }
+    }
+}
+
+/// Assembles `source` into machine code to be loaded at 0x8000, where [`crate::cpu::CPU::load`] puts it.
+///
+/// # Example
+/// ```
+///  use nes::asm::assemble;
+///  use nes::cpu::CPU;
+///
+///  let program = assemble("
+///          LDX #$03
+///  loop:   DEX
+///          BNE loop
+///          BRK
+///  ").unwrap();
+///  assert_eq!(program, vec![0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x00]);
+///
+///  let mut cpu = CPU::new();
+///  cpu.load_and_run(program).unwrap();
+///  assert_eq!(cpu.register_x, 0);
+/// ```
+pub fn assemble(source : &str) -> Result<Vec<u8>, AsmError> {
+    assemble_at(source, DEFAULT_ORIGIN)
+}
+
+/// Assembles `source` into machine code to be loaded at `origin`, which labels and branches are relative to.
+pub fn assemble_at(source : &str, origin : u16) -> Result<Vec<u8>, AsmError> {
+    // The first pass parses every line and works out where the labels are, the size of every statement is known
+    // from its syntax alone.
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = origin;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = text.split(';').next().unwrap_or("").trim();
+
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if is_identifier(label) {
+                if labels.insert(label.to_string(), address).is_some() {
+                    return Err(AsmError::DuplicateLabel { line, label : label.to_string() });
+                }
+                text = rest.trim();
+            }
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line, text)?;
+        address = address.wrapping_add(statement.len());
+        statements.push((line, statement));
+    }
+
+    let mut code = Vec::new();
+    let mut address = origin;
+    for (line, statement) in &statements {
+        let line = *line;
+        let resolve = |value : &Value| match value {
+            Value::Number(number, _) => Ok(*number),
+            Value::Label(label) => {
+                labels.get(label).copied().ok_or_else(|| AsmError::UndefinedLabel { line, label : label.clone() })
+            }
+        };
+        let byte = |value : u16| u8::try_from(value).map_err(|_| AsmError::ValueOutOfRange { line, value });
+
+        match statement {
+            Statement::Instruction { opcode, operand } => {
+                code.push(opcode.code);
+                match operand {
+                    Operand::None => {}
+                    _ if opcode.addressing_mode == AddressingMode::Relative => {
+                        let target = resolve(operand_value(operand))?;
+                        let offset = target as i32 - address.wrapping_add(2) as i32;
+                        if !(-128 ..= 127).contains(&offset) {
+                            return Err(AsmError::BranchOutOfRange { line, offset });
+                        }
+                        code.push(offset as i8 as u8);
+                    }
+                    _ if opcode.bytes == 2 => code.push(byte(resolve(operand_value(operand))?)?),
+                    _ => code.extend(resolve(operand_value(operand))?.to_le_bytes()),
+                }
+            }
+            Statement::Bytes(values) => {
+                for value in values {
+                    code.push(byte(resolve(value)?)?);
+                }
+            }
+            Statement::Words(values) => {
+                for value in values {
+                    code.extend(resolve(value)?.to_le_bytes());
+                }
+            }
+        }
+        address = address.wrapping_add(statement.len());
+    }
+    Ok(code)
+}
+
+fn is_identifier(text : &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn operand_value(operand : &Operand) -> &Value {
+    match operand {
+        Operand::Immediate(value) | Operand::Direct(value) | Operand::DirectX(value) | Operand::DirectY(value)
+        | Operand::Indirect(value) | Operand::IndirectX(value) | Operand::IndirectY(value) => value,
+        Operand::None => unreachable!("instructions without an operand have no value"),
+    }
+}
+
+fn parse_statement(line : usize, text : &str) -> Result<Statement, AsmError> {
+    let (mnemonic, operand) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+        None => (text, ""),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let invalid = || AsmError::InvalidOperand { line, operand : operand.to_string() };
+
+    match mnemonic.as_str() {
+        ".BYTE" | ".WORD" => {
+            let values = operand.split(',').map(|value| parse_value(value.trim()).ok_or_else(invalid));
+            let values = values.collect::<Result<Vec<_>, _>>()?;
+            return Ok(if mnemonic == ".BYTE" { Statement::Bytes(values) } else { Statement::Words(values) });
+        }
+        _ => {}
+    }
+
+    if !CPU_OPS_CODES.iter().any(|opcode| !opcode.unofficial && opcode.name == mnemonic) {
+        return Err(AsmError::UnknownMnemonic { line, mnemonic });
+    }
+    let operand = parse_operand(operand).ok_or_else(invalid)?;
+
+    let branch = CPU_OPS_CODES.iter().any(|opcode| opcode.name == mnemonic && opcode.addressing_mode == AddressingMode::Relative);
+    let zero_page = !matches!(operand, Operand::None) && operand_value(&operand).is_zero_page();
+    let modes : &[AddressingMode] = match &operand {
+        Operand::None => &[AddressingMode::NoneAddressing],
+        Operand::Immediate(_) => &[AddressingMode::Immediate],
+        Operand::Direct(_) if branch => &[AddressingMode::Relative],
+        Operand::Direct(_) if zero_page => &[AddressingMode::ZeroPage, AddressingMode::Absolute],
+        Operand::Direct(_) => &[AddressingMode::Absolute],
+        Operand::DirectX(_) if zero_page => &[AddressingMode::ZeroPage_X, AddressingMode::Absolute_X],
+        Operand::DirectX(_) => &[AddressingMode::Absolute_X],
+        Operand::DirectY(_) if zero_page => &[AddressingMode::ZeroPage_Y, AddressingMode::Absolute_Y],
+        Operand::DirectY(_) => &[AddressingMode::Absolute_Y],
+        Operand::Indirect(_) => &[AddressingMode::Indirect],
+        Operand::IndirectX(_) => &[AddressingMode::Indirect_X],
+        Operand::IndirectY(_) => &[AddressingMode::Indirect_Y],
+    };
+
+    modes
+        .iter()
+        .find_map(|mode| {
+            CPU_OPS_CODES.iter().find(|opcode| !opcode.unofficial && opcode.name == mnemonic && opcode.addressing_mode == *mode)
+        })
+        .map(|opcode| Statement::Instruction { opcode, operand })
+        .ok_or(AsmError::UnsupportedAddressingMode { line, mnemonic })
+}
+
+fn parse_operand(text : &str) -> Option<Operand> {
+    let text : String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let upper = text.to_ascii_uppercase();
+
+    if text.is_empty() || upper == "A" {
+        return Some(Operand::None);
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return parse_value(value).map(Operand::Immediate);
+    }
+    if upper.starts_with('(') {
+        if let Some(value) = upper.strip_suffix(",X)") {
+            return parse_value(&text[1 .. value.len()]).map(Operand::IndirectX);
+        }
+        if let Some(value) = upper.strip_suffix("),Y") {
+            return parse_value(&text[1 .. value.len()]).map(Operand::IndirectY);
+        }
+        return parse_value(text.strip_prefix('(')?.strip_suffix(')')?).map(Operand::Indirect);
+    }
+    if let Some(value) = upper.strip_suffix(",X") {
+        return parse_value(&text[.. value.len()]).map(Operand::DirectX);
+    }
+    if let Some(value) = upper.strip_suffix(",Y") {
+        return parse_value(&text[.. value.len()]).map(Operand::DirectY);
+    }
+    parse_value(&text).map(Operand::Direct)
+}
+
+fn parse_value(text : &str) -> Option<Value> {
+    let (digits, radix, byte_digits) = if let Some(hex) = text.strip_prefix('$') {
+        (hex, 16, 2)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        (binary, 2, 8)
+    } else if is_identifier(text) {
+        return Some(Value::Label(text.to_string()));
+    } else {
+        (text, 10, usize::MAX)
+    };
+
+    let value = u16::from_str_radix(digits, radix).ok()?;
+    Some(Value::Number(value, digits.len() > byte_digits))
+}
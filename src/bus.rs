@@ -0,0 +1,472 @@
+//! # Bus Module
+//!
+//! `bus` is the memory map of the NES as the CPU sees it. The address decides which device answers:
+//!
+//! | Range       | Device                                                   |
+//! |-------------|----------------------------------------------------------|
+//! | $0000-$1FFF | 2KiB CPU RAM, mirrored four times                        |
+//! | $2000-$3FFF | PPU registers, eight of them mirrored every 8 bytes      |
+//! | $4000-$4017 | APU and controller registers                             |
+//! | $4018-$401F | APU test registers, disabled on a retail console         |
+//! | $4020-$FFFF | Cartridge space: expansion, PRG RAM and PRG ROM          |
+//!
+//! The PPU registers go to the [`Ppu`]. The APU and controllers are not emulated yet, they are a [`StubDevice`] for
+//! now. The cartridge space goes to the board of the inserted [`Cartridge`], through its
+//! [`crate::mapper::Mapper`], and is a stub until one is inserted. The PPU reaches the cartridge's pattern tables
+//! through the same board.
+//!
+//! What the CPU RAM holds at power on is chosen with [`RamInit`].
+//!
+//! Reads of the APU's write-only registers and of the disconnected APU test registers are not answered by
+//! anything, they return the open bus: the last value that was on the data bus, which fades to $00 if it is not
+//! refreshed for a while (see [`Bus::set_open_bus_decay`]). The PPU's write-only registers are different: the PPU
+//! answers them with its own data latch, which fades the same way bit by bit, see [`Ppu::latch`].
+//!
+//! Other hardware can be mounted over any part of the map with [`Bus::register_device`], and an [`AccessLog`] can
+//! be installed to record the accesses going over the bus.
+
+mod access_log;
+mod device;
+
+pub use access_log::{AccessLog, BusAccess};
+pub use device::{BusDevice, Ram};
+
+use crate::cartridge::{Cartridge, Mirroring, Rom};
+use crate::cpu::Access;
+use crate::mem::Mem;
+use crate::ppu::Ppu;
+use crate::region::Region;
+
+const RAM : u16 = 0x0000;
+const RAM_MIRRORS_END : u16 = 0x1FFF;
+const PPU_REGISTERS : u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END : u16 = 0x3FFF;
+const APU_IO_REGISTERS : u16 = 0x4000;
+const APU_IO_REGISTERS_END : u16 = 0x4017;
+const OAM_DMA : u16 = 0x4014;
+const APU_TEST_REGISTERS : u16 = 0x4018;
+const APU_TEST_REGISTERS_END : u16 = 0x401F;
+const CARTRIDGE : u16 = 0x4020;
+const CARTRIDGE_END : u16 = 0xFFFF;
+
+/// How many CPU cycles the open bus holds its value for by default, before the charge left on the data lines has
+/// leaked away.
+pub const DEFAULT_OPEN_BUS_DECAY : u64 = 1_070_000;
+
+/// The CPU RAM is 2KiB, addresses in $0000-$1FFF are folded onto it by dropping the upper bits.
+const RAM_MIRROR_MASK : u16 = 0x07FF;
+
+/// The contents of the CPU RAM at power on. The hardware leaves RAM in whatever state the chips settle in, which
+/// differs between consoles, and some games (accidentally) depend on it. Trying the other patterns helps track
+/// down bugs that only show up on one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamInit {
+    /// Every byte is $00.
+    #[default]
+    AllZero,
+    /// Every byte is $FF.
+    AllFF,
+    /// Runs of four $00 bytes and four $FF bytes, a pattern commonly seen on real consoles.
+    Alternating,
+    /// Pseudo random bytes. The same seed always gives the same contents.
+    Random(u64),
+}
+
+impl RamInit {
+    /// Fills `ram` with the pattern.
+    fn fill(self, ram : &mut [u8]) {
+        match self {
+            RamInit::AllZero => ram.fill(0x00),
+            RamInit::AllFF => ram.fill(0xFF),
+            RamInit::Alternating => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 0x04 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            RamInit::Random(seed) => {
+                // SplitMix64, good enough for junk RAM and fine with any seed, zero included.
+                let mut state = seed;
+                for chunk in ram.chunks_mut(8) {
+                    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[.. chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+/// Stands in for a device that is not emulated yet: a block of registers that read back whatever was last written
+/// to them.
+pub struct StubDevice {
+    registers : Vec<u8>,
+}
+
+impl StubDevice {
+    fn new(len : usize) -> Self {
+        StubDevice { registers : vec![0 ; len] }
+    }
+
+    /// The value last written to register `index`, counted from the start of the device's address range.
+    pub fn register(&self, index : usize) -> u8 {
+        self.registers.get(index).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, index : usize, value : u8) {
+        if let Some(register) = self.registers.get_mut(index) {
+            *register = value;
+        }
+    }
+}
+
+/// The NES memory map, connecting the CPU to its RAM and the other devices.
+///
+/// # Example
+/// ```
+///  use nes::bus::Bus;
+///  use nes::mem::Mem;
+///
+///  let mut bus = Bus::new();
+///  bus.write(0x0012, 0x34);
+///  assert_eq!(bus.read(0x0812), 0x34);
+///  assert_eq!(bus.read(0x1812), 0x34);
+/// ```
+pub struct Bus {
+    cpu_ram : [u8 ; 0x800],
+    ram_init : RamInit,
+    ppu : Ppu,
+    apu_io : StubDevice,
+    cartridge_stub : StubDevice,
+    cartridge : Option<Cartridge>,
+    /// The last value on the data bus.
+    open_bus : u8,
+    /// The cycle [`Bus::open_bus`] was last driven on.
+    open_bus_refreshed : u64,
+    open_bus_decay : Option<u64>,
+    /// CPU cycles seen so far, counted by [`Bus::tick`].
+    cycles : u64,
+    region : Region,
+    ppu_dots : u64,
+    /// The fraction of a PPU dot carried over to the next tick, in units of 1 / the denominator of
+    /// [`Region::ppu_dots_per_cpu_cycle`].
+    ppu_dot_remainder : u64,
+    access_log : Option<AccessLog>,
+    devices : Vec<device::Mounted>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    /// Creates the bus with the RAM cleared.
+    pub fn new() -> Self {
+        Self::with_ram_init(RamInit::AllZero)
+    }
+
+    /// Creates the bus with the RAM filled according to `ram_init`.
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::bus::{Bus, RamInit};
+    ///  use nes::mem::Mem;
+    ///
+    ///  let bus = Bus::with_ram_init(RamInit::AllFF);
+    ///  assert_eq!(bus.peek(0x0000), 0xFF);
+    /// ```
+    pub fn with_ram_init(ram_init : RamInit) -> Self {
+        let mut cpu_ram = [0 ; 0x800];
+        ram_init.fill(&mut cpu_ram);
+        Bus {
+            cpu_ram,
+            ram_init,
+            ppu : Ppu::new(),
+            apu_io : StubDevice::new((APU_IO_REGISTERS_END - APU_IO_REGISTERS + 1) as usize),
+            cartridge_stub : StubDevice::new((CARTRIDGE_END - CARTRIDGE) as usize + 1),
+            cartridge : None,
+            open_bus : 0,
+            open_bus_refreshed : 0,
+            open_bus_decay : Some(DEFAULT_OPEN_BUS_DECAY),
+            cycles : 0,
+            region : Region::default(),
+            ppu_dots : 0,
+            ppu_dot_remainder : 0,
+            access_log : None,
+            devices : Vec::new(),
+        }
+    }
+
+    /// The pattern the RAM is filled with at power on.
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init
+    }
+
+    /// Changes the pattern used by the next [`Bus::power_on`].
+    pub fn set_ram_init(&mut self, ram_init : RamInit) {
+        self.ram_init = ram_init;
+    }
+
+    /// Power cycles the RAM, filling it with the [`RamInit`] pattern again. The other devices and an attached
+    /// cartridge are left as they are.
+    pub fn power_on(&mut self) {
+        self.ram_init.fill(&mut self.cpu_ram);
+    }
+
+    /// Passes the reset button on to the cartridge, see [`Mapper::reset`](crate::mapper::Mapper::reset). RAM keeps
+    /// its contents, as it does on the console.
+    pub fn reset(&mut self) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.mapper_mut().reset();
+        }
+    }
+
+    /// Plugs in `cartridge`, which then answers all of $4020-$FFFF, taking the place of any cartridge inserted
+    /// before.
+    ///
+    /// Until a cartridge is inserted the whole cartridge space is RAM, which is what [`crate::cpu::CPU::load`]
+    /// relies on to put test programs at $8000.
+    pub fn insert_cartridge(&mut self, cartridge : Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    /// Unplugs the cartridge and hands it back, leaving the cartridge space to the stub.
+    pub fn remove_cartridge(&mut self) -> Option<Cartridge> {
+        self.cartridge.take()
+    }
+
+    /// The inserted cartridge, if any.
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    /// The inserted cartridge, if any, to get at its board.
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    /// Inserts a cartridge made of nothing but `prg_rom` on an NROM board, which answers reads of $8000-$FFFF. A
+    /// single 16KiB bank (as on NROM-128 boards) appears in both halves. The ROM cannot be written, writes to it
+    /// are ignored.
+    ///
+    /// # Panics
+    /// If `prg_rom` is empty.
+    pub fn attach_prg_rom(&mut self, prg_rom : Vec<u8>) {
+        assert!(!prg_rom.is_empty(), "PRG ROM cannot be empty");
+        let rom = Rom {
+            prg_rom,
+            chr_rom : Vec::new(),
+            mapper : 0,
+            submapper : 0,
+            mirroring : Mirroring::Horizontal,
+            battery : false,
+            trainer : None,
+            nes2 : false,
+            prg_ram_size : 0,
+            prg_nvram_size : 0,
+            chr_ram_size : 0,
+            chr_nvram_size : 0,
+            region : None,
+        };
+        self.insert_cartridge(Cartridge::new(rom).expect("NROM is always supported"));
+    }
+
+    /// Whether the cartridge is holding the CPU's IRQ line low.
+    pub fn irq_pending(&self) -> bool {
+        self.cartridge.as_ref().is_some_and(|cartridge| cartridge.mapper().irq_pending())
+    }
+
+    /// The value a read of an address nothing answers gives right now: the last value on the data bus, or $00 once
+    /// it has decayed.
+    pub fn open_bus(&self) -> u8 {
+        match self.open_bus_decay {
+            Some(decay) if self.cycles - self.open_bus_refreshed >= decay => 0,
+            _ => self.open_bus,
+        }
+    }
+
+    /// Sets how many CPU cycles the open bus keeps its value for when nothing drives it, `None` keeps it forever.
+    /// Defaults to [`DEFAULT_OPEN_BUS_DECAY`].
+    pub fn set_open_bus_decay(&mut self, cycles : Option<u64>) {
+        self.open_bus_decay = cycles;
+    }
+
+    /// The number of CPU cycles the bus has been clocked for.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The console timing the devices are clocked with, NTSC by default.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Changes the console timing the devices are clocked with.
+    pub fn set_region(&mut self, region : Region) {
+        self.region = region;
+        self.ppu_dot_remainder = 0;
+        self.ppu.set_region(region);
+    }
+
+    /// The number of PPU dots the bus has clocked the PPU for.
+    pub fn ppu_dots(&self) -> u64 {
+        self.ppu_dots
+    }
+
+    /// The master clock: catches the devices up with `cpu_cycles` CPU cycles. The PPU gets three dots per CPU
+    /// cycle (3.2 on PAL, the fractions adding up over successive ticks), and the cartridge and registered devices
+    /// are given the CPU cycles. The APU is only a [`StubDevice`], so nothing is clocked for it. The CPU calls this
+    /// for every cycle it spends.
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::bus::Bus;
+    ///  use nes::cpu::CPU;
+    ///
+    ///  let mut cpu = CPU::with_memory(Bus::new());
+    ///  // LDA #$01; BRK
+    ///  cpu.load_and_run(vec![0xa9, 0x01, 0x00]).unwrap();
+    ///  assert_eq!(cpu.memory().cycles(), cpu.cycles);
+    ///  assert_eq!(cpu.memory().ppu_dots(), cpu.cycles * 3);
+    /// ```
+    pub fn tick(&mut self, cpu_cycles : u64) {
+        self.cycles += cpu_cycles;
+
+        let (numerator, denominator) = self.region.ppu_dots_per_cpu_cycle();
+        let dots = self.ppu_dot_remainder + cpu_cycles * numerator;
+        self.ppu_dots += dots / denominator;
+        self.ppu_dot_remainder = dots % denominator;
+        self.ppu.tick(dots / denominator, self.cartridge.as_mut().map(|cartridge| cartridge.mapper_mut()));
+
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.mapper_mut().tick(cpu_cycles);
+        }
+        self.tick_devices(cpu_cycles);
+    }
+
+    fn drive_open_bus(&mut self, value : u8) {
+        self.open_bus = value;
+        self.open_bus_refreshed = self.cycles;
+    }
+
+    /// Whether reading `addr` gives the open bus, because it is a write-only register or nothing is connected.
+    fn is_open_bus(addr : u16) -> bool {
+        match addr {
+            // The APU channel registers and OAMDMA.
+            APU_IO_REGISTERS ..= OAM_DMA => true,
+            APU_TEST_REGISTERS ..= APU_TEST_REGISTERS_END => true,
+            _ => false,
+        }
+    }
+
+    /// The PPU, behind the eight registers at $2000-$2007 (and their mirrors).
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// The PPU, to change it directly.
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// The stand-in for the APU and controller registers at $4000-$4017.
+    pub fn apu_io(&self) -> &StubDevice {
+        &self.apu_io
+    }
+
+    /// The stand-in for a cartridge, which behaves as RAM over $4020-$FFFF while no cartridge is inserted.
+    pub fn cartridge_stub(&self) -> &StubDevice {
+        &self.cartridge_stub
+    }
+}
+
+impl Mem for Bus {
+    fn read(&mut self, addr : u16) -> u8 {
+        let value = match self.device_at(addr) {
+            Some(index) => {
+                let mounted = &mut self.devices[index];
+                mounted.device.read(addr - mounted.range.start())
+            }
+            None => match (addr, &mut self.cartridge) {
+                (CARTRIDGE ..= CARTRIDGE_END, Some(cartridge)) => match cartridge.mapper_mut().cpu_read(addr) {
+                    Some(value) => value,
+                    None => self.open_bus(),
+                },
+                (PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END, cartridge) => {
+                    self.ppu.read_register(addr, cartridge.as_mut().map(|cartridge| cartridge.mapper_mut()))
+                }
+                _ => self.peek(addr),
+            },
+        };
+        self.record_access(Access::Read, addr, value);
+        self.drive_open_bus(value);
+        value
+    }
+
+    fn write(&mut self, addr : u16, value : u8) {
+        self.record_access(Access::Write, addr, value);
+        self.drive_open_bus(value);
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.mapper_mut().snoop_cpu_write(addr, value);
+        }
+        if let Some(index) = self.device_at(addr) {
+            let mounted = &mut self.devices[index];
+            mounted.device.write(addr - mounted.range.start(), value);
+            return;
+        }
+        match addr {
+            RAM ..= RAM_MIRRORS_END => self.cpu_ram[(addr & RAM_MIRROR_MASK) as usize] = value,
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.write_register(addr, value, self.cartridge.as_mut().map(|cartridge| cartridge.mapper_mut()))
+            }
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => self.apu_io.write((addr - APU_IO_REGISTERS) as usize, value),
+            CARTRIDGE ..= CARTRIDGE_END => match &mut self.cartridge {
+                Some(cartridge) => cartridge.cpu_write_on_cycle(addr, value, self.cycles),
+                None => self.cartridge_stub.write((addr - CARTRIDGE) as usize, value),
+            },
+            _ => {}
+        }
+    }
+
+    fn peek(&self, addr : u16) -> u8 {
+        if let Some(index) = self.device_at(addr) {
+            let mounted = &self.devices[index];
+            return mounted.device.peek(addr - mounted.range.start());
+        }
+        if Self::is_open_bus(addr) {
+            return self.open_bus();
+        }
+        match addr {
+            RAM ..= RAM_MIRRORS_END => self.cpu_ram[(addr & RAM_MIRROR_MASK) as usize],
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.peek_register(addr, self.cartridge.as_ref().map(|cartridge| cartridge.mapper()))
+            }
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => self.apu_io.register((addr - APU_IO_REGISTERS) as usize),
+            CARTRIDGE ..= CARTRIDGE_END => match &self.cartridge {
+                Some(cartridge) => cartridge.mapper().cpu_peek(addr).unwrap_or_else(|| self.open_bus()),
+                None => self.cartridge_stub.register((addr - CARTRIDGE) as usize),
+            },
+            _ => self.open_bus(),
+        }
+    }
+
+    fn tick(&mut self, cycles : u64) {
+        Bus::tick(self, cycles);
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_pending()
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.ppu.take_nmi()
+    }
+
+    fn region(&self) -> Region {
+        self.region
+    }
+}
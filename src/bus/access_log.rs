@@ -0,0 +1,125 @@
+//! # Access Log Module
+//!
+//! `access_log` records what goes over the bus, so the order of mapper and PPU register accesses can be looked at
+//! after the fact instead of printing from inside the devices.
+
+use super::Bus;
+use crate::cpu::Access;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+/// One read or write seen by the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    /// The CPU cycle the access was made on, counted by the bus. This is exact when the CPU runs
+    /// [`crate::cpu::ExecutionMode::PerCycle`]. Running whole instructions, the CPU only ticks the bus once an
+    /// instruction is done, so every access the instruction makes has the cycle it started on.
+    pub cycle : u64,
+    pub address : u16,
+    /// The value read or written.
+    pub value : u8,
+    pub access : Access,
+}
+
+/// Called with every access the log records, see [`AccessLog::sink`].
+type AccessSink = Box<dyn FnMut(&BusAccess)>;
+
+/// Keeps the most recent bus accesses in a ring buffer, optionally only those to some address ranges, and passes
+/// them on to a sink.
+///
+/// # Example
+/// ```
+///  use nes::bus::{AccessLog, Bus};
+///  use nes::cpu::Access;
+///  use nes::mem::Mem;
+///
+///  let mut bus = Bus::new();
+///  bus.log_accesses(AccessLog::new(16).filter(0x2000 ..= 0x3fff));
+///  bus.write(0x0000, 0x01);
+///  bus.write(0x2006, 0x21);
+///
+///  let log : Vec<_> = bus.access_log().unwrap().entries().collect();
+///  assert_eq!(log.len(), 1);
+///  assert_eq!((log[0].address, log[0].value, log[0].access), (0x2006, 0x21, Access::Write));
+/// ```
+pub struct AccessLog {
+    entries : VecDeque<BusAccess>,
+    capacity : usize,
+    ranges : Vec<RangeInclusive<u16>>,
+    sink : Option<AccessSink>,
+}
+
+impl AccessLog {
+    /// Creates a log that keeps the last `capacity` accesses to any address. A capacity of 0 keeps nothing, which
+    /// is useful with a [`AccessLog::sink`].
+    pub fn new(capacity : usize) -> Self {
+        AccessLog { entries : VecDeque::with_capacity(capacity), capacity, ranges : Vec::new(), sink : None }
+    }
+
+    /// Only records accesses to `range`. Can be called more than once to record several ranges.
+    pub fn filter(mut self, range : RangeInclusive<u16>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Passes every recorded access to `sink` as it happens, for instance to write it to a file.
+    pub fn sink(mut self, sink : impl FnMut(&BusAccess) + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// The accesses kept, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = BusAccess> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Forgets the accesses kept so far.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn record(&mut self, access : BusAccess) {
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&access.address)) {
+            return;
+        }
+        if let Some(sink) = &mut self.sink {
+            sink(&access);
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(access);
+    }
+}
+
+impl Bus {
+    /// Starts recording accesses in `log`, replacing any log already installed. Looking at memory with
+    /// [`crate::mem::Mem::peek`] is not an access and is not recorded.
+    pub fn log_accesses(&mut self, log : AccessLog) {
+        self.access_log = Some(log);
+    }
+
+    /// The installed log, if any.
+    pub fn access_log(&self) -> Option<&AccessLog> {
+        self.access_log.as_ref()
+    }
+
+    /// The installed log, to clear it between runs.
+    pub fn access_log_mut(&mut self) -> Option<&mut AccessLog> {
+        self.access_log.as_mut()
+    }
+
+    /// Stops recording and hands back the log.
+    pub fn take_access_log(&mut self) -> Option<AccessLog> {
+        self.access_log.take()
+    }
+
+    pub(super) fn record_access(&mut self, access : Access, address : u16, value : u8) {
+        if let Some(log) = &mut self.access_log {
+            log.record(BusAccess { cycle : self.cycles, address, value, access });
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! # Device Module
+//!
+//! `device` lets code outside the crate put its own hardware on the bus: extra RAM, a debug port a test ROM
+//! writes its results to, or anything else that answers a range of addresses.
+
+use super::{Bus, StubDevice};
+use std::ops::RangeInclusive;
+
+/// Something that can be mounted on the [`Bus`] with [`Bus::register_device`]. Addresses are given as the offset
+/// from the start of the range the device is mounted at.
+///
+/// # Example
+/// ```
+///  use nes::bus::{Bus, BusDevice};
+///  use nes::mem::Mem;
+///
+///  /// Reads back the last value written, plus one.
+///  struct Counter(u8);
+///
+///  impl BusDevice for Counter {
+///      fn read(&mut self, _offset : u16) -> u8 { self.peek(0) }
+///      fn write(&mut self, _offset : u16, value : u8) { self.0 = value; }
+///      fn peek(&self, _offset : u16) -> u8 { self.0.wrapping_add(1) }
+///  }
+///
+///  let mut bus = Bus::new();
+///  bus.register_device(0x5000 ..= 0x5000, Counter(0));
+///  bus.write(0x5000, 0x41);
+///  assert_eq!(bus.read(0x5000), 0x42);
+/// ```
+pub trait BusDevice {
+    /// Reads the byte at `offset`, which can have side effects.
+    fn read(&mut self, offset : u16) -> u8;
+
+    /// Writes `value` to `offset`.
+    fn write(&mut self, offset : u16, value : u8);
+
+    /// Returns the byte a read of `offset` would, without any side effects.
+    fn peek(&self, offset : u16) -> u8;
+
+    /// Advances the device by `cpu_cycles` CPU cycles, called from [`Bus::tick`]. Devices without a clock can
+    /// ignore it.
+    fn tick(&mut self, _cpu_cycles : u64) {}
+}
+
+/// Plain RAM to mount anywhere, mirrored when it is smaller than the range it is mounted at. For instance 8KiB of
+/// work RAM at $6000-$7FFF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ram {
+    bytes : Vec<u8>,
+}
+
+impl Ram {
+    /// Creates `len` bytes of RAM, cleared.
+    ///
+    /// # Panics
+    /// If `len` is 0.
+    pub fn new(len : usize) -> Self {
+        assert!(len > 0, "RAM cannot be empty");
+        Ram { bytes : vec![0 ; len] }
+    }
+
+    /// The contents of the RAM.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl BusDevice for Ram {
+    fn read(&mut self, offset : u16) -> u8 {
+        self.peek(offset)
+    }
+
+    fn write(&mut self, offset : u16, value : u8) {
+        let len = self.bytes.len();
+        self.bytes[offset as usize % len] = value;
+    }
+
+    fn peek(&self, offset : u16) -> u8 {
+        self.bytes[offset as usize % self.bytes.len()]
+    }
+}
+
+impl BusDevice for StubDevice {
+    fn read(&mut self, offset : u16) -> u8 {
+        self.register(offset as usize)
+    }
+
+    fn write(&mut self, offset : u16, value : u8) {
+        StubDevice::write(self, offset as usize, value);
+    }
+
+    fn peek(&self, offset : u16) -> u8 {
+        self.register(offset as usize)
+    }
+}
+
+/// A device and the addresses it answers.
+pub(super) struct Mounted {
+    pub(super) range : RangeInclusive<u16>,
+    pub(super) device : Box<dyn BusDevice>,
+}
+
+impl Bus {
+    /// Mounts `device` at `range`. It answers every read and write there, in front of whatever the memory map
+    /// puts at those addresses, RAM and PRG ROM included. When ranges overlap the device registered last wins.
+    pub fn register_device(&mut self, range : RangeInclusive<u16>, device : impl BusDevice + 'static) {
+        self.devices.push(Mounted { range, device : Box::new(device) });
+    }
+
+    /// Unmounts every device registered with [`Bus::register_device`], restoring the standard memory map.
+    pub fn clear_devices(&mut self) {
+        self.devices.clear();
+    }
+
+    /// Clocks the registered devices.
+    pub(super) fn tick_devices(&mut self, cpu_cycles : u64) {
+        for mounted in &mut self.devices {
+            mounted.device.tick(cpu_cycles);
+        }
+    }
+
+    /// The index of the device answering `addr`, if one is registered there.
+    pub(super) fn device_at(&self, addr : u16) -> Option<usize> {
+        self.devices.iter().rposition(|mounted| mounted.range.contains(&addr))
+    }
+}
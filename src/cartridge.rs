@@ -0,0 +1,702 @@
+//! # Cartridge Module
+//!
+//! `cartridge` reads game cartridges dumped to iNES (.nes) files. A file is a 16 byte header followed by an
+//! optional 512 byte trainer, the PRG ROM the CPU runs and the CHR ROM holding the PPU's tiles:
+//!
+//! | Byte  | Meaning                                                                  |
+//! |-------|--------------------------------------------------------------------------|
+//! | 0-3   | `NES` followed by $1A                                                    |
+//! | 4     | PRG ROM size in 16KiB units                                              |
+//! | 5     | CHR ROM size in 8KiB units, 0 when the board has CHR RAM instead         |
+//! | 6     | Mirroring, battery, trainer, four-screen VRAM and the mapper's low nibble |
+//! | 7     | The mapper's high nibble                                                 |
+//! | 8-15  | Unused by iNES                                                           |
+//!
+//! NES 2.0 is a compatible extension of the format, marked by bits 2-3 of byte 7 being `10`. It puts bytes 8-15 to
+//! use for the higher bits of the mapper number, a submapper, larger ROM sizes, the sizes of PRG and CHR RAM
+//! (volatile and battery backed) and the console the game was made for.
+//!
+//! UNIF (.unf) files are read too. They name their board rather than give a mapper number, the boards the
+//! emulated mappers cover are listed by [`unif_board_mapper`].
+//!
+//! A [`Rom`] is only the file's contents. Plugged in, it becomes a [`Cartridge`]: the ROMs on the board its mapper
+//! number calls for, which is what the bus and the PPU talk to.
+//!
+//! Plenty of files in the wild do not match their header: overdumps with junk after the ROM, or dumps missing
+//! some of the CHR ROM. [`Rom::from_bytes`] rejects them, [`Rom::from_bytes_tolerant`] loads what is there and
+//! says what it had to fix in a [`LoadReport`].
+//!
+//! Dumps are identified by the checksums of their contents (see [`RomHashes`]), which are looked up in a
+//! [`GameDatabase`] when a cartridge is made, to name the game and correct bad headers.
+//!
+//! Cartridges with a battery keep their PRG RAM, and the games saved in it, when the console is off. Loaded from
+//! a file, such a cartridge keeps its PRG RAM in a .sav file next to the ROM: `Zelda.nes` saves to `Zelda.sav`.
+
+mod database;
+mod hash;
+mod unif;
+
+pub use database::{DatabaseError, GameDatabase, GameInfo};
+pub use hash::{crc32, sha1, RomHashes};
+pub use unif::unif_board_mapper;
+
+use crate::mapper::{self, Mapper, StateError, StateReader, StateWriter};
+use crate::region::Region;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MAGIC : [u8 ; 4] = *b"NES\x1a";
+const HEADER_SIZE : usize = 16;
+const TRAINER_SIZE : usize = 512;
+/// Where the trainer goes in PRG RAM, which starts at $6000.
+const TRAINER_RAM_OFFSET : usize = 0x7000 - 0x6000;
+const PRG_ROM_BANK_SIZE : usize = 0x4000;
+const CHR_ROM_BANK_SIZE : usize = 0x2000;
+
+const MIRRORING_VERTICAL : u8 = 0b0000_0001;
+const BATTERY : u8 = 0b0000_0010;
+const TRAINER : u8 = 0b0000_0100;
+const FOUR_SCREEN : u8 = 0b0000_1000;
+const NES2_MASK : u8 = 0b0000_1100;
+const NES2 : u8 = 0b0000_1000;
+
+/// The PRG RAM iNES files leave implied, which is what every board of the time had.
+const DEFAULT_PRG_RAM_SIZE : usize = 0x2000;
+/// The CHR RAM implied when an iNES file has no CHR ROM.
+const DEFAULT_CHR_RAM_SIZE : usize = 0x2000;
+
+/// The largest ROM a tolerant load pads a short dump up to, more than any header could honestly claim. Past this
+/// the header is garbage.
+const MAX_TOLERATED_ROM_SIZE : usize = 64 << 20;
+
+/// Save states start with the signature, then the mapper number and the version of the board's state format.
+const STATE_MAGIC : &[u8 ; 4] = b"NSTA";
+const STATE_HEADER_SIZE : usize = 8;
+
+/// The size of a nametable: 32x30 tiles and the 64 byte attribute table after them.
+pub const NAMETABLE_SIZE : usize = 0x400;
+/// The console's own VRAM, enough for two nametables.
+const CONSOLE_VRAM_SIZE : usize = 2 * NAMETABLE_SIZE;
+
+/// How the cartridge wires the PPU's two nametables into its four nametable slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    /// The top two slots are one nametable and the bottom two the other, for vertical scrolling.
+    Horizontal,
+    /// The left two slots are one nametable and the right two the other, for horizontal scrolling.
+    Vertical,
+    /// The cartridge has its own VRAM for all four slots.
+    FourScreen,
+    /// All four slots are the first nametable. Only boards that can switch mirroring use this.
+    SingleScreenLower,
+    /// All four slots are the second nametable.
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    /// The nametable the PPU address `addr` ($2000-$3EFF) is wired to. 0 and 1 are the console's VRAM, 2 and 3
+    /// the extra VRAM on four-screen boards.
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::cartridge::Mirroring;
+    ///
+    ///  assert_eq!(Mirroring::Vertical.nametable(0x2800), 0);
+    ///  assert_eq!(Mirroring::Horizontal.nametable(0x2800), 1);
+    ///  assert_eq!(Mirroring::FourScreen.nametable(0x2c00), 3);
+    /// ```
+    pub fn nametable(self, addr : u16) -> usize {
+        let slot = (addr as usize / NAMETABLE_SIZE) & 0b11;
+        match self {
+            Mirroring::Horizontal => slot >> 1,
+            Mirroring::Vertical => slot & 1,
+            Mirroring::FourScreen => slot,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        }
+    }
+
+    /// Translates the PPU address `addr` ($2000-$3EFF) into an offset into nametable VRAM of
+    /// [`Mirroring::vram_size`] bytes. This is all the address translation there is: $3000-$3EFF mirrors
+    /// $2000-$2EFF because the PPU does not decode address line 12 for nametables.
+    pub fn vram_offset(self, addr : u16) -> usize {
+        self.nametable(addr) * NAMETABLE_SIZE + addr as usize % NAMETABLE_SIZE
+    }
+
+    /// The bytes of VRAM the nametables need: the console's 2KiB, plus 2KiB on the cartridge for four-screen.
+    pub fn vram_size(self) -> usize {
+        match self {
+            Mirroring::FourScreen => 2 * CONSOLE_VRAM_SIZE,
+            _ => CONSOLE_VRAM_SIZE,
+        }
+    }
+}
+
+/// Why a ROM file could not be read.
+#[derive(Debug)]
+pub enum RomError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file starts with neither the iNES nor the UNIF signature.
+    NotINes,
+    /// The file is shorter than its header says it is.
+    Truncated { expected : usize, actual : usize },
+    /// The header says there is no PRG ROM, leaving the CPU nothing to run.
+    NoPrgRom,
+    /// The ROM is for a board (mapper number) that is not emulated.
+    UnsupportedMapper(u16),
+    /// The UNIF file does not name its board.
+    MissingBoard,
+    /// The UNIF file is for a board that is not emulated.
+    UnknownBoard(String),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(error) => write!(f, "could not read ROM: {}", error),
+            RomError::NotINes => write!(f, "not an iNES file, the signature NES<EOF> is missing"),
+            RomError::Truncated { expected, actual } => {
+                write!(f, "ROM is truncated, the header needs {} bytes but there are {}", expected, actual)
+            }
+            RomError::NoPrgRom => write!(f, "ROM has no PRG ROM"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            RomError::MissingBoard => write!(f, "UNIF file has no MAPR chunk naming its board"),
+            RomError::UnknownBoard(board) => write!(f, "UNIF board {} is not supported", board),
+        }
+    }
+}
+
+impl std::error::Error for RomError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RomError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(error : io::Error) -> Self {
+        RomError::Io(error)
+    }
+}
+
+/// Something wrong with a ROM file that [`Rom::from_bytes_tolerant`] worked around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadWarning {
+    /// The file goes on after the ROM the header describes, the extra bytes were ignored.
+    TrailingData { bytes : usize },
+    /// The header has the trainer flag set but the file is too short for one. The flag was ignored.
+    TrainerMissing,
+    /// The PRG ROM is shorter than the header says, it was padded with zeros.
+    PrgRomTruncated { expected : usize, actual : usize },
+    /// The CHR ROM is shorter than the header says, it was padded with zeros.
+    ChrRomTruncated { expected : usize, actual : usize },
+    /// The header says there is CHR ROM but the file ends before it. The board gets CHR RAM instead.
+    ChrRomMissing { expected : usize },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadWarning::TrailingData { bytes } => write!(f, "ignored {} bytes after the end of the ROM", bytes),
+            LoadWarning::TrainerMissing => write!(f, "the header says there is a trainer but there is none"),
+            LoadWarning::PrgRomTruncated { expected, actual } => {
+                write!(f, "PRG ROM is {} bytes instead of {}, padded with zeros", actual, expected)
+            }
+            LoadWarning::ChrRomTruncated { expected, actual } => {
+                write!(f, "CHR ROM is {} bytes instead of {}, padded with zeros", actual, expected)
+            }
+            LoadWarning::ChrRomMissing { expected } => {
+                write!(f, "the {} bytes of CHR ROM are missing, using CHR RAM", expected)
+            }
+        }
+    }
+}
+
+/// What [`Rom::from_bytes_tolerant`] had to fix to load a file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    pub warnings : Vec<LoadWarning>,
+}
+
+impl LoadReport {
+    /// Whether the file was exactly what its header said.
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// The contents of a cartridge, as read from an iNES file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    /// The program, a multiple of 16KiB (NES 2.0 headers can give other sizes).
+    pub prg_rom : Vec<u8>,
+    /// The tiles, a multiple of 8KiB like the PRG ROM. Empty when the board has CHR RAM instead.
+    pub chr_rom : Vec<u8>,
+    /// The mapper (board) number, which decides how the ROMs are banked into the address space.
+    pub mapper : u16,
+    /// Tells apart variants of a board sharing a mapper number, 0 unless the header is NES 2.0.
+    pub submapper : u8,
+    pub mirroring : Mirroring,
+    /// Whether the cartridge has battery backed PRG RAM at $6000-$7FFF, used for saved games.
+    pub battery : bool,
+    /// 512 bytes some dumps include, meant to be loaded at $7000-$71FF. Old copier devices used it for patches to
+    /// make the game run on them.
+    pub trainer : Option<Vec<u8>>,
+    /// Whether the header is NES 2.0. For iNES headers the RAM sizes below are the usual ones for the time.
+    pub nes2 : bool,
+    /// Bytes of PRG RAM that are lost at power off.
+    pub prg_ram_size : usize,
+    /// Bytes of battery backed PRG RAM (or EEPROM).
+    pub prg_nvram_size : usize,
+    /// Bytes of CHR RAM that are lost at power off.
+    pub chr_ram_size : usize,
+    /// Bytes of battery backed CHR RAM.
+    pub chr_nvram_size : usize,
+    /// The console the game was made for. `None` when the header does not say, or the game adapts to any.
+    pub region : Option<Region>,
+}
+
+impl Rom {
+    /// Parses an iNES image, or a UNIF one.
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::cartridge::{Mirroring, Rom};
+    ///
+    ///  let mut image = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    ///  image.resize(16 + 0x4000 + 0x2000, 0);
+    ///  let rom = Rom::from_bytes(&image).unwrap();
+    ///  assert_eq!(rom.prg_rom.len(), 0x4000);
+    ///  assert_eq!(rom.mapper, 0);
+    ///  assert_eq!(rom.mirroring, Mirroring::Vertical);
+    /// ```
+    pub fn from_bytes(bytes : &[u8]) -> Result<Rom, RomError> {
+        Rom::parse(bytes, false).map(|(rom, _)| rom)
+    }
+
+    /// Parses an iNES image that may not match its header, fixing what it can and reporting what it fixed. Only
+    /// files that are not iNES or have no PRG ROM at all are rejected.
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::cartridge::{LoadWarning, Rom};
+    ///
+    ///  // Half of the CHR ROM is missing.
+    ///  let mut image = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    ///  image.resize(16 + 0x4000 + 0x1000, 0xea);
+    ///  let (rom, report) = Rom::from_bytes_tolerant(&image).unwrap();
+    ///  assert_eq!(rom.chr_rom.len(), 0x2000);
+    ///  assert_eq!(report.warnings, [LoadWarning::ChrRomTruncated { expected : 0x2000, actual : 0x1000 }]);
+    /// ```
+    pub fn from_bytes_tolerant(bytes : &[u8]) -> Result<(Rom, LoadReport), RomError> {
+        Rom::parse(bytes, true)
+    }
+
+    fn parse(bytes : &[u8], tolerant : bool) -> Result<(Rom, LoadReport), RomError> {
+        let mut report = LoadReport::default();
+        if bytes.starts_with(unif::MAGIC) {
+            return unif::parse(bytes).map(|rom| (rom, report));
+        }
+        if bytes.len() < MAGIC.len() || bytes[.. MAGIC.len()] != MAGIC {
+            return Err(RomError::NotINes);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(RomError::Truncated { expected : HEADER_SIZE, actual : bytes.len() });
+        }
+        let header = &bytes[.. HEADER_SIZE];
+        let flags6 = header[6];
+        let mut flags7 = header[7];
+        let nes2 = flags7 & NES2_MASK == NES2;
+        // Old dumping tools wrote their name over bytes 7-15 ("DiskDude!"), which would make the mapper's high
+        // nibble garbage. A clean header has zeros at the end.
+        if !nes2 && header[12 .. 16].iter().any(|&byte| byte != 0) {
+            flags7 = 0;
+        }
+
+        let (prg_rom_size, chr_rom_size) = if nes2 {
+            (
+                nes2_rom_size(header[4], header[9] & 0x0f, PRG_ROM_BANK_SIZE),
+                nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_BANK_SIZE),
+            )
+        } else {
+            (header[4] as usize * PRG_ROM_BANK_SIZE, header[5] as usize * CHR_ROM_BANK_SIZE)
+        };
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+        let mut trainer_size = if flags6 & TRAINER != 0 { TRAINER_SIZE } else { 0 };
+        let expected =
+            (HEADER_SIZE + trainer_size).saturating_add(prg_rom_size).saturating_add(chr_rom_size);
+        let truncated = Err(RomError::Truncated { expected, actual : bytes.len() });
+        if bytes.len() < expected && (!tolerant || prg_rom_size.max(chr_rom_size) > MAX_TOLERATED_ROM_SIZE) {
+            return truncated;
+        }
+        if bytes.len() > expected {
+            report.warnings.push(LoadWarning::TrailingData { bytes : bytes.len() - expected });
+        }
+        if bytes.len() < HEADER_SIZE + trainer_size {
+            report.warnings.push(LoadWarning::TrainerMissing);
+            trainer_size = 0;
+        }
+
+        let prg_rom_start = HEADER_SIZE + trainer_size;
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+        // What the file has of each ROM, all of it unless the file is short.
+        let available = |start : usize, size : usize| &bytes[start.min(bytes.len()) .. (start + size).min(bytes.len())];
+        let mut prg_rom = available(prg_rom_start, prg_rom_size).to_vec();
+        let mut chr_rom = available(chr_rom_start, chr_rom_size).to_vec();
+        if prg_rom.is_empty() {
+            return truncated;
+        }
+        if prg_rom.len() < prg_rom_size {
+            report.warnings.push(LoadWarning::PrgRomTruncated { expected : prg_rom_size, actual : prg_rom.len() });
+            prg_rom.resize(prg_rom_size, 0);
+        }
+        let chr_rom_missing = chr_rom_size > 0 && chr_rom.is_empty();
+        if chr_rom_missing {
+            report.warnings.push(LoadWarning::ChrRomMissing { expected : chr_rom_size });
+        } else if chr_rom.len() < chr_rom_size {
+            report.warnings.push(LoadWarning::ChrRomTruncated { expected : chr_rom_size, actual : chr_rom.len() });
+            chr_rom.resize(chr_rom_size, 0);
+        }
+        let mirroring = if flags6 & FOUR_SCREEN != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & MIRRORING_VERTICAL != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let battery = flags6 & BATTERY != 0;
+        let mut rom = Rom {
+            prg_rom,
+            chr_rom,
+            mapper : ((flags7 & 0xf0) | (flags6 >> 4)) as u16,
+            submapper : 0,
+            mirroring,
+            battery,
+            trainer : (trainer_size > 0).then(|| bytes[HEADER_SIZE .. prg_rom_start].to_vec()),
+            nes2,
+            prg_ram_size : if battery { 0 } else { DEFAULT_PRG_RAM_SIZE },
+            prg_nvram_size : if battery { DEFAULT_PRG_RAM_SIZE } else { 0 },
+            chr_ram_size : if chr_rom_size == 0 || chr_rom_missing { DEFAULT_CHR_RAM_SIZE } else { 0 },
+            chr_nvram_size : 0,
+            region : None,
+        };
+        if nes2 {
+            rom.mapper |= ((header[8] & 0x0f) as u16) << 8;
+            rom.submapper = header[8] >> 4;
+            rom.prg_ram_size = nes2_ram_size(header[10] & 0x0f);
+            rom.prg_nvram_size = nes2_ram_size(header[10] >> 4);
+            rom.chr_ram_size = nes2_ram_size(header[11] & 0x0f);
+            rom.chr_nvram_size = nes2_ram_size(header[11] >> 4);
+            rom.region = match header[12] & 0x03 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                _ => None,
+            };
+        }
+        Ok((rom, report))
+    }
+
+    /// Reads and parses the iNES file at `path`.
+    pub fn from_file<P : AsRef<Path>>(path : P) -> Result<Rom, RomError> {
+        Rom::from_bytes(&fs::read(path)?)
+    }
+
+    /// Reads the iNES file at `path`, tolerating a mismatch with the header like [`Rom::from_bytes_tolerant`].
+    pub fn from_file_tolerant<P : AsRef<Path>>(path : P) -> Result<(Rom, LoadReport), RomError> {
+        Rom::from_bytes_tolerant(&fs::read(path)?)
+    }
+}
+
+/// Decodes a NES 2.0 ROM size from its low byte (bytes 4 and 5) and high nibble (byte 9). Normally the two make a
+/// 12 bit count of `bank_size` banks. A high nibble of $F switches the low byte to an exponent-multiplier form,
+/// `EEEEEEMM`, for sizes that are not a multiple of the bank size: 2^E * (MM * 2 + 1) bytes.
+fn nes2_rom_size(low : u8, high : u8, bank_size : usize) -> usize {
+    if high == 0x0f {
+        let exponent = (low >> 2) as u32;
+        let multiplier = (low & 0x03) as usize * 2 + 1;
+        1usize.checked_shl(exponent).map_or(usize::MAX, |size| size.saturating_mul(multiplier))
+    } else {
+        (((high as usize) << 8) | low as usize) * bank_size
+    }
+}
+
+/// Decodes a NES 2.0 RAM size: a shift count `s` stands for 64 << s bytes, 0 for none.
+fn nes2_ram_size(shift : u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// A cartridge plugged into the console: the board, with the ROMs on it.
+///
+/// # Example
+/// ```
+///  use nes::cartridge::{Cartridge, Rom};
+///
+///  let mut image = vec![b'N', b'E', b'S', 0x1a, 2, 1, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+///  image.resize(16 + 0x8000 + 0x2000, 0xea);
+///  let mut cartridge = Cartridge::new(Rom::from_bytes(&image).unwrap()).unwrap();
+///  assert_eq!(cartridge.mapper_number(), 1);
+///  assert_eq!(cartridge.mapper_mut().cpu_read(0xfffc), Some(0xea));
+/// ```
+pub struct Cartridge {
+    mapper : Box<dyn Mapper>,
+    mapper_number : u16,
+    battery : bool,
+    /// Where the battery backed PRG RAM is kept between runs.
+    save_path : Option<PathBuf>,
+    hashes : Option<RomHashes>,
+    game : Option<GameInfo>,
+    region : Option<Region>,
+    report : LoadReport,
+    bus_conflicts : bool,
+}
+
+impl Cartridge {
+    /// Puts `rom` on the board its mapper number calls for, after correcting its header from the bundled
+    /// [`GameDatabase::builtin`]. A trainer is loaded into PRG RAM at $7000 when the board has enough PRG RAM to
+    /// hold it, and dropped otherwise.
+    ///
+    /// # Errors
+    /// [`RomError::UnsupportedMapper`] when that board is not emulated.
+    pub fn new(rom : Rom) -> Result<Cartridge, RomError> {
+        Cartridge::with_database(rom, GameDatabase::builtin())
+    }
+
+    /// Like [`Cartridge::new`], looking the game up in `database` instead of the bundled one.
+    pub fn with_database(mut rom : Rom, database : &GameDatabase) -> Result<Cartridge, RomError> {
+        let hashes = rom.hashes();
+        let game = database.lookup(hashes.crc32).cloned();
+        if let Some(game) = &game {
+            game.apply(&mut rom);
+        }
+        let (mapper_number, battery, region, trainer) = (rom.mapper, rom.battery, rom.region, rom.trainer.take());
+        let bus_conflicts = mapper::has_bus_conflicts(rom.mapper, rom.submapper);
+        let mut cartridge = Cartridge {
+            mapper : mapper::from_rom(rom)?,
+            mapper_number,
+            battery,
+            save_path : None,
+            hashes : Some(hashes),
+            game,
+            region,
+            report : LoadReport::default(),
+            bus_conflicts,
+        };
+        if let Some(trainer) = trainer {
+            let ram = cartridge.mapper.prg_ram_mut();
+            if let Some(window) = ram.get_mut(TRAINER_RAM_OFFSET .. TRAINER_RAM_OFFSET + trainer.len()) {
+                window.copy_from_slice(&trainer);
+            }
+        }
+        Ok(cartridge)
+    }
+
+    /// Reads the iNES file at `path` and puts it on its board. A cartridge with a battery saves to the path with
+    /// the extension changed to .sav, and the RAM saved there last time is loaded.
+    ///
+    /// Files that do not match their header are loaded anyway, see [`Cartridge::load_report`].
+    pub fn from_file<P : AsRef<Path>>(path : P) -> Result<Cartridge, RomError> {
+        let (rom, report) = Rom::from_file_tolerant(&path)?;
+        let mut cartridge = Cartridge::new(rom)?;
+        cartridge.report = report;
+        if cartridge.battery {
+            cartridge.save_path = Some(path.as_ref().with_extension("sav"));
+            cartridge.load_from_disk()?;
+        }
+        Ok(cartridge)
+    }
+
+    /// A cartridge built around a board of the caller's own, for mappers the crate does not have.
+    pub fn with_mapper(mapper_number : u16, mapper : impl Mapper + 'static) -> Cartridge {
+        Cartridge {
+            mapper : Box::new(mapper),
+            mapper_number,
+            battery : false,
+            save_path : None,
+            hashes : None,
+            game : None,
+            region : None,
+            report : LoadReport::default(),
+            bus_conflicts : false,
+        }
+    }
+
+    /// What had to be fixed to load the file, clean unless the cartridge came from [`Cartridge::from_file`].
+    pub fn load_report(&self) -> &LoadReport {
+        &self.report
+    }
+
+    /// The checksums of the ROM, `None` for a cartridge made [`Cartridge::with_mapper`].
+    pub fn hashes(&self) -> Option<RomHashes> {
+        self.hashes
+    }
+
+    /// What the game database knows about the game, `None` when it is not in there.
+    pub fn game(&self) -> Option<&GameInfo> {
+        self.game.as_ref()
+    }
+
+    /// The console the game was made for, from the header or the game database. `None` when neither says, and for
+    /// a cartridge made [`Cartridge::with_mapper`].
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Whether the cartridge has a battery keeping its PRG RAM.
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// The battery backed PRG RAM to save, `None` when the cartridge has no battery or no RAM.
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        let ram = self.mapper.prg_ram();
+        (self.battery && !ram.is_empty()).then_some(ram)
+    }
+
+    /// Restores PRG RAM saved from [`Cartridge::save_ram`]. A save of the wrong size fills what it can.
+    pub fn load_ram(&mut self, saved : &[u8]) {
+        let ram = self.mapper.prg_ram_mut();
+        let len = ram.len().min(saved.len());
+        ram[.. len].copy_from_slice(&saved[.. len]);
+    }
+
+    /// The file the battery backed RAM is saved to, if any.
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    /// Changes the file the battery backed RAM is saved to, `None` to keep it only in memory.
+    pub fn set_save_path(&mut self, path : Option<PathBuf>) {
+        self.save_path = path;
+    }
+
+    /// Writes the battery backed RAM to the save file. Does nothing without a battery or a save file, and is
+    /// done when the cartridge is dropped too.
+    pub fn save_to_disk(&self) -> io::Result<()> {
+        match (self.save_ram(), &self.save_path) {
+            (Some(ram), Some(path)) => fs::write(path, ram),
+            _ => Ok(()),
+        }
+    }
+
+    /// Loads the battery backed RAM from the save file, returning whether there was one to load.
+    pub fn load_from_disk(&mut self) -> io::Result<bool> {
+        let Some(path) = &self.save_path else { return Ok(false) };
+        match fs::read(path) {
+            Ok(saved) => {
+                self.load_ram(&saved);
+                Ok(true)
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The iNES mapper number of the board.
+    pub fn mapper_number(&self) -> u16 {
+        self.mapper_number
+    }
+
+    /// The board.
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+
+    /// The board, to read and write it.
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+
+    /// Whether writes to $8000-$FFFF conflict with the ROM, from the header's submapper, the game database or
+    /// [`Cartridge::set_bus_conflicts`]. See [`mapper::has_bus_conflicts`].
+    pub fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+
+    /// Turns bus conflicts on or off, for games the header and database get wrong.
+    pub fn set_bus_conflicts(&mut self, bus_conflicts : bool) {
+        self.bus_conflicts = bus_conflicts;
+    }
+
+    /// Writes `value` to the board at `addr`. With bus conflicts the ROM drives the data bus during writes to
+    /// $8000-$FFFF, so the board gets the value ANDed with the ROM byte there.
+    pub fn cpu_write(&mut self, addr : u16, value : u8) {
+        let value = self.bus_conflict(addr, value);
+        self.mapper.cpu_write(addr, value);
+    }
+
+    /// [`Cartridge::cpu_write`] for a write the CPU makes on cycle `cycle`, see [`Mapper::cpu_write_on_cycle`].
+    pub fn cpu_write_on_cycle(&mut self, addr : u16, value : u8, cycle : u64) {
+        let value = self.bus_conflict(addr, value);
+        self.mapper.cpu_write_on_cycle(addr, value, cycle);
+    }
+
+    /// The value the board sees when the CPU writes `value` to `addr`.
+    fn bus_conflict(&self, addr : u16, value : u8) -> u8 {
+        match self.mapper.cpu_peek(addr) {
+            Some(rom) if self.bus_conflicts && addr >= 0x8000 => value & rom,
+            _ => value,
+        }
+    }
+
+    /// Saves the state of the board for a save state, see [`crate::mapper::state`]. The ROM is not included, the
+    /// state can only be loaded into a cartridge of the same game.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write_bytes(STATE_MAGIC);
+        state.write(&self.mapper_number);
+        state.write(&self.mapper.state_version());
+        self.mapper.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    /// Restores a state made by [`Cartridge::save_state`], from this version of the emulator or an older one.
+    ///
+    /// # Errors
+    /// [`StateError::WrongMapper`] for a state from another board, [`StateError::UnsupportedVersion`] for a state
+    /// from a newer emulator, and the others when the state is damaged or from another game with the same board.
+    /// On an error the board can be left half restored.
+    pub fn load_state(&mut self, bytes : &[u8]) -> Result<(), StateError> {
+        let mut header = StateReader::new(bytes, 0);
+        if header.read_bytes(STATE_MAGIC.len()).ok() != Some(STATE_MAGIC.as_slice()) {
+            return Err(StateError::NotAState);
+        }
+        let (mut mapper_number, mut version) = (0u16, 0u16);
+        header.read(&mut mapper_number)?;
+        header.read(&mut version)?;
+        if mapper_number != self.mapper_number {
+            return Err(StateError::WrongMapper { expected : self.mapper_number, found : mapper_number });
+        }
+        if version > self.mapper.state_version() {
+            return Err(StateError::UnsupportedVersion { mapper : mapper_number, version });
+        }
+        let mut state = StateReader::new(&bytes[STATE_HEADER_SIZE ..], version);
+        self.mapper.load_state(&mut state)?;
+        state.finish()
+    }
+
+    /// How the nametables are wired right now. Boards that switch mirroring change it as the game writes to them.
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+}
+
+impl Drop for Cartridge {
+    /// Saves the game, like the battery would keep it. There is no one to report a failure to at this point,
+    /// call [`Cartridge::save_to_disk`] first to find out about them.
+    fn drop(&mut self) {
+        let _ = self.save_to_disk();
+    }
+}
@@ -0,0 +1,197 @@
+//! # Database Module
+//!
+//! `database` identifies dumps by the CRC32 of their contents, to report which game a ROM is and to fix the
+//! headers of dumps that have them wrong (the wrong mapper, mirroring or battery, or garbage left by old tools).
+//!
+//! Databases are text, one game per line with fields separated by `;`:
+//!
+//! ```text
+//! # CRC32;mapper;mirroring;battery;region;title
+//! 3337ec46;0;V;;NTSC;Super Mario Bros.
+//! ```
+//!
+//! The mapper can be followed by a `.` and an NES 2.0 submapper, as in `3.1`, which for mappers 2, 3 and 7 says
+//! whether the board has bus conflicts (see [`has_bus_conflicts`](crate::mapper::has_bus_conflicts)).
+//! Mirroring is `H`, `V` or `4` (four-screen), battery is `B` or `-` for none, and region is `NTSC`, `PAL` or `Dendy`. Any of these
+//! can be left empty to keep what the header says. Lines starting with `#` are comments. A small database is
+//! bundled, see [`GameDatabase::builtin`], and others can be loaded with [`GameDatabase::from_file`].
+
+use super::{Mirroring, Rom};
+use crate::region::Region;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const BUILTIN : &str = include_str!("games.txt");
+
+/// What a database knows about a game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameInfo {
+    pub title : String,
+    /// The mapper the game really uses.
+    pub mapper : u16,
+    /// The submapper, `None` to trust the header.
+    pub submapper : Option<u8>,
+    /// The mirroring of the board, `None` when the board switches it or the header is trusted.
+    pub mirroring : Option<Mirroring>,
+    /// Whether the board has a battery, `None` to trust the header.
+    pub battery : Option<bool>,
+    /// The console the game was made for, `None` to trust the header.
+    pub region : Option<Region>,
+}
+
+impl GameInfo {
+    /// Corrects the header fields of `rom` the database knows better.
+    pub fn apply(&self, rom : &mut Rom) {
+        rom.mapper = self.mapper;
+        if let Some(submapper) = self.submapper {
+            rom.submapper = submapper;
+        }
+        if let Some(mirroring) = self.mirroring {
+            rom.mirroring = mirroring;
+        }
+        if let Some(battery) = self.battery {
+            rom.battery = battery;
+        }
+        if self.region.is_some() {
+            rom.region = self.region;
+        }
+    }
+}
+
+/// Why a database could not be loaded.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// Line `line` (counted from 1) is not a valid entry.
+    Syntax { line : usize, reason : String },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Io(error) => write!(f, "could not read game database: {}", error),
+            DatabaseError::Syntax { line, reason } => write!(f, "game database line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DatabaseError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(error : io::Error) -> Self {
+        DatabaseError::Io(error)
+    }
+}
+
+/// Games by the CRC32 of their PRG and CHR ROM.
+///
+/// # Example
+/// ```
+///  use nes::cartridge::GameDatabase;
+///
+///  let database = GameDatabase::parse("12345678;4;;B;;Some Game").unwrap();
+///  let game = database.lookup(0x1234_5678).unwrap();
+///  assert_eq!((game.title.as_str(), game.mapper, game.battery), ("Some Game", 4, Some(true)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameDatabase {
+    games : HashMap<u32, GameInfo>,
+}
+
+impl GameDatabase {
+    /// An empty database.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The database bundled with the crate.
+    pub fn builtin() -> &'static GameDatabase {
+        static BUILTIN_DATABASE : OnceLock<GameDatabase> = OnceLock::new();
+        BUILTIN_DATABASE.get_or_init(|| GameDatabase::parse(BUILTIN).expect("the bundled game database is valid"))
+    }
+
+    /// Parses a database in the text format described in the module documentation.
+    pub fn parse(text : &str) -> Result<GameDatabase, DatabaseError> {
+        let mut database = GameDatabase::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let syntax = |reason : &str| DatabaseError::Syntax { line : index + 1, reason : reason.to_string() };
+            let fields : Vec<&str> = line.splitn(6, ';').map(str::trim).collect();
+            let [crc, mapper, mirroring, battery, region, title] = fields[..] else {
+                return Err(syntax("expected 6 fields"));
+            };
+            let crc = u32::from_str_radix(crc, 16).map_err(|_| syntax("bad CRC32"))?;
+            let (mapper, submapper) = match mapper.split_once('.') {
+                Some((mapper, submapper)) => (mapper, Some(submapper.parse().map_err(|_| syntax("bad submapper"))?)),
+                None => (mapper, None),
+            };
+            let game = GameInfo {
+                title : title.to_string(),
+                mapper : mapper.parse().map_err(|_| syntax("bad mapper number"))?,
+                submapper,
+                mirroring : match mirroring {
+                    "" => None,
+                    "H" => Some(Mirroring::Horizontal),
+                    "V" => Some(Mirroring::Vertical),
+                    "4" => Some(Mirroring::FourScreen),
+                    _ => return Err(syntax("mirroring must be H, V or 4")),
+                },
+                battery : match battery {
+                    "" => None,
+                    "B" => Some(true),
+                    "-" => Some(false),
+                    _ => return Err(syntax("battery must be B or -")),
+                },
+                region : match region {
+                    "" => None,
+                    "NTSC" => Some(Region::Ntsc),
+                    "PAL" => Some(Region::Pal),
+                    "Dendy" => Some(Region::Dendy),
+                    _ => return Err(syntax("region must be NTSC, PAL or Dendy")),
+                },
+            };
+            database.games.insert(crc, game);
+        }
+        Ok(database)
+    }
+
+    /// Reads and parses the database file at `path`.
+    pub fn from_file<P : AsRef<Path>>(path : P) -> Result<GameDatabase, DatabaseError> {
+        GameDatabase::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Adds the games of `other`, replacing those this database already has.
+    pub fn extend(&mut self, other : GameDatabase) {
+        self.games.extend(other.games);
+    }
+
+    /// The game whose PRG and CHR ROM have the CRC32 `crc32`.
+    pub fn lookup(&self, crc32 : u32) -> Option<&GameInfo> {
+        self.games.get(&crc32)
+    }
+
+    /// The number of games in the database.
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    /// Whether the database has no games.
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+}
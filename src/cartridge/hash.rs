@@ -0,0 +1,136 @@
+//! # Hash Module
+//!
+//! `hash` computes the checksums ROM databases identify dumps by: CRC32 and SHA-1, both over the PRG ROM followed
+//! by the CHR ROM, without the iNES header (which differs between dumps of the same game).
+
+use super::Rom;
+
+/// The CRC32 (the zlib / PNG one) lookup table, built at compile time.
+const CRC32_TABLE : [u32 ; 256] = {
+    let mut table = [0 ; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC32 of several slices, as if they were one.
+fn crc32_of(parts : &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// The CRC32 of `bytes`.
+///
+/// # Example
+/// ```
+///  use nes::cartridge::crc32;
+///
+///  assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+/// ```
+pub fn crc32(bytes : &[u8]) -> u32 {
+    crc32_of(&[bytes])
+}
+
+/// SHA-1 of several slices, as if they were one.
+fn sha1_of(parts : &[&[u8]]) -> [u8 ; 20] {
+    let mut state : [u32 ; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let len : usize = parts.iter().map(|part| part.len()).sum();
+
+    // The message is padded with a 1 bit, zeros, and its length in bits, to a multiple of 64 bytes.
+    let mut padding = vec![0x80];
+    padding.resize((119 - len % 64) % 64 + 1, 0);
+    padding.extend(((len as u64) * 8).to_be_bytes());
+
+    let mut block = [0u8 ; 64];
+    let mut filled = 0;
+    for byte in parts.iter().flat_map(|part| part.iter()).chain(padding.iter()) {
+        block[filled] = *byte;
+        filled += 1;
+        if filled == 64 {
+            sha1_block(&mut state, &block);
+            filled = 0;
+        }
+    }
+
+    let mut digest = [0 ; 20];
+    for (chunk, word) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn sha1_block(state : &mut [u32 ; 5], block : &[u8 ; 64]) {
+    let mut w = [0u32 ; 80];
+    for (i, chunk) in block.chunks(4).enumerate() {
+        w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    for i in 16 .. 80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, word) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0 ..= 19 => ((b & c) | (!b & d), 0x5A82_7999),
+            20 ..= 39 => (b ^ c ^ d, 0x6ED9_EBA1),
+            40 ..= 59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+            _ => (b ^ c ^ d, 0xCA62_C1D6),
+        };
+        let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// The SHA-1 digest of `bytes`.
+///
+/// # Example
+/// ```
+///  use nes::cartridge::sha1;
+///
+///  assert_eq!(sha1(b"abc")[.. 4], [0xa9, 0x99, 0x3e, 0x36]);
+/// ```
+pub fn sha1(bytes : &[u8]) -> [u8 ; 20] {
+    sha1_of(&[bytes])
+}
+
+/// The checksums of a ROM's contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomHashes {
+    /// CRC32 of the PRG ROM and CHR ROM, what databases look games up by.
+    pub crc32 : u32,
+    pub prg_crc32 : u32,
+    pub chr_crc32 : u32,
+    /// SHA-1 of the PRG ROM and CHR ROM.
+    pub sha1 : [u8 ; 20],
+}
+
+impl Rom {
+    /// Computes the checksums of the PRG and CHR ROM.
+    pub fn hashes(&self) -> RomHashes {
+        RomHashes {
+            crc32 : crc32_of(&[&self.prg_rom, &self.chr_rom]),
+            prg_crc32 : crc32(&self.prg_rom),
+            chr_crc32 : crc32(&self.chr_rom),
+            sha1 : sha1_of(&[&self.prg_rom, &self.chr_rom]),
+        }
+    }
+}
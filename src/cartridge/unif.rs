@@ -0,0 +1,183 @@
+//! # UNIF Module
+//!
+//! `unif` reads UNIF (.unf) files, the format many unlicensed and bootleg carts were only ever dumped to. Instead
+//! of a mapper number a UNIF file names its board, and instead of a fixed header it is a 32 byte header (`UNIF`
+//! and a revision number) followed by chunks, each a 4 character ID, a 32-bit little endian length and the data:
+//!
+//! | Chunk       | Contents                                                                     |
+//! |-------------|------------------------------------------------------------------------------|
+//! | `MAPR`      | The board name, such as `NES-SNROM` or `UNL-...`, NUL terminated             |
+//! | `PRG0-PRGF` | The PRG ROM chips, in order                                                  |
+//! | `CHR0-CHRF` | The CHR ROM chips, in order                                                  |
+//! | `MIRR`      | Mirroring: horizontal, vertical, single-screen lower or upper, four-screen  |
+//! | `BATR`      | Present when the board has a battery                                         |
+//! | `TVCI`      | The console: 0 for NTSC, 1 for PAL, 2 for both                               |
+//!
+//! Other chunks (the name, dumper, CRCs of the chips...) are skipped. The board name picks the mapper, see
+//! [`unif_board_mapper`].
+
+use super::{Mirroring, Rom, RomError, DEFAULT_CHR_RAM_SIZE, DEFAULT_PRG_RAM_SIZE};
+use crate::region::Region;
+
+pub(super) const MAGIC : &[u8] = b"UNIF";
+const HEADER_SIZE : usize = 32;
+const CHUNK_HEADER_SIZE : usize = 8;
+/// Prefixes for who made the board, dropped before the name is looked up.
+const PREFIXES : [&str ; 4] = ["NES-", "HVC-", "UNL-", "BMC-"];
+
+/// The boards the emulated mappers cover, by UNIF name without the prefix.
+const BOARDS : &[(&str, u16)] = &[
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("HROM", 0),
+    ("RROM", 0),
+    ("RROM-128", 0),
+    ("SROM", 0),
+    ("RTROM", 0),
+    ("STROM", 0),
+    ("SAROM", 1),
+    ("SBROM", 1),
+    ("SCROM", 1),
+    ("SC1ROM", 1),
+    ("SEROM", 1),
+    ("SFROM", 1),
+    ("SGROM", 1),
+    ("SHROM", 1),
+    ("SH1ROM", 1),
+    ("SIROM", 1),
+    ("SJROM", 1),
+    ("SKROM", 1),
+    ("SLROM", 1),
+    ("SL1ROM", 1),
+    ("SL2ROM", 1),
+    ("SL3ROM", 1),
+    ("SLRROM", 1),
+    ("SMROM", 1),
+    ("SNROM", 1),
+    ("SOROM", 1),
+    ("SUROM", 1),
+    ("SXROM", 1),
+    ("UNROM", 2),
+    ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4),
+    ("TEROM", 4),
+    ("TFROM", 4),
+    ("TGROM", 4),
+    ("TKROM", 4),
+    ("TLROM", 4),
+    ("TL1ROM", 4),
+    ("TNROM", 4),
+    ("TR1ROM", 4),
+    ("TSROM", 4),
+    ("TVROM", 4),
+    ("EKROM", 5),
+    ("ELROM", 5),
+    ("ETROM", 5),
+    ("EWROM", 5),
+    ("AMROM", 7),
+    ("ANROM", 7),
+    ("AN1ROM", 7),
+    ("AOROM", 7),
+    ("PNROM", 9),
+    ("PEEOROM", 9),
+    ("GNROM", 66),
+    ("MHROM", 66),
+    ("BTR", 69),
+    ("JLROM", 69),
+    ("JSROM", 69),
+];
+
+/// The mapper number of the board UNIF calls `name`, `None` when it is not one of the emulated boards.
+///
+/// # Example
+/// ```
+///  use nes::cartridge::unif_board_mapper;
+///
+///  assert_eq!(unif_board_mapper("NES-SNROM"), Some(1));
+///  assert_eq!(unif_board_mapper("HVC-TLROM"), Some(4));
+///  assert_eq!(unif_board_mapper("UNL-SOMETHING"), None);
+/// ```
+pub fn unif_board_mapper(name : &str) -> Option<u16> {
+    let board = PREFIXES.iter().find_map(|prefix| name.strip_prefix(prefix)).unwrap_or(name);
+    BOARDS.iter().find(|(known, _)| *known == board).map(|&(_, mapper)| mapper)
+}
+
+/// Parses a UNIF image, which starts with [`MAGIC`].
+pub(super) fn parse(bytes : &[u8]) -> Result<Rom, RomError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(RomError::Truncated { expected : HEADER_SIZE, actual : bytes.len() });
+    }
+    let mut board = None;
+    let mut prg_chips : [&[u8] ; 16] = Default::default();
+    let mut chr_chips : [&[u8] ; 16] = Default::default();
+    let mut mirroring = Mirroring::Horizontal;
+    let mut battery = false;
+    let mut region = None;
+
+    let mut position = HEADER_SIZE;
+    while position < bytes.len() {
+        let start = position + CHUNK_HEADER_SIZE;
+        let Some(header) = bytes.get(position .. start) else {
+            return Err(RomError::Truncated { expected : start, actual : bytes.len() });
+        };
+        let length = u32::from_le_bytes(header[4 ..].try_into().unwrap()) as usize;
+        let end = start.saturating_add(length);
+        let Some(data) = bytes.get(start .. end) else {
+            return Err(RomError::Truncated { expected : end, actual : bytes.len() });
+        };
+        let chip = || (header[3] as char).to_digit(16).map(|chip| chip as usize);
+        match &header[.. 4] {
+            b"MAPR" => {
+                let name = data.split(|&byte| byte == 0).next().unwrap_or_default();
+                board = Some(String::from_utf8_lossy(name).trim().to_string());
+            }
+            [b'P', b'R', b'G', _] if chip().is_some() => prg_chips[chip().unwrap()] = data,
+            [b'C', b'H', b'R', _] if chip().is_some() => chr_chips[chip().unwrap()] = data,
+            b"MIRR" => {
+                mirroring = match data.first() {
+                    Some(1) => Mirroring::Vertical,
+                    Some(2) => Mirroring::SingleScreenLower,
+                    Some(3) => Mirroring::SingleScreenUpper,
+                    Some(4) => Mirroring::FourScreen,
+                    // 0 is horizontal, 5 leaves it to the mapper.
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            b"BATR" => battery = data.first() != Some(&0),
+            b"TVCI" => {
+                region = match data.first() {
+                    Some(0) => Some(Region::Ntsc),
+                    Some(1) => Some(Region::Pal),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+        position = end;
+    }
+
+    let board = board.ok_or(RomError::MissingBoard)?;
+    let mapper = unif_board_mapper(&board).ok_or(RomError::UnknownBoard(board))?;
+    let prg_rom = prg_chips.concat();
+    let chr_rom = chr_chips.concat();
+    if prg_rom.is_empty() {
+        return Err(RomError::NoPrgRom);
+    }
+    Ok(Rom {
+        chr_ram_size : if chr_rom.is_empty() { DEFAULT_CHR_RAM_SIZE } else { 0 },
+        prg_rom,
+        chr_rom,
+        mapper,
+        submapper : 0,
+        mirroring,
+        battery,
+        trainer : None,
+        nes2 : false,
+        prg_ram_size : DEFAULT_PRG_RAM_SIZE,
+        prg_nvram_size : 0,
+        chr_nvram_size : 0,
+        region,
+    })
+}
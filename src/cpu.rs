@@ -2,21 +2,434 @@
 //!
 //! `cpu` implements the hardware and ALU for the cpu in this project.
 
+use crate::mem::{FlatMemory, Mem};
+use crate::opcodes;
+use crate::region::Region;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::RangeInclusive;
 
-/// This struct implements the hardware available to the NES in the CPU.
-pub struct CPU {
+mod state;
+mod tick;
+mod watch;
+
+pub use state::CpuState;
+pub use watch::{Access, WatchHit};
+
+/// The stack lives in page one of memory, the stack pointer is an offset into this page.
+const STACK : u16 = 0x0100;
+
+/// The value the stack pointer is set to on reset.
+const STACK_RESET : u8 = 0xfd;
+
+/// Writing a page number here copies that page into the PPU sprite memory, suspending the CPU while it does.
+const OAM_DMA : u16 = 0x4014;
+
+/// The PPU's OAMDATA register, which OAM DMA writes each byte of the page to.
+const OAM_DATA : u16 = 0x2004;
+
+/// The address of the pointer to the non-maskable interrupt handler.
+const NMI_VECTOR : u16 = 0xFFFA;
+
+/// The address of the pointer to the first instruction executed after reset.
+const RESET_VECTOR : u16 = 0xFFFC;
+
+/// The address of the pointer to the handler shared by IRQ and BRK.
+const IRQ_BRK_VECTOR : u16 = 0xFFFE;
+
+/// The processor status register (P). Each bit is a flag, from bit 0 upwards: carry, zero, interrupt disable,
+/// decimal mode, break, break2 (unused, always reads as 1 when pushed), overflow and negative.
+///
+/// # Example
+/// ```
+///  use nes::cpu::StatusFlags;
+///
+///  let mut status = StatusFlags::from_bits(0b1000_0001);
+///  assert!(status.carry() && status.negative());
+///  status.set_zero(true);
+///  assert_eq!(status.bits(), 0b1000_0011);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatusFlags(u8);
+
+impl StatusFlags {
+    pub const CARRY : u8 = 0b0000_0001;
+    pub const ZERO : u8 = 0b0000_0010;
+    pub const INTERRUPT_DISABLE : u8 = 0b0000_0100;
+    pub const DECIMAL_MODE : u8 = 0b0000_1000;
+    pub const BREAK : u8 = 0b0001_0000;
+    pub const BREAK2 : u8 = 0b0010_0000;
+    pub const OVERFLOW : u8 = 0b0100_0000;
+    pub const NEGATIVE : u8 = 0b1000_0000;
+
+    /// Creates the register from its raw byte.
+    pub const fn from_bits(bits : u8) -> Self {
+        StatusFlags(bits)
+    }
+
+    /// Returns the raw byte of the register.
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if every flag in the mask is set.
+    pub const fn contains(&self, mask : u8) -> bool {
+        self.0 & mask == mask
+    }
+
+    /// Sets or clears every flag in the mask.
+    pub fn set(&mut self, mask : u8, value : bool) {
+        if value {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
+
+    pub const fn carry(&self) -> bool {
+        self.contains(Self::CARRY)
+    }
+
+    pub fn set_carry(&mut self, value : bool) {
+        self.set(Self::CARRY, value);
+    }
+
+    pub const fn zero(&self) -> bool {
+        self.contains(Self::ZERO)
+    }
+
+    pub fn set_zero(&mut self, value : bool) {
+        self.set(Self::ZERO, value);
+    }
+
+    pub const fn interrupt_disable(&self) -> bool {
+        self.contains(Self::INTERRUPT_DISABLE)
+    }
+
+    pub fn set_interrupt_disable(&mut self, value : bool) {
+        self.set(Self::INTERRUPT_DISABLE, value);
+    }
+
+    pub const fn decimal_mode(&self) -> bool {
+        self.contains(Self::DECIMAL_MODE)
+    }
+
+    pub fn set_decimal_mode(&mut self, value : bool) {
+        self.set(Self::DECIMAL_MODE, value);
+    }
+
+    pub const fn overflow(&self) -> bool {
+        self.contains(Self::OVERFLOW)
+    }
+
+    pub fn set_overflow(&mut self, value : bool) {
+        self.set(Self::OVERFLOW, value);
+    }
+
+    pub const fn negative(&self) -> bool {
+        self.contains(Self::NEGATIVE)
+    }
+
+    pub fn set_negative(&mut self, value : bool) {
+        self.set(Self::NEGATIVE, value);
+    }
+}
+
+impl From<u8> for StatusFlags {
+    fn from(bits : u8) -> Self {
+        StatusFlags::from_bits(bits)
+    }
+}
+
+impl From<StatusFlags> for u8 {
+    fn from(status : StatusFlags) -> Self {
+        status.bits()
+    }
+}
+
+
+/// Returns the address an indexed access that crossed a page reads first: the low byte has been indexed but the
+/// carry has not yet reached the high byte.
+fn unfixed_address(address : u16) -> u16 {
+    address.wrapping_sub(0x100)
+}
+
+/// Returns how long an OAM DMA started at cycle `cycles` suspends the CPU: one cycle to halt, one more to line
+/// up with a read cycle when started on an odd cycle, then 256 read and write pairs.
+fn oam_dma_cycles(cycles : u64) -> u64 {
+    513 + cycles % 2
+}
+
+/// Returns true if two addresses are in different 256 byte pages.
+fn page_crossed(a : u16, b : u16) -> bool {
+    a & 0xff00 != b & 0xff00
+}
+
+
+/// How [`CPU::run`] and [`CPU::step`] execute instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// Each instruction runs in one go and its cycles are added at the end. This is the fast path.
+    #[default]
+    PerInstruction,
+    /// Each instruction runs one cycle at a time through [`CPU::tick`], making its bus accesses in the order the
+    /// hardware does and polling for interrupts on the cycle the hardware polls them.
+    PerCycle,
+}
+
+
+/// Matches the addressing mode provided by the opcode, returns the absolute address of the memory to be accessed and
+/// whether indexing crossed a page boundary. `pc` points at the first operand byte and `read` fetches the operand
+/// and any pointer it refers to.
+///
+/// Note that this is a poor analogy for the an actual CPU as the there are no cycle, or space saves for using paged
+/// references. The page crossing flag is how the extra cycle the hardware needs to fix up the high byte of an
+/// indexed address is accounted for.
+fn operand_address<R>(mode : &AddressingMode, pc : u16, x : u8, y : u8, mut read : R) -> Result<(u16, bool), CpuError>
+where
+    R : FnMut(u16) -> u8,
+{
+    let operand = match mode {
+        AddressingMode::Immediate => (pc, false),
+
+        AddressingMode::ZeroPage => (read(pc) as u16, false),
+
+        AddressingMode::Absolute => (read_u16(&mut read, pc), false),
+
+        AddressingMode::ZeroPage_X => {
+            let pos = read(pc);
+            (pos.wrapping_add(x) as u16, false)
+        },
+
+        AddressingMode::ZeroPage_Y => {
+            let pos = read(pc);
+            (pos.wrapping_add(y) as u16, false)
+        },
+
+        AddressingMode::Absolute_X => {
+            let base = read_u16(&mut read, pc);
+            let addr = base.wrapping_add(x as u16);
+            (addr, page_crossed(base, addr))
+        },
+
+        AddressingMode::Absolute_Y => {
+            let base = read_u16(&mut read, pc);
+            let addr = base.wrapping_add(y as u16);
+            (addr, page_crossed(base, addr))
+        },
+
+        AddressingMode::Indirect => {
+            let pointer = read_u16(&mut read, pc);
+            // The 6502 does not carry into the high byte of the pointer when fetching the target,
+            // so JMP ($30FF) reads the target from $30FF and $3000 rather than $3100.
+            let lo = read(pointer) as u16;
+            let hi = read((pointer & 0xff00) | (pointer.wrapping_add(1) & 0x00ff)) as u16;
+            ((hi << 8) | lo, false)
+        }
+
+        AddressingMode::Indirect_X => {
+            let zero_page = read(pc);
+            (read_zero_page_pointer(&mut read, zero_page.wrapping_add(x)), false)
+        }
+
+        AddressingMode::Indirect_Y => {
+            let base = read(pc);
+            let deref_base = read_zero_page_pointer(&mut read, base);
+            let deref = deref_base.wrapping_add(y as u16);
+            (deref, page_crossed(deref_base, deref))
+        }
+
+        AddressingMode::Relative => {
+            // The signed offset is relative to the address of the next instruction, i.e. after the operand.
+            let offset = read(pc) as i8;
+            let next = pc.wrapping_add(1);
+            let target = next.wrapping_add(offset as u16);
+            (target, page_crossed(next, target))
+        }
+
+        AddressingMode::NoneAddressing => {
+            return Err(CpuError::UnsupportedAddressingMode(*mode));
+        }
+    };
+    Ok(operand)
+}
+
+/// Reads a little endian word through `read`.
+fn read_u16<R : FnMut(u16) -> u8>(read : &mut R, addr : u16) -> u16 {
+    let lo = read(addr) as u16;
+    let hi = read(addr.wrapping_add(1)) as u16;
+    (hi << 8) | lo
+}
+
+/// Reads a little endian pointer from the zero page. The address of the high byte wraps within the zero page, so a
+/// pointer at 0xFF takes its high byte from 0x00 rather than 0x100.
+fn read_zero_page_pointer<R : FnMut(u16) -> u8>(read : &mut R, address : u8) -> u16 {
+    let lo = read(address as u16) as u16;
+    let hi = read(address.wrapping_add(1) as u16) as u16;
+    (hi << 8) | lo
+}
+
+
+/// This struct implements the hardware available to the NES in the CPU. Everything outside the CPU is reached
+/// through `M`, see [`Mem`].
+pub struct CPU<M = FlatMemory> {
     pub register_a : u8,
     pub register_x : u8,
     pub register_y : u8,
-    pub status : u8,
+    pub status : StatusFlags,
     pub program_counter : u16,
-    memory : [u8 ; 0xFFFF]
+    pub stack_pointer : u8,
+    /// When set, a BRK reached while no handler is installed (the vector at 0xFFFE is 0x0000) returns from
+    /// [`CPU::run`] without being executed, instead of jumping to 0x0000. This is how the test programs and
+    /// examples in this crate end, [`CPU::new`] turns it on.
+    pub stop_on_brk : bool,
+    /// The number of CPU cycles elapsed, every instruction adds its cycle count from the opcode table.
+    pub cycles : u64,
+    /// Behaviour of the unstable undocumented opcodes.
+    pub quirks : CpuQuirks,
+    /// When set, ADC and SBC (and the undocumented RRA and ISB) do binary coded decimal arithmetic while the
+    /// decimal mode flag is set, as on an NMOS 6502. The 2A03 in the NES has the BCD circuitry cut out, so this is
+    /// off by default and the flag then only exists to be set, cleared and pushed.
+    pub bcd_enabled : bool,
+    /// Whether instructions run whole or one cycle at a time.
+    pub execution_mode : ExecutionMode,
+    halted : bool,
+    nmi_pending : bool,
+    irq_line : bool,
+    /// The interrupt disable flag as seen by the last interrupt poll, when the previous instruction (CLI, SEI or
+    /// PLP) changed it after polling.
+    polled_i : Option<bool>,
+    /// The (NMI, IRQ) lines as seen by the last interrupt poll, when the previous instruction polled before its
+    /// final cycle (a taken branch that stays on its page) or did not poll at all (BRK and the interrupt sequence).
+    polled_lines : Option<(bool, bool)>,
+    /// Cycles the CPU is suspended for (by DMA) before it runs the next instruction.
+    stall : u64,
+    /// The cycles of OAM DMA still to come, OAM DMA is in progress while this is not 0.
+    oam_dma : u64,
+    /// The last value driven on the data bus, returned by reads of unmapped addresses.
+    data_bus : u8,
+    /// The address of the last bus access if it was a read, a DMA halt repeats it.
+    last_read : Option<u16>,
+    unmapped : Vec<RangeInclusive<u16>>,
+    breakpoints : HashSet<u16>,
+    watchpoints : Vec<watch::Watchpoint>,
+    watch_handler : Option<watch::WatchHandler>,
+    /// The first watchpoint hit not reported yet.
+    watch_hit : Option<WatchHit>,
+    /// Set while an instruction is running, only its accesses are checked against the watchpoints.
+    executing : bool,
+    /// The address of the instruction being executed.
+    instruction_address : u16,
+    /// The instruction part way through being ticked, if any.
+    micro : Option<tick::Micro>,
+    memory : M,
+}
+
+
+/// Selects how the unstable undocumented opcodes behave. Their results depend on analog effects that differ
+/// between CPU revisions (and even between individual chips and temperatures), so there is no single correct
+/// answer, only the behaviour a particular test ROM or game expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuQuirks {
+    /// The constant XAA ($8B) ORs into the A register before combining it with X and the operand, i.e.
+    /// `A = (A | magic) & X & operand`. 0xEE is the most commonly observed value and the default, 0xFF and 0x00
+    /// are seen on other chips.
+    pub xaa_magic : u8,
+    /// When set, AHX and TAS replace the high byte of the target address with the value being stored if
+    /// indexing crossed a page, as most 2A03s do. When clear the store always goes to the indexed address.
+    pub unstable_store_page_cross : bool,
+}
+
+impl Default for CpuQuirks {
+    fn default() -> Self {
+        CpuQuirks {
+            xaa_magic : 0xee,
+            unstable_store_page_cross : true,
+        }
+    }
+}
+
+
+/// Errors that stop the CPU from executing a program. The CPU is left as it was when the error occurred, so
+/// the caller can inspect it, fix things up and carry on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// The byte at `address` is not an opcode this CPU knows how to execute. The program counter is left pointing
+    /// at it.
+    UnknownOpcode { opcode : u8, address : u16 },
+    /// An instruction tried to work out an operand address in a mode that has none, which means the opcode table
+    /// and the instruction disagree.
+    UnsupportedAddressingMode(AddressingMode),
+    /// The program passed to [`CPU::load`] does not fit between 0x8000 and the end of memory.
+    ProgramTooLarge { size : usize, max : usize },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { opcode, address } => {
+                write!(f, "unknown opcode {:#04x} at {:#06x}", opcode, address)
+            }
+            CpuError::UnsupportedAddressingMode(mode) => {
+                write!(f, "addressing mode {:?} has no operand address", mode)
+            }
+            CpuError::ProgramTooLarge { size, max } => {
+                write!(f, "program of {} bytes is larger than the {} bytes available", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+
+/// The hardware interrupts the CPU can service between instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+
+/// Why [`CPU::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// The program reached a BRK with no handler installed, see [`CPU::stop_on_brk`].
+    Exit,
+    /// The CPU jammed, see [`CPU::is_halted`].
+    Halted,
+    /// The program counter reached a breakpoint, the instruction there has not run yet.
+    Breakpoint(u16),
+    /// An instruction accessed a watched address, see [`CPU::watch_read`] and [`CPU::watch_write`].
+    Watchpoint(WatchHit),
+}
+
+
+/// Describes the instruction executed by [`CPU::step`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepResult {
+    /// The address the opcode was fetched from.
+    pub address : u16,
+    pub opcode : u8,
+    pub mnemonic : &'static str,
+    /// The operand bytes following the opcode, empty for implied and accumulator instructions.
+    pub operands : Vec<u8>,
+    /// The address the instruction read, wrote or jumped to, worked out before it ran. `None` for immediate,
+    /// implied and accumulator instructions.
+    pub effective_address : Option<u16>,
+    /// The interrupt entered before the instruction, which is then the first one of the handler.
+    pub interrupt : Option<Interrupt>,
+    /// The cycles taken, including the 7 of an interrupt sequence.
+    pub cycles : u64,
+    /// Set when the CPU cannot go on (it jammed), the instruction hit a watchpoint or the next instruction is at a
+    /// breakpoint.
+    pub stopped : Option<Stopped>,
 }
 
 
 /// This enum allows matching against the different available addressing modes for each opcode. [This](https://skilldrick.github.io/easy6502/#addressing) resource more
 /// information about addressing modes 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -27,112 +440,217 @@ pub enum AddressingMode {
     Absolute_X,
     Absolute_Y,
     Indirect_X,
+    Indirect,
     Indirect_Y,
+    Relative,
     NoneAddressing,
 }
 
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl CPU {
     /// Initialises the CPU, all registers and memory addresses are initialised with 0x00.
     pub fn new() -> Self {
+        Self::with_memory(FlatMemory::new())
+    }
+}
+
+impl<M : Mem> CPU<M> {
+    /// Initialises the CPU connected to `memory`, all registers are initialised with 0x00.
+    pub fn with_memory(memory : M) -> Self {
         CPU {
             register_a: 0,
             register_x : 0,
             register_y : 0,
-            status: 0,
+            status: StatusFlags::default(),
             program_counter: 0,
-            memory : [0 ; 0xFFFF]
+            stack_pointer : STACK_RESET,
+            stop_on_brk : true,
+            cycles : 0,
+            quirks : CpuQuirks::default(),
+            bcd_enabled : false,
+            execution_mode : ExecutionMode::default(),
+            halted : false,
+            nmi_pending : false,
+            irq_line : false,
+            polled_i : None,
+            polled_lines : None,
+            stall : 0,
+            oam_dma : 0,
+            data_bus : 0,
+            last_read : None,
+            unmapped : Vec::new(),
+            breakpoints : HashSet::new(),
+            watchpoints : Vec::new(),
+            watch_handler : None,
+            watch_hit : None,
+            executing : false,
+            instruction_address : 0,
+            micro : None,
+            memory,
         }
     }
 
-    /// Matches the addressing mode provided by the opcode, returns the absolute address of the memory to
-    /// be accessed. 
-    /// 
-    /// Note that this is a poor analogy for the an actual CPU as the there are no cycle, or space saves 
-    /// for using paged references. 
-    fn get_operand_address(&self, mode : &AddressingMode) -> u16 {
-        match mode {
-            AddressingMode::Immediate => self.program_counter,
+    /// The console timing being emulated, used to convert [`CPU::cycles`] into PPU dots and wall clock time. It is
+    /// the memory's, see [`Mem::region`], so changing the bus's region changes the CPU's.
+    pub fn region(&self) -> Region {
+        self.memory.region()
+    }
 
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+    /// The memory the CPU is connected to.
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
 
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter) as u16,
+    /// The memory the CPU is connected to. Accesses made through this bypass the CPU, so they do not touch the
+    /// data bus or trigger DMA.
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
 
-            AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
-            },
+    /// Works out the address the operand of the instruction at the program counter refers to, see
+    /// [`operand_address`]. Pointers are read through the bus like the hardware does.
+    fn get_operand_address(&mut self, mode : &AddressingMode) -> Result<(u16, bool), CpuError> {
+        let (pc, x, y) = (self.program_counter, self.register_x, self.register_y);
+        operand_address(mode, pc, x, y, |addr| self.mem_read(addr))
+    }
 
-            AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
-            },
+    /// Reads the operand of an instruction that only reads memory. These take an extra cycle when indexing
+    /// crosses a page, unlike stores and read-modify-write instructions which always spend it.
+    ///
+    /// The extra cycle is a read from the address before the carry into the high byte was fixed up, it happens
+    /// on the bus like any other read.
+    fn read_operand(&mut self, mode : &AddressingMode) -> Result<u8, CpuError> {
+        let (addr, page_cross) = self.get_operand_address(mode)?;
+        if page_cross {
+            self.cycles += 1;
+            self.dummy_read(unfixed_address(addr));
+        }
+        Ok(self.mem_read(addr))
+    }
 
-            AddressingMode::Absolute_X => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x as u16) as u16;
-                addr
-            },
+    /// Works out the address a store or read-modify-write instruction writes to. Indexed absolute and ($nn),Y
+    /// addressing always read from the address before the carry into the high byte was fixed up (the same
+    /// address when no page is crossed), because the CPU cannot undo a write the way it can ignore a read.
+    fn write_address(&mut self, mode : &AddressingMode) -> Result<u16, CpuError> {
+        let (addr, page_cross) = self.get_operand_address(mode)?;
+        if let AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y = mode {
+            self.dummy_read(if page_cross { unfixed_address(addr) } else { addr });
+        }
+        Ok(addr)
+    }
 
-            AddressingMode::Absolute_Y => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y as u16) as u16;
-                addr
-            },
+    /// A read the CPU makes only as a side effect of how an instruction is sequenced, the value is thrown away.
+    /// Reads of I/O registers have side effects (acknowledging interrupts, advancing the PPU address), so these
+    /// still go through the normal read path.
+    fn dummy_read(&mut self, address : u16) {
+        self.mem_read(address);
+    }
 
-            AddressingMode::Indirect_X => {
-                let zero_page = self.mem_read(self.program_counter);
-                let address = zero_page.wrapping_add(self.register_x) as u16;
+    /// Reads the the byte from the memory address. Nothing drives the data bus for an unmapped address, so the
+    /// value read is whatever was last on it (the open bus), usually the high byte of the address itself.
+    pub fn mem_read(&mut self, address : u16) -> u8 {
+        self.last_read = Some(address);
+        if !self.is_unmapped(address) {
+            self.data_bus = self.memory.read(address);
+        }
+        self.check_watchpoints(Access::Read, address, self.data_bus);
+        self.data_bus
+    }
 
-                let lo = self.mem_read(address) as u16;
-                let hi = self.mem_read(address.wrapping_add(1)) as u16;
-                (hi << 8) | (lo as u16)
-            }
+    /// Returns the byte [`CPU::mem_read`] would, without reading it: the data bus is left alone and memory mapped
+    /// registers see no access. This is how tracers and debuggers look at memory.
+    pub fn peek(&self, address : u16) -> u8 {
+        if self.is_unmapped(address) {
+            self.data_bus
+        } else {
+            self.memory.peek(address)
+        }
+    }
 
-            AddressingMode::Indirect_Y => {
-                let base = self.mem_read(self.program_counter);
-    
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
-            }
+    /// Suspends the CPU for `cycles` cycles, as DMA does. The cycles are added to [`CPU::cycles`] before the next
+    /// instruction runs.
+    pub fn stall(&mut self, cycles : u64) {
+        self.stall += cycles;
+    }
 
-            AddressingMode::NoneAddressing => {
-                panic!("mode {:?} is not supported", mode);
+    /// Fetches a DMC sample byte from `address` for the APU, stealing cycles from the CPU.
+    ///
+    /// The fetch halts the CPU for 4 cycles, or 2 when it lands during OAM DMA which has already halted it. While
+    /// halting, the CPU repeats the read it was making, so when that was a register with read side effects (the
+    /// controller ports or the PPU data port) the register is read twice, losing a controller bit or skipping a
+    /// VRAM byte exactly as on the hardware.
+    pub fn dmc_dma(&mut self, address : u16) -> u8 {
+        let cycles = if self.oam_dma > 0 { 2 } else { 4 };
+        self.stall(cycles);
+
+        if let Some(last) = self.last_read {
+            // PPUDATA is mirrored every 8 bytes up to $3FFF.
+            if last & 0xe007 == 0x2007 || matches!(last, 0x4016 | 0x4017) {
+                self.dummy_read(last);
             }
         }
+        self.mem_read(address)
+    }
+
+    /// Marks a range of addresses as having nothing connected to them: reads return the open bus value and
+    /// writes are dropped. Everything is mapped to RAM until this is called.
+    pub fn unmap(&mut self, range : RangeInclusive<u16>) {
+        self.unmapped.push(range);
     }
 
-    /// Reads the the byte from the memory address. 
-    fn mem_read(&self, address : u16) -> u8 {
-        self.memory[address as usize]
+    fn is_unmapped(&self, address : u16) -> bool {
+        self.unmapped.iter().any(|range| range.contains(&address))
     }
 
     /// Reads two bytes from the provided address and the next address, note that the bytes returned use little endian 
     /// notation (i.e. pos -> LSB, pos + 1 -> MSB). 
-    fn mem_read_u16(&self, pos : u16) -> u16 {
+    pub fn mem_read_u16(&mut self, pos : u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
     /// Writes a byte to memory at provided absolute address. 
-    fn mem_write(&mut self, address : u16, data : u8) {
-        self.memory[address as usize] = data;
+    pub fn mem_write(&mut self, address : u16, data : u8) {
+        if address == OAM_DMA {
+            let cycles = oam_dma_cycles(self.cycles);
+            self.stall(cycles);
+            self.oam_dma += cycles;
+        }
+        self.data_bus = data;
+        self.last_read = None;
+        self.check_watchpoints(Access::Write, address, data);
+        if !self.is_unmapped(address) {
+            self.memory.write(address, data);
+            if address == OAM_DMA {
+                self.oam_dma_transfer(data);
+            }
+        }
+    }
+
+    /// Copies the 256 bytes of `page` to OAMDATA, which stores them from the current OAMADDR on. This is the
+    /// transfer OAM DMA makes, the cycles it takes are the stall [`CPU::mem_write`] sets up.
+    fn oam_dma_transfer(&mut self, page : u8) {
+        let start = (page as u16) << 8;
+        for offset in 0 ..= 0xff {
+            let value = self.memory.read(start | offset);
+            self.memory.write(OAM_DATA, value);
+        }
     }
 
 
     /// Writes two bytes starting at position provided using little endian addressing. (i.e. pos = LSB, pos + 1 = MSB). 
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+    pub fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+        self.mem_write(pos.wrapping_add(1), hi);
     }
 
 
@@ -144,86 +662,1024 @@ impl CPU {
     ///  use nes::cpu::CPU;  
     ///  
     ///  let mut cpu = CPU::new();
-    ///  cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00]);
+    ///  cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00]).unwrap();
     ///  assert_eq!(cpu.register_x, 1);
     /// ```
-    pub fn load_and_run(&mut self, program : Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run();
+    pub fn load_and_run(&mut self, program : Vec<u8>) -> Result<Stopped, CpuError> {
+        self.load(program)?;
+        self.power_on();
+        self.run()
     }
 
-    /// Sets all registers to 0x00 and then moves the program counter to the absolute address referenced by the bytes stored at 0xFFFC and 0xFFFD. 
-    pub fn reset(&mut self) {
+    /// Returns true once the CPU has executed one of the JAM (also known as KIL) opcodes. A jammed 6502 stops
+    /// fetching instructions until it is reset, so [`CPU::run`] returns straight away while this is set. The
+    /// program counter is left pointing at the JAM opcode.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Signals a non-maskable interrupt, as the PPU does at the start of vertical blank. The NMI line is edge
+    /// triggered, so the request is latched and serviced once, before the next instruction, regardless of the
+    /// interrupt disable flag.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Drives the IRQ line, shared by mapper IRQs and the APU frame counter. The line is level triggered: while it
+    /// is asserted an interrupt is taken before every instruction that runs with the interrupt disable flag clear,
+    /// so the source has to be acknowledged (and the line released) by the handler. The memory's own line (see
+    /// [`Mem::irq_line`]) is wired to it as well.
+    pub fn set_irq(&mut self, asserted : bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Whether anything is holding the IRQ line low, from outside or through the memory.
+    fn irq_asserted(&self) -> bool {
+        self.irq_line || self.memory.irq_line()
+    }
+
+    /// Lets the memory catch up with `cycles` cycles and latches an NMI it raised while doing so.
+    fn tick_memory(&mut self, cycles : u64) {
+        self.memory.tick(cycles);
+        if self.memory.take_nmi() {
+            self.nmi_pending = true;
+        }
+    }
+
+    /// Returns the vector of the interrupt that will be serviced before the next instruction, if any.
+    ///
+    /// The 6502 polls its interrupt lines during the second-to-last cycle of each instruction, so what is seen
+    /// here is the state at that point of the previous instruction rather than the current one. Signals raised
+    /// between instructions are treated as arriving in time for that poll, unless the previous instruction polled
+    /// early (see `polled_lines`). CLI, SEI and PLP change the I flag after the poll, so an IRQ is held off (or let
+    /// through) for one more instruction, while RTI restores it before and takes effect immediately.
+    fn polled_interrupt(&self) -> Option<u16> {
+        let (nmi, irq) = self.polled_lines.unwrap_or((self.nmi_pending, self.irq_asserted()));
+        let interrupt_disable = self.polled_i.unwrap_or(self.status.interrupt_disable());
+        if nmi {
+            Some(NMI_VECTOR)
+        } else if irq && !interrupt_disable {
+            Some(IRQ_BRK_VECTOR)
+        } else {
+            None
+        }
+    }
+
+    /// Puts the CPU into a known clean state: all registers and flags are set to 0x00, the stack pointer to 0xFD
+    /// and then the program counter is moved to the absolute address referenced by the bytes stored at 0xFFFC and
+    /// 0xFFFD. Memory is left as it is.
+    pub fn power_on(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
-        self.status = 0;
-    
-        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.register_y = 0;
+        self.stack_pointer = STACK_RESET;
+        self.status = StatusFlags::default();
+        self.clear_interrupt_state();
+
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+    }
+
+    /// Presses the reset button. Like the hardware this runs an interrupt sequence with its stack writes
+    /// suppressed: the registers and memory are left alone, the stack pointer is decremented by 3, interrupts are
+    /// disabled and the program counter is loaded from the reset vector at 0xFFFC, taking 7 cycles. A jammed CPU
+    /// is brought back to life.
+    pub fn reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status.set_interrupt_disable(true);
+        self.clear_interrupt_state();
+        self.cycles += 7;
+        self.tick_memory(7);
+
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+    }
+
+    /// Clears the jam, any latched interrupt and any instruction left part way through by [`CPU::tick`], shared by [`CPU::power_on`] and [`CPU::reset`].
+    fn clear_interrupt_state(&mut self) {
+        self.halted = false;
+        self.nmi_pending = false;
+        self.polled_i = None;
+        self.polled_lines = None;
+        self.micro = None;
     }
 
 
     /// Loads a program (vector of opcodes) to 0x8000 to 0x8000 + length of program. Sets the program start bytes at 0xFFFC and 0xFFFD to 0x8000.
-    pub fn load(&mut self, program : Vec<u8>) {
-        self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x8000);
+    pub fn load(&mut self, program : Vec<u8>) -> Result<(), CpuError> {
+        let max = 0x8000;
+        if program.len() > max {
+            return Err(CpuError::ProgramTooLarge { size : program.len(), max });
+        }
+        for (i, byte) in program.iter().enumerate() {
+            self.memory.write(0x8000 + i as u16, *byte);
+        }
+        self.mem_write_u16(RESET_VECTOR, 0x8000);
+        Ok(())
     }
 
-    /// Loads a byte into A register
-    fn lda(&mut self, value : u8) {
+    /// Sets the A register and updates the zero and negative flags to match.
+    fn set_register_a(&mut self, value : u8) {
         self.register_a = value;
-        self.update_zero_and_negative(value)
+        self.update_zero_and_negative(value);
+    }
+
+    /// Sets the X register and updates the zero and negative flags to match.
+    fn set_register_x(&mut self, value : u8) {
+        self.register_x = value;
+        self.update_zero_and_negative(value);
+    }
+
+    /// Sets the Y register and updates the zero and negative flags to match.
+    fn set_register_y(&mut self, value : u8) {
+        self.register_y = value;
+        self.update_zero_and_negative(value);
+    }
+
+    /// Loads a byte into A register
+    fn lda(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.set_register_a(value);
+        Ok(())
+    }
+
+    /// Loads a byte into X register
+    fn ldx(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.set_register_x(value);
+        Ok(())
+    }
+
+    /// Loads a byte into Y register
+    fn ldy(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.set_register_y(value);
+        Ok(())
+    }
+
+    /// Stores the A register in memory.
+    fn sta(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.write_address(mode)?;
+        self.mem_write(addr, self.register_a);
+        Ok(())
+    }
+
+    /// Stores the X register in memory.
+    fn stx(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.write_address(mode)?;
+        self.mem_write(addr, self.register_x);
+        Ok(())
+    }
+
+    /// Stores the Y register in memory.
+    fn sty(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.write_address(mode)?;
+        self.mem_write(addr, self.register_y);
+        Ok(())
     }
 
     /// Loads the byte stored in A register to X register
     fn tax (&mut self) {
-        self.register_x = self.register_a;
-        self.update_zero_and_negative(self.register_x);
+        self.set_register_x(self.register_a);
+    }
+
+    /// Loads the byte stored in A register to Y register
+    fn tay(&mut self) {
+        self.set_register_y(self.register_a);
+    }
+
+    /// Loads the stack pointer into the X register
+    fn tsx(&mut self) {
+        self.set_register_x(self.stack_pointer);
+    }
+
+    /// Loads the X register into the stack pointer, note that no flags are affected.
+    fn txs(&mut self) {
+        self.stack_pointer = self.register_x;
+    }
+
+    /// Loads the byte stored in X register to A register
+    fn txa(&mut self) {
+        self.set_register_a(self.register_x);
+    }
+
+    /// Loads the byte stored in Y register to A register
+    fn tya(&mut self) {
+        self.set_register_a(self.register_y);
     }
 
     /// Increments (with wrapping) the byte stored in the X register.
     fn inx(&mut self) {
-        self.register_x = self.register_x.wrapping_add(1);
-        self.update_zero_and_negative(self.register_x);
+        self.set_register_x(self.register_x.wrapping_add(1));
     }
 
-    /// This is used to update the status register zero and negative flags.
-    fn update_zero_and_negative(&mut self, result : u8) {
-        if result == 0 {
-            self.status = self.status | 0b0000_0010;
-        } else {
-            self.status = self.status & 0b1111_1101;
+    /// Increments (with wrapping) the byte stored in the Y register.
+    fn iny(&mut self) {
+        self.set_register_y(self.register_y.wrapping_add(1));
+    }
+
+    /// Decrements (with wrapping) the byte stored in the X register.
+    fn dex(&mut self) {
+        self.set_register_x(self.register_x.wrapping_sub(1));
+    }
+
+    /// Decrements (with wrapping) the byte stored in the Y register.
+    fn dey(&mut self) {
+        self.set_register_y(self.register_y.wrapping_sub(1));
+    }
+
+    /// Increments (with wrapping) the byte stored in memory, returning the new value.
+    fn inc(&mut self, mode : &AddressingMode) -> Result<u8, CpuError> {
+        self.read_modify_write(mode, |_, value| value.wrapping_add(1))
+    }
+
+    /// Decrements (with wrapping) the byte stored in memory, returning the new value.
+    fn dec(&mut self, mode : &AddressingMode) -> Result<u8, CpuError> {
+        self.read_modify_write(mode, |_, value| value.wrapping_sub(1))
+    }
+
+    /// Adds a byte and the carry flag to the A register, setting the carry flag on unsigned overflow and
+    /// the overflow flag when the sign of the result is impossible for the signed operands.
+    fn add_to_register_a(&mut self, data : u8) {
+        let sum = self.register_a as u16 + data as u16 + self.status.carry() as u16;
+        self.status.set_carry(sum > 0xff);
+
+        let result = sum as u8;
+        self.status.set_overflow((data ^ result) & (result ^ self.register_a) & 0x80 != 0);
+        self.set_register_a(result);
+    }
+
+    /// Returns true if ADC and SBC should do decimal arithmetic, see [`CPU::bcd_enabled`].
+    fn decimal_arithmetic(&self) -> bool {
+        self.bcd_enabled && self.status.decimal_mode()
+    }
+
+    /// Adds a byte and the carry flag to the A register, in decimal if [`CPU::bcd_enabled`] allows it.
+    fn add_with_carry(&mut self, value : u8) {
+        if !self.decimal_arithmetic() {
+            self.add_to_register_a(value);
+            return;
         }
 
-        if result & 0b1000_0000 != 0 {
-            self.status = self.status | 0b1000_0000;
+        // The NMOS 6502 sets Z from the binary sum, and N and V from the sum after only the low digit has been
+        // adjusted.
+        let a = self.register_a as u16;
+        let m = value as u16;
+        let carry = self.status.carry() as u16;
+        let binary = (a + m + carry) as u8;
+
+        let mut low = (a & 0x0f) + (m & 0x0f) + carry;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (a & 0xf0) + (m & 0xf0) + low;
+        let intermediate = sum as u8;
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+
+        self.register_a = sum as u8;
+        self.status.set_carry(sum >= 0x100);
+        self.status.set_zero(binary == 0);
+        self.status.set_negative(intermediate & 0x80 != 0);
+        self.status.set_overflow((value ^ intermediate) & (intermediate ^ a as u8) & 0x80 != 0);
+    }
+
+    /// Subtracts a byte and the borrow (inverted carry) from the A register, in decimal if [`CPU::bcd_enabled`]
+    /// allows it. A - M - (1 - C) is the same as A + !M + C, so in binary this is an addition of the ones
+    /// complement of the operand.
+    fn subtract_with_borrow(&mut self, value : u8) {
+        let a = self.register_a as i16;
+        let decimal = self.decimal_arithmetic();
+        let borrow = 1 - self.status.carry() as i16;
+
+        // All the flags come from the binary subtraction, even in decimal mode.
+        self.add_to_register_a(!value);
+        if !decimal {
+            return;
+        }
+
+        let m = value as i16;
+        let mut low = (a & 0x0f) - (m & 0x0f) - borrow;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0f) - 0x10;
+        }
+        let mut difference = (a & 0xf0) - (m & 0xf0) + low;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        self.register_a = difference as u8;
+    }
+
+    /// Adds memory to the A register with carry.
+    fn adc(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.add_with_carry(value);
+        Ok(())
+    }
+
+    /// Subtracts memory from the A register with borrow.
+    fn sbc(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.subtract_with_borrow(value);
+        Ok(())
+    }
+
+    /// Bitwise AND of memory with the A register.
+    fn and(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.set_register_a(self.register_a & value);
+        Ok(())
+    }
+
+    /// Bitwise exclusive OR of memory with the A register.
+    fn eor(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.set_register_a(self.register_a ^ value);
+        Ok(())
+    }
+
+    /// Bitwise OR of memory with the A register.
+    fn ora(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.set_register_a(self.register_a | value);
+        Ok(())
+    }
+
+    /// Performs a read-modify-write instruction on memory: the operand is read, passed through `operation` and
+    /// the result written back to the same address. Every memory form of ASL, LSR, ROL, ROR, INC and DEC goes
+    /// through here, so this is the single place the bus sees the write of the modified value.
+    ///
+    /// Like the hardware, the unmodified value is written back while the operation is worked out, before the
+    /// modified one is written.
+    fn read_modify_write(&mut self, mode : &AddressingMode, operation : fn(&mut Self, u8) -> u8) -> Result<u8, CpuError> {
+        let addr = self.write_address(mode)?;
+        let value = self.mem_read(addr);
+        self.mem_write(addr, value);
+        let result = operation(self, value);
+        self.mem_write(addr, result);
+        self.update_zero_and_negative(result);
+        Ok(result)
+    }
+
+    /// Shifts a byte left, bit 7 is moved into the carry flag.
+    fn shift_left(&mut self, value : u8) -> u8 {
+        self.status.set_carry(value & 0b1000_0000 != 0);
+        value << 1
+    }
+
+    /// Shifts a byte right, bit 0 is moved into the carry flag.
+    fn shift_right(&mut self, value : u8) -> u8 {
+        self.status.set_carry(value & 0b0000_0001 != 0);
+        value >> 1
+    }
+
+    /// Rotates a byte left through the carry flag.
+    fn rotate_left(&mut self, value : u8) -> u8 {
+        let carry_in = self.status.carry() as u8;
+        self.status.set_carry(value & 0b1000_0000 != 0);
+        (value << 1) | carry_in
+    }
+
+    /// Rotates a byte right through the carry flag.
+    fn rotate_right(&mut self, value : u8) -> u8 {
+        let carry_in = self.status.carry() as u8;
+        self.status.set_carry(value & 0b0000_0001 != 0);
+        (value >> 1) | (carry_in << 7)
+    }
+
+    /// Applies a shift or rotate to the A register (the accumulator addressing mode).
+    fn accumulator(&mut self, operation : fn(&mut Self, u8) -> u8) {
+        let result = operation(self, self.register_a);
+        self.set_register_a(result);
+    }
+
+    /// Compares memory with a register, the carry flag is set when the register is greater than or equal to memory.
+    fn compare(&mut self, mode : &AddressingMode, register : u8) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.compare_value(register, value);
+        Ok(())
+    }
+
+    /// Sets the carry, zero and negative flags as if `value` were subtracted from `register`.
+    fn compare_value(&mut self, register : u8, value : u8) {
+        self.status.set_carry(register >= value);
+        self.update_zero_and_negative(register.wrapping_sub(value));
+    }
+
+    /// Tests bits in memory against the A register. Bits 6 and 7 of memory are copied into the overflow and negative flags.
+    fn bit(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.bit_value(value);
+        Ok(())
+    }
+
+    fn bit_value(&mut self, value : u8) {
+        self.status.set_zero(self.register_a & value == 0);
+        self.status.set_negative(value & 0b1000_0000 != 0);
+        self.status.set_overflow(value & 0b0100_0000 != 0);
+    }
+
+    /// Consumes the relative operand following the opcode and jumps to its target when the condition is met.
+    /// A taken branch costs one extra cycle, and one more if the target is on a different page to the next
+    /// instruction.
+    fn branch(&mut self, mode : &AddressingMode, condition : bool) -> Result<(), CpuError> {
+        let (target, page_cross) = self.get_operand_address(mode)?;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        if condition {
+            if !page_cross {
+                // The interrupt poll happens before the extra cycle, not during it.
+                self.polled_lines = Some((self.nmi_pending, self.irq_asserted()));
+            }
+            self.cycles += if page_cross { 2 } else { 1 };
+            self.program_counter = target;
+        }
+        Ok(())
+    }
+
+    /// Undocumented: loads a byte into both the A and X registers.
+    fn lax(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.lax_value(value);
+        Ok(())
+    }
+
+    fn lax_value(&mut self, value : u8) {
+        self.set_register_a(value);
+        self.register_x = value;
+    }
+
+    /// Undocumented: stores the bitwise AND of the A and X registers, no flags are affected.
+    fn sax(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.write_address(mode)?;
+        self.mem_write(addr, self.register_a & self.register_x);
+        Ok(())
+    }
+
+    /// Undocumented: DEC followed by CMP on the decremented value.
+    fn dcp(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.dec(mode)?;
+        self.compare_value(self.register_a, value);
+        Ok(())
+    }
+
+    /// Undocumented: INC followed by SBC of the incremented value.
+    fn isb(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.inc(mode)?;
+        self.subtract_with_borrow(value);
+        Ok(())
+    }
+
+    /// Undocumented: ASL followed by ORA of the shifted value.
+    fn slo(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_modify_write(mode, Self::shift_left)?;
+        self.set_register_a(self.register_a | value);
+        Ok(())
+    }
+
+    /// Undocumented: ROL followed by AND of the rotated value.
+    fn rla(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_modify_write(mode, Self::rotate_left)?;
+        self.set_register_a(self.register_a & value);
+        Ok(())
+    }
+
+    /// Undocumented: LSR followed by EOR of the shifted value.
+    fn sre(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_modify_write(mode, Self::shift_right)?;
+        self.set_register_a(self.register_a ^ value);
+        Ok(())
+    }
+
+    /// Undocumented: ROR followed by ADC of the rotated value, the carry out of the rotate is the carry in of the add.
+    fn rra(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_modify_write(mode, Self::rotate_right)?;
+        self.add_with_carry(value);
+        Ok(())
+    }
+
+    /// Unstable: `A = (A | magic) & X & operand`, with the magic constant taken from [`CpuQuirks::xaa_magic`].
+    fn xaa(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.xaa_value(value);
+        Ok(())
+    }
+
+    fn xaa_value(&mut self, value : u8) {
+        self.set_register_a((self.register_a | self.quirks.xaa_magic) & self.register_x & value);
+    }
+
+    /// Undocumented: ANDs memory with the stack pointer and loads the result into A, X and the stack pointer.
+    fn las(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let value = self.read_operand(mode)?;
+        self.las_value(value);
+        Ok(())
+    }
+
+    fn las_value(&mut self, value : u8) {
+        let value = value & self.stack_pointer;
+        self.stack_pointer = value;
+        self.register_x = value;
+        self.set_register_a(value);
+    }
+
+    /// Shared store of AHX and TAS: writes `value & (H + 1)` where H is the high byte of the address before it
+    /// was indexed by Y. See [`CpuQuirks::unstable_store_page_cross`] for what happens when a page is crossed.
+    fn unstable_store(&mut self, mode : &AddressingMode, value : u8) -> Result<(), CpuError> {
+        let addr = self.write_address(mode)?;
+        self.unstable_store_at(addr, value);
+        Ok(())
+    }
+
+    fn unstable_store_at(&mut self, addr : u16, value : u8) {
+        let base = addr.wrapping_sub(self.register_y as u16);
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
+
+        let addr = if self.quirks.unstable_store_page_cross && (base & 0xff00) != (addr & 0xff00) {
+            ((result as u16) << 8) | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.mem_write(addr, result);
+    }
+
+    /// Unstable: stores `A & X & (H + 1)`.
+    fn ahx(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        self.unstable_store(mode, self.register_a & self.register_x)
+    }
+
+    /// Unstable: sets the stack pointer to `A & X` and then stores it like AHX.
+    fn tas(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        self.stack_pointer = self.register_a & self.register_x;
+        self.unstable_store(mode, self.stack_pointer)
+    }
+
+    /// The multi-byte undocumented NOPs perform the read of their operand like any other instruction in
+    /// their addressing mode, the value is discarded.
+    fn nop_read(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        self.read_operand(mode)?;
+        Ok(())
+    }
+
+    /// Returns the address in page one the stack pointer refers to. The stack pointer is only eight bits wide, so
+    /// pushing past $0100 wraps round to $01FF and pulling past $01FF wraps round to $0100; the stack never
+    /// spills into the zero page or page two.
+    fn stack_address(&self) -> u16 {
+        STACK | self.stack_pointer as u16
+    }
+
+    /// Pushes a byte onto the stack in page one.
+    fn stack_push(&mut self, data : u8) {
+        self.mem_write(self.stack_address(), data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    /// Pulls a byte from the stack in page one.
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(self.stack_address())
+    }
+
+    /// Pushes two bytes onto the stack, the most significant byte is pushed first.
+    fn stack_push_u16(&mut self, data : u16) {
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0xff) as u8);
+    }
+
+    /// Pulls two bytes from the stack, the least significant byte is pulled first.
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        (hi << 8) | lo
+    }
+
+    /// Jumps to a subroutine. The address pushed is that of the last byte of the JSR instruction (the return
+    /// address minus one), which is what RTS expects to pull.
+    fn jsr(&mut self, mode : &AddressingMode) -> Result<(), CpuError> {
+        let (target, _) = self.get_operand_address(mode)?;
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.program_counter = target;
+        Ok(())
+    }
+
+    /// Returns from a subroutine by pulling the address pushed by JSR and adding one.
+    fn rts(&mut self) {
+        self.program_counter = self.stack_pop_u16().wrapping_add(1);
+    }
+
+    /// Software interrupt. BRK is followed by a padding byte, so the return address pushed is that of the
+    /// opcode plus two. The status is pushed with the break flag set so a handler can tell BRK apart from
+    /// an IRQ, then interrupts are disabled and the handler at the IRQ/BRK vector is entered.
+    fn brk(&mut self) {
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.php();
+        self.status.set_interrupt_disable(true);
+        let vector = self.hijack_vector(IRQ_BRK_VECTOR);
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
+    /// Enters a hardware interrupt handler: pushes the program counter and the status (with the break flag clear,
+    /// which is how handlers tell an interrupt apart from BRK), disables interrupts and jumps through `vector`.
+    /// This takes 7 cycles, the same as BRK.
+    fn interrupt(&mut self, vector : u16) {
+        self.stack_push_u16(self.program_counter);
+        self.stack_push((self.status.bits() | StatusFlags::BREAK2) & !StatusFlags::BREAK);
+        self.status.set_interrupt_disable(true);
+        let vector = self.hijack_vector(vector);
+        self.program_counter = self.mem_read_u16(vector);
+        self.cycles += 7;
+    }
+
+    /// The vector is only fetched at the end of the BRK/IRQ sequence, so an NMI that is pending by then hijacks
+    /// it: the handler entered is the NMI one, with the status already pushed (and the B flag still set for BRK).
+    /// Either way the sequence does not poll for interrupts, so the first instruction of the handler always runs.
+    fn hijack_vector(&mut self, vector : u16) -> u16 {
+        self.polled_lines = Some((false, false));
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
         } else {
-            self.status = self.status & 0b0111_1111;
+            vector
         }
     }
 
-    /// Runs a program by iteratively incrementing the program counter until the exit code is reached (0x00)
-    pub fn run(&mut self) {
-        loop {
-            let opscode = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+    /// Returns from an interrupt handler by pulling the status register and then the program counter. Unlike
+    /// RTS the pulled address is used as is.
+    fn rti(&mut self) {
+        self.plp();
+        // Unlike PLP the flags are restored before the interrupt poll.
+        self.polled_i = None;
+        self.program_counter = self.stack_pop_u16();
+    }
 
-            match opscode {
-                0xA9 => {
-                    let param = self.mem_read(self.program_counter);
-                    self.program_counter += 1;
-                    self.lda(param);
-                }
+    /// Pushes a copy of the status register onto the stack, the break flags are always set in the pushed copy.
+    fn php(&mut self) {
+        self.stack_push(self.status.bits() | StatusFlags::BREAK | StatusFlags::BREAK2);
+    }
+
+    /// CLI and SEI: changes the interrupt disable flag after this instruction's interrupt poll.
+    fn set_interrupt_disable_after_poll(&mut self, value : bool) {
+        self.polled_i = Some(self.status.interrupt_disable());
+        self.status.set_interrupt_disable(value);
+    }
+
+    /// Pulls the status register from the stack, the break flags do not exist in the register and are ignored.
+    fn plp(&mut self) {
+        self.polled_i = Some(self.status.interrupt_disable());
+        self.status = StatusFlags::from_bits(self.stack_pop());
+        self.status.set(StatusFlags::BREAK | StatusFlags::BREAK2, false);
+    }
 
-                0xAA => self.tax(),
+    /// Pushes the A register onto the stack.
+    fn pha(&mut self) {
+        self.stack_push(self.register_a);
+    }
+
+    /// Pulls the A register from the stack.
+    fn pla(&mut self) {
+        let value = self.stack_pop();
+        self.set_register_a(value);
+    }
+
+    /// This is used to update the status register zero and negative flags.
+    fn update_zero_and_negative(&mut self, result : u8) {
+        self.status.set_zero(result == 0);
+        self.status.set_negative(result & 0b1000_0000 != 0);
+    }
 
-                0xE8 => self.inx(),
+    /// Runs a program by iteratively incrementing the program counter until the exit code is reached (0x00 with no
+    /// BRK handler installed, see [`CPU::stop_on_brk`]), the CPU jams (see [`CPU::is_halted`]) or the program
+    /// counter reaches a breakpoint (see [`CPU::add_breakpoint`]), and says which.
+    ///
+    /// The breakpoint check is skipped for the first instruction, so calling this again carries on from the
+    /// breakpoint it stopped at.
+    pub fn run(&mut self) -> Result<Stopped, CpuError> {
+        self.run_with_callback(|_| {})
+    }
 
-                0x00 => {
-                    return;
+    /// Same as [`CPU::run`], but calls `callback` before each instruction. This is the hook a frontend uses to poll
+    /// input, render or trace the CPU state, the callback may change anything on the CPU, including raising
+    /// interrupts or moving the program counter.
+    pub fn run_with_callback<F>(&mut self, mut callback : F) -> Result<Stopped, CpuError>
+    where
+        F : FnMut(&mut CPU<M>),
+    {
+        let mut first = true;
+        self.watch_hit = None;
+        while !self.halted {
+            callback(self);
+            if self.halted {
+                break;
+            }
+            if self.micro.is_none() {
+                if !first && self.breakpoints.contains(&self.program_counter) {
+                    return Ok(Stopped::Breakpoint(self.program_counter));
+                }
+                if self.polled_interrupt().is_none() && self.at_exit_brk() {
+                    self.program_counter = self.program_counter.wrapping_add(1);
+                    return Ok(Stopped::Exit);
                 }
-                _ => todo!("")
             }
+            first = false;
+            self.run_instruction()?;
+            if let Some(hit) = self.watch_hit.take() {
+                return Ok(Stopped::Watchpoint(hit));
+            }
+        }
+        Ok(Stopped::Halted)
+    }
+
+    /// Makes [`CPU::run`] stop before executing the instruction at `address`.
+    pub fn add_breakpoint(&mut self, address : u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Removes a breakpoint, returns false if there was none at `address`.
+    pub fn remove_breakpoint(&mut self, address : u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Removes every breakpoint.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Executes exactly one instruction, ignoring [`CPU::stop_on_brk`], and describes what ran. If an interrupt is
+    /// due it is entered first and the instruction is the first one of its handler. A jammed CPU does nothing,
+    /// the result then describes the JAM opcode with no cycles taken. Breakpoints do not stop it, instead
+    /// [`StepResult::stopped`] says when the next instruction is at one.
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        if self.micro.is_some() {
+            self.run_instruction()?;
+        }
+        let start_cycles = self.cycles;
+        let mut interrupt = None;
+        if !self.halted {
+            if let Some(vector) = self.polled_interrupt() {
+                interrupt = Some(if vector == NMI_VECTOR { Interrupt::Nmi } else { Interrupt::Irq });
+                self.run_instruction()?;
+            }
+        }
+
+        let address = self.program_counter;
+        let code = self.peek(address);
+        let opcode = match opcodes::lookup(code) {
+            Some(opcode) => *opcode,
+            None => return Err(CpuError::UnknownOpcode { opcode : code, address }),
+        };
+        let operands = (1..opcode.bytes as u16).map(|i| self.peek(address.wrapping_add(i))).collect();
+        let effective_address = match opcode.addressing_mode {
+            AddressingMode::Immediate | AddressingMode::NoneAddressing => None,
+            ref mode => {
+                let (x, y) = (self.register_x, self.register_y);
+                Some(operand_address(mode, address.wrapping_add(1), x, y, |addr| self.peek(addr))?.0)
+            }
+        };
+
+        if !self.halted {
+            self.run_instruction()?;
         }
+
+        Ok(StepResult {
+            address,
+            opcode : code,
+            mnemonic : opcode.name,
+            operands,
+            effective_address,
+            interrupt,
+            cycles : self.cycles - start_cycles,
+            stopped : if self.halted {
+                Some(Stopped::Halted)
+            } else if let Some(hit) = self.watch_hit.take() {
+                Some(Stopped::Watchpoint(hit))
+            } else if self.breakpoints.contains(&self.program_counter) {
+                Some(Stopped::Breakpoint(self.program_counter))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Returns true if the next instruction is a BRK that [`CPU::stop_on_brk`] says should end [`CPU::run`]. A BRK
+    /// that a pending NMI is about to hijack is not an exit.
+    fn at_exit_brk(&self) -> bool {
+        let vector = u16::from_le_bytes([self.peek(IRQ_BRK_VECTOR), self.peek(IRQ_BRK_VECTOR + 1)]);
+        self.stop_on_brk && !self.nmi_pending && self.peek(self.program_counter) == 0x00 && vector == 0
     }
-}
\ No newline at end of file
+
+    /// Runs the next instruction (or the rest of one left part way through by [`CPU::tick`]) in the current
+    /// [`CPU::execution_mode`], returning the number of cycles taken.
+    fn run_instruction(&mut self) -> Result<u64, CpuError> {
+        if self.micro.is_none() && self.execution_mode == ExecutionMode::PerInstruction {
+            let start_cycles = self.cycles;
+            self.executing = true;
+            let cycles = self.execute_instruction();
+            self.executing = false;
+            self.tick_memory(self.cycles - start_cycles);
+            return cycles;
+        }
+        let start_cycles = self.cycles;
+        while !self.halted && !self.tick()? {}
+        Ok(self.cycles - start_cycles)
+    }
+
+    /// Fetches, decodes and executes the instruction at the program counter, adding its cycles to
+    /// [`CPU::cycles`]. A pending interrupt is serviced in place of the instruction. Returns the number of
+    /// cycles taken.
+    fn execute_instruction(&mut self) -> Result<u64, CpuError> {
+        let start_cycles = self.cycles;
+        self.cycles += self.stall;
+        self.stall = 0;
+        self.oam_dma = 0;
+        self.instruction_address = self.program_counter;
+
+        if let Some(vector) = self.polled_interrupt() {
+            self.interrupt(vector);
+            return Ok(self.cycles - start_cycles);
+        }
+        self.polled_i = None;
+        self.polled_lines = None;
+
+        let code = self.mem_read(self.program_counter);
+        let opcode = match opcodes::lookup(code) {
+            Some(opcode) => opcode,
+            None => return Err(CpuError::UnknownOpcode { opcode : code, address : self.program_counter }),
+        };
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        let mode = &opcode.addressing_mode;
+        self.cycles += opcode.cycles as u64;
+
+        match code {
+            0x00 => {
+                self.brk();
+                return Ok(self.cycles - start_cycles);
+            }
+
+            0xea | 0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => {}
+
+            /* Undocumented NOPs that still read their operand */
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 | 0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 |
+            0x0c | 0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => self.nop_read(mode)?,
+
+            /* Loads and stores */
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => self.lda(mode)?,
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => self.ldx(mode)?,
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => self.ldy(mode)?,
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => self.sta(mode)?,
+            0x86 | 0x96 | 0x8e => self.stx(mode)?,
+            0x84 | 0x94 | 0x8c => self.sty(mode)?,
+
+            /* Arithmetic and logic */
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => self.adc(mode)?,
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => self.sbc(mode)?,
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => self.and(mode)?,
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => self.eor(mode)?,
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => self.ora(mode)?,
+            0x24 | 0x2c => self.bit(mode)?,
+
+            /* Shifts */
+            0x0a => self.accumulator(Self::shift_left),
+            0x06 | 0x16 | 0x0e | 0x1e => {
+                self.read_modify_write(mode, Self::shift_left)?;
+            }
+            0x4a => self.accumulator(Self::shift_right),
+            0x46 | 0x56 | 0x4e | 0x5e => {
+                self.read_modify_write(mode, Self::shift_right)?;
+            }
+            0x2a => self.accumulator(Self::rotate_left),
+            0x26 | 0x36 | 0x2e | 0x3e => {
+                self.read_modify_write(mode, Self::rotate_left)?;
+            }
+            0x6a => self.accumulator(Self::rotate_right),
+            0x66 | 0x76 | 0x6e | 0x7e => {
+                self.read_modify_write(mode, Self::rotate_right)?;
+            }
+
+            /* Increments and decrements */
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(mode)?;
+            }
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(mode)?;
+            }
+            0xe8 => self.inx(),
+            0xc8 => self.iny(),
+            0xca => self.dex(),
+            0x88 => self.dey(),
+
+            /* Compares */
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => self.compare(mode, self.register_a)?,
+            0xe0 | 0xe4 | 0xec => self.compare(mode, self.register_x)?,
+            0xc0 | 0xc4 | 0xcc => self.compare(mode, self.register_y)?,
+
+            /* Branches */
+            0x90 => {
+                self.branch(mode, !self.status.carry())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0xb0 => {
+                self.branch(mode, self.status.carry())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0xf0 => {
+                self.branch(mode, self.status.zero())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0xd0 => {
+                self.branch(mode, !self.status.zero())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0x30 => {
+                self.branch(mode, self.status.negative())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0x10 => {
+                self.branch(mode, !self.status.negative())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0x70 => {
+                self.branch(mode, self.status.overflow())?;
+                return Ok(self.cycles - start_cycles);
+            }
+            0x50 => {
+                self.branch(mode, !self.status.overflow())?;
+                return Ok(self.cycles - start_cycles);
+            }
+
+            /* Jumps and subroutines */
+            0x4c | 0x6c => {
+                self.program_counter = self.get_operand_address(mode)?.0;
+                return Ok(self.cycles - start_cycles);
+            }
+
+            0x20 => {
+                self.jsr(mode)?;
+                return Ok(self.cycles - start_cycles);
+            }
+
+            0x60 => {
+                self.rts();
+                return Ok(self.cycles - start_cycles);
+            }
+
+            0x40 => {
+                self.rti();
+                return Ok(self.cycles - start_cycles);
+            }
+
+            /* Flags */
+            0x18 => self.status.set_carry(false),
+            0x38 => self.status.set_carry(true),
+            0x58 => self.set_interrupt_disable_after_poll(false),
+            0x78 => self.set_interrupt_disable_after_poll(true),
+            0xb8 => self.status.set_overflow(false),
+            0xd8 => self.status.set_decimal_mode(false),
+            0xf8 => self.status.set_decimal_mode(true),
+
+            /* Transfers */
+            0xaa => self.tax(),
+            0xa8 => self.tay(),
+            0xba => self.tsx(),
+            0x8a => self.txa(),
+            0x9a => self.txs(),
+            0x98 => self.tya(),
+
+            /* Stack */
+            0x48 => self.pha(),
+            0x68 => self.pla(),
+            0x08 => self.php(),
+            0x28 => self.plp(),
+
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                self.program_counter = self.program_counter.wrapping_sub(1);
+                self.halted = true;
+                return Ok(self.cycles - start_cycles);
+            }
+
+            /* Stable undocumented opcodes */
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => self.lax(mode)?,
+            0x87 | 0x97 | 0x8f | 0x83 => self.sax(mode)?,
+            0xeb => self.sbc(mode)?,
+            0xc7 | 0xd7 | 0xcf | 0xdf | 0xdb | 0xc3 | 0xd3 => self.dcp(mode)?,
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => self.isb(mode)?,
+            0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => self.slo(mode)?,
+            0x27 | 0x37 | 0x2f | 0x3f | 0x3b | 0x23 | 0x33 => self.rla(mode)?,
+            0x47 | 0x57 | 0x4f | 0x5f | 0x5b | 0x43 | 0x53 => self.sre(mode)?,
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => self.rra(mode)?,
+
+            /* Unstable undocumented opcodes */
+            0x8b => self.xaa(mode)?,
+            0x93 | 0x9f => self.ahx(mode)?,
+            0x9b => self.tas(mode)?,
+            0xbb => self.las(mode)?,
+
+            _ => {
+                self.program_counter = self.program_counter.wrapping_sub(1);
+                return Err(CpuError::UnknownOpcode { opcode : code, address : self.program_counter });
+            }
+        }
+
+        // Control flow instructions set the program counter themselves, everything else skips its operand bytes.
+        self.program_counter = self.program_counter.wrapping_add((opcode.bytes - 1) as u16);
+        Ok(self.cycles - start_cycles)
+    }
+}
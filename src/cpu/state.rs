@@ -0,0 +1,98 @@
+//! # State Module
+//!
+//! `state` copies the CPU state out and back in, which is what save states, rewinding and comparing two runs of
+//! the same program are built on. Memory is not part of it, that belongs to the [`crate::mem::Mem`] the CPU is
+//! connected to.
+
+use super::{tick::Micro, StatusFlags, CPU};
+use crate::mem::Mem;
+
+/// A copy of everything the CPU holds apart from its configuration (quirks, region and execution mode), taken by
+/// [`CPU::snapshot`] and put back with [`CPU::restore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub register_a : u8,
+    pub register_x : u8,
+    pub register_y : u8,
+    pub status : StatusFlags,
+    pub program_counter : u16,
+    pub stack_pointer : u8,
+    pub cycles : u64,
+    /// An NMI has been signalled and not serviced yet.
+    pub nmi_pending : bool,
+    /// The level of the IRQ line.
+    pub irq_line : bool,
+    /// The CPU has jammed.
+    pub halted : bool,
+    /// Cycles of DMA still to be spent before the next instruction.
+    pub stall : u64,
+    oam_dma : u64,
+    polled_i : Option<bool>,
+    polled_lines : Option<(bool, bool)>,
+    data_bus : u8,
+    last_read : Option<u16>,
+    micro : Option<Micro>,
+}
+
+impl<M : Mem> CPU<M> {
+    /// Takes a copy of the CPU state, including interrupts that are pending and an instruction left part way
+    /// through by [`CPU::tick`].
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::cpu::CPU;
+    ///
+    ///  let mut cpu = CPU::new();
+    ///  cpu.load(vec![0xe8, 0xe8, 0x00]).unwrap();
+    ///  cpu.power_on();
+    ///  let state = cpu.snapshot();
+    ///  cpu.run().unwrap();
+    ///  assert_eq!(cpu.register_x, 2);
+    ///
+    ///  cpu.restore(&state);
+    ///  assert_eq!(cpu.register_x, 0);
+    ///  assert_eq!(cpu.program_counter, 0x8000);
+    /// ```
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            register_a : self.register_a,
+            register_x : self.register_x,
+            register_y : self.register_y,
+            status : self.status,
+            program_counter : self.program_counter,
+            stack_pointer : self.stack_pointer,
+            cycles : self.cycles,
+            nmi_pending : self.nmi_pending,
+            irq_line : self.irq_line,
+            halted : self.halted,
+            stall : self.stall,
+            oam_dma : self.oam_dma,
+            polled_i : self.polled_i,
+            polled_lines : self.polled_lines,
+            data_bus : self.data_bus,
+            last_read : self.last_read,
+            micro : self.micro.clone(),
+        }
+    }
+
+    /// Puts back a state taken by [`CPU::snapshot`]. Memory is left as it is.
+    pub fn restore(&mut self, state : &CpuState) {
+        self.register_a = state.register_a;
+        self.register_x = state.register_x;
+        self.register_y = state.register_y;
+        self.status = state.status;
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.cycles = state.cycles;
+        self.nmi_pending = state.nmi_pending;
+        self.irq_line = state.irq_line;
+        self.halted = state.halted;
+        self.stall = state.stall;
+        self.oam_dma = state.oam_dma;
+        self.polled_i = state.polled_i;
+        self.polled_lines = state.polled_lines;
+        self.data_bus = state.data_bus;
+        self.last_read = state.last_read;
+        self.micro = state.micro.clone();
+    }
+}
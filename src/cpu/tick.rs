@@ -0,0 +1,663 @@
+//! # Tick Module
+//!
+//! `tick` is the per-cycle execution model of the CPU. Each instruction is broken down into the bus accesses
+//! the 6502 really makes, one per cycle (including the dummy reads and writes), so anything watching the bus sees
+//! them at the right time, and interrupts are polled on the cycle the hardware polls them.
+//!
+//! The sequences follow the cycle tables in "6502_cpu.txt" by John West and Marko Mäkelä. The instruction
+//! semantics themselves are shared with the per-instruction interpreter.
+
+use super::{page_crossed, unfixed_address, AddressingMode, CpuError, StatusFlags, CPU, IRQ_BRK_VECTOR};
+use crate::mem::Mem;
+use crate::opcodes;
+
+/// The cycle by cycle pattern an instruction follows, instructions in the same group only differ in what they do
+/// with the value read or which value they write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sequence {
+    Implied,
+    Read,
+    Write,
+    ReadModifyWrite,
+    Branch,
+    JmpAbsolute,
+    JmpIndirect,
+    Jsr,
+    Rts,
+    Rti,
+    Push,
+    Pull,
+    Brk,
+    Interrupt,
+    Jam,
+}
+
+/// The state of the instruction being executed one cycle at a time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Micro {
+    code : u8,
+    name : &'static str,
+    mode : AddressingMode,
+    sequence : Sequence,
+    /// The cycle that runs next, the opcode fetch is cycle 1.
+    cycle : u8,
+    /// The cycle on which the effective address became known.
+    address_ready : Option<u8>,
+    pointer : u8,
+    base : u16,
+    addr : u16,
+    value : u8,
+    vector : u16,
+}
+
+impl Micro {
+    fn new(code : u8, name : &'static str, mode : AddressingMode, sequence : Sequence) -> Self {
+        Micro {
+            code,
+            name,
+            mode,
+            sequence,
+            cycle : 2,
+            address_ready : None,
+            pointer : 0,
+            base : 0,
+            addr : 0,
+            value : 0,
+            vector : 0,
+        }
+    }
+
+    /// Indexed absolute and ($nn),Y addressing make an extra read from the address before the carry into the high
+    /// byte is fixed up.
+    fn indexed(&self) -> bool {
+        matches!(self.mode, AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y)
+    }
+
+    fn page_cross(&self) -> bool {
+        self.indexed() && page_crossed(self.base, self.addr)
+    }
+}
+
+fn sequence(name : &str, mode : &AddressingMode) -> Sequence {
+    match name {
+        "BRK" => Sequence::Brk,
+        "JSR" => Sequence::Jsr,
+        "RTS" => Sequence::Rts,
+        "RTI" => Sequence::Rti,
+        "JMP" if *mode == AddressingMode::Indirect => Sequence::JmpIndirect,
+        "JMP" => Sequence::JmpAbsolute,
+        "PHA" | "PHP" => Sequence::Push,
+        "PLA" | "PLP" => Sequence::Pull,
+        "JAM" => Sequence::Jam,
+        "BCC" | "BCS" | "BEQ" | "BNE" | "BMI" | "BPL" | "BVC" | "BVS" => Sequence::Branch,
+        "STA" | "STX" | "STY" | "SAX" | "AHX" | "TAS" => Sequence::Write,
+        _ if *mode == AddressingMode::NoneAddressing => Sequence::Implied,
+        "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "SLO" | "SRE" | "RLA" | "RRA" | "ISB" | "DCP" => {
+            Sequence::ReadModifyWrite
+        }
+        _ => Sequence::Read,
+    }
+}
+
+impl<M : Mem> CPU<M> {
+    /// Advances the CPU by one clock cycle, making the single bus access the hardware makes on that cycle.
+    /// Returns true when the cycle completed an instruction (or an interrupt sequence). A jammed CPU does
+    /// nothing.
+    ///
+    /// This is always available, [`CPU::execution_mode`] chooses whether [`CPU::run`] and [`CPU::step`] use it.
+    /// If an instruction is left half way through, they finish it one cycle at a time before going on.
+    pub fn tick(&mut self) -> Result<bool, CpuError> {
+        let start_cycles = self.cycles;
+        self.executing = true;
+        let done = self.advance();
+        self.executing = false;
+        self.tick_memory(self.cycles - start_cycles);
+        done
+    }
+
+    fn advance(&mut self) -> Result<bool, CpuError> {
+        if self.halted {
+            return Ok(false);
+        }
+        let mut micro = match self.micro.take() {
+            Some(micro) => micro,
+            None => return self.tick_fetch(),
+        };
+
+        let done = self.tick_cycle(&mut micro);
+        self.cycles += 1;
+        if !done {
+            micro.cycle += 1;
+            self.micro = Some(micro);
+        }
+        Ok(done)
+    }
+
+    /// The first cycle: a DMA stall, the start of an interrupt sequence or the opcode fetch.
+    fn tick_fetch(&mut self) -> Result<bool, CpuError> {
+        if self.stall > 0 {
+            self.stall -= 1;
+            self.oam_dma = self.oam_dma.saturating_sub(1);
+            self.cycles += 1;
+            return Ok(false);
+        }
+        self.instruction_address = self.program_counter;
+
+        if let Some(vector) = self.polled_interrupt() {
+            self.dummy_read(self.program_counter);
+            let mut micro = Micro::new(0x00, "BRK", AddressingMode::NoneAddressing, Sequence::Interrupt);
+            micro.vector = vector;
+            self.micro = Some(micro);
+            self.cycles += 1;
+            return Ok(false);
+        }
+        self.polled_i = None;
+        self.polled_lines = None;
+
+        let code = self.mem_read(self.program_counter);
+        let opcode = match opcodes::lookup(code) {
+            Some(opcode) => opcode,
+            None => return Err(CpuError::UnknownOpcode { opcode : code, address : self.program_counter }),
+        };
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        let mode = opcode.addressing_mode;
+        let mut micro = Micro::new(code, opcode.name, mode, sequence(opcode.name, &mode));
+        if mode == AddressingMode::Immediate {
+            micro.address_ready = Some(1);
+        }
+        self.micro = Some(micro);
+        self.cycles += 1;
+        Ok(false)
+    }
+
+    /// Latches the interrupt lines and the I flag, the 6502 does this at the end of the second-to-last cycle of
+    /// an instruction so it is called at the start of the last one.
+    fn poll(&mut self) {
+        self.polled_lines = Some((self.nmi_pending, self.irq_asserted()));
+        self.polled_i = Some(self.status.interrupt_disable());
+    }
+
+    /// Reads the byte at the program counter and moves past it.
+    fn fetch_operand(&mut self) -> u8 {
+        let value = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        value
+    }
+
+    /// Runs cycle `micro.cycle` of the instruction, returns true if it was the last one.
+    fn tick_cycle(&mut self, micro : &mut Micro) -> bool {
+        match micro.sequence {
+            Sequence::Read | Sequence::Write | Sequence::ReadModifyWrite => match micro.address_ready {
+                None => {
+                    if self.address_cycle(micro) {
+                        micro.address_ready = Some(micro.cycle);
+                    }
+                    false
+                }
+                Some(ready) => {
+                    let step = micro.cycle - ready;
+                    match micro.sequence {
+                        Sequence::Read => self.read_cycle(micro, step),
+                        Sequence::Write => self.write_cycle(micro, step),
+                        _ => self.read_modify_write_cycle(micro, step),
+                    }
+                }
+            },
+
+            Sequence::Implied => {
+                self.poll();
+                self.dummy_read(self.program_counter);
+                self.implied_operation(micro.code, micro.name);
+                true
+            }
+
+            Sequence::Jam => {
+                self.dummy_read(self.program_counter);
+                self.program_counter = self.program_counter.wrapping_sub(1);
+                self.halted = true;
+                true
+            }
+
+            Sequence::Push => match micro.cycle {
+                2 => {
+                    self.dummy_read(self.program_counter);
+                    false
+                }
+                _ => {
+                    self.poll();
+                    if micro.name == "PHA" { self.pha() } else { self.php() }
+                    true
+                }
+            },
+
+            Sequence::Pull => match micro.cycle {
+                2 => {
+                    self.dummy_read(self.program_counter);
+                    false
+                }
+                3 => {
+                    self.dummy_read(self.stack_address());
+                    false
+                }
+                _ => {
+                    self.poll();
+                    if micro.name == "PLA" { self.pla() } else { self.plp() }
+                    true
+                }
+            },
+
+            Sequence::Branch => self.branch_cycle(micro),
+
+            Sequence::JmpAbsolute => match micro.cycle {
+                2 => {
+                    micro.base = self.fetch_operand() as u16;
+                    false
+                }
+                _ => {
+                    self.poll();
+                    let hi = self.mem_read(self.program_counter) as u16;
+                    self.program_counter = (hi << 8) | micro.base;
+                    true
+                }
+            },
+
+            Sequence::JmpIndirect => match micro.cycle {
+                2 => {
+                    micro.base = self.fetch_operand() as u16;
+                    false
+                }
+                3 => {
+                    micro.base |= (self.fetch_operand() as u16) << 8;
+                    false
+                }
+                4 => {
+                    micro.value = self.mem_read(micro.base);
+                    false
+                }
+                _ => {
+                    self.poll();
+                    // The pointer does not carry into its high byte.
+                    let pointer = (micro.base & 0xff00) | (micro.base.wrapping_add(1) & 0x00ff);
+                    let hi = self.mem_read(pointer) as u16;
+                    self.program_counter = (hi << 8) | micro.value as u16;
+                    true
+                }
+            },
+
+            Sequence::Jsr => match micro.cycle {
+                2 => {
+                    micro.value = self.fetch_operand();
+                    false
+                }
+                3 => {
+                    self.dummy_read(self.stack_address());
+                    false
+                }
+                4 => {
+                    self.stack_push((self.program_counter >> 8) as u8);
+                    false
+                }
+                5 => {
+                    self.stack_push(self.program_counter as u8);
+                    false
+                }
+                _ => {
+                    self.poll();
+                    let hi = self.mem_read(self.program_counter) as u16;
+                    self.program_counter = (hi << 8) | micro.value as u16;
+                    true
+                }
+            },
+
+            Sequence::Rts => match micro.cycle {
+                2 => {
+                    self.dummy_read(self.program_counter);
+                    false
+                }
+                3 => {
+                    self.dummy_read(self.stack_address());
+                    false
+                }
+                4 => {
+                    micro.value = self.stack_pop();
+                    false
+                }
+                5 => {
+                    let hi = self.stack_pop() as u16;
+                    self.program_counter = (hi << 8) | micro.value as u16;
+                    false
+                }
+                _ => {
+                    self.poll();
+                    self.dummy_read(self.program_counter);
+                    self.program_counter = self.program_counter.wrapping_add(1);
+                    true
+                }
+            },
+
+            Sequence::Rti => match micro.cycle {
+                2 => {
+                    self.dummy_read(self.program_counter);
+                    false
+                }
+                3 => {
+                    self.dummy_read(self.stack_address());
+                    false
+                }
+                4 => {
+                    self.plp();
+                    false
+                }
+                5 => {
+                    micro.value = self.stack_pop();
+                    false
+                }
+                _ => {
+                    self.poll();
+                    let hi = self.stack_pop() as u16;
+                    self.program_counter = (hi << 8) | micro.value as u16;
+                    true
+                }
+            },
+
+            Sequence::Brk | Sequence::Interrupt => self.interrupt_cycle(micro),
+        }
+    }
+
+    /// Runs one cycle of working out the effective address, returns true once it is known.
+    fn address_cycle(&mut self, micro : &mut Micro) -> bool {
+        match (micro.mode, micro.cycle) {
+            (AddressingMode::ZeroPage, _) => {
+                micro.addr = self.fetch_operand() as u16;
+                true
+            }
+
+            (AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y, 2) => {
+                micro.pointer = self.fetch_operand();
+                false
+            }
+            (AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y, _) => {
+                self.dummy_read(micro.pointer as u16);
+                let index = if micro.mode == AddressingMode::ZeroPage_X { self.register_x } else { self.register_y };
+                micro.addr = micro.pointer.wrapping_add(index) as u16;
+                true
+            }
+
+            (AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y, 2) => {
+                micro.base = self.fetch_operand() as u16;
+                false
+            }
+            (AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y, _) => {
+                micro.base |= (self.fetch_operand() as u16) << 8;
+                let index = match micro.mode {
+                    AddressingMode::Absolute_X => self.register_x,
+                    AddressingMode::Absolute_Y => self.register_y,
+                    _ => 0,
+                };
+                micro.addr = micro.base.wrapping_add(index as u16);
+                true
+            }
+
+            (AddressingMode::Indirect_X, 2) => {
+                micro.pointer = self.fetch_operand();
+                false
+            }
+            (AddressingMode::Indirect_X, 3) => {
+                self.dummy_read(micro.pointer as u16);
+                micro.pointer = micro.pointer.wrapping_add(self.register_x);
+                false
+            }
+            (AddressingMode::Indirect_X, 4) => {
+                micro.addr = self.mem_read(micro.pointer as u16) as u16;
+                false
+            }
+            (AddressingMode::Indirect_X, _) => {
+                micro.addr |= (self.mem_read(micro.pointer.wrapping_add(1) as u16) as u16) << 8;
+                true
+            }
+
+            (AddressingMode::Indirect_Y, 2) => {
+                micro.pointer = self.fetch_operand();
+                false
+            }
+            (AddressingMode::Indirect_Y, 3) => {
+                micro.base = self.mem_read(micro.pointer as u16) as u16;
+                false
+            }
+            (AddressingMode::Indirect_Y, _) => {
+                micro.base |= (self.mem_read(micro.pointer.wrapping_add(1) as u16) as u16) << 8;
+                micro.addr = micro.base.wrapping_add(self.register_y as u16);
+                true
+            }
+
+            // Immediate is ready from the start, and no other mode is used by memory instructions.
+            _ => true,
+        }
+    }
+
+    /// Cycles after the address is known for instructions that only read memory. Indexing that crosses a page
+    /// reads the wrong address first and costs one more cycle.
+    fn read_cycle(&mut self, micro : &mut Micro, step : u8) -> bool {
+        if micro.mode == AddressingMode::Immediate {
+            self.poll();
+            let value = self.fetch_operand();
+            self.read_operation(micro.name, value);
+            return true;
+        }
+        if step == 1 && micro.page_cross() {
+            self.dummy_read(unfixed_address(micro.addr));
+            return false;
+        }
+        self.poll();
+        let value = self.mem_read(micro.addr);
+        self.read_operation(micro.name, value);
+        true
+    }
+
+    /// Cycles after the address is known for stores, indexed modes always spend a cycle reading first.
+    fn write_cycle(&mut self, micro : &mut Micro, step : u8) -> bool {
+        if step == 1 && micro.indexed() {
+            let addr = if micro.page_cross() { unfixed_address(micro.addr) } else { micro.addr };
+            self.dummy_read(addr);
+            return false;
+        }
+        self.poll();
+        match micro.name {
+            "STA" => self.mem_write(micro.addr, self.register_a),
+            "STX" => self.mem_write(micro.addr, self.register_x),
+            "STY" => self.mem_write(micro.addr, self.register_y),
+            "SAX" => self.mem_write(micro.addr, self.register_a & self.register_x),
+            "AHX" => self.unstable_store_at(micro.addr, self.register_a & self.register_x),
+            _ => {
+                self.stack_pointer = self.register_a & self.register_x;
+                self.unstable_store_at(micro.addr, self.stack_pointer);
+            }
+        }
+        true
+    }
+
+    /// Cycles after the address is known for read-modify-write instructions: (a dummy read for indexed modes),
+    /// the read, the write back of the unmodified value while it is modified, then the write of the result.
+    fn read_modify_write_cycle(&mut self, micro : &mut Micro, step : u8) -> bool {
+        let step = if micro.indexed() {
+            if step == 1 {
+                let addr = if micro.page_cross() { unfixed_address(micro.addr) } else { micro.addr };
+                self.dummy_read(addr);
+                return false;
+            }
+            step - 1
+        } else {
+            step
+        };
+
+        match step {
+            1 => {
+                micro.value = self.mem_read(micro.addr);
+                false
+            }
+            2 => {
+                self.mem_write(micro.addr, micro.value);
+                micro.value = match micro.name {
+                    "ASL" | "SLO" => self.shift_left(micro.value),
+                    "LSR" | "SRE" => self.shift_right(micro.value),
+                    "ROL" | "RLA" => self.rotate_left(micro.value),
+                    "ROR" | "RRA" => self.rotate_right(micro.value),
+                    "INC" | "ISB" => micro.value.wrapping_add(1),
+                    _ => micro.value.wrapping_sub(1),
+                };
+                false
+            }
+            _ => {
+                self.poll();
+                let result = micro.value;
+                self.mem_write(micro.addr, result);
+                self.update_zero_and_negative(result);
+                match micro.name {
+                    "SLO" => self.set_register_a(self.register_a | result),
+                    "RLA" => self.set_register_a(self.register_a & result),
+                    "SRE" => self.set_register_a(self.register_a ^ result),
+                    "RRA" => self.add_with_carry(result),
+                    "DCP" => self.compare_value(self.register_a, result),
+                    "ISB" => self.subtract_with_borrow(result),
+                    _ => {}
+                }
+                true
+            }
+        }
+    }
+
+    /// A branch polls for interrupts on its second cycle. When taken it spends a cycle adding the offset to the
+    /// low byte of the program counter, and one more fixing the high byte if a page was crossed, which is the
+    /// only case it polls again.
+    fn branch_cycle(&mut self, micro : &mut Micro) -> bool {
+        match micro.cycle {
+            2 => {
+                self.poll();
+                micro.value = self.fetch_operand();
+                let condition = match micro.code {
+                    0x90 => !self.status.carry(),
+                    0xb0 => self.status.carry(),
+                    0xf0 => self.status.zero(),
+                    0xd0 => !self.status.zero(),
+                    0x30 => self.status.negative(),
+                    0x10 => !self.status.negative(),
+                    0x70 => self.status.overflow(),
+                    _ => !self.status.overflow(),
+                };
+                !condition
+            }
+            3 => {
+                self.dummy_read(self.program_counter);
+                let target = self.program_counter.wrapping_add(micro.value as i8 as u16);
+                if !page_crossed(self.program_counter, target) {
+                    self.program_counter = target;
+                    return true;
+                }
+                micro.addr = target;
+                self.program_counter = (self.program_counter & 0xff00) | (target & 0x00ff);
+                false
+            }
+            _ => {
+                self.poll();
+                self.dummy_read(self.program_counter);
+                self.program_counter = micro.addr;
+                true
+            }
+        }
+    }
+
+    /// BRK and the hardware interrupts share a sequence. BRK skips its padding byte where an interrupt only
+    /// reads it, and the vector is picked as the status is pushed, so an NMI by then hijacks it. Neither polls.
+    fn interrupt_cycle(&mut self, micro : &mut Micro) -> bool {
+        let brk = micro.sequence == Sequence::Brk;
+        match micro.cycle {
+            2 => {
+                if brk {
+                    self.fetch_operand();
+                } else {
+                    self.dummy_read(self.program_counter);
+                }
+                false
+            }
+            3 => {
+                self.stack_push((self.program_counter >> 8) as u8);
+                false
+            }
+            4 => {
+                self.stack_push(self.program_counter as u8);
+                false
+            }
+            5 => {
+                let vector = if brk { IRQ_BRK_VECTOR } else { micro.vector };
+                micro.vector = self.hijack_vector(vector);
+                if brk {
+                    self.php();
+                } else {
+                    self.stack_push((self.status.bits() | StatusFlags::BREAK2) & !StatusFlags::BREAK);
+                }
+                self.status.set_interrupt_disable(true);
+                false
+            }
+            6 => {
+                micro.value = self.mem_read(micro.vector);
+                false
+            }
+            _ => {
+                let hi = self.mem_read(micro.vector.wrapping_add(1)) as u16;
+                self.program_counter = (hi << 8) | micro.value as u16;
+                true
+            }
+        }
+    }
+
+    /// What the instructions that only read memory do with the value.
+    fn read_operation(&mut self, name : &str, value : u8) {
+        match name {
+            "LDA" => self.set_register_a(value),
+            "LDX" => self.set_register_x(value),
+            "LDY" => self.set_register_y(value),
+            "ADC" => self.add_with_carry(value),
+            "SBC" => self.subtract_with_borrow(value),
+            "AND" => self.set_register_a(self.register_a & value),
+            "EOR" => self.set_register_a(self.register_a ^ value),
+            "ORA" => self.set_register_a(self.register_a | value),
+            "CMP" => self.compare_value(self.register_a, value),
+            "CPX" => self.compare_value(self.register_x, value),
+            "CPY" => self.compare_value(self.register_y, value),
+            "BIT" => self.bit_value(value),
+            "LAX" => self.lax_value(value),
+            "LAS" => self.las_value(value),
+            "XAA" => self.xaa_value(value),
+            _ => {}
+        }
+    }
+
+    /// The single byte instructions that only touch registers and flags.
+    fn implied_operation(&mut self, code : u8, name : &str) {
+        match (code, name) {
+            (0x0a, _) => self.accumulator(Self::shift_left),
+            (0x4a, _) => self.accumulator(Self::shift_right),
+            (0x2a, _) => self.accumulator(Self::rotate_left),
+            (0x6a, _) => self.accumulator(Self::rotate_right),
+            (_, "CLC") => self.status.set_carry(false),
+            (_, "SEC") => self.status.set_carry(true),
+            (_, "CLI") => self.set_interrupt_disable_after_poll(false),
+            (_, "SEI") => self.set_interrupt_disable_after_poll(true),
+            (_, "CLV") => self.status.set_overflow(false),
+            (_, "CLD") => self.status.set_decimal_mode(false),
+            (_, "SED") => self.status.set_decimal_mode(true),
+            (_, "TAX") => self.tax(),
+            (_, "TAY") => self.tay(),
+            (_, "TSX") => self.tsx(),
+            (_, "TXA") => self.txa(),
+            (_, "TXS") => self.txs(),
+            (_, "TYA") => self.tya(),
+            (_, "INX") => self.inx(),
+            (_, "INY") => self.iny(),
+            (_, "DEX") => self.dex(),
+            (_, "DEY") => self.dey(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! # Watch Module
+//!
+//! `watch` tells a debugger when the CPU touches particular addresses, which is how to find the instruction that
+//! corrupts a variable or writes a PPU register at the wrong time.
+
+use super::CPU;
+use crate::mem::Mem;
+use std::ops::RangeInclusive;
+
+/// Whether a bus access was a read or a write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// An access to a watched address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The address of the instruction that made the access, or where the program was when an interrupt sequence
+    /// made it.
+    pub pc : u16,
+    pub access : Access,
+    pub address : u16,
+    /// The value read or written.
+    pub value : u8,
+}
+
+/// Called for every watchpoint hit, see [`CPU::on_watch`].
+pub(super) type WatchHandler = Box<dyn FnMut(&WatchHit)>;
+
+/// A range of addresses watched for one kind of access.
+pub(super) struct Watchpoint {
+    access : Access,
+    range : RangeInclusive<u16>,
+}
+
+impl<M : Mem> CPU<M> {
+    /// Watches `range` for reads by the CPU, including the dummy reads instructions make. A hit stops
+    /// [`CPU::run`] with [`crate::cpu::Stopped::Watchpoint`] once the instruction making it has finished, unless a
+    /// handler is installed with [`CPU::on_watch`].
+    pub fn watch_read(&mut self, range : RangeInclusive<u16>) {
+        self.watchpoints.push(Watchpoint { access : Access::Read, range });
+    }
+
+    /// Watches `range` for writes by the CPU, see [`CPU::watch_read`].
+    ///
+    /// # Example
+    /// ```
+    ///  use nes::cpu::{Access, Stopped, WatchHit, CPU};
+    ///
+    ///  let mut cpu = CPU::new();
+    ///  cpu.watch_write(0x2000 ..= 0x2007);
+    ///  // LDA #$80; STA $2000; BRK
+    ///  let stopped = cpu.load_and_run(vec![0xa9, 0x80, 0x8d, 0x00, 0x20, 0x00]).unwrap();
+    ///  assert_eq!(stopped, Stopped::Watchpoint(WatchHit { pc : 0x8002, access : Access::Write, address : 0x2000, value : 0x80 }));
+    /// ```
+    pub fn watch_write(&mut self, range : RangeInclusive<u16>) {
+        self.watchpoints.push(Watchpoint { access : Access::Write, range });
+    }
+
+    /// Removes every watchpoint.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Calls `handler` for every watchpoint hit instead of stopping [`CPU::run`].
+    pub fn on_watch<F>(&mut self, handler : F)
+    where
+        F : FnMut(&WatchHit) + 'static,
+    {
+        self.watch_handler = Some(Box::new(handler));
+    }
+
+    /// Returns the first watchpoint hit since the last call, for when the CPU is driven by [`CPU::tick`] rather
+    /// than [`CPU::run`]. Hits that went to a handler are not kept.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Records a hit if the access made while executing an instruction is watched.
+    pub(super) fn check_watchpoints(&mut self, access : Access, address : u16, value : u8) {
+        if !self.executing || !self.watchpoints.iter().any(|watch| watch.access == access && watch.range.contains(&address)) {
+            return;
+        }
+        let hit = WatchHit { pc : self.instruction_address, access, address, value };
+        match self.watch_handler.as_mut() {
+            Some(handler) => handler(&hit),
+            None => {
+                self.watch_hit.get_or_insert(hit);
+            }
+        }
+    }
+}
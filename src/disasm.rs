@@ -0,0 +1,164 @@
+//! # Disasm Module
+//!
+//! `disasm` turns machine code back into 6502 assembly, from a byte slice or straight out of the memory the CPU is
+//! connected to. Branch and jump targets inside the disassembled code are given labels, so loops read naturally.
+
+use crate::cpu::AddressingMode;
+use crate::mem::Mem;
+use crate::opcodes::{self, OpCode};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// One decoded instruction, or a single byte that does not start one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// The address of the opcode.
+    pub address : u16,
+    /// The opcode followed by its operand bytes.
+    pub bytes : Vec<u8>,
+    /// `None` when the byte is not a known opcode, or its operand runs past the end of the input.
+    pub opcode : Option<OpCode>,
+}
+
+impl Instruction {
+    /// The address a branch, JMP or JSR transfers control to. JMP ($nnnn) jumps through memory, so it has none.
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode?;
+        match opcode.addressing_mode {
+            AddressingMode::Relative => Some(branch_target(self.address, self.bytes[1])),
+            AddressingMode::Absolute if opcode.name == "JMP" || opcode.name == "JSR" => {
+                Some(u16::from_le_bytes([self.bytes[1], self.bytes[2]]))
+            }
+            _ => None,
+        }
+    }
+
+    /// The instruction as assembly, e.g. `LDA $10,X`. Bytes that are not an instruction are written as data,
+    /// `.byte $AB`.
+    pub fn assembly(&self) -> String {
+        match self.opcode {
+            Some(opcode) => {
+                let operand = format_operand(opcode.code, &opcode.addressing_mode, &self.bytes[1 ..], self.address);
+                format!("{} {}", opcode.name, operand).trim_end().to_string()
+            }
+            None => format!(".byte ${:02X}", self.bytes[0]),
+        }
+    }
+
+    /// The length of the instruction in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Always false, every instruction is at least one byte.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction as a listing line: `C000  A2 05     LDX #$05`.
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}  {:8}  {}", self.address, hex(&self.bytes), self.assembly())
+    }
+}
+
+/// The address a branch at `address` with the offset `offset` goes to. The offset is relative to the instruction
+/// after the branch.
+fn branch_target(address : u16, offset : u8) -> u16 {
+    address.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
+
+fn hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Formats an operand in the standard syntax for its addressing mode: `#$05`, `$10,X`, `($1234)`, `($10),Y`, and
+/// so on. `operand` holds the bytes after the opcode, `address` is where the opcode is, which relative branches
+/// need to show their target.
+pub fn format_operand(code : u8, mode : &AddressingMode, operand : &[u8], address : u16) -> String {
+    let byte = operand.first().copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, operand.get(1).copied().unwrap_or(0)]);
+
+    match mode {
+        AddressingMode::Immediate => format!("#${:02X}", byte),
+        AddressingMode::ZeroPage => format!("${:02X}", byte),
+        AddressingMode::ZeroPage_X => format!("${:02X},X", byte),
+        AddressingMode::ZeroPage_Y => format!("${:02X},Y", byte),
+        AddressingMode::Absolute => format!("${:04X}", word),
+        AddressingMode::Absolute_X => format!("${:04X},X", word),
+        AddressingMode::Absolute_Y => format!("${:04X},Y", word),
+        AddressingMode::Indirect => format!("(${:04X})", word),
+        AddressingMode::Indirect_X => format!("(${:02X},X)", byte),
+        AddressingMode::Indirect_Y => format!("(${:02X}),Y", byte),
+        AddressingMode::Relative => format!("${:04X}", branch_target(address, byte)),
+        AddressingMode::NoneAddressing => match code {
+            // The accumulator forms of the shifts and rotates.
+            0x0a | 0x4a | 0x2a | 0x6a => "A".to_string(),
+            _ => String::new(),
+        },
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which is at `address`. `bytes` must not be empty.
+pub fn decode(bytes : &[u8], address : u16) -> Instruction {
+    match opcodes::lookup(bytes[0]) {
+        Some(opcode) if opcode.bytes as usize <= bytes.len() => Instruction {
+            address,
+            bytes : bytes[.. opcode.bytes as usize].to_vec(),
+            opcode : Some(*opcode),
+        },
+        _ => Instruction { address, bytes : vec![bytes[0]], opcode : None },
+    }
+}
+
+/// Disassembles `bytes`, loaded at `origin`, one instruction after another.
+///
+/// # Example
+/// ```
+///  use nes::disasm;
+///
+///  let code = disasm::disassemble(&[0xa2, 0x05, 0xca, 0xd0, 0xfd], 0xc000);
+///  assert_eq!(code[0].to_string(), "C000  A2 05     LDX #$05");
+///  assert_eq!(disasm::listing(&code), "C000  A2 05     LDX #$05\nL_C002:\nC002  CA        DEX\nC003  D0 FD     BNE L_C002\n");
+/// ```
+pub fn disassemble(bytes : &[u8], origin : u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = decode(&bytes[offset ..], origin.wrapping_add(offset as u16));
+        offset += instruction.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Disassembles the code in `range` of `memory`. Memory is read with [`Mem::peek`], so this has no side effects.
+pub fn disassemble_memory<M : Mem>(memory : &M, range : RangeInclusive<u16>) -> Vec<Instruction> {
+    let origin = *range.start();
+    let bytes : Vec<u8> = range.map(|addr| memory.peek(addr)).collect();
+    disassemble(&bytes, origin)
+}
+
+/// Formats instructions as a listing, one per line. Targets of branches, JMP and JSR that are the start of one of
+/// the instructions get a label line, `L_C002:`, and the instructions going there refer to the label instead of
+/// the address.
+pub fn listing(instructions : &[Instruction]) -> String {
+    let starts : HashSet<u16> = instructions.iter().map(|instruction| instruction.address).collect();
+    let labels : HashSet<u16> =
+        instructions.iter().filter_map(Instruction::target).filter(|target| starts.contains(target)).collect();
+
+    let mut listing = String::new();
+    for instruction in instructions {
+        if labels.contains(&instruction.address) {
+            listing += &format!("L_{:04X}:\n", instruction.address);
+        }
+        let assembly = match (instruction.opcode, instruction.target()) {
+            (Some(opcode), Some(target)) if labels.contains(&target) => format!("{} L_{:04X}", opcode.name, target),
+            _ => instruction.assembly(),
+        };
+        listing += &format!("{:04X}  {:8}  {}\n", instruction.address, hex(&instruction.bytes), assembly);
+    }
+    listing
+}
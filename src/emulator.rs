@@ -0,0 +1,118 @@
+//! # Emulator Module
+//!
+//! `emulator` puts the pieces together into a console: a [`CPU`] wired to a [`Bus`] with a cartridge slot.
+//! Cartridges can be swapped while it runs, as a frontend with a ROM browser would, without building a new
+//! console:
+//!
+//! ```no_run
+//!  use nes::cartridge::Cartridge;
+//!  use nes::emulator::Emulator;
+//!
+//!  let mut emulator = Emulator::with_cartridge(Cartridge::from_file("first.nes").unwrap());
+//!  for _ in 0 .. 1000 {
+//!      emulator.step().unwrap();
+//!  }
+//!  // The first game is handed back, dropping it saves its battery RAM.
+//!  let first = emulator.insert(Cartridge::from_file("second.nes").unwrap());
+//! ```
+//!
+//! The CPU runs a cycle at a time ([`ExecutionMode::PerCycle`]), the bus clocking the PPU after each one, so a
+//! register write reaches the PPU at the dot it is made on and the splits games time mid-frame land where they
+//! should.
+
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cpu::{CpuError, ExecutionMode, StepResult, CPU};
+use crate::region::Region;
+
+/// The console.
+pub struct Emulator {
+    cpu : CPU<Bus>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// A console with an empty cartridge slot, its cartridge space is RAM until a game is inserted.
+    pub fn new() -> Self {
+        let mut cpu = CPU::with_memory(Bus::new());
+        cpu.execution_mode = ExecutionMode::PerCycle;
+        Emulator { cpu }
+    }
+
+    /// A console with `cartridge` inserted and powered on.
+    pub fn with_cartridge(cartridge : Cartridge) -> Self {
+        let mut emulator = Emulator::new();
+        emulator.insert(cartridge);
+        emulator
+    }
+
+    /// Swaps in `cartridge` and powers the console on again, as swapping games on the real one takes: RAM is
+    /// refilled (see [`Bus::power_on`]) and the CPU starts at the new game's reset vector. A cartridge that knows
+    /// its region switches the console to it. The cartridge that was inserted before is handed back.
+    pub fn insert(&mut self, cartridge : Cartridge) -> Option<Cartridge> {
+        let ejected = self.eject();
+        if let Some(region) = cartridge.region() {
+            self.set_region(region);
+        }
+        self.cpu.memory_mut().insert_cartridge(cartridge);
+        self.power_on();
+        ejected
+    }
+
+    /// Pulls the cartridge out and hands it back, leaving the slot empty. Its IRQ line goes with it, so a board
+    /// interrupting when it was pulled does not keep interrupting the CPU.
+    pub fn eject(&mut self) -> Option<Cartridge> {
+        self.cpu.memory_mut().remove_cartridge()
+    }
+
+    /// The inserted cartridge, if any.
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cpu.memory().cartridge()
+    }
+
+    /// The inserted cartridge, if any, to get at its board.
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cpu.memory_mut().cartridge_mut()
+    }
+
+    /// The console timing being emulated, NTSC until a cartridge or [`Emulator::set_region`] says otherwise.
+    pub fn region(&self) -> Region {
+        self.cpu.memory().region()
+    }
+
+    /// Changes the console timing, which the CPU reads from the bus, see [`Bus::set_region`].
+    pub fn set_region(&mut self, region : Region) {
+        self.cpu.memory_mut().set_region(region);
+    }
+
+    /// Turns the console off and on again, see [`Bus::power_on`] and [`CPU::power_on`].
+    pub fn power_on(&mut self) {
+        self.cpu.memory_mut().power_on();
+        self.cpu.power_on();
+    }
+
+    /// Presses the reset button, see [`Bus::reset`] and [`CPU::reset`].
+    pub fn reset(&mut self) {
+        self.cpu.memory_mut().reset();
+        self.cpu.reset();
+    }
+
+    /// Runs one instruction. The CPU samples the cartridge's IRQ line and the PPU's NMI line from the bus on every
+    /// cycle, see [`crate::mem::Mem::irq_line`] and [`crate::mem::Mem::take_nmi`].
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        self.cpu.step()
+    }
+
+    pub fn cpu(&self) -> &CPU<Bus> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<Bus> {
+        &mut self.cpu
+    }
+}
@@ -6,7 +6,17 @@
 //!To build this project ```cargo run```. To see documentation for the API run ```cargo doc --open```
 
 
-extern crate lazy_static;
-
+pub mod asm;
+pub mod bus;
+pub mod cartridge;
 pub mod cpu;
-pub mod opcodes;
\ No newline at end of file
+pub mod disasm;
+pub mod emulator;
+pub mod mapper;
+pub mod mem;
+pub mod nestest;
+pub mod nsf;
+pub mod opcodes;
+pub mod ppu;
+pub mod region;
+pub mod trace;
\ No newline at end of file
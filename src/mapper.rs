@@ -0,0 +1,289 @@
+//! # Mapper Module
+//!
+//! `mapper` holds the logic of the cartridge boards. A cartridge is more than its ROMs: the board decides which
+//! part of them the CPU and PPU see, and many boards can switch banks, change the nametable mirroring or raise
+//! interrupts when the game writes to them. Boards are numbered by iNES mapper number, each one here implements
+//! [`Mapper`], and [`from_rom`] picks the one a ROM needs:
+//!
+//! | Mapper   | Board             | Module           |
+//! |----------|-------------------|------------------|
+//! | 0        | NROM              | [`nrom`]         |
+//! | 1        | MMC1              | [`mmc1`]         |
+//! | 2        | UxROM             | [`uxrom`]        |
+//! | 3        | CNROM             | [`cnrom`]        |
+//! | 4        | MMC3              | [`mmc3`]         |
+//! | 5        | MMC5              | [`mmc5`]         |
+//! | 7        | AxROM             | [`axrom`]        |
+//! | 9        | MMC2              | [`mmc2`]         |
+//! | 11       | Color Dreams      | [`color_dreams`] |
+//! | 20       | FDS               | [`fds`]          |
+//! | 24, 26   | VRC6              | [`vrc6`]         |
+//! | 58       | GK-192 multicart  | [`multicart`]    |
+//! | 66       | GxROM             | [`gxrom`]        |
+//! | 69       | FME-7             | [`fme7`]         |
+//! | 105      | NWC               | [`nwc`]          |
+//! | 225      | ET-4310 multicart | [`multicart`]    |
+//!
+//! The Famicom Disk System has no ROM to build it from, so [`from_rom`] leaves it out: build an [`Fds`] from the
+//! BIOS and a disk image and put it in a cartridge with
+//! [`Cartridge::with_mapper`](crate::cartridge::Cartridge::with_mapper).
+//!
+//! Boards whose ROM has no CHR banks have CHR RAM in their place, see [`Chr`]. Every board can save and restore
+//! its state for save states, in the versioned format of [`state`].
+
+pub mod axrom;
+pub mod cnrom;
+pub mod color_dreams;
+pub mod fds;
+pub mod fme7;
+pub mod gxrom;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod multicart;
+pub mod nrom;
+pub mod nwc;
+pub mod state;
+pub mod uxrom;
+pub mod vrc6;
+
+pub use axrom::Axrom;
+pub use cnrom::Cnrom;
+pub use color_dreams::ColorDreams;
+pub use fds::Fds;
+pub use fme7::Fme7;
+pub use gxrom::Gxrom;
+pub use mmc1::Mmc1;
+pub use mmc2::Mmc2;
+pub use mmc3::Mmc3;
+pub use mmc5::Mmc5;
+pub use multicart::{Multicart225, Multicart58};
+pub use nrom::Nrom;
+pub use nwc::Nwc;
+pub use state::{StateError, StateReader, StateValue, StateWriter};
+pub use uxrom::Uxrom;
+pub use vrc6::Vrc6;
+
+use crate::cartridge::{Mirroring, Rom, RomError};
+use std::ops::Deref;
+
+/// Whether the board for `mapper` has bus conflicts: writes to its registers in $8000-$FFFF latch the value ANDed
+/// with the ROM byte at the address, because the ROM drives the data bus too. For the discrete boards of mappers
+/// 2, 3 and 7, NES 2.0 submapper 1 says the board has no conflicts and submapper 2 that it has. Without one CNROM,
+/// Color Dreams and GxROM have them, UxROM and AxROM do not: most of their games write where the ROM agrees and a few need
+/// the conflicts off.
+pub fn has_bus_conflicts(mapper : u16, submapper : u8) -> bool {
+    match (mapper, submapper) {
+        (2 | 3 | 7, 1) => false,
+        (2 | 3 | 7, 2) => true,
+        (3 | 11 | 66, _) => true,
+        _ => false,
+    }
+}
+
+/// Builds the board `rom` is for, from its mapper number.
+///
+/// # Errors
+/// [`RomError::UnsupportedMapper`] when there is no board for the mapper number.
+pub fn from_rom(rom : Rom) -> Result<Box<dyn Mapper>, RomError> {
+    Ok(match rom.mapper {
+        0 => Box::new(Nrom::new(rom)),
+        1 => Box::new(Mmc1::new(rom)),
+        2 => Box::new(Uxrom::new(rom)),
+        3 => Box::new(Cnrom::new(rom)),
+        4 => Box::new(Mmc3::new(rom)),
+        5 => Box::new(Mmc5::new(rom)),
+        7 => Box::new(Axrom::new(rom)),
+        9 => Box::new(Mmc2::new(rom)),
+        11 => Box::new(ColorDreams::new(rom)),
+        24 | 26 => Box::new(Vrc6::new(rom)),
+        58 => Box::new(Multicart58::new(rom)),
+        66 => Box::new(Gxrom::new(rom)),
+        69 => Box::new(Fme7::new(rom)),
+        105 => Box::new(Nwc::new(rom)),
+        225 => Box::new(Multicart225::new(rom)),
+        mapper => return Err(RomError::UnsupportedMapper(mapper)),
+    })
+}
+
+/// A cartridge board as seen from the CPU (addresses $4020-$FFFF) and the PPU (pattern tables at $0000-$1FFF, and
+/// the nametables for boards that supply their own).
+///
+/// Only the `peek` functions are needed for boards whose reads have no side effects, `cpu_read`, `ppu_read` and
+/// `nametable_read` default to them.
+pub trait Mapper {
+    /// Reads the byte at `addr` in cartridge space. `None` where the board leaves the data bus alone, because
+    /// nothing on it answers the address, and the read gives the open bus.
+    fn cpu_read(&mut self, addr : u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+
+    /// Returns the byte [`Mapper::cpu_read`] would, without any side effects.
+    fn cpu_peek(&self, addr : u16) -> Option<u8>;
+
+    /// Writes `value` to `addr` in cartridge space, which is how games talk to the board's registers.
+    fn cpu_write(&mut self, addr : u16, value : u8);
+
+    /// [`Mapper::cpu_write`] for a write the CPU makes on cycle `cycle`, as the bus counts them. The bus writes to
+    /// the board through this, boards that care how far apart writes are (the MMC1) override it.
+    fn cpu_write_on_cycle(&mut self, addr : u16, value : u8, _cycle : u64) {
+        self.cpu_write(addr, value);
+    }
+
+    /// Reads the byte at `addr` in the pattern tables.
+    fn ppu_read(&mut self, addr : u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+
+    /// Returns the byte [`Mapper::ppu_read`] would, without any side effects.
+    fn ppu_peek(&self, addr : u16) -> u8;
+
+    /// Writes `value` to `addr` in the pattern tables.
+    fn ppu_write(&mut self, addr : u16, value : u8);
+
+    /// How the nametables are mirrored right now.
+    fn mirroring(&self) -> Mirroring;
+
+    /// Reads the nametable byte at `addr` ($2000-$2FFF) when the board supplies it from its own memory. `None`
+    /// leaves it to the PPU's VRAM, arranged as [`Mapper::mirroring`] says. The PPU calls this for every
+    /// nametable and attribute fetch, so boards can also follow the rendering with it.
+    fn nametable_read(&mut self, addr : u16) -> Option<u8> {
+        self.nametable_peek(addr)
+    }
+
+    /// Returns the byte [`Mapper::nametable_read`] would, without any side effects.
+    fn nametable_peek(&self, _addr : u16) -> Option<u8> {
+        None
+    }
+
+    /// Writes a nametable byte the board supplies itself, returning false to leave it to the PPU's VRAM.
+    fn nametable_write(&mut self, _addr : u16, _value : u8) -> bool {
+        false
+    }
+
+    /// Sees every CPU write, not just those to cartridge space. A few boards (the MMC5) watch the PPU registers
+    /// this way.
+    fn snoop_cpu_write(&mut self, _addr : u16, _value : u8) {}
+
+    /// The output of the board's expansion sound channels, on the scale of the APU's own mix where full volume
+    /// of all its channels is about 1.0. Only the Famicom's cartridge port carries audio, boards without sound
+    /// are silent.
+    fn audio_sample(&self) -> f32 {
+        0.0
+    }
+
+    /// Called when the console's reset button is pressed. Most boards ignore it, multicarts go back to their menu.
+    fn reset(&mut self) {}
+
+    /// Advances the board by `cpu_cycles` CPU cycles. Boards with timers or timing dependent registers use this,
+    /// the others ignore it.
+    fn tick(&mut self, _cpu_cycles : u64) {}
+
+    /// Whether the board is holding the CPU's IRQ line low.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// The board's PRG RAM, the work RAM at $6000-$7FFF that holds saved games on boards with a battery. Empty
+    /// on boards without it.
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    /// The board's PRG RAM, to restore a saved game into it.
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// The version of the format [`Mapper::save_state`] writes. Boards bump it when they change what they save,
+    /// and keep loading the older versions.
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    /// Writes everything about the board but its ROM: bank registers, IRQ counters, sound channels, RAM.
+    fn save_state(&self, state : &mut StateWriter);
+
+    /// Restores what [`Mapper::save_state`] wrote, in the format version [`StateReader::version`]. On an error
+    /// the board can be left half restored.
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError>;
+}
+
+/// The CHR RAM a board gets when the ROM has no CHR ROM and the header does not give a size, the 8KiB every
+/// such board of the time had.
+const DEFAULT_CHR_RAM_SIZE : usize = 0x2000;
+
+/// The pattern table memory on a board: the CHR ROM, or CHR RAM the game fills in itself when the ROM has no CHR
+/// banks. Reads index it like a slice, [`Chr::write`] only changes RAM.
+///
+/// # Example
+/// ```
+///  use nes::mapper::Chr;
+///
+///  let mut chr = Chr::new(Vec::new(), 0);
+///  assert_eq!(chr.len(), 0x2000);
+///  chr.write(0x0010, 0x42);
+///  assert_eq!(chr[0x0010], 0x42);
+///
+///  let mut chr = Chr::new(vec![0x11 ; 0x2000], 0);
+///  chr.write(0x0010, 0x42);
+///  assert_eq!(chr[0x0010], 0x11);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chr {
+    bytes : Vec<u8>,
+    ram : bool,
+}
+
+impl Chr {
+    /// The board's CHR ROM, or when `chr_rom` is empty `ram_size` bytes of CHR RAM (8KiB if that is 0 too).
+    pub fn new(chr_rom : Vec<u8>, ram_size : usize) -> Self {
+        if chr_rom.is_empty() {
+            let size = if ram_size == 0 { DEFAULT_CHR_RAM_SIZE } else { ram_size };
+            Chr { bytes : vec![0 ; size], ram : true }
+        } else {
+            Chr { bytes : chr_rom, ram : false }
+        }
+    }
+
+    /// The board's CHR memory as the header of `rom` describes it, CHR RAM sizes from NES 2.0 headers included.
+    pub fn from_rom(rom : &mut Rom) -> Self {
+        Chr::new(std::mem::take(&mut rom.chr_rom), rom.chr_ram_size + rom.chr_nvram_size)
+    }
+
+    /// Whether this is CHR RAM.
+    pub fn is_ram(&self) -> bool {
+        self.ram
+    }
+
+    /// Writes `value` at `index` if this is CHR RAM. Writes to CHR ROM are ignored.
+    pub fn write(&mut self, index : usize, value : u8) {
+        if self.ram {
+            self.bytes[index] = value;
+        }
+    }
+}
+
+impl Deref for Chr {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// The index into `memory` of `offset` in bank `bank` of `bank_size` bytes. Bank numbers past the end of the
+/// memory wrap around, as they do on boards whose bank registers have more bits than the ROM needs, and memory
+/// smaller than a bank is mirrored through it.
+fn banked(memory : &[u8], bank_size : usize, bank : usize, offset : usize) -> usize {
+    (bank * bank_size + offset % bank_size) % memory.len()
+}
+
+/// The number of the bank `back` banks before the last of the `bank_size` banks of `memory`, for boards that fix
+/// banks at the end of the ROM. Memory smaller than a bank counts as one bank, and the count wraps around as bank
+/// numbers do in [`banked`].
+fn last_bank(memory : &[u8], bank_size : usize, back : usize) -> usize {
+    let banks = (memory.len() / bank_size).max(1);
+    banks - 1 - back % banks
+}
@@ -0,0 +1,75 @@
+//! # AxROM Module
+//!
+//! `axrom` is mapper 7, Nintendo's AxROM boards (Battletoads, Wizards & Warriors). A write anywhere in
+//! $8000-$FFFF selects the 32KiB PRG ROM bank (bits 0-2) and which of the two nametables fills the whole screen
+//! (bit 4), the board has no other mirroring. The PPU gets 8KiB of CHR RAM.
+
+use super::{banked, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const PRG_ROM_BANK_SIZE : usize = 0x8000;
+
+const PRG_BANK : u8 = 0b0000_0111;
+const NAMETABLE : u8 = 0b0001_0000;
+
+/// Mapper 7.
+pub struct Axrom {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    bank : u8,
+}
+
+impl Axrom {
+    /// Builds the board for `rom`, with PRG ROM bank 0 and the first nametable selected.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Axrom {
+            prg_rom : rom.prg_rom,
+            chr,
+            bank : 0,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        Some(self.prg_rom[banked(&self.prg_rom, PRG_ROM_BANK_SIZE, (self.bank & PRG_BANK) as usize, addr as usize)])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        if addr >= PRG_ROM {
+            self.bank = value;
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(addr as usize % self.chr.len(), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.bank & NAMETABLE == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.bank);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.bank)
+    }
+}
@@ -0,0 +1,73 @@
+//! # CNROM Module
+//!
+//! `cnrom` is mapper 3 (Gradius, Arkanoid). The PRG ROM is mapped like NROM, a write anywhere in $8000-$FFFF
+//! selects the 8KiB CHR ROM bank.
+//!
+//! The bank register is a plain latch and the ROM does not stop driving the data bus while the CPU writes, so the
+//! two fight: the value latched is the written value ANDed with the ROM byte at the address. Games avoid trouble
+//! by writing to a byte of the ROM that holds the same value, but a few depend on the conflict. The conflict is
+//! emulated by the [`Cartridge`](crate::cartridge::Cartridge), which CNROM boards have it on by default.
+
+use super::{banked, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const CHR_BANK_SIZE : usize = 0x2000;
+
+/// Mapper 3.
+pub struct Cnrom {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    mirroring : Mirroring,
+    chr_bank : u8,
+}
+
+impl Cnrom {
+    /// Builds the board for `rom`, with CHR bank 0 selected.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Cnrom { prg_rom : rom.prg_rom, chr, mirroring : rom.mirroring, chr_bank : 0 }
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        banked(&self.chr, CHR_BANK_SIZE, self.chr_bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        Some(self.prg_rom[(addr - PRG_ROM) as usize % self.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        if addr >= PRG_ROM {
+            self.chr_bank = value;
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.chr_bank);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.chr_bank)
+    }
+}
@@ -0,0 +1,81 @@
+//! # Color Dreams Module
+//!
+//! `color_dreams` is mapper 11, the board of the unlicensed Color Dreams and Wisdom Tree games (Crystal Mines,
+//! Bible Adventures). A write anywhere in $8000-$FFFF selects both banks at once:
+//!
+//! ```text
+//! CCCC LLPP
+//! |||| ||++- 32KiB PRG ROM bank
+//! |||| ++--- Lockout defeat charge pumps, not emulated
+//! ++++------ 8KiB CHR ROM bank
+//! ```
+//!
+//! Up to 128KiB of PRG ROM and 128KiB of CHR ROM. Mirroring is soldered, as the header says, and the board has bus
+//! conflicts.
+
+use super::{banked, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const PRG_ROM_BANK_SIZE : usize = 0x8000;
+const CHR_BANK_SIZE : usize = 0x2000;
+
+const PRG_BANK : u8 = 0b0000_0011;
+
+/// Mapper 11.
+pub struct ColorDreams {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    mirroring : Mirroring,
+    bank : u8,
+}
+
+impl ColorDreams {
+    /// Builds the board for `rom`, with PRG ROM bank 0 and CHR bank 0 selected.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        ColorDreams { prg_rom : rom.prg_rom, chr, mirroring : rom.mirroring, bank : 0 }
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        banked(&self.chr, CHR_BANK_SIZE, (self.bank >> 4) as usize, addr as usize)
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        Some(self.prg_rom[banked(&self.prg_rom, PRG_ROM_BANK_SIZE, (self.bank & PRG_BANK) as usize, addr as usize)])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        if addr >= PRG_ROM {
+            self.bank = value;
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.bank);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.bank)
+    }
+}
@@ -0,0 +1,444 @@
+//! # FDS Module
+//!
+//! `fds` is the Famicom Disk System (mapper 20): the RAM adapter that plugs into the cartridge slot, and the disk
+//! drive behind it. Games come on disks rather than ROMs, loaded by the 8KiB BIOS into the adapter's 32KiB of
+//! RAM, so an [`Fds`] is built from the BIOS and an [`FdsDisk`] rather than from a [`Rom`](crate::cartridge::Rom).
+//!
+//! | Address     | Register                                                                          |
+//! |-------------|-----------------------------------------------------------------------------------|
+//! | $4020-$4021 | Timer IRQ reload value, low and high byte                                         |
+//! | $4022       | Timer IRQ control: bit 0 repeats, bit 1 enables                                   |
+//! | $4023       | Master I/O enable: bit 0 the disk registers, bit 1 the sound registers             |
+//! | $4024       | Byte to write to the disk                                                         |
+//! | $4025       | Drive control: motor, transfer reset, read/write, mirroring, CRC, ready, disk IRQ |
+//! | $4030       | Status, read: timer IRQ, byte transferred, end of the disk. Acknowledges both IRQs |
+//! | $4031       | Byte read from the disk                                                           |
+//! | $4032       | Drive status, read: no disk, not ready, write protected                           |
+//! | $4040-$4092 | Sound, see [`audio`]                                                              |
+//!
+//! The drive moves a byte under the head every 150 CPU cycles once the motor runs, raising the disk IRQ for each
+//! one when it is enabled. At the end of the side the motor stops and the head goes back to the start.
+
+mod audio;
+mod disk;
+
+pub use disk::{FdsDisk, FdsError, SIDE_SIZE};
+
+use super::{Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::Mirroring;
+use audio::Audio;
+use disk::{update_crc, BLOCK_START};
+use std::path::Path;
+
+/// The size of the BIOS, at $E000-$FFFF.
+pub const BIOS_SIZE : usize = 0x2000;
+const RAM : u16 = 0x6000;
+const RAM_END : u16 = 0xdfff;
+const RAM_SIZE : usize = 0x8000;
+const BIOS : u16 = 0xe000;
+
+const DISK_REGISTERS_ENABLE : u8 = 0b01;
+const SOUND_REGISTERS_ENABLE : u8 = 0b10;
+
+const TIMER_REPEAT : u8 = 0b01;
+const TIMER_ENABLE : u8 = 0b10;
+
+const MOTOR_ON : u8 = 0b0000_0001;
+const TRANSFER_RESET : u8 = 0b0000_0010;
+const READ_MODE : u8 = 0b0000_0100;
+const HORIZONTAL : u8 = 0b0000_1000;
+const CRC_CONTROL : u8 = 0b0001_0000;
+const DRIVE_READY : u8 = 0b0100_0000;
+const DISK_IRQ_ENABLE : u8 = 0b1000_0000;
+
+const STATUS_TIMER_IRQ : u8 = 0b0000_0001;
+const STATUS_TRANSFERRED : u8 = 0b0000_0010;
+const STATUS_END_OF_HEAD : u8 = 0b0100_0000;
+const DRIVE_NO_DISK : u8 = 0b001;
+const DRIVE_NOT_READY : u8 = 0b010;
+const DRIVE_WRITE_PROTECTED : u8 = 0b100;
+/// $4033 bit 7, the batteries of the drive are fine.
+const BATTERY_GOOD : u8 = 0b1000_0000;
+
+/// CPU cycles the drive takes to get going after the head returns to the start of the disk.
+const SPIN_UP_CYCLES : u32 = 50000;
+/// CPU cycles per byte read or written, about 96kbit/s.
+const BYTE_CYCLES : u32 = 150;
+/// A write lands this many bytes behind the head, the delay between the BIOS seeing a block and switching the
+/// drive to writing.
+const WRITE_LAG : usize = 2;
+/// At full volume the wave is about 2.4 times as loud as one of the APU's pulse channels.
+const AUDIO_SCALE : f32 = 0.36 / 63.0;
+
+/// Mapper 20, the Famicom Disk System.
+pub struct Fds {
+    bios : Vec<u8>,
+    ram : Vec<u8>,
+    chr : Chr,
+    disk : FdsDisk,
+    /// The disk side in the drive.
+    side : Option<usize>,
+    /// $4023.
+    io_enable : u8,
+    timer_reload : u16,
+    timer_counter : u16,
+    timer_control : u8,
+    timer_irq : bool,
+    /// $4025.
+    control : u8,
+    write_data : u8,
+    read_data : u8,
+    transferred : bool,
+    disk_irq : bool,
+    position : usize,
+    delay : u32,
+    scanning : bool,
+    end_of_head : bool,
+    /// A block has started since the drive was last not ready.
+    gap_ended : bool,
+    crc : u16,
+    previous_crc_control : bool,
+    audio : Audio,
+}
+
+impl Fds {
+    /// Builds the RAM adapter with `bios` and side 0 of `disk` in the drive.
+    ///
+    /// # Errors
+    /// [`FdsError::BiosSize`] when `bios` is not 8KiB.
+    pub fn new(bios : Vec<u8>, disk : FdsDisk) -> Result<Fds, FdsError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(FdsError::BiosSize(bios.len()));
+        }
+        Ok(Fds {
+            bios,
+            ram : vec![0 ; RAM_SIZE],
+            chr : Chr::new(Vec::new(), 0),
+            disk,
+            side : Some(0),
+            io_enable : DISK_REGISTERS_ENABLE | SOUND_REGISTERS_ENABLE,
+            timer_reload : 0,
+            timer_counter : 0,
+            timer_control : 0,
+            timer_irq : false,
+            control : 0,
+            write_data : 0,
+            read_data : 0,
+            transferred : false,
+            disk_irq : false,
+            position : 0,
+            delay : 0,
+            scanning : false,
+            end_of_head : true,
+            gap_ended : false,
+            crc : 0,
+            previous_crc_control : false,
+            audio : Audio::new(),
+        })
+    }
+
+    /// Reads the BIOS (usually disksys.rom) and the .fds disk image.
+    pub fn from_files<P : AsRef<Path>, Q : AsRef<Path>>(bios : P, disk : Q) -> Result<Fds, FdsError> {
+        Fds::new(std::fs::read(bios)?, FdsDisk::from_file(disk)?)
+    }
+
+    /// The disks, with what the game has written to them.
+    pub fn disk(&self) -> &FdsDisk {
+        &self.disk
+    }
+
+    /// The disk side in the drive, `None` when it is empty.
+    pub fn disk_side(&self) -> Option<usize> {
+        self.side
+    }
+
+    /// Puts side `side` in the drive, returning false when there is no such side. Games only notice a new disk
+    /// after the drive has been empty for a moment, so eject the old one a second or so before.
+    pub fn insert_disk(&mut self, side : usize) -> bool {
+        if side >= self.disk.side_count() {
+            return false;
+        }
+        self.side = Some(side);
+        true
+    }
+
+    /// Takes the disk out of the drive.
+    pub fn eject_disk(&mut self) {
+        self.side = None;
+    }
+
+    fn disk_registers_enabled(&self) -> bool {
+        self.io_enable & DISK_REGISTERS_ENABLE != 0
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer_control & TIMER_ENABLE == 0 || !self.disk_registers_enabled() {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            if self.timer_control & TIMER_REPEAT == 0 {
+                self.timer_control &= !TIMER_ENABLE;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        let Some(side) = self.side.filter(|_| self.control & MOTOR_ON != 0) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if self.control & TRANSFER_RESET != 0 && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = SPIN_UP_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let ready = self.control & DRIVE_READY != 0;
+        let crc_control = self.control & CRC_CONTROL != 0;
+        let mut irq = self.control & DISK_IRQ_ENABLE != 0;
+        if self.control & READ_MODE != 0 {
+            let data = self.disk.side(side)[self.position];
+            if !self.previous_crc_control {
+                self.crc = update_crc(self.crc, data);
+            }
+            if !ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data == BLOCK_START && !self.gap_ended {
+                // The start mark is passed on, but without an IRQ.
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.read_data = data;
+                self.transferred = true;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut data = 0;
+            if !crc_control {
+                self.transferred = true;
+                self.disk_irq |= irq;
+                if ready {
+                    data = self.write_data;
+                }
+                self.crc = update_crc(self.crc, data);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = update_crc(update_crc(self.crc, 0), 0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            let index = self.position.saturating_sub(WRITE_LAG);
+            self.disk.side_mut(side)[index] = data;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = crc_control;
+
+        self.position += 1;
+        if self.position >= self.disk.side(side).len() {
+            self.control &= !MOTOR_ON;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read(&mut self, addr : u16) -> Option<u8> {
+        let value = self.cpu_peek(addr);
+        match addr {
+            0x4030 => {
+                self.timer_irq = false;
+                self.disk_irq = false;
+                self.transferred = false;
+            }
+            0x4031 => {
+                self.disk_irq = false;
+                self.transferred = false;
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        let value = match addr {
+            0x4030 => {
+                let mut status = 0;
+                if self.timer_irq {
+                    status |= STATUS_TIMER_IRQ;
+                }
+                if self.transferred {
+                    status |= STATUS_TRANSFERRED;
+                }
+                if self.end_of_head {
+                    status |= STATUS_END_OF_HEAD;
+                }
+                // CRC errors (bit 4) are never reported, the blocks are checked when the image is loaded.
+                status
+            }
+            0x4031 => self.read_data,
+            0x4032 => match self.side {
+                None => DRIVE_NO_DISK | DRIVE_NOT_READY | DRIVE_WRITE_PROTECTED,
+                Some(_) if !self.scanning => DRIVE_NOT_READY,
+                Some(_) => 0,
+            },
+            0x4033 => BATTERY_GOOD,
+            0x4040 ..= 0x407f | 0x4090 | 0x4092 => self.audio.peek(addr),
+            RAM ..= RAM_END => self.ram[(addr - RAM) as usize],
+            BIOS ..= 0xffff => self.bios[(addr - BIOS) as usize],
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        let disk_registers = self.disk_registers_enabled();
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xff00) | value as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00ff) | (value as u16) << 8,
+            0x4022 if disk_registers => {
+                self.timer_control = value & (TIMER_REPEAT | TIMER_ENABLE);
+                if value & TIMER_ENABLE != 0 {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.io_enable = value;
+                if value & DISK_REGISTERS_ENABLE == 0 {
+                    self.timer_control &= !TIMER_ENABLE;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if disk_registers => {
+                self.write_data = value;
+                self.transferred = false;
+                self.disk_irq = false;
+            }
+            0x4025 if disk_registers => {
+                self.control = value;
+                self.disk_irq = false;
+            }
+            0x4040 ..= 0x408a if self.io_enable & SOUND_REGISTERS_ENABLE != 0 => self.audio.write(addr, value),
+            RAM ..= RAM_END => self.ram[(addr - RAM) as usize] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[addr as usize & 0x1fff]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(addr as usize & 0x1fff, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.control & HORIZONTAL != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn audio_sample(&self) -> f32 {
+        self.audio.output() as f32 * AUDIO_SCALE
+    }
+
+    fn tick(&mut self, cpu_cycles : u64) {
+        for _ in 0 .. cpu_cycles {
+            self.clock_timer();
+            self.audio.clock();
+            self.clock_drive();
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.ram);
+        state.write(&self.chr);
+        state.write(&self.disk);
+        state.write(&self.side);
+        state.write(&self.io_enable);
+        state.write(&self.timer_reload);
+        state.write(&self.timer_counter);
+        state.write(&self.timer_control);
+        state.write(&self.timer_irq);
+        state.write(&self.control);
+        state.write(&self.write_data);
+        state.write(&self.read_data);
+        state.write(&self.transferred);
+        state.write(&self.disk_irq);
+        state.write(&self.position);
+        state.write(&self.delay);
+        state.write(&self.scanning);
+        state.write(&self.end_of_head);
+        state.write(&self.gap_ended);
+        state.write(&self.crc);
+        state.write(&self.previous_crc_control);
+        state.write(&self.audio);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.ram)?;
+        state.read(&mut self.chr)?;
+        state.read(&mut self.disk)?;
+        state.read(&mut self.side)?;
+        state.read(&mut self.io_enable)?;
+        state.read(&mut self.timer_reload)?;
+        state.read(&mut self.timer_counter)?;
+        state.read(&mut self.timer_control)?;
+        state.read(&mut self.timer_irq)?;
+        state.read(&mut self.control)?;
+        state.read(&mut self.write_data)?;
+        state.read(&mut self.read_data)?;
+        state.read(&mut self.transferred)?;
+        state.read(&mut self.disk_irq)?;
+        state.read(&mut self.position)?;
+        state.read(&mut self.delay)?;
+        state.read(&mut self.scanning)?;
+        state.read(&mut self.end_of_head)?;
+        state.read(&mut self.gap_ended)?;
+        state.read(&mut self.crc)?;
+        state.read(&mut self.previous_crc_control)?;
+        state.read(&mut self.audio)?;
+        let sides = self.disk.side_count();
+        let past_end = (0 .. sides).any(|side| self.position > self.disk.side(side).len());
+        if self.side.is_some_and(|side| side >= sides) || past_end {
+            return Err(StateError::BadValue);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! # Audio Module
+//!
+//! `audio` is the FDS's sound channel: a 64 step wavetable of 6-bit samples, played at a 12-bit frequency that a
+//! modulation unit bends up and down for vibrato. Both have an envelope, for the volume and the depth of the
+//! modulation.
+
+use crate::mapper::state::{StateError, StateReader, StateValue, StateWriter};
+
+/// How far each entry of the modulation table moves the modulation counter. Entry 4 resets it instead.
+const MOD_STEPS : [i16 ; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET : u8 = 4;
+/// The master volume, 2/2, 2/3, 2/4 and 2/5, scaled to multiply the gain with.
+const MASTER_VOLUME : [u32 ; 4] = [36, 24, 17, 14];
+/// The envelope speed multiplier at power on.
+const DEFAULT_MASTER_SPEED : u8 = 0xe8;
+
+/// The volume or modulation depth envelope. Either it holds its gain, or it moves it by one towards 0 or 32 at its
+/// speed.
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+    speed : u8,
+    increase : bool,
+    /// The gain is set directly, bit 7 of the envelope register.
+    direct : bool,
+    gain : u8,
+    timer : u32,
+}
+
+impl Envelope {
+    fn write(&mut self, value : u8, master_speed : u8) {
+        self.speed = value & 0x3f;
+        self.increase = value & 0x40 != 0;
+        self.direct = value & 0x80 != 0;
+        self.reset_timer(master_speed);
+        if self.direct {
+            self.gain = self.speed;
+        }
+    }
+
+    fn reset_timer(&mut self, master_speed : u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    /// Clocks the envelope once a CPU cycle, returning whether the gain stepped.
+    fn clock(&mut self, master_speed : u8) -> bool {
+        if self.direct || master_speed == 0 {
+            return false;
+        }
+        if self.timer > 1 {
+            self.timer -= 1;
+            return false;
+        }
+        self.reset_timer(master_speed);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+        true
+    }
+}
+
+impl StateValue for Envelope {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.speed);
+        state.write(&self.increase);
+        state.write(&self.direct);
+        state.write(&self.gain);
+        state.write(&self.timer);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.speed)?;
+        state.read(&mut self.increase)?;
+        state.read(&mut self.direct)?;
+        state.read(&mut self.gain)?;
+        state.read(&mut self.timer)
+    }
+}
+
+/// The FDS sound channel, registers $4040-$408A.
+#[derive(Debug, Clone)]
+pub(super) struct Audio {
+    wave : [u8 ; 64],
+    wave_position : u8,
+    wave_accumulator : u16,
+    wave_frequency : u16,
+    wave_halted : bool,
+    /// The wavetable can be written, which holds the channel still.
+    wave_write : bool,
+    envelopes_halted : bool,
+    master_volume : u8,
+    master_speed : u8,
+    volume : Envelope,
+    modulator : Envelope,
+    mod_frequency : u16,
+    mod_halted : bool,
+    mod_table : [u8 ; 64],
+    mod_position : u8,
+    mod_accumulator : u16,
+    /// The 7-bit signed modulation counter.
+    mod_counter : i8,
+    /// How much the modulation bends the wave frequency.
+    mod_pitch : i32,
+    output : u8,
+}
+
+impl Audio {
+    pub(super) fn new() -> Self {
+        Audio {
+            wave : [0 ; 64],
+            wave_position : 0,
+            wave_accumulator : 0,
+            wave_frequency : 0,
+            wave_halted : true,
+            wave_write : false,
+            envelopes_halted : false,
+            master_volume : 0,
+            master_speed : DEFAULT_MASTER_SPEED,
+            volume : Envelope::default(),
+            modulator : Envelope::default(),
+            mod_frequency : 0,
+            mod_halted : true,
+            mod_table : [0 ; 64],
+            mod_position : 0,
+            mod_accumulator : 0,
+            mod_counter : 0,
+            mod_pitch : 0,
+            output : 0,
+        }
+    }
+
+    pub(super) fn peek(&self, addr : u16) -> u8 {
+        match addr {
+            0x4040 ..= 0x407f => self.wave[addr as usize & 0x3f],
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.modulator.gain | 0x40,
+            _ => 0,
+        }
+    }
+
+    pub(super) fn write(&mut self, addr : u16, value : u8) {
+        match addr {
+            0x4040 ..= 0x407f if self.wave_write => self.wave[addr as usize & 0x3f] = value & 0x3f,
+            0x4080 => self.volume.write(value, self.master_speed),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0xf00) | value as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0xff) | ((value as u16 & 0x0f) << 8);
+                self.envelopes_halted = value & 0x40 != 0;
+                self.wave_halted = value & 0x80 != 0;
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.master_speed);
+                    self.modulator.reset_timer(self.master_speed);
+                }
+            }
+            0x4084 => self.modulator.write(value, self.master_speed),
+            0x4085 => self.mod_counter = wrap_counter(value as i16),
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0xf00) | value as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0xff) | ((value as u16 & 0x0f) << 8);
+                self.mod_halted = value & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // Each write fills two entries of the table, and only while the modulation is halted.
+            0x4088 if self.mod_halted => {
+                for _ in 0 .. 2 {
+                    self.mod_table[self.mod_position as usize] = value & 0b111;
+                    self.mod_position = (self.mod_position + 1) & 0x3f;
+                }
+            }
+            0x4089 => {
+                self.wave_write = value & 0x80 != 0;
+                self.master_volume = value & 0b11;
+            }
+            0x408a => {
+                self.master_speed = value;
+                self.volume.reset_timer(value);
+                self.modulator.reset_timer(value);
+            }
+            _ => {}
+        }
+    }
+
+    /// Clocks the channel once a CPU cycle.
+    pub(super) fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.clock(self.master_speed);
+            if self.modulator.clock(self.master_speed) {
+                self.update_mod_pitch();
+            }
+        }
+        if !self.mod_halted && self.mod_frequency > 0 {
+            let (accumulator, overflow) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+            self.mod_accumulator = accumulator;
+            if overflow {
+                let step = self.mod_table[self.mod_position as usize];
+                self.mod_counter =
+                    if step == MOD_RESET { 0 } else { wrap_counter(self.mod_counter as i16 + MOD_STEPS[step as usize]) };
+                self.mod_position = (self.mod_position + 1) & 0x3f;
+                self.update_mod_pitch();
+            }
+        }
+        if self.wave_write {
+            return;
+        }
+        if self.wave_halted {
+            self.wave_position = 0;
+            self.wave_accumulator = 0;
+        } else {
+            let pitch = self.wave_frequency as i32 + self.mod_pitch;
+            if pitch > 0 {
+                let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+                self.wave_accumulator = accumulator;
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) & 0x3f;
+                }
+            }
+        }
+        let level = (self.volume.gain.min(32) as u32) * MASTER_VOLUME[self.master_volume as usize];
+        self.output = (self.wave[self.wave_position as usize] as u32 * level / 1152) as u8;
+    }
+
+    /// The output level, 0-63.
+    pub(super) fn output(&self) -> u8 {
+        self.output
+    }
+
+    /// Works out how far the modulation bends the wave frequency, with the rounding of the real chip.
+    fn update_mod_pitch(&mut self) {
+        let mut temp = self.mod_counter as i32 * self.modulator.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.wave_frequency as i32;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        self.mod_pitch = temp;
+    }
+}
+
+impl StateValue for Audio {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.wave);
+        state.write(&self.wave_position);
+        state.write(&self.wave_accumulator);
+        state.write(&self.wave_frequency);
+        state.write(&self.wave_halted);
+        state.write(&self.wave_write);
+        state.write(&self.envelopes_halted);
+        state.write(&self.master_volume);
+        state.write(&self.master_speed);
+        state.write(&self.volume);
+        state.write(&self.modulator);
+        state.write(&self.mod_frequency);
+        state.write(&self.mod_halted);
+        state.write(&self.mod_table);
+        state.write(&self.mod_position);
+        state.write(&self.mod_accumulator);
+        state.write(&self.mod_counter);
+        state.write(&self.mod_pitch);
+        state.write(&self.output);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.wave)?;
+        state.read(&mut self.wave_position)?;
+        state.read(&mut self.wave_accumulator)?;
+        state.read(&mut self.wave_frequency)?;
+        state.read(&mut self.wave_halted)?;
+        state.read(&mut self.wave_write)?;
+        state.read(&mut self.envelopes_halted)?;
+        state.read(&mut self.master_volume)?;
+        state.read(&mut self.master_speed)?;
+        state.read(&mut self.volume)?;
+        state.read(&mut self.modulator)?;
+        state.read(&mut self.mod_frequency)?;
+        state.read(&mut self.mod_halted)?;
+        state.read(&mut self.mod_table)?;
+        state.read(&mut self.mod_position)?;
+        state.read(&mut self.mod_accumulator)?;
+        state.read(&mut self.mod_counter)?;
+        state.read(&mut self.mod_pitch)?;
+        state.read(&mut self.output)?;
+        let positions = self.wave_position < 64 && self.mod_position < 64 && self.master_volume < 4;
+        let modulation = self.mod_table.iter().all(|&step| step < 8) && i16::try_from(self.mod_pitch).is_ok();
+        if !positions || !modulation {
+            return Err(StateError::BadValue);
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `value` to the 7-bit signed range of the modulation counter, -64 to 63.
+fn wrap_counter(value : i16) -> i8 {
+    (((value as u8) << 1) as i8) >> 1
+}
@@ -0,0 +1,170 @@
+//! # Disk Module
+//!
+//! `disk` reads .fds disk images. A .fds file is the data of each disk side, 65500 bytes a side, optionally after
+//! a 16 byte header starting with `FDS\x1a`. The file keeps only the blocks on the disk, so to give the drive
+//! something to read they are laid out as on a real disk: a long gap before the first block, then each block
+//! after a start mark and followed by its CRC and a shorter gap.
+
+use crate::mapper::state::{StateError, StateReader, StateValue, StateWriter};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The size of a disk side in a .fds file.
+pub const SIDE_SIZE : usize = 65500;
+const HEADER_SIZE : usize = 16;
+const HEADER_MAGIC : &[u8] = b"FDS\x1a";
+/// Every side starts with the disk info block, which starts with this.
+const DISK_MAGIC : &[u8] = b"\x01*NINTENDO-HVC*";
+/// The gap before the first block, 28300 bits of zeros.
+const LEAD_IN : usize = 28300 / 8;
+/// The gap after each block, 976 bits of zeros.
+const BLOCK_GAP : usize = 976 / 8;
+/// The byte that ends a gap and starts a block.
+pub(super) const BLOCK_START : u8 = 0x80;
+/// The polynomial of the drive's CRC-16, bit reversed.
+const CRC_POLYNOMIAL : u16 = 0x8408;
+
+/// Why a disk image or BIOS could not be loaded.
+#[derive(Debug)]
+pub enum FdsError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file is not a .fds disk image.
+    NotFds,
+    /// The BIOS is not the 8KiB it should be.
+    BiosSize(usize),
+}
+
+impl fmt::Display for FdsError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FdsError::Io(error) => write!(f, "could not read file: {}", error),
+            FdsError::NotFds => write!(f, "not a .fds disk image"),
+            FdsError::BiosSize(size) => write!(f, "the FDS BIOS is {} bytes instead of 8192", size),
+        }
+    }
+}
+
+impl std::error::Error for FdsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FdsError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FdsError {
+    fn from(error : io::Error) -> Self {
+        FdsError::Io(error)
+    }
+}
+
+/// The sides of a disk set, each as the drive sees it: gaps, blocks and CRCs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdsDisk {
+    sides : Vec<Vec<u8>>,
+}
+
+impl FdsDisk {
+    /// Parses a .fds image, with or without its header.
+    ///
+    /// # Errors
+    /// [`FdsError::NotFds`] when the image has no sides, or a side does not start with a disk info block.
+    pub fn from_bytes(bytes : &[u8]) -> Result<FdsDisk, FdsError> {
+        let data = if bytes.starts_with(HEADER_MAGIC) { &bytes[HEADER_SIZE.min(bytes.len()) ..] } else { bytes };
+        let sides : Vec<_> = data.chunks_exact(SIDE_SIZE).collect();
+        if sides.is_empty() || sides.iter().any(|side| !side.starts_with(DISK_MAGIC)) {
+            return Err(FdsError::NotFds);
+        }
+        Ok(FdsDisk { sides : sides.into_iter().map(raw_side).collect() })
+    }
+
+    /// Reads and parses the .fds file at `path`.
+    pub fn from_file<P : AsRef<Path>>(path : P) -> Result<FdsDisk, FdsError> {
+        FdsDisk::from_bytes(&fs::read(path)?)
+    }
+
+    /// How many disk sides there are, two for each disk.
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    /// Side `side` as the drive sees it, including whatever the game wrote to it.
+    pub fn side(&self, side : usize) -> &[u8] {
+        &self.sides[side]
+    }
+
+    pub(super) fn side_mut(&mut self, side : usize) -> &mut [u8] {
+        &mut self.sides[side]
+    }
+}
+
+/// The disk is saved with the state, it changes as the game saves to it.
+impl StateValue for FdsDisk {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.sides.len());
+        for side in &self.sides {
+            state.write(side);
+        }
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut count = 0usize;
+        state.read(&mut count)?;
+        if count != self.sides.len() {
+            return Err(StateError::SizeMismatch { expected : self.sides.len(), found : count });
+        }
+        self.sides.iter_mut().try_for_each(|side| state.read(side))
+    }
+}
+
+/// Lays the blocks of a side out with their gaps and CRCs. The blocks end at the first byte that is not a block
+/// type, the rest of the side is blank.
+fn raw_side(data : &[u8]) -> Vec<u8> {
+    let mut raw = vec![0 ; LEAD_IN];
+    let mut position = 0;
+    let mut file_size = 0;
+    while position < data.len() {
+        let length = match data[position] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            4 => 1 + file_size,
+            _ => break,
+        };
+        let Some(block) = data.get(position .. position + length) else { break };
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        raw.push(BLOCK_START);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&block_crc(block).to_le_bytes());
+        raw.extend([0 ; BLOCK_GAP]);
+        position += length;
+    }
+    raw.resize(raw.len().max(LEAD_IN + SIDE_SIZE), 0);
+    raw
+}
+
+/// Feeds `value` into the drive's CRC, low bit first.
+pub(super) fn update_crc(mut crc : u16, value : u8) -> u16 {
+    for bit in 0 .. 8 {
+        let carry = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= CRC_POLYNOMIAL;
+        }
+        if (value >> bit) & 1 != 0 {
+            crc ^= CRC_POLYNOMIAL;
+        }
+    }
+    crc
+}
+
+/// The CRC the drive writes after `block`. It covers the start mark too.
+fn block_crc(block : &[u8]) -> u16 {
+    [BLOCK_START].iter().chain(block).chain(&[0, 0]).fold(0, |crc, &value| update_crc(crc, value))
+}
@@ -0,0 +1,204 @@
+//! # FME-7 Module
+//!
+//! `fme7` is mapper 69, Sunsoft's FME-7 (Gimmick!, Batman: Return of the Joker, Hebereke). Like the MMC3 it is
+//! programmed through two registers: the command number is written to $8000-$9FFF and its parameter to
+//! $A000-$BFFF.
+//!
+//! | Command | Parameter                                                                          |
+//! |---------|------------------------------------------------------------------------------------|
+//! | $0-$7   | 1KiB CHR banks at $0000-$1C00                                                      |
+//! | $8      | 8KiB bank at $6000: bit 6 picks RAM over ROM, bit 7 enables the RAM, bits 0-5 bank |
+//! | $9-$B   | 8KiB PRG ROM banks at $8000, $A000 and $C000                                       |
+//! | $C      | Mirroring: vertical, horizontal, single-screen lower or upper                      |
+//! | $D      | IRQ control: bit 0 enables the IRQ, bit 7 the counter. Acknowledges the IRQ         |
+//! | $E, $F  | Low and high byte of the IRQ counter                                               |
+//!
+//! The last 8KiB of PRG ROM is fixed at $E000. The IRQ counter is 16 bits, counting down once per CPU cycle, and
+//! raises the IRQ when it wraps from $0000 to $FFFF.
+
+use super::{banked, last_bank, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_RAM : u16 = 0x6000;
+const PRG_RAM_END : u16 = 0x7fff;
+const PRG_BANK_SIZE : usize = 0x2000;
+const CHR_BANK_SIZE : usize = 0x0400;
+
+const RAM_SELECT : u8 = 0b0100_0000;
+const RAM_ENABLE : u8 = 0b1000_0000;
+const IRQ_ENABLE : u8 = 0b0000_0001;
+const IRQ_COUNTER_ENABLE : u8 = 0b1000_0000;
+
+/// Mapper 69.
+pub struct Fme7 {
+    prg_rom : Vec<u8>,
+    prg_ram : Vec<u8>,
+    chr : Chr,
+    command : u8,
+    chr_banks : [u8 ; 8],
+    /// Command $8, the bank at $6000.
+    prg_low : u8,
+    prg_banks : [u8 ; 3],
+    mirroring : Mirroring,
+    irq_control : u8,
+    irq_counter : u16,
+    irq_pending : bool,
+}
+
+impl Fme7 {
+    /// Builds the board for `rom`. Boards without CHR ROM get 8KiB of CHR RAM.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Fme7 {
+            prg_rom : rom.prg_rom,
+            prg_ram : vec![0 ; rom.prg_ram_size + rom.prg_nvram_size],
+            chr,
+            command : 0,
+            chr_banks : [0 ; 8],
+            prg_low : 0,
+            prg_banks : [0 ; 3],
+            mirroring : Mirroring::Vertical,
+            irq_control : 0,
+            irq_counter : 0,
+            irq_pending : false,
+        }
+    }
+
+    fn ram_at_6000(&self) -> bool {
+        self.prg_low & RAM_SELECT != 0
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.ram_at_6000() && self.prg_low & RAM_ENABLE != 0 && !self.prg_ram.is_empty()
+    }
+
+    fn prg_ram_index(&self, addr : u16) -> usize {
+        banked(&self.prg_ram, PRG_BANK_SIZE, (self.prg_low & 0x3f) as usize, addr as usize)
+    }
+
+    fn prg_rom_index(&self, bank : u8, addr : u16) -> usize {
+        banked(&self.prg_rom, PRG_BANK_SIZE, bank as usize, addr as usize)
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        let bank = self.chr_banks[(addr as usize / CHR_BANK_SIZE) & 0b111];
+        banked(&self.chr, CHR_BANK_SIZE, bank as usize, addr as usize)
+    }
+
+    fn write_parameter(&mut self, value : u8) {
+        match self.command {
+            command @ 0x0 ..= 0x7 => self.chr_banks[command as usize] = value,
+            0x8 => self.prg_low = value,
+            command @ 0x9 ..= 0xb => self.prg_banks[(command - 0x9) as usize] = value & 0x3f,
+            0xc => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0xd => {
+                self.irq_control = value;
+                self.irq_pending = false;
+            }
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | (value as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => Some(self.prg_ram[self.prg_ram_index(addr)]),
+            // Selected but disabled RAM leaves the bus open.
+            PRG_RAM ..= PRG_RAM_END if self.ram_at_6000() => None,
+            PRG_RAM ..= PRG_RAM_END => Some(self.prg_rom[self.prg_rom_index(self.prg_low & 0x3f, addr)]),
+            0x8000 ..= 0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE];
+                Some(self.prg_rom[self.prg_rom_index(bank, addr)])
+            }
+            0xe000 ..= 0xffff => {
+                let last = last_bank(&self.prg_rom, PRG_BANK_SIZE, 0);
+                Some(self.prg_rom[banked(&self.prg_rom, PRG_BANK_SIZE, last, addr as usize)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => {
+                let index = self.prg_ram_index(addr);
+                self.prg_ram[index] = value;
+            }
+            0x8000 ..= 0x9fff => self.command = value & 0x0f,
+            0xa000 ..= 0xbfff => self.write_parameter(value),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn tick(&mut self, cpu_cycles : u64) {
+        if self.irq_control & IRQ_COUNTER_ENABLE == 0 {
+            return;
+        }
+        for _ in 0 .. cpu_cycles {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_control & IRQ_ENABLE != 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.prg_ram);
+        state.write(&self.chr);
+        state.write(&self.command);
+        state.write(&self.chr_banks);
+        state.write(&self.prg_low);
+        state.write(&self.prg_banks);
+        state.write(&self.mirroring);
+        state.write(&self.irq_control);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_pending);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.prg_ram)?;
+        state.read(&mut self.chr)?;
+        state.read(&mut self.command)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.prg_low)?;
+        state.read(&mut self.prg_banks)?;
+        state.read(&mut self.mirroring)?;
+        state.read(&mut self.irq_control)?;
+        state.read(&mut self.irq_counter)?;
+        state.read(&mut self.irq_pending)
+    }
+}
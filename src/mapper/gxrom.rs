@@ -0,0 +1,82 @@
+//! # GxROM Module
+//!
+//! `gxrom` is mapper 66, Nintendo's GNROM and MHROM boards (Super Mario Bros. + Duck Hunt, Dragon Power). A write
+//! anywhere in $8000-$FFFF selects both banks at once:
+//!
+//! ```text
+//! ..PP ..CC
+//!   ||   ++- 8KiB CHR ROM bank
+//!   ++------ 32KiB PRG ROM bank
+//! ```
+//!
+//! Up to 128KiB of PRG ROM and 32KiB of CHR ROM. Mirroring is soldered, as the header says, and the board has bus
+//! conflicts.
+
+use super::{banked, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const PRG_ROM_BANK_SIZE : usize = 0x8000;
+const CHR_BANK_SIZE : usize = 0x2000;
+
+const CHR_BANK : u8 = 0b0000_0011;
+const PRG_BANK : u8 = 0b0011_0000;
+
+/// Mapper 66.
+pub struct Gxrom {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    mirroring : Mirroring,
+    bank : u8,
+}
+
+impl Gxrom {
+    /// Builds the board for `rom`, with PRG ROM bank 0 and CHR bank 0 selected.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Gxrom { prg_rom : rom.prg_rom, chr, mirroring : rom.mirroring, bank : 0 }
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        banked(&self.chr, CHR_BANK_SIZE, (self.bank & CHR_BANK) as usize, addr as usize)
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let bank = (self.bank & PRG_BANK) >> 4;
+        Some(self.prg_rom[banked(&self.prg_rom, PRG_ROM_BANK_SIZE, bank as usize, addr as usize)])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        if addr >= PRG_ROM {
+            self.bank = value;
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.bank);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.bank)
+    }
+}
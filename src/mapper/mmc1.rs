@@ -0,0 +1,228 @@
+//! # MMC1 Module
+//!
+//! `mmc1` is mapper 1, Nintendo's SxROM boards (The Legend of Zelda, Metroid). The MMC1 has four 5 bit registers
+//! that are loaded one bit at a time: each write to $8000-$FFFF shifts bit 0 into a shift register, and the fifth
+//! write copies it into the register picked by that write's address:
+//!
+//! | Address     | Register                                                                   |
+//! |-------------|----------------------------------------------------------------------------|
+//! | $8000-$9FFF | Control: mirroring (bits 0-1), PRG ROM banking mode (2-3), CHR mode (4)      |
+//! | $A000-$BFFF | CHR bank for $0000 (or the 8KiB bank, ignoring bit 0)                       |
+//! | $C000-$DFFF | CHR bank for $1000                                                          |
+//! | $E000-$FFFF | PRG ROM bank (bits 0-3) and PRG RAM disable (bit 4)                         |
+//!
+//! A write with bit 7 set clears the shift register and selects the PRG mode with the last bank fixed.
+//!
+//! The MMC1 ignores a write on the cycle right after another one, so the two writes a read-modify-write
+//! instruction makes only count once. It goes by the CPU cycle the bus passes with each write (see
+//! [`Mapper::cpu_write_on_cycle`]), a write made without one always counts.
+
+use super::{banked, last_bank, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_RAM : u16 = 0x6000;
+const PRG_RAM_END : u16 = 0x7FFF;
+const PRG_ROM : u16 = 0x8000;
+const PRG_ROM_BANK_SIZE : usize = 0x4000;
+const CHR_BANK_SIZE : usize = 0x1000;
+
+const SHIFT_RESET : u8 = 0b1000_0000;
+/// The control register after a reset: 16KiB PRG banks with the last one fixed at $C000.
+const CONTROL_RESET : u8 = 0b0_1100;
+const PRG_RAM_DISABLE : u8 = 0b1_0000;
+
+/// Mapper 1.
+pub struct Mmc1 {
+    pub(super) prg_rom : Vec<u8>,
+    prg_ram : Vec<u8>,
+    pub(super) chr : Chr,
+    shift : u8,
+    shift_count : u8,
+    control : u8,
+    pub(super) chr_bank_0 : u8,
+    chr_bank_1 : u8,
+    prg_bank : u8,
+    /// The CPU cycle of the last write to the serial port, if the bus said.
+    last_write : Option<u64>,
+}
+
+impl Mmc1 {
+    /// Builds the board for `rom`, with the registers in their power on state. Boards without CHR ROM get 8KiB of
+    /// CHR RAM.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Mmc1 {
+            prg_rom : rom.prg_rom,
+            prg_ram : vec![0 ; rom.prg_ram_size + rom.prg_nvram_size],
+            chr,
+            shift : 0,
+            shift_count : 0,
+            control : CONTROL_RESET,
+            chr_bank_0 : 0,
+            chr_bank_1 : 0,
+            prg_bank : 0,
+            last_write : None,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_bank & PRG_RAM_DISABLE == 0
+    }
+
+    /// The 16KiB PRG ROM bank mapped at `addr`.
+    pub(super) fn prg_rom_bank(&self, addr : u16) -> usize {
+        let bank = (self.prg_bank & 0x0f) as usize;
+        let upper = addr >= 0xc000;
+        match (self.control >> 2) & 0b11 {
+            // 32KiB mode, the low bit of the bank number is ignored.
+            0 | 1 => (bank & !1) | upper as usize,
+            2 => if upper { bank } else { 0 },
+            _ => if upper { last_bank(&self.prg_rom, PRG_ROM_BANK_SIZE, 0) } else { bank },
+        }
+    }
+
+    /// The 4KiB CHR bank mapped at `addr`.
+    fn chr_bank(&self, addr : u16) -> usize {
+        let upper = addr >= 0x1000;
+        if self.control & 0b1_0000 == 0 {
+            // 8KiB mode, the low bit of the bank number is ignored.
+            (self.chr_bank_0 & !1) as usize | upper as usize
+        } else if upper {
+            self.chr_bank_1 as usize
+        } else {
+            self.chr_bank_0 as usize
+        }
+    }
+
+    /// Writes `value` to `addr`, made on CPU cycle `cycle` if it is known.
+    fn write(&mut self, addr : u16, value : u8, cycle : Option<u64>) {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM) as usize % len] = value;
+            }
+            PRG_ROM ..= 0xffff => {
+                let consecutive = match (self.last_write, cycle) {
+                    (Some(last), Some(cycle)) => cycle.wrapping_sub(last) <= 1,
+                    _ => false,
+                };
+                self.last_write = cycle;
+                if !consecutive {
+                    self.write_serial(addr, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Shifts a bit into the shift register, loading the register at `addr` on the fifth write.
+    fn write_serial(&mut self, addr : u16, value : u8) {
+        if value & SHIFT_RESET != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= CONTROL_RESET;
+            return;
+        }
+        self.shift |= (value & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+        match addr {
+            0x8000 ..= 0x9fff => self.control = self.shift,
+            0xa000 ..= 0xbfff => self.chr_bank_0 = self.shift,
+            0xc000 ..= 0xdfff => self.chr_bank_1 = self.shift,
+            _ => self.prg_bank = self.shift,
+        }
+        self.shift = 0;
+        self.shift_count = 0;
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(addr - PRG_RAM) as usize % self.prg_ram.len()])
+            }
+            PRG_ROM ..= 0xffff => {
+                Some(self.prg_rom[banked(&self.prg_rom, PRG_ROM_BANK_SIZE, self.prg_rom_bank(addr), addr as usize)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        self.write(addr, value, None);
+    }
+
+    fn cpu_write_on_cycle(&mut self, addr : u16, value : u8, cycle : u64) {
+        self.write(addr, value, Some(cycle));
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[banked(&self.chr, CHR_BANK_SIZE, self.chr_bank(addr), addr as usize)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(banked(&self.chr, CHR_BANK_SIZE, self.chr_bank(addr), addr as usize), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    /// Version 2 leaves out the cycle count of version 1, the bus passes the cycle in.
+    fn state_version(&self) -> u16 {
+        2
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.prg_ram);
+        state.write(&self.chr);
+        state.write(&self.shift);
+        state.write(&self.shift_count);
+        state.write(&self.control);
+        state.write(&self.chr_bank_0);
+        state.write(&self.chr_bank_1);
+        state.write(&self.prg_bank);
+        state.write(&self.last_write);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.prg_ram)?;
+        state.read(&mut self.chr)?;
+        state.read(&mut self.shift)?;
+        state.read(&mut self.shift_count)?;
+        if self.shift_count >= 5 {
+            return Err(StateError::BadValue);
+        }
+        state.read(&mut self.control)?;
+        state.read(&mut self.chr_bank_0)?;
+        state.read(&mut self.chr_bank_1)?;
+        state.read(&mut self.prg_bank)?;
+        if state.version() < 2 {
+            // Version 1 counted the cycles itself: the count is dropped, and the last write with it.
+            let mut cycles = 0u64;
+            state.read(&mut cycles)?;
+            state.read(&mut self.last_write)?;
+            self.last_write = None;
+            return Ok(());
+        }
+        state.read(&mut self.last_write)
+    }
+}
@@ -0,0 +1,148 @@
+//! # MMC2 Module
+//!
+//! `mmc2` is mapper 9, the PxROM board made for Punch-Out!!. An 8KiB PRG ROM bank is switchable at $8000, the
+//! last three are fixed at $A000-$FFFF. Each 4KiB half of the pattern tables has two CHR banks and a latch that
+//! picks between them, flipped by the PPU itself: fetching the tile at $xFD8 selects the FD bank and $xFE8 the FE
+//! bank. Games put those tiles at the edges of big sprites and backgrounds to switch banks part way down the screen
+//! without using the CPU.
+//!
+//! | Address     | Register                      |
+//! |-------------|-------------------------------|
+//! | $A000-$AFFF | PRG ROM bank at $8000         |
+//! | $B000-$BFFF | CHR bank at $0000, latch FD   |
+//! | $C000-$CFFF | CHR bank at $0000, latch FE   |
+//! | $D000-$DFFF | CHR bank at $1000, latch FD   |
+//! | $E000-$EFFF | CHR bank at $1000, latch FE   |
+//! | $F000-$FFFF | Mirroring                     |
+
+use super::{banked, last_bank, Chr, Mapper};
+use super::state::{StateError, StateReader, StateValue, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const PRG_BANK_SIZE : usize = 0x2000;
+const CHR_BANK_SIZE : usize = 0x1000;
+
+/// Which of its two CHR banks a half of the pattern tables uses, named after the tile that selects it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Latch {
+    Fd,
+    Fe,
+}
+
+impl StateValue for Latch {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&(*self == Latch::Fe));
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut fe = false;
+        state.read(&mut fe)?;
+        *self = if fe { Latch::Fe } else { Latch::Fd };
+        Ok(())
+    }
+}
+
+/// Mapper 9.
+pub struct Mmc2 {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    prg_bank : u8,
+    /// The FD and FE banks of each half of the pattern tables.
+    chr_banks : [[u8 ; 2] ; 2],
+    latches : [Latch ; 2],
+    mirroring : Mirroring,
+}
+
+impl Mmc2 {
+    /// Builds the board for `rom`, with both latches on their FE bank.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Mmc2 {
+            prg_rom : rom.prg_rom,
+            chr,
+            prg_bank : 0,
+            chr_banks : [[0 ; 2] ; 2],
+            latches : [Latch::Fe ; 2],
+            mirroring : rom.mirroring,
+        }
+    }
+
+    /// The index into CHR of `addr`, through the bank its half's latch selects.
+    fn chr_index(&self, addr : u16) -> usize {
+        let half = (addr as usize / CHR_BANK_SIZE) & 1;
+        let bank = self.chr_banks[half][self.latches[half] as usize];
+        banked(&self.chr, CHR_BANK_SIZE, bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let bank = match addr {
+            0x8000 ..= 0x9fff => (self.prg_bank & 0x0f) as usize,
+            0xa000 ..= 0xbfff => last_bank(&self.prg_rom, PRG_BANK_SIZE, 2),
+            0xc000 ..= 0xdfff => last_bank(&self.prg_rom, PRG_BANK_SIZE, 1),
+            _ => last_bank(&self.prg_rom, PRG_BANK_SIZE, 0),
+        };
+        Some(self.prg_rom[banked(&self.prg_rom, PRG_BANK_SIZE, bank, addr as usize)])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        let bank = value & 0x1f;
+        match addr {
+            0xa000 ..= 0xafff => self.prg_bank = value,
+            0xb000 ..= 0xbfff => self.chr_banks[0][0] = bank,
+            0xc000 ..= 0xcfff => self.chr_banks[0][1] = bank,
+            0xd000 ..= 0xdfff => self.chr_banks[1][0] = bank,
+            0xe000 ..= 0xefff => self.chr_banks[1][1] = bank,
+            0xf000 ..= 0xffff => {
+                self.mirroring = if value & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            _ => {}
+        }
+    }
+
+    /// The latches switch after the fetch, so the tile that flips one is still drawn from the old bank.
+    fn ppu_read(&mut self, addr : u16) -> u8 {
+        let value = self.ppu_peek(addr);
+        match addr {
+            0x0fd8 => self.latches[0] = Latch::Fd,
+            0x0fe8 => self.latches[0] = Latch::Fe,
+            0x1fd8 ..= 0x1fdf => self.latches[1] = Latch::Fd,
+            0x1fe8 ..= 0x1fef => self.latches[1] = Latch::Fe,
+            _ => {}
+        }
+        value
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.prg_bank);
+        state.write(&self.chr_banks);
+        state.write(&self.latches);
+        state.write(&self.mirroring);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.prg_bank)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.latches)?;
+        state.read(&mut self.mirroring)
+    }
+}
@@ -0,0 +1,245 @@
+//! # MMC3 Module
+//!
+//! `mmc3` is mapper 4, Nintendo's TxROM boards (Super Mario Bros. 3, Kirby's Adventure, Mega Man 3-6). The MMC3
+//! has eight bank registers, written by picking one at $8000 and then writing its value to $8001:
+//!
+//! | Register | Bank                                                                         |
+//! |----------|------------------------------------------------------------------------------|
+//! | R0, R1   | 2KiB of CHR at $0000 and $0800 (or $1000 and $1800 with CHR A12 inversion)   |
+//! | R2-R5    | 1KiB of CHR at $1000-$1C00 (or $0000-$0C00 with CHR A12 inversion)          |
+//! | R6       | 8KiB of PRG ROM at $8000 (or $C000 in PRG mode 1)                           |
+//! | R7       | 8KiB of PRG ROM at $A000                                                    |
+//!
+//! The second to last PRG bank sits in whichever of $8000 and $C000 R6 is not using, and the last is fixed at
+//! $E000. The other registers, paired by address (even, odd):
+//!
+//! | Address     | Even                           | Odd                              |
+//! |-------------|--------------------------------|----------------------------------|
+//! | $8000-$9FFF | Bank select, PRG and CHR modes | Bank data                        |
+//! | $A000-$BFFF | Mirroring                      | PRG RAM enable and write protect |
+//! | $C000-$DFFF | IRQ latch                      | IRQ reload                       |
+//! | $E000-$FFFF | IRQ disable and acknowledge    | IRQ enable                       |
+//!
+//! The IRQ counter is clocked by rising edges of PPU address line A12. With backgrounds using the pattern table
+//! at $0000 and sprites the one at $1000, that happens once per scanline, which games use to split the screen.
+
+use super::{banked, last_bank, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_RAM : u16 = 0x6000;
+const PRG_RAM_END : u16 = 0x7FFF;
+const PRG_BANK_SIZE : usize = 0x2000;
+const CHR_BANK_SIZE : usize = 0x0400;
+
+const PRG_MODE : u8 = 0b0100_0000;
+const CHR_INVERSION : u8 = 0b1000_0000;
+const PRG_RAM_ENABLE : u8 = 0b1000_0000;
+const PRG_RAM_WRITE_PROTECT : u8 = 0b0100_0000;
+const A12 : u16 = 0x1000;
+
+/// Mapper 4.
+pub struct Mmc3 {
+    prg_rom : Vec<u8>,
+    prg_ram : Vec<u8>,
+    chr : Chr,
+    four_screen : bool,
+    bank_select : u8,
+    registers : [u8 ; 8],
+    mirroring : Mirroring,
+    prg_ram_protect : u8,
+    irq_latch : u8,
+    irq_counter : u8,
+    irq_reload : bool,
+    irq_enabled : bool,
+    irq_pending : bool,
+    a12 : bool,
+}
+
+impl Mmc3 {
+    /// Builds the board for `rom`. Boards without CHR ROM get 8KiB of CHR RAM, and the PRG RAM starts out
+    /// enabled.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        let four_screen = rom.mirroring == Mirroring::FourScreen;
+        Mmc3 {
+            prg_rom : rom.prg_rom,
+            prg_ram : vec![0 ; rom.prg_ram_size + rom.prg_nvram_size],
+            chr,
+            four_screen,
+            bank_select : 0,
+            registers : [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring : rom.mirroring,
+            prg_ram_protect : PRG_RAM_ENABLE,
+            irq_latch : 0,
+            irq_counter : 0,
+            irq_reload : false,
+            irq_enabled : false,
+            irq_pending : false,
+            a12 : false,
+        }
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_ram_protect & PRG_RAM_ENABLE != 0
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_readable() && self.prg_ram_protect & PRG_RAM_WRITE_PROTECT == 0
+    }
+
+    /// The 8KiB PRG ROM bank mapped at `addr`.
+    fn prg_bank(&self, addr : u16) -> usize {
+        let swapped = self.bank_select & PRG_MODE != 0;
+        match (addr >> 13) & 0b11 {
+            0 if swapped => last_bank(&self.prg_rom, PRG_BANK_SIZE, 1),
+            0 => self.registers[6] as usize,
+            1 => self.registers[7] as usize,
+            2 if swapped => self.registers[6] as usize,
+            2 => last_bank(&self.prg_rom, PRG_BANK_SIZE, 1),
+            _ => last_bank(&self.prg_rom, PRG_BANK_SIZE, 0),
+        }
+    }
+
+    /// The 1KiB CHR bank mapped at `addr`.
+    fn chr_bank(&self, addr : u16) -> usize {
+        let mut slot = (addr as usize / CHR_BANK_SIZE) & 0b111;
+        if self.bank_select & CHR_INVERSION != 0 {
+            slot ^= 0b100;
+        }
+        match slot {
+            // The 2KiB banks ignore the low bit of the register.
+            0 | 1 => (self.registers[0] & !1) as usize | slot,
+            2 | 3 => (self.registers[1] & !1) as usize | (slot & 1),
+            _ => self.registers[slot - 2] as usize,
+        }
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        banked(&self.chr, CHR_BANK_SIZE, self.chr_bank(addr), addr as usize)
+    }
+
+    /// Watches PPU address line A12, clocking the IRQ counter when it rises.
+    fn watch_a12(&mut self, addr : u16) {
+        let a12 = addr & A12 != 0;
+        if a12 && !self.a12 {
+            self.clock_irq_counter();
+        }
+        self.a12 = a12;
+    }
+
+    /// The counter is reloaded from the latch when it is zero (or a reload was asked for) and decremented
+    /// otherwise. Reaching zero, including by a reload with a latch of zero, raises the IRQ if it is enabled.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_readable() => {
+                Some(self.prg_ram[(addr - PRG_RAM) as usize % self.prg_ram.len()])
+            }
+            0x8000 ..= 0xffff => {
+                Some(self.prg_rom[banked(&self.prg_rom, PRG_BANK_SIZE, self.prg_bank(addr), addr as usize)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_writable() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM) as usize % len] = value;
+            }
+            0x8000 ..= 0x9fff if even => self.bank_select = value,
+            0x8000 ..= 0x9fff => self.registers[(self.bank_select & 0b111) as usize] = value,
+            0xa000 ..= 0xbfff if !even => self.prg_ram_protect = value,
+            0xa000 ..= 0xbfff if !self.four_screen => {
+                self.mirroring = if value & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            0xc000 ..= 0xdfff if even => self.irq_latch = value,
+            0xc000 ..= 0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xe000 ..= 0xffff if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xe000 ..= 0xffff => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr : u16) -> u8 {
+        self.watch_a12(addr);
+        self.ppu_peek(addr)
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.watch_a12(addr);
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.prg_ram);
+        state.write(&self.chr);
+        state.write(&self.bank_select);
+        state.write(&self.registers);
+        state.write(&self.mirroring);
+        state.write(&self.prg_ram_protect);
+        state.write(&self.irq_latch);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_reload);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.a12);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.prg_ram)?;
+        state.read(&mut self.chr)?;
+        state.read(&mut self.bank_select)?;
+        state.read(&mut self.registers)?;
+        state.read(&mut self.mirroring)?;
+        state.read(&mut self.prg_ram_protect)?;
+        state.read(&mut self.irq_latch)?;
+        state.read(&mut self.irq_counter)?;
+        state.read(&mut self.irq_reload)?;
+        state.read(&mut self.irq_enabled)?;
+        state.read(&mut self.irq_pending)?;
+        state.read(&mut self.a12)
+    }
+}
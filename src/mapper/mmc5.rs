@@ -0,0 +1,797 @@
+//! # MMC5 Module
+//!
+//! `mmc5` is mapper 5, Nintendo's ExROM boards (Castlevania III, Just Breed, Laser Invasion). The MMC5 is the most
+//! capable board Nintendo made:
+//!
+//! * PRG ROM and RAM in 8KiB to 32KiB banks, CHR ROM in 1KiB to 8KiB banks, with separate CHR banks for the
+//!   background and 8x16 sprites.
+//! * 1KiB of extra RAM (ExRAM) that can be a third nametable, a per tile attribute and CHR bank table
+//!   (extended attribute mode), or plain work RAM.
+//! * Each nametable slot can come from either VRAM page, ExRAM, or a fill tile.
+//! * A vertical split: tiles left or right of a column come from ExRAM with their own vertical scroll.
+//! * A scanline IRQ, an 8x8 bit multiplier, and two pulse channels plus an 8 bit PCM channel.
+//!
+//! The MMC5 cannot see the PPU's registers or timing, it works out where the PPU is from its memory fetches. A
+//! rendered scanline ends with the PPU fetching the same nametable byte twice in a row, which is how scanlines are
+//! counted. Between those, 32 background tiles come first (nametable, attribute and two pattern fetches each),
+//! then the eight sprites, then the first two tiles of the next line.
+//!
+//! | Address     | Register                                                                   |
+//! |-------------|----------------------------------------------------------------------------|
+//! | $5000-$5007 | Pulse channels 1 and 2, as the APU's without the sweep                     |
+//! | $5010       | PCM mode and IRQ enable, reads the PCM IRQ flag                            |
+//! | $5011       | PCM sample                                                                 |
+//! | $5015       | Pulse channel enable, reads their length counter status                    |
+//! | $5100       | PRG banking mode                                                           |
+//! | $5101       | CHR banking mode                                                           |
+//! | $5102-$5103 | PRG RAM write protect, writable only with $02 and $01 written             |
+//! | $5104       | ExRAM mode                                                                 |
+//! | $5105       | The source of each nametable slot, two bits each                           |
+//! | $5106-$5107 | Fill mode tile and attribute                                               |
+//! | $5113-$5117 | PRG banks: RAM at $6000, then ROM or RAM at $8000-$E000                    |
+//! | $5120-$512B | CHR banks: eight for sprites (and 8x8 backgrounds), four for backgrounds   |
+//! | $5130       | Upper bits of the CHR banks                                                |
+//! | $5200-$5202 | Vertical split control, scroll and CHR bank                               |
+//! | $5203-$5204 | Scanline IRQ compare and status                                            |
+//! | $5205-$5206 | Multiplier                                                                 |
+//! | $5C00-$5FFF | ExRAM                                                                      |
+
+use super::{Chr, Mapper};
+use super::state::{StateError, StateReader, StateValue, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_RAM : u16 = 0x6000;
+const PRG_ROM : u16 = 0x8000;
+const EXRAM : u16 = 0x5c00;
+const EXRAM_END : u16 = 0x5fff;
+const EXRAM_SIZE : usize = 0x400;
+const PRG_BANK_SIZE : usize = 0x2000;
+const ATTRIBUTES : usize = 0x3c0;
+
+/// Pattern fetches in a scanline before the sprites are fetched: two for each of 32 background tiles.
+const BACKGROUND_FETCHES : u32 = 64;
+/// Pattern fetches up to the end of the sprites: two for each of 8.
+const SPRITE_FETCHES_END : u32 = 80;
+/// CPU cycles without a PPU fetch after which the MMC5 decides the PPU has stopped rendering.
+const IDLE_CYCLES : u64 = 3;
+/// The pulse channels' envelopes and length counters are clocked at about 240Hz.
+const FRAME_CYCLES : u64 = 7457;
+
+const LENGTH_TABLE : [u8 ; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26,
+    16, 28, 32, 30,
+];
+
+const DUTY_CYCLES : [[u8 ; 8] ; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// One of the MMC5's pulse channels, the same as the APU's pulse channels apart from having no sweep unit.
+#[derive(Debug, Clone, Default)]
+struct Pulse {
+    enabled : bool,
+    duty : u8,
+    halt : bool,
+    constant_volume : bool,
+    volume : u8,
+    timer_period : u16,
+    timer : u16,
+    step : u8,
+    length : u8,
+    envelope_start : bool,
+    envelope_divider : u8,
+    envelope_decay : u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register : u16, value : u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.halt = value & 0x20 != 0;
+                self.constant_volume = value & 0x10 != 0;
+                self.volume = value & 0x0f;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled : bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    /// Clocks the timer, once every other CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the envelope and the length counter.
+    fn clock_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else if self.constant_volume {
+            self.volume
+        } else {
+            self.envelope_decay
+        }
+    }
+}
+
+impl StateValue for Pulse {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.enabled);
+        state.write(&self.duty);
+        state.write(&self.halt);
+        state.write(&self.constant_volume);
+        state.write(&self.volume);
+        state.write(&self.timer_period);
+        state.write(&self.timer);
+        state.write(&self.step);
+        state.write(&self.length);
+        state.write(&self.envelope_start);
+        state.write(&self.envelope_divider);
+        state.write(&self.envelope_decay);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.enabled)?;
+        state.read(&mut self.duty)?;
+        state.read(&mut self.halt)?;
+        state.read(&mut self.constant_volume)?;
+        state.read(&mut self.volume)?;
+        state.read(&mut self.timer_period)?;
+        state.read(&mut self.timer)?;
+        state.read(&mut self.step)?;
+        state.read(&mut self.length)?;
+        state.read(&mut self.envelope_start)?;
+        state.read(&mut self.envelope_divider)?;
+        state.read(&mut self.envelope_decay)?;
+        if self.duty >= 4 || self.step >= 8 {
+            return Err(StateError::BadValue);
+        }
+        Ok(())
+    }
+}
+
+/// Where a PRG address is mapped.
+enum Prg {
+    Rom(usize),
+    Ram(usize),
+}
+
+/// How the background tile being fetched is drawn, decided at its nametable fetch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tile {
+    Normal,
+    /// In the split region: tile and attribute from ExRAM, for this column and scrolled line.
+    Split { column : u32, y : u16 },
+    /// Extended attribute mode: the ExRAM byte for the tile gives its CHR bank and palette.
+    Extended(u8),
+}
+
+impl StateValue for Tile {
+    fn save(&self, state : &mut StateWriter) {
+        match *self {
+            Tile::Normal => state.write(&0u8),
+            Tile::Split { column, y } => {
+                state.write(&1u8);
+                state.write(&column);
+                state.write(&y);
+            }
+            Tile::Extended(exram) => {
+                state.write(&2u8);
+                state.write(&exram);
+            }
+        }
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut kind = 0u8;
+        state.read(&mut kind)?;
+        *self = match kind {
+            0 => Tile::Normal,
+            1 => {
+                let (mut column, mut y) = (0, 0);
+                state.read(&mut column)?;
+                state.read(&mut y)?;
+                Tile::Split { column, y }
+            }
+            2 => {
+                let mut exram = 0;
+                state.read(&mut exram)?;
+                Tile::Extended(exram)
+            }
+            _ => return Err(StateError::BadValue),
+        };
+        Ok(())
+    }
+}
+
+/// Mapper 5.
+pub struct Mmc5 {
+    prg_rom : Vec<u8>,
+    prg_ram : Vec<u8>,
+    chr : Chr,
+    exram : [u8 ; EXRAM_SIZE],
+    prg_mode : u8,
+    chr_mode : u8,
+    prg_ram_protect : [u8 ; 2],
+    exram_mode : u8,
+    nametables : u8,
+    fill_tile : u8,
+    fill_attribute : u8,
+    /// $5113-$5117.
+    prg_banks : [u8 ; 5],
+    /// $5120-$512B, with the upper bits from $5130 at the time of the write.
+    chr_banks : [u16 ; 12],
+    chr_upper : u8,
+    /// Whether $5128-$512B were written after $5120-$5127, which decides the set used outside 8x16 sprites.
+    background_set_last : bool,
+    split_control : u8,
+    split_scroll : u8,
+    split_bank : u8,
+    irq_compare : u8,
+    irq_enabled : bool,
+    irq_pending : bool,
+    multiplicand : u8,
+    multiplier : u8,
+    sprites_8x16 : bool,
+
+    in_frame : bool,
+    scanline : u8,
+    last_nametable_read : Option<u16>,
+    pattern_fetches : u32,
+    tile_fetches : u32,
+    tile : Tile,
+    fetched_since_tick : bool,
+    idle_cycles : u64,
+
+    pulses : [Pulse ; 2],
+    pcm_read_mode : bool,
+    pcm_irq_enabled : bool,
+    pcm_irq_pending : bool,
+    pcm : u8,
+    cycles : u64,
+}
+
+impl Mmc5 {
+    /// Builds the board for `rom`, in PRG mode 3 with the last bank at $E000 as the MMC5 powers on.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Mmc5 {
+            prg_rom : rom.prg_rom,
+            prg_ram : vec![0 ; rom.prg_ram_size + rom.prg_nvram_size],
+            chr,
+            exram : [0 ; EXRAM_SIZE],
+            prg_mode : 3,
+            chr_mode : 0,
+            prg_ram_protect : [0 ; 2],
+            exram_mode : 0,
+            nametables : 0,
+            fill_tile : 0,
+            fill_attribute : 0,
+            prg_banks : [0, 0, 0, 0, 0xff],
+            chr_banks : [0 ; 12],
+            chr_upper : 0,
+            background_set_last : false,
+            split_control : 0,
+            split_scroll : 0,
+            split_bank : 0,
+            irq_compare : 0,
+            irq_enabled : false,
+            irq_pending : false,
+            multiplicand : 0xff,
+            multiplier : 0xff,
+            sprites_8x16 : false,
+            in_frame : false,
+            scanline : 0,
+            last_nametable_read : None,
+            pattern_fetches : 0,
+            tile_fetches : 0,
+            tile : Tile::Normal,
+            fetched_since_tick : false,
+            idle_cycles : 0,
+            pulses : [Pulse::default(), Pulse::default()],
+            pcm_read_mode : false,
+            pcm_irq_enabled : false,
+            pcm_irq_pending : false,
+            pcm : 0,
+            cycles : 0,
+        }
+    }
+
+    /// Where the PRG address `addr` ($6000-$FFFF) is mapped in the current banking mode.
+    fn prg_target(&self, addr : u16) -> Prg {
+        let (register, size) = match (self.prg_mode, addr) {
+            (_, PRG_RAM ..= 0x7fff) => (0, 0x2000),
+            (0, _) => (4, 0x8000),
+            (1, 0x8000 ..= 0xbfff) | (2, 0x8000 ..= 0xbfff) => (2, 0x4000),
+            (1, _) => (4, 0x4000),
+            (2, 0xc000 ..= 0xdfff) => (3, 0x2000),
+            (2, _) => (4, 0x2000),
+            _ => (1 + (addr as usize - PRG_ROM as usize) / PRG_BANK_SIZE, 0x2000),
+        };
+        let value = self.prg_banks[register];
+        // Larger banks ignore the low bits of the 8KiB bank number.
+        let bank = (value & 0x7f) as usize & !(size / PRG_BANK_SIZE - 1);
+        let offset = bank * PRG_BANK_SIZE + (addr as usize & (size - 1));
+        if register == 4 || (register > 0 && value & 0x80 != 0) {
+            Prg::Rom(offset % self.prg_rom.len())
+        } else {
+            Prg::Ram(offset)
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    /// Whether the PPU is fetching sprite patterns rather than background ones.
+    fn fetching_sprites(&self) -> bool {
+        self.in_frame && (BACKGROUND_FETCHES .. SPRITE_FETCHES_END).contains(&self.pattern_fetches)
+    }
+
+    /// Whether the background CHR banks ($5128-$512B) are used for a pattern fetch now.
+    fn background_set(&self) -> bool {
+        if self.sprites_8x16 && self.in_frame {
+            !self.fetching_sprites()
+        } else {
+            self.background_set_last
+        }
+    }
+
+    /// The index into CHR of pattern address `addr` in the current banking mode.
+    fn chr_index(&self, addr : u16) -> usize {
+        let size = 0x2000 >> self.chr_mode;
+        let units = size / 0x400;
+        let slot = (addr as usize & 0x1fff) / size;
+        // Each bank is set by the last register of its group, the background set repeats in both halves.
+        let register = if self.background_set() { 8 + (slot * units + units - 1) % 4 } else { (slot + 1) * units - 1 };
+        (self.chr_banks[register] as usize * size + addr as usize % size) % self.chr.len()
+    }
+
+    /// The column of the background tile the PPU is fetching. The first two tiles of a line are fetched at the
+    /// end of the one before.
+    fn tile_column(&self) -> u32 {
+        if self.tile_fetches < 32 {
+            self.tile_fetches + 2
+        } else {
+            self.tile_fetches - 32
+        }
+    }
+
+    /// Whether the tile in `column` falls in the split region.
+    fn in_split(&self, column : u32) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let split_tile = (self.split_control & 0x1f) as u32;
+        let right = self.split_control & 0x40 != 0;
+        if right {
+            column % 32 >= split_tile
+        } else {
+            column % 32 < split_tile
+        }
+    }
+
+    /// The scanline the tile being fetched is drawn on.
+    fn tile_line(&self) -> u16 {
+        if self.tile_fetches < 32 {
+            self.scanline as u16
+        } else {
+            self.scanline as u16 + 1
+        }
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.irq_compare != 0 && self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.pattern_fetches = 0;
+        self.tile_fetches = 0;
+        self.tile = Tile::Normal;
+    }
+
+    fn leave_frame(&mut self) {
+        self.in_frame = false;
+        self.last_nametable_read = None;
+    }
+
+    /// The nametable byte the slot mapping puts at `addr`, `None` for the VRAM pages.
+    fn mapped_nametable(&self, addr : u16) -> Option<u8> {
+        let offset = addr as usize & 0x3ff;
+        match self.nametable_source(addr) {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset]),
+            2 => Some(0),
+            3 if offset < ATTRIBUTES => Some(self.fill_tile),
+            3 => Some((self.fill_attribute & 0b11) * 0x55),
+            _ => None,
+        }
+    }
+
+    fn nametable_source(&self, addr : u16) -> u8 {
+        let slot = (addr as usize & 0x0fff) / 0x400;
+        (self.nametables >> (slot * 2)) & 0b11
+    }
+
+    fn write_register(&mut self, addr : u16, value : u8) {
+        match addr {
+            0x5000 ..= 0x5003 => self.pulses[0].write(addr - 0x5000, value),
+            0x5004 ..= 0x5007 => self.pulses[1].write(addr - 0x5004, value),
+            0x5010 => {
+                self.pcm_read_mode = value & 0x01 != 0;
+                self.pcm_irq_enabled = value & 0x80 != 0;
+            }
+            0x5011 if !self.pcm_read_mode => self.write_pcm(value),
+            0x5015 => {
+                self.pulses[0].set_enabled(value & 0x01 != 0);
+                self.pulses[1].set_enabled(value & 0x02 != 0);
+            }
+            0x5100 => self.prg_mode = value & 0b11,
+            0x5101 => self.chr_mode = value & 0b11,
+            0x5102 => self.prg_ram_protect[0] = value & 0b11,
+            0x5103 => self.prg_ram_protect[1] = value & 0b11,
+            0x5104 => self.exram_mode = value & 0b11,
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0b11,
+            0x5113 ..= 0x5117 => self.prg_banks[(addr - 0x5113) as usize] = value,
+            0x5120 ..= 0x512b => {
+                let register = (addr - 0x5120) as usize;
+                self.chr_banks[register] = value as u16 | (self.chr_upper as u16) << 8;
+                self.background_set_last = register >= 8;
+            }
+            0x5130 => self.chr_upper = value & 0b11,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            EXRAM ..= EXRAM_END if self.exram_mode != 3 => self.exram[(addr - EXRAM) as usize] = value,
+            _ => {}
+        }
+    }
+
+    /// A PCM sample of zero does not play, it raises the PCM IRQ instead.
+    fn write_pcm(&mut self, value : u8) {
+        if value == 0 {
+            self.pcm_irq_pending = true;
+        } else {
+            self.pcm = value;
+        }
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, addr : u16) -> Option<u8> {
+        let value = self.cpu_peek(addr);
+        match (addr, value) {
+            (0x5010, _) => self.pcm_irq_pending = false,
+            (0x5204, _) => self.irq_pending = false,
+            (0x8000 ..= 0xbfff, Some(value)) if self.pcm_read_mode => self.write_pcm(value),
+            _ => {}
+        }
+        value
+    }
+
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        let value = match addr {
+            0x5010 => (self.pcm_irq_pending as u8) << 7 | self.pcm_read_mode as u8,
+            0x5015 => (self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1,
+            0x5204 => (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6,
+            0x5205 => self.product() as u8,
+            0x5206 => (self.product() >> 8) as u8,
+            // In the nametable modes ExRAM belongs to the PPU.
+            EXRAM ..= EXRAM_END if self.exram_mode >= 2 => self.exram[(addr - EXRAM) as usize],
+            PRG_RAM ..= 0xffff => match self.prg_target(addr) {
+                Prg::Rom(index) => self.prg_rom[index],
+                Prg::Ram(_) if self.prg_ram.is_empty() => return None,
+                Prg::Ram(index) => self.prg_ram[index % self.prg_ram.len()],
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        match addr {
+            PRG_RAM ..= 0xffff => {
+                if let Prg::Ram(index) = self.prg_target(addr) {
+                    if self.prg_ram_writable() {
+                        let len = self.prg_ram.len();
+                        self.prg_ram[index % len] = value;
+                    }
+                }
+            }
+            _ => self.write_register(addr, value),
+        }
+    }
+
+    fn ppu_read(&mut self, addr : u16) -> u8 {
+        let value = self.ppu_peek(addr);
+        self.last_nametable_read = None;
+        self.fetched_since_tick = true;
+        if self.in_frame {
+            self.pattern_fetches += 1;
+        }
+        value
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        let background = self.in_frame && !self.fetching_sprites();
+        match self.tile {
+            Tile::Split { y, .. } if background => {
+                let index = self.split_bank as usize * 0x1000 + ((addr as usize & 0x0ff8) | (y % 8) as usize);
+                self.chr[index % self.chr.len()]
+            }
+            Tile::Extended(attribute) if background => {
+                let bank = (attribute & 0x3f) as usize | (self.chr_upper as usize) << 6;
+                self.chr[(bank * 0x1000 + (addr as usize & 0x0fff)) % self.chr.len()]
+            }
+            _ => self.chr[self.chr_index(addr)],
+        }
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // The VRAM pages of the four slots, ExRAM and fill slots matching anything.
+        let pages = [0, 1, 2, 3].map(|slot| match (self.nametables >> (slot * 2)) & 0b11 {
+            page @ (0 | 1) => Some(page),
+            _ => None,
+        });
+        let matches = |layout : [u8 ; 4]| pages.iter().zip(layout).all(|(page, want)| page.is_none_or(|p| p == want));
+        if matches([0, 1, 0, 1]) {
+            Mirroring::Vertical
+        } else if matches([0, 0, 1, 1]) {
+            Mirroring::Horizontal
+        } else if matches([0, 0, 0, 0]) {
+            Mirroring::SingleScreenLower
+        } else if matches([1, 1, 1, 1]) {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn nametable_read(&mut self, addr : u16) -> Option<u8> {
+        self.fetched_since_tick = true;
+        if self.last_nametable_read == Some(addr) {
+            self.last_nametable_read = None;
+            self.detect_scanline();
+            return self.mapped_nametable(addr);
+        }
+        self.last_nametable_read = Some(addr);
+        if !self.in_frame || self.fetching_sprites() {
+            return self.mapped_nametable(addr);
+        }
+
+        let offset = addr as usize & 0x3ff;
+        if offset < ATTRIBUTES {
+            let column = self.tile_column();
+            let y = (self.split_scroll as u16 + self.tile_line()) % 240;
+            self.tile_fetches += 1;
+            if self.in_split(column) {
+                self.tile = Tile::Split { column, y };
+                return Some(self.exram[(y / 8) as usize * 32 + column as usize % 32]);
+            }
+            self.tile = if self.exram_mode == 1 { Tile::Extended(self.exram[offset]) } else { Tile::Normal };
+            return self.mapped_nametable(addr);
+        }
+
+        match self.tile {
+            Tile::Split { column, y } => {
+                let (row, column) = ((y / 8) as usize, column as usize % 32);
+                let attribute = self.exram[ATTRIBUTES + (row / 4) * 8 + column / 4];
+                let shift = ((row & 2) << 1) | (column & 2);
+                Some(((attribute >> shift) & 0b11) * 0x55)
+            }
+            Tile::Extended(attribute) => Some((attribute >> 6) * 0x55),
+            Tile::Normal => self.mapped_nametable(addr),
+        }
+    }
+
+    fn nametable_peek(&self, addr : u16) -> Option<u8> {
+        self.mapped_nametable(addr)
+    }
+
+    fn nametable_write(&mut self, addr : u16, value : u8) -> bool {
+        match self.nametable_source(addr) {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[addr as usize & 0x3ff] = value;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn snoop_cpu_write(&mut self, addr : u16, value : u8) {
+        match addr {
+            0x2000 => self.sprites_8x16 = value & 0x20 != 0,
+            0x2001 if value & 0x18 == 0 => self.leave_frame(),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cpu_cycles : u64) {
+        if self.fetched_since_tick {
+            self.idle_cycles = 0;
+        } else {
+            self.idle_cycles += cpu_cycles;
+            if self.idle_cycles >= IDLE_CYCLES {
+                self.leave_frame();
+            }
+        }
+        self.fetched_since_tick = false;
+
+        for _ in 0 .. cpu_cycles {
+            self.cycles += 1;
+            if self.cycles.is_multiple_of(2) {
+                self.pulses.iter_mut().for_each(Pulse::clock_timer);
+            }
+            if self.cycles.is_multiple_of(FRAME_CYCLES) {
+                self.pulses.iter_mut().for_each(Pulse::clock_frame);
+            }
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq_pending && self.pcm_irq_enabled)
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    /// The pulse channels are mixed like the APU's. The PCM channel comes in at about the level of the APU's
+    /// DMC at full scale.
+    fn audio_sample(&self) -> f32 {
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+        pulse_out + self.pcm as f32 / 255.0 * 0.57
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.prg_ram);
+        state.write(&self.chr);
+        state.write(&self.exram);
+        state.write(&self.prg_mode);
+        state.write(&self.chr_mode);
+        state.write(&self.prg_ram_protect);
+        state.write(&self.exram_mode);
+        state.write(&self.nametables);
+        state.write(&self.fill_tile);
+        state.write(&self.fill_attribute);
+        state.write(&self.prg_banks);
+        state.write(&self.chr_banks);
+        state.write(&self.chr_upper);
+        state.write(&self.background_set_last);
+        state.write(&self.split_control);
+        state.write(&self.split_scroll);
+        state.write(&self.split_bank);
+        state.write(&self.irq_compare);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.multiplicand);
+        state.write(&self.multiplier);
+        state.write(&self.sprites_8x16);
+        state.write(&self.in_frame);
+        state.write(&self.scanline);
+        state.write(&self.last_nametable_read);
+        state.write(&self.pattern_fetches);
+        state.write(&self.tile_fetches);
+        state.write(&self.tile);
+        state.write(&self.fetched_since_tick);
+        state.write(&self.idle_cycles);
+        state.write(&self.pulses);
+        state.write(&self.pcm_read_mode);
+        state.write(&self.pcm_irq_enabled);
+        state.write(&self.pcm_irq_pending);
+        state.write(&self.pcm);
+        state.write(&self.cycles);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.prg_ram)?;
+        state.read(&mut self.chr)?;
+        state.read(&mut self.exram)?;
+        state.read(&mut self.prg_mode)?;
+        state.read(&mut self.chr_mode)?;
+        if self.chr_mode >= 4 {
+            return Err(StateError::BadValue);
+        }
+        state.read(&mut self.prg_ram_protect)?;
+        state.read(&mut self.exram_mode)?;
+        state.read(&mut self.nametables)?;
+        state.read(&mut self.fill_tile)?;
+        state.read(&mut self.fill_attribute)?;
+        state.read(&mut self.prg_banks)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.chr_upper)?;
+        state.read(&mut self.background_set_last)?;
+        state.read(&mut self.split_control)?;
+        state.read(&mut self.split_scroll)?;
+        state.read(&mut self.split_bank)?;
+        state.read(&mut self.irq_compare)?;
+        state.read(&mut self.irq_enabled)?;
+        state.read(&mut self.irq_pending)?;
+        state.read(&mut self.multiplicand)?;
+        state.read(&mut self.multiplier)?;
+        state.read(&mut self.sprites_8x16)?;
+        state.read(&mut self.in_frame)?;
+        state.read(&mut self.scanline)?;
+        state.read(&mut self.last_nametable_read)?;
+        state.read(&mut self.pattern_fetches)?;
+        state.read(&mut self.tile_fetches)?;
+        state.read(&mut self.tile)?;
+        state.read(&mut self.fetched_since_tick)?;
+        state.read(&mut self.idle_cycles)?;
+        state.read(&mut self.pulses)?;
+        state.read(&mut self.pcm_read_mode)?;
+        state.read(&mut self.pcm_irq_enabled)?;
+        state.read(&mut self.pcm_irq_pending)?;
+        state.read(&mut self.pcm)?;
+        state.read(&mut self.cycles)
+    }
+}
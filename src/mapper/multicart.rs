@@ -0,0 +1,178 @@
+//! # Multicart Module
+//!
+//! `multicart` is the simple multicarts, one cart holding many NROM or CNROM sized games and a menu to pick one.
+//! Their bank register is a latch of the address written to in $8000-$FFFF, the value written does not matter.
+//! The latch is cleared when the console is reset, which brings back the menu in the first bank.
+//!
+//! | Mapper | Board                                   | Latch (address bits)                                   |
+//! |--------|-----------------------------------------|--------------------------------------------------------|
+//! | 58     | GK-192, Study and Game 32-in-1, 68-in-1 | `MOCC CPPP`                                            |
+//! | 225    | ET-4310, 52 Games, 64-in-1, 72-in-1     | `.HMO PPPP PPCC CCCC`, plus 4 nibbles of RAM at $5800  |
+//!
+//! P is the 16KiB PRG bank, C the 8KiB CHR bank, O picks 16KiB banks (mirrored at $8000 and $C000) over 32KiB
+//! ones, M is horizontal mirroring over vertical, and H is the high bit of both banks on the 2MiB carts.
+
+use super::{banked, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const PRG_BANK_SIZE : usize = 0x4000;
+const CHR_BANK_SIZE : usize = 0x2000;
+/// The 16KiB PRG bank is mirrored rather than paired with the next one.
+const PRG_16K : u16 = 0b1_0000_0000_0000;
+
+/// The 16KiB PRG bank mapped at `addr`, with the 32KiB mode pairing `bank` with the next one.
+fn prg_bank(bank : usize, mode_16k : bool, addr : u16) -> usize {
+    if mode_16k {
+        bank
+    } else {
+        (bank & !1) | (addr >= 0xc000) as usize
+    }
+}
+
+/// Mapper 58.
+pub struct Multicart58 {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    latch : u16,
+}
+
+impl Multicart58 {
+    /// Builds the board for `rom`, showing the menu.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Multicart58 { prg_rom : rom.prg_rom, chr, latch : 0 }
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        banked(&self.chr, CHR_BANK_SIZE, (self.latch as usize >> 3) & 0b111, addr as usize)
+    }
+}
+
+impl Mapper for Multicart58 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let bank = prg_bank(self.latch as usize & 0b111, self.latch & 0b0100_0000 != 0, addr);
+        Some(self.prg_rom[banked(&self.prg_rom, PRG_BANK_SIZE, bank, addr as usize)])
+    }
+
+    fn cpu_write(&mut self, addr : u16, _value : u8) {
+        if addr >= PRG_ROM {
+            self.latch = addr;
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.latch & 0b1000_0000 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn reset(&mut self) {
+        self.latch = 0;
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.latch);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.latch)
+    }
+}
+
+/// Mapper 225.
+pub struct Multicart225 {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    latch : u16,
+    /// Four nibbles the menu keeps across resets, at $5800-$5803 mirrored up to $5FFF.
+    ram : [u8 ; 4],
+}
+
+impl Multicart225 {
+    /// Builds the board for `rom`, showing the menu.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Multicart225 { prg_rom : rom.prg_rom, chr, latch : 0, ram : [0 ; 4] }
+    }
+
+    /// The high bit of both banks.
+    fn high(&self) -> usize {
+        ((self.latch as usize >> 14) & 1) << 6
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        let bank = self.high() | (self.latch as usize & 0x3f);
+        banked(&self.chr, CHR_BANK_SIZE, bank, addr as usize)
+    }
+}
+
+impl Mapper for Multicart225 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        match addr {
+            // The upper bits are open bus.
+            0x5800 ..= 0x5fff => Some(self.ram[addr as usize & 0b11]),
+            PRG_ROM ..= 0xffff => {
+                let bank = prg_bank(self.high() | ((self.latch as usize >> 6) & 0x3f), self.latch & PRG_16K != 0, addr);
+                Some(self.prg_rom[banked(&self.prg_rom, PRG_BANK_SIZE, bank, addr as usize)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        match addr {
+            0x5800 ..= 0x5fff => self.ram[addr as usize & 0b11] = value & 0x0f,
+            PRG_ROM ..= 0xffff => self.latch = addr,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.latch & 0b10_0000_0000_0000 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn reset(&mut self) {
+        self.latch = 0;
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.latch);
+        state.write(&self.ram);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.latch)?;
+        state.read(&mut self.ram)
+    }
+}
@@ -0,0 +1,69 @@
+//! # NROM Module
+//!
+//! `nrom` is mapper 0, the board of the first games (Donkey Kong, Super Mario Bros.). It has no registers: 16KiB
+//! or 32KiB of PRG ROM at $8000-$FFFF, 8KiB of CHR (ROM, or RAM on some homebrew boards) and mirroring fixed by a
+//! solder pad.
+
+use super::{Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+
+/// Mapper 0.
+///
+/// # Example
+/// ```
+///  use nes::cartridge::{Mirroring, Rom};
+///  use nes::mapper::{Mapper, Nrom};
+///
+///  let mut image = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+///  image.resize(16 + 0x4000 + 0x2000, 0xea);
+///  let nrom = Nrom::new(Rom::from_bytes(&image).unwrap());
+///  assert_eq!(nrom.cpu_peek(0xc000), Some(0xea));
+///  assert_eq!(nrom.mirroring(), Mirroring::Horizontal);
+/// ```
+pub struct Nrom {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    mirroring : Mirroring,
+}
+
+impl Nrom {
+    /// Builds the board for `rom`. A 16KiB PRG ROM (NROM-128) is mirrored into both halves of $8000-$FFFF.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Nrom { prg_rom : rom.prg_rom, chr, mirroring : rom.mirroring }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        Some(self.prg_rom[(addr - PRG_ROM) as usize % self.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, _addr : u16, _value : u8) {}
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(addr as usize % self.chr.len(), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)
+    }
+}
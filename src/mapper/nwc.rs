@@ -0,0 +1,214 @@
+//! # NWC Module
+//!
+//! `nwc` is mapper 105, the Nintendo World Championships 1990 cart: an MMC1 with two 128KiB PRG ROM chips, 8KiB of
+//! CHR RAM and a timer that ends the competition round. The MMC1's CHR bank 0 register, useless with CHR RAM, is
+//! put to other uses instead:
+//!
+//! | Bit | Use                                                                                         |
+//! |-----|---------------------------------------------------------------------------------------------|
+//! | 1-2 | The 32KiB bank of the first chip, when bit 3 is clear                                       |
+//! | 3   | Set to map the second chip, banked by the MMC1's PRG bank register as usual                  |
+//! | 4   | Set to stop the timer, clear it and acknowledge its IRQ, clear to run it                     |
+//!
+//! At power on the first 32KiB of the first chip is mapped whatever the registers say and the timer is held,
+//! until bit 4 has been set and then cleared once. The timer counts CPU cycles and raises the IRQ after $20000000 plus $2000000 times the
+//! setting of four DIP switches on the cart, from 5.0 to 9.7 minutes.
+
+use super::{banked, Mapper, Mmc1};
+use super::state::{StateError, StateReader, StateValue, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_RAM : u16 = 0x6000;
+const PRG_RAM_END : u16 = 0x7fff;
+const PRG_ROM : u16 = 0x8000;
+const PRG_BANK_SIZE : usize = 0x4000;
+const CHR_SIZE : usize = 0x2000;
+
+const FIRST_CHIP_BANK : u8 = 0b0_0110;
+const SECOND_CHIP : u8 = 0b0_1000;
+const TIMER_STOP : u8 = 0b1_0000;
+
+/// The DIP switches of the competition carts, 6.25 minutes.
+pub const DEFAULT_DIP_SWITCHES : u8 = 4;
+const TIMER_BASE : u64 = 0x2000_0000;
+const TIMER_STEP : u64 = 0x0200_0000;
+
+/// How far the board is into unlocking the PRG banking after power on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Init {
+    Locked,
+    /// The timer stop bit has been set, clearing it unlocks.
+    StopSet,
+    Unlocked,
+}
+
+impl StateValue for Init {
+    fn save(&self, state : &mut StateWriter) {
+        let value : u8 = match self {
+            Init::Locked => 0,
+            Init::StopSet => 1,
+            Init::Unlocked => 2,
+        };
+        state.write(&value);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u8;
+        state.read(&mut value)?;
+        *self = match value {
+            0 => Init::Locked,
+            1 => Init::StopSet,
+            2 => Init::Unlocked,
+            _ => return Err(StateError::BadValue),
+        };
+        Ok(())
+    }
+}
+
+/// Mapper 105.
+pub struct Nwc {
+    mmc1 : Mmc1,
+    init : Init,
+    dip_switches : u8,
+    timer : u64,
+    irq_pending : bool,
+}
+
+impl Nwc {
+    /// Builds the board for `rom` with the DIP switches as they were in the competition.
+    pub fn new(rom : Rom) -> Self {
+        Nwc::with_dip_switches(rom, DEFAULT_DIP_SWITCHES)
+    }
+
+    /// Builds the board for `rom` with the 4 DIP switches set to `dip_switches`.
+    pub fn with_dip_switches(rom : Rom, dip_switches : u8) -> Self {
+        Nwc { mmc1 : Mmc1::new(rom), init : Init::Locked, dip_switches : dip_switches & 0x0f, timer : 0, irq_pending : false }
+    }
+
+    pub fn dip_switches(&self) -> u8 {
+        self.dip_switches
+    }
+
+    /// Flips the DIP switches, which takes effect on the running timer.
+    pub fn set_dip_switches(&mut self, dip_switches : u8) {
+        self.dip_switches = dip_switches & 0x0f;
+    }
+
+    /// CPU cycles the timer runs before it raises the IRQ.
+    pub fn timer_length(&self) -> u64 {
+        TIMER_BASE + TIMER_STEP * self.dip_switches as u64
+    }
+
+    /// CPU cycles the timer has counted.
+    pub fn timer(&self) -> u64 {
+        self.timer
+    }
+
+    fn register(&self) -> u8 {
+        self.mmc1.chr_bank_0
+    }
+
+    /// The 16KiB PRG bank mapped at `addr`, the second chip being banks 8-15.
+    fn prg_rom_bank(&self, addr : u16) -> usize {
+        let upper = (addr >= 0xc000) as usize;
+        if self.init != Init::Unlocked {
+            upper
+        } else if self.register() & SECOND_CHIP != 0 {
+            (self.mmc1.prg_rom_bank(addr) & 0b111) | 0b1000
+        } else {
+            (self.register() & FIRST_CHIP_BANK) as usize | upper
+        }
+    }
+
+    /// Follows the timer stop bit after a write: setting then clearing it unlocks the board, and setting it holds
+    /// the timer at 0.
+    fn follow_timer_control(&mut self) {
+        let stopped = self.register() & TIMER_STOP != 0;
+        self.init = match (self.init, stopped) {
+            (Init::Locked, true) => Init::StopSet,
+            (Init::StopSet, false) => Init::Unlocked,
+            (init, _) => init,
+        };
+        if stopped {
+            self.timer = 0;
+            self.irq_pending = false;
+        }
+    }
+}
+
+impl Mapper for Nwc {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        match addr {
+            PRG_ROM ..= 0xffff => {
+                let prg_rom = &self.mmc1.prg_rom;
+                Some(prg_rom[banked(prg_rom, PRG_BANK_SIZE, self.prg_rom_bank(addr), addr as usize)])
+            }
+            PRG_RAM ..= PRG_RAM_END => self.mmc1.cpu_peek(addr),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        self.mmc1.cpu_write(addr, value);
+        self.follow_timer_control();
+    }
+
+    fn cpu_write_on_cycle(&mut self, addr : u16, value : u8, cycle : u64) {
+        self.mmc1.cpu_write_on_cycle(addr, value, cycle);
+        self.follow_timer_control();
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.mmc1.chr[addr as usize % CHR_SIZE]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.mmc1.chr.write(addr as usize % CHR_SIZE, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mmc1.mirroring()
+    }
+
+    fn tick(&mut self, cpu_cycles : u64) {
+        if self.init != Init::Unlocked || self.register() & TIMER_STOP != 0 {
+            return;
+        }
+        let length = self.timer_length();
+        if self.timer < length && self.timer + cpu_cycles >= length {
+            self.irq_pending = true;
+        }
+        self.timer += cpu_cycles;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        self.mmc1.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.mmc1.prg_ram_mut()
+    }
+
+    /// Follows the MMC1's version.
+    fn state_version(&self) -> u16 {
+        self.mmc1.state_version()
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        self.mmc1.save_state(state);
+        state.write(&self.init);
+        state.write(&self.timer);
+        state.write(&self.irq_pending);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        self.mmc1.load_state(state)?;
+        state.read(&mut self.init)?;
+        state.read(&mut self.timer)?;
+        state.read(&mut self.irq_pending)
+    }
+}
@@ -0,0 +1,294 @@
+//! # State Module
+//!
+//! `state` is the format boards save their state in for save states: the bank registers, IRQ counters, sound
+//! channels and RAM, everything the board would need to carry on from where it was. A board writes its fields
+//! one after the other with a [`StateWriter`] and reads them back in the same order with a [`StateReader`], values
+//! are little endian and carry no names.
+//!
+//! Each board numbers its format, see [`Mapper::state_version`](super::Mapper::state_version). A board that
+//! changes what it saves bumps the number and keeps reading the older layouts, which [`StateReader::version`]
+//! tells it apart by, so save states made by older versions of the emulator keep loading.
+
+use super::Chr;
+use crate::cartridge::Mirroring;
+use std::fmt;
+
+/// Why a save state could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state signature.
+    NotAState,
+    /// The state ends before the board has read all of it.
+    Truncated,
+    /// The state was saved from a board with another mapper number.
+    WrongMapper { expected : u16, found : u16 },
+    /// The state was saved by a newer version of the board than this one.
+    UnsupportedVersion { mapper : u16, version : u16 },
+    /// A block of memory is not the size the board has, the state was saved from another game.
+    SizeMismatch { expected : usize, found : usize },
+    /// A field holds a value the board cannot be in.
+    BadValue,
+    /// There is data left over after the board has read its state.
+    TrailingData,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state, the signature is missing"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::WrongMapper { expected, found } => {
+                write!(f, "save state is for mapper {}, the cartridge is mapper {}", found, expected)
+            }
+            StateError::UnsupportedVersion { mapper, version } => {
+                write!(f, "save state version {} of mapper {} is newer than this emulator", version, mapper)
+            }
+            StateError::SizeMismatch { expected, found } => {
+                write!(f, "save state has {} bytes of memory where the board has {}", found, expected)
+            }
+            StateError::BadValue => write!(f, "save state holds a value the board cannot be in"),
+            StateError::TrailingData => write!(f, "save state has data left over"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A value that can be written to and read back from a save state.
+pub trait StateValue {
+    fn save(&self, state : &mut StateWriter);
+
+    /// Overwrites the value with the one read from `state`. Memory blocks must already be the size they were
+    /// saved at.
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Collects the state of a board.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    bytes : Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    /// Appends `value`.
+    pub fn write<T : StateValue + ?Sized>(&mut self, value : &T) {
+        value.save(self);
+    }
+
+    /// Appends raw bytes, the building block of the [`StateValue`] implementations.
+    pub fn write_bytes(&mut self, bytes : &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads back the state of a board.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    bytes : &'a [u8],
+    position : usize,
+    version : u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Reads `bytes`, written by version `version` of the board's format.
+    pub fn new(bytes : &'a [u8], version : u16) -> Self {
+        StateReader { bytes, position : 0, version }
+    }
+
+    /// The version of the board's format the state was saved in.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Reads the next value into `value`.
+    pub fn read<T : StateValue + ?Sized>(&mut self, value : &mut T) -> Result<(), StateError> {
+        value.load(self)
+    }
+
+    /// Takes the next `count` raw bytes.
+    pub fn read_bytes(&mut self, count : usize) -> Result<&'a [u8], StateError> {
+        let bytes = self.bytes.get(self.position .. self.position + count).ok_or(StateError::Truncated)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    /// Fails unless all of the state has been read.
+    pub fn finish(&self) -> Result<(), StateError> {
+        if self.position == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(StateError::TrailingData)
+        }
+    }
+}
+
+macro_rules! state_value_int {
+    ($($ty:ty),*) => {
+        $(impl StateValue for $ty {
+            fn save(&self, state : &mut StateWriter) {
+                state.write_bytes(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+                let bytes = state.read_bytes(std::mem::size_of::<$ty>())?;
+                *self = <$ty>::from_le_bytes(bytes.try_into().expect("read_bytes returns the size asked for"));
+                Ok(())
+            }
+        })*
+    };
+}
+
+state_value_int!(u8, u16, u32, u64, i8, i16, i32);
+
+impl StateValue for f32 {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.to_bits());
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut bits = 0u32;
+        state.read(&mut bits)?;
+        *self = f32::from_bits(bits);
+        Ok(())
+    }
+}
+
+/// Saved as 64 bits, whatever the platform.
+impl StateValue for usize {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&(*self as u64));
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u64;
+        state.read(&mut value)?;
+        *self = usize::try_from(value).map_err(|_| StateError::BadValue)?;
+        Ok(())
+    }
+}
+
+impl StateValue for bool {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&(*self as u8));
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u8;
+        state.read(&mut value)?;
+        *self = match value {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::BadValue),
+        };
+        Ok(())
+    }
+}
+
+impl<T : StateValue + Default> StateValue for Option<T> {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.is_some());
+        if let Some(value) = self {
+            state.write(value);
+        }
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut some = false;
+        state.read(&mut some)?;
+        *self = if some {
+            let mut value = T::default();
+            state.read(&mut value)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+impl<T : StateValue> StateValue for [T] {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.len());
+        for value in self {
+            state.write(value);
+        }
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut len = 0usize;
+        state.read(&mut len)?;
+        if len != self.len() {
+            return Err(StateError::SizeMismatch { expected : self.len(), found : len });
+        }
+        self.iter_mut().try_for_each(|value| state.read(value))
+    }
+}
+
+impl<T : StateValue, const N : usize> StateValue for [T ; N] {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(self.as_slice());
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(self.as_mut_slice())
+    }
+}
+
+/// Memory the board keeps, its length fixed by the ROM it was built for.
+impl<T : StateValue> StateValue for Vec<T> {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(self.as_slice());
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(self.as_mut_slice())
+    }
+}
+
+impl StateValue for Mirroring {
+    fn save(&self, state : &mut StateWriter) {
+        let value : u8 = match self {
+            Mirroring::Horizontal => 0,
+            Mirroring::Vertical => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::SingleScreenLower => 3,
+            Mirroring::SingleScreenUpper => 4,
+        };
+        state.write(&value);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u8;
+        state.read(&mut value)?;
+        *self = match value {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            _ => return Err(StateError::BadValue),
+        };
+        Ok(())
+    }
+}
+
+/// CHR RAM is saved, CHR ROM is part of the game and is not.
+impl StateValue for Chr {
+    fn save(&self, state : &mut StateWriter) {
+        let ram : &[u8] = if self.ram { &self.bytes } else { &[] };
+        state.write(ram);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        let ram : &mut [u8] = if self.ram { &mut self.bytes } else { &mut [] };
+        state.read(ram)
+    }
+}
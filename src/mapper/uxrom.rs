@@ -0,0 +1,71 @@
+//! # UxROM Module
+//!
+//! `uxrom` is mapper 2, the UNROM and UOROM boards (Mega Man, Castlevania, Contra). A write anywhere in
+//! $8000-$FFFF selects the 16KiB PRG ROM bank at $8000, the last bank is fixed at $C000. The PPU gets 8KiB of CHR
+//! RAM, which the game fills with its tiles.
+
+use super::{banked, last_bank, Chr, Mapper};
+use super::state::{StateError, StateReader, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_ROM : u16 = 0x8000;
+const PRG_ROM_BANK_SIZE : usize = 0x4000;
+
+/// Mapper 2.
+pub struct Uxrom {
+    prg_rom : Vec<u8>,
+    chr : Chr,
+    mirroring : Mirroring,
+    prg_bank : u8,
+}
+
+impl Uxrom {
+    /// Builds the board for `rom`, with bank 0 at $8000. The few dumps that come with CHR ROM keep it read-only.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Uxrom {
+            prg_rom : rom.prg_rom,
+            chr,
+            mirroring : rom.mirroring,
+            prg_bank : 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        if addr < PRG_ROM {
+            return None;
+        }
+        let bank = if addr < 0xc000 { self.prg_bank as usize } else { last_bank(&self.prg_rom, PRG_ROM_BANK_SIZE, 0) };
+        Some(self.prg_rom[banked(&self.prg_rom, PRG_ROM_BANK_SIZE, bank, addr as usize)])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        if addr >= PRG_ROM {
+            self.prg_bank = value;
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(addr as usize % self.chr.len(), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.chr);
+        state.write(&self.prg_bank);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.chr)?;
+        state.read(&mut self.prg_bank)
+    }
+}
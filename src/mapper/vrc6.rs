@@ -0,0 +1,437 @@
+//! # VRC6 Module
+//!
+//! `vrc6` is mappers 24 and 26, Konami's VRC6 (Akumajou Densetsu, Madara, Esper Dream 2). The two mapper numbers
+//! are the same chip wired differently: mapper 26 swaps address lines A0 and A1, so its registers are the ones
+//! below with the last two address bits swapped.
+//!
+//! | Address     | Register                                                                |
+//! |-------------|-------------------------------------------------------------------------|
+//! | $8000-$8003 | 16KiB PRG ROM bank at $8000                                             |
+//! | $9000-$9002 | Pulse 1: duty and volume, period low, enable and period high            |
+//! | $9003       | Frequency control: halt and speed up all three channels                 |
+//! | $A000-$A002 | Pulse 2                                                                 |
+//! | $B000-$B002 | Sawtooth: accumulator rate, period low, enable and period high          |
+//! | $B003       | CHR banking mode, mirroring and PRG RAM enable                          |
+//! | $C000-$C003 | 8KiB PRG ROM bank at $C000                                              |
+//! | $D000-$E003 | CHR banks R0-R7                                                         |
+//! | $F000-$F002 | IRQ latch, control and acknowledge                                      |
+//!
+//! The last 8KiB of PRG ROM is fixed at $E000. The IRQ counter counts up from the latch to $FF, either every CPU
+//! cycle or, through a prescaler, every scanline (341 PPU dots, each 1/3 of a CPU cycle).
+
+use super::{banked, last_bank, Chr, Mapper};
+use super::state::{StateError, StateReader, StateValue, StateWriter};
+use crate::cartridge::{Mirroring, Rom};
+
+const PRG_RAM : u16 = 0x6000;
+const PRG_RAM_END : u16 = 0x7fff;
+const PRG_BANK_SIZE : usize = 0x2000;
+const CHR_BANK_SIZE : usize = 0x0400;
+
+const PRG_RAM_ENABLE : u8 = 0b1000_0000;
+/// In 2KiB CHR banking, use bit 0 of the register rather than PPU A10 for the low bit of the 1KiB bank.
+const CHR_A10_FROM_REGISTER : u8 = 0b0010_0000;
+
+const IRQ_ENABLE_AFTER_ACK : u8 = 0b001;
+const IRQ_ENABLE : u8 = 0b010;
+const IRQ_CYCLE_MODE : u8 = 0b100;
+/// PPU dots per scanline, the prescaler counts them down three per CPU cycle.
+const PRESCALER_PERIOD : i16 = 341;
+
+/// One of the VRC6's pulse channels. They have no envelope or length counter, but eight duty cycles and a mode
+/// that holds the output at full volume (used as a crude PCM).
+#[derive(Debug, Clone, Default)]
+struct Pulse {
+    control : u8,
+    period : u16,
+    enabled : bool,
+    timer : u16,
+    step : u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register : u16, value : u8) {
+        match register {
+            0 => self.control = value,
+            1 => self.period = (self.period & 0xf00) | value as u16,
+            2 => {
+                self.period = (self.period & 0xff) | ((value as u16 & 0x0f) << 8);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, shift : u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.checked_sub(1).unwrap_or(15);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        let digitized = self.control & 0x80 != 0;
+        let duty = (self.control >> 4) & 0b111;
+        if self.enabled && (digitized || self.step <= duty) {
+            self.control & 0x0f
+        } else {
+            0
+        }
+    }
+}
+
+impl StateValue for Pulse {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.control);
+        state.write(&self.period);
+        state.write(&self.enabled);
+        state.write(&self.timer);
+        state.write(&self.step);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.control)?;
+        state.read(&mut self.period)?;
+        state.read(&mut self.enabled)?;
+        state.read(&mut self.timer)?;
+        state.read(&mut self.step)
+    }
+}
+
+/// The sawtooth channel: an accumulator that adds its rate every other clock, and restarts every 14 clocks.
+#[derive(Debug, Clone, Default)]
+struct Sawtooth {
+    rate : u8,
+    period : u16,
+    enabled : bool,
+    timer : u16,
+    step : u8,
+    accumulator : u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register : u16, value : u8) {
+        match register {
+            0 => self.rate = value & 0x3f,
+            1 => self.period = (self.period & 0xf00) | value as u16,
+            2 => {
+                self.period = (self.period & 0xff) | ((value as u16 & 0x0f) << 8);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, shift : u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl StateValue for Sawtooth {
+    fn save(&self, state : &mut StateWriter) {
+        state.write(&self.rate);
+        state.write(&self.period);
+        state.write(&self.enabled);
+        state.write(&self.timer);
+        state.write(&self.step);
+        state.write(&self.accumulator);
+    }
+
+    fn load(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.rate)?;
+        state.read(&mut self.period)?;
+        state.read(&mut self.enabled)?;
+        state.read(&mut self.timer)?;
+        state.read(&mut self.step)?;
+        state.read(&mut self.accumulator)?;
+        if self.step >= 14 {
+            return Err(StateError::BadValue);
+        }
+        Ok(())
+    }
+}
+
+/// Mappers 24 and 26.
+pub struct Vrc6 {
+    prg_rom : Vec<u8>,
+    prg_ram : Vec<u8>,
+    chr : Chr,
+    /// Mapper 26, with A0 and A1 swapped.
+    swapped_lines : bool,
+    prg_16k : u8,
+    prg_8k : u8,
+    chr_banks : [u8 ; 8],
+    banking : u8,
+    irq_latch : u8,
+    irq_control : u8,
+    irq_counter : u8,
+    irq_prescaler : i16,
+    irq_pending : bool,
+    frequency_control : u8,
+    pulses : [Pulse ; 2],
+    sawtooth : Sawtooth,
+}
+
+impl Vrc6 {
+    /// Builds the board for `rom`, telling the two wirings apart by its mapper number.
+    pub fn new(mut rom : Rom) -> Self {
+        let chr = Chr::from_rom(&mut rom);
+        Vrc6 {
+            prg_rom : rom.prg_rom,
+            prg_ram : vec![0 ; rom.prg_ram_size + rom.prg_nvram_size],
+            chr,
+            swapped_lines : rom.mapper == 26,
+            prg_16k : 0,
+            prg_8k : 0,
+            chr_banks : [0 ; 8],
+            banking : 0,
+            irq_latch : 0,
+            irq_control : 0,
+            irq_counter : 0,
+            irq_prescaler : PRESCALER_PERIOD,
+            irq_pending : false,
+            frequency_control : 0,
+            pulses : [Pulse::default(), Pulse::default()],
+            sawtooth : Sawtooth::default(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.banking & PRG_RAM_ENABLE != 0
+    }
+
+    /// The register a write to `addr` reaches: $x000-$x003 as wired on mapper 24.
+    fn register(&self, addr : u16) -> u16 {
+        let addr = addr & 0xf003;
+        if self.swapped_lines {
+            (addr & 0xf000) | ((addr & 1) << 1) | ((addr >> 1) & 1)
+        } else {
+            addr
+        }
+    }
+
+    /// The 1KiB CHR bank mapped at `addr`.
+    fn chr_bank(&self, addr : u16) -> usize {
+        let slot = (addr as usize / CHR_BANK_SIZE) & 0b111;
+        // 2KiB banks take their low bit from PPU A10 unless told otherwise.
+        let two_k = |register : u8| {
+            if self.banking & CHR_A10_FROM_REGISTER != 0 {
+                register as usize
+            } else {
+                (register as usize & !1) | (slot & 1)
+            }
+        };
+        match (self.banking & 0b11, slot) {
+            (0, _) => self.chr_banks[slot] as usize,
+            (1, _) => two_k(self.chr_banks[slot / 2]),
+            // Modes 2 and 3: 1KiB banks R0-R3 in the first pattern table, 2KiB banks R4-R5 in the second.
+            (_, 0 ..= 3) => self.chr_banks[slot] as usize,
+            (_, _) => two_k(self.chr_banks[4 + (slot - 4) / 2]),
+        }
+    }
+
+    fn chr_index(&self, addr : u16) -> usize {
+        banked(&self.chr, CHR_BANK_SIZE, self.chr_bank(addr), addr as usize)
+    }
+
+    fn write_irq_control(&mut self, value : u8) {
+        self.irq_control = value & 0b111;
+        if value & IRQ_ENABLE != 0 {
+            self.irq_counter = self.irq_latch;
+            self.irq_prescaler = PRESCALER_PERIOD;
+        }
+        self.irq_pending = false;
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+        if self.irq_control & IRQ_ENABLE_AFTER_ACK != 0 {
+            self.irq_control |= IRQ_ENABLE;
+        } else {
+            self.irq_control &= !IRQ_ENABLE;
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xff {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    /// How far bits 1 and 2 of $9003 shift the channels' periods right, `None` when bit 0 halts them.
+    fn frequency_shift(&self) -> Option<u8> {
+        match self.frequency_control {
+            control if control & 0b001 != 0 => None,
+            control if control & 0b100 != 0 => Some(8),
+            control if control & 0b010 != 0 => Some(4),
+            _ => Some(0),
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        let index = match addr {
+            PRG_RAM ..= PRG_RAM_END if self.prg_ram_enabled() => {
+                return Some(self.prg_ram[(addr - PRG_RAM) as usize % self.prg_ram.len()]);
+            }
+            0x8000 ..= 0xbfff => banked(&self.prg_rom, 0x4000, self.prg_16k as usize, addr as usize),
+            0xc000 ..= 0xdfff => banked(&self.prg_rom, PRG_BANK_SIZE, self.prg_8k as usize, addr as usize),
+            0xe000 ..= 0xffff => {
+                banked(&self.prg_rom, PRG_BANK_SIZE, last_bank(&self.prg_rom, PRG_BANK_SIZE, 0), addr as usize)
+            }
+            _ => return None,
+        };
+        Some(self.prg_rom[index])
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        if let PRG_RAM ..= PRG_RAM_END = addr {
+            if self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM) as usize % len] = value;
+            }
+            return;
+        }
+        let register = self.register(addr);
+        match register {
+            0x8000 ..= 0x8003 => self.prg_16k = value & 0x0f,
+            0x9000 ..= 0x9002 => self.pulses[0].write(register & 0b11, value),
+            0x9003 => self.frequency_control = value & 0b111,
+            0xa000 ..= 0xa002 => self.pulses[1].write(register & 0b11, value),
+            0xb000 ..= 0xb002 => self.sawtooth.write(register & 0b11, value),
+            0xb003 => self.banking = value,
+            0xc000 ..= 0xc003 => self.prg_8k = value & 0x1f,
+            0xd000 ..= 0xe003 => {
+                let index = ((register - 0xd000) >> 12) * 4 + (register & 0b11);
+                self.chr_banks[index as usize] = value;
+            }
+            0xf000 => self.irq_latch = value,
+            0xf001 => self.write_irq_control(value),
+            0xf002 => self.acknowledge_irq(),
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, addr : u16) -> u8 {
+        self.chr[self.chr_index(addr)]
+    }
+
+    fn ppu_write(&mut self, addr : u16, value : u8) {
+        self.chr.write(self.chr_index(addr), value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn tick(&mut self, cpu_cycles : u64) {
+        for _ in 0 .. cpu_cycles {
+            if self.irq_control & IRQ_ENABLE != 0 {
+                if self.irq_control & IRQ_CYCLE_MODE != 0 {
+                    self.clock_irq_counter();
+                } else {
+                    self.irq_prescaler -= 3;
+                    if self.irq_prescaler <= 0 {
+                        self.irq_prescaler += PRESCALER_PERIOD;
+                        self.clock_irq_counter();
+                    }
+                }
+            }
+            if let Some(shift) = self.frequency_shift() {
+                self.pulses.iter_mut().for_each(|pulse| pulse.clock(shift));
+                self.sawtooth.clock(shift);
+            }
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    /// A pulse channel at full volume is about as loud as one of the APU's.
+    fn audio_sample(&self) -> f32 {
+        let pulses = self.pulses[0].output() + self.pulses[1].output();
+        (pulses + self.sawtooth.output()) as f32 * 0.00995
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.prg_ram);
+        state.write(&self.chr);
+        state.write(&self.prg_16k);
+        state.write(&self.prg_8k);
+        state.write(&self.chr_banks);
+        state.write(&self.banking);
+        state.write(&self.irq_latch);
+        state.write(&self.irq_control);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_prescaler);
+        state.write(&self.irq_pending);
+        state.write(&self.frequency_control);
+        state.write(&self.pulses);
+        state.write(&self.sawtooth);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.prg_ram)?;
+        state.read(&mut self.chr)?;
+        state.read(&mut self.prg_16k)?;
+        state.read(&mut self.prg_8k)?;
+        state.read(&mut self.chr_banks)?;
+        state.read(&mut self.banking)?;
+        state.read(&mut self.irq_latch)?;
+        state.read(&mut self.irq_control)?;
+        state.read(&mut self.irq_counter)?;
+        state.read(&mut self.irq_prescaler)?;
+        state.read(&mut self.irq_pending)?;
+        state.read(&mut self.frequency_control)?;
+        state.read(&mut self.pulses)?;
+        state.read(&mut self.sawtooth)
+    }
+}
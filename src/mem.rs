@@ -0,0 +1,193 @@
+//! # Mem Module
+//!
+//! `mem` defines what the CPU is connected to. The CPU only ever reads and writes bytes at 16 bit addresses, what
+//! answers them (RAM, ROM, memory mapped registers) is up to the [`Mem`] implementation it is built with.
+//!
+//! Besides the NES memory map in [`crate::bus`], there are two plain 64KiB RAM implementations for running code
+//! without any hardware around it: [`FlatMemory`] and [`TestBus`], which also records what the CPU did.
+
+use crate::bus::BusAccess;
+use crate::cpu::Access;
+use crate::region::Region;
+
+/// A 16 bit address space the CPU reads and writes through.
+///
+/// # Example
+/// ```
+///  use nes::cpu::CPU;
+///  use nes::mem::FlatMemory;
+///
+///  let mut cpu = CPU::with_memory(FlatMemory::new());
+///  cpu.load_and_run(vec![0xa9, 0x42, 0x85, 0x10, 0x00]).unwrap();
+///  assert_eq!(cpu.memory().bytes()[0x10], 0x42);
+/// ```
+pub trait Mem {
+    /// Reads the byte at `addr`. Reading a memory mapped register can have side effects, which is why this takes
+    /// `&mut self`.
+    fn read(&mut self, addr : u16) -> u8;
+
+    /// Writes `value` to `addr`.
+    fn write(&mut self, addr : u16, value : u8);
+
+    /// Returns the byte a read of `addr` would, without any side effects. Tracers and debuggers use this so
+    /// looking at memory does not change it.
+    fn peek(&self, addr : u16) -> u8;
+
+    /// Lets the hardware behind the memory catch up with `cycles` CPU cycles. The CPU calls this for every cycle
+    /// it spends, so clocked devices (the PPU, the cartridge's board) stay in step with it. Plain memory has
+    /// nothing to do.
+    fn tick(&mut self, _cycles : u64) {}
+
+    /// Whether the hardware behind the memory is holding the CPU's IRQ line low. The CPU samples this when it
+    /// polls for interrupts, together with [`crate::cpu::CPU::set_irq`]. Plain memory never interrupts.
+    fn irq_line(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the hardware behind the memory has pulled the CPU's NMI line since the last call. The CPU
+    /// asks after every [`Mem::tick`], so an NMI raised part way through an instruction is seen by its poll.
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// The console timing the hardware behind the memory is clocked with. Plain memory has no clock and reports
+    /// NTSC.
+    fn region(&self) -> Region {
+        Region::default()
+    }
+}
+
+/// 64KiB of RAM covering the whole address space, with no mirroring and nothing memory mapped. This is what
+/// [`crate::cpu::CPU::new`] uses.
+#[derive(Clone)]
+pub struct FlatMemory {
+    bytes : [u8 ; 0x10000],
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatMemory {
+    /// Creates the memory with every byte set to 0x00.
+    pub fn new() -> Self {
+        FlatMemory { bytes : [0 ; 0x10000] }
+    }
+
+    /// The whole address space as a slice, indexed by address.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Mem for FlatMemory {
+    fn read(&mut self, addr : u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+
+    fn write(&mut self, addr : u16, value : u8) {
+        self.bytes[addr as usize] = value;
+    }
+
+    fn peek(&self, addr : u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+}
+
+/// 64KiB of flat RAM like [`FlatMemory`], for tests: regions can be preloaded anywhere and every read and write is
+/// recorded so a test can check exactly what the CPU touched.
+///
+/// # Example
+/// ```
+///  use nes::cpu::CPU;
+///  use nes::mem::TestBus;
+///
+///  let mut bus = TestBus::new();
+///  bus.load(0x0600, &[0xa5, 0x10, 0x85, 0x11]); // LDA $10; STA $11
+///  bus.load(0x0010, &[0x42]);
+///  let mut cpu = CPU::with_memory(bus);
+///  cpu.program_counter = 0x0600;
+///  cpu.step().unwrap();
+///  cpu.step().unwrap();
+///
+///  assert_eq!(cpu.memory().reads(0x0010), 1);
+///  assert_eq!(cpu.memory().writes(0x0011), vec![0x42]);
+/// ```
+#[derive(Clone, Default)]
+pub struct TestBus {
+    memory : FlatMemory,
+    accesses : Vec<BusAccess>,
+    cycles : u64,
+}
+
+impl TestBus {
+    /// Creates the bus with every byte set to 0x00 and no accesses recorded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies `bytes` into memory starting at `addr`, wrapping around at the end of the address space. This is not
+    /// recorded as an access.
+    pub fn load(&mut self, addr : u16, bytes : &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.memory.write(addr.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// The whole address space as a slice, indexed by address.
+    pub fn bytes(&self) -> &[u8] {
+        self.memory.bytes()
+    }
+
+    /// Every read and write so far, in order. The cycle is the CPU cycle count at the time, as told by
+    /// [`Mem::tick`].
+    pub fn accesses(&self) -> &[BusAccess] {
+        &self.accesses
+    }
+
+    /// Forgets the accesses recorded so far, for instance the ones made while setting up.
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+
+    /// How many times `addr` was read.
+    pub fn reads(&self, addr : u16) -> usize {
+        self.accesses.iter().filter(|access| access.access == Access::Read && access.address == addr).count()
+    }
+
+    /// The values written to `addr`, in order.
+    pub fn writes(&self, addr : u16) -> Vec<u8> {
+        self.accesses
+            .iter()
+            .filter(|access| access.access == Access::Write && access.address == addr)
+            .map(|access| access.value)
+            .collect()
+    }
+
+    fn record(&mut self, access : Access, address : u16, value : u8) {
+        self.accesses.push(BusAccess { cycle : self.cycles, address, value, access });
+    }
+}
+
+impl Mem for TestBus {
+    fn read(&mut self, addr : u16) -> u8 {
+        let value = self.memory.read(addr);
+        self.record(Access::Read, addr, value);
+        value
+    }
+
+    fn write(&mut self, addr : u16, value : u8) {
+        self.record(Access::Write, addr, value);
+        self.memory.write(addr, value);
+    }
+
+    fn peek(&self, addr : u16) -> u8 {
+        self.memory.peek(addr)
+    }
+
+    fn tick(&mut self, cycles : u64) {
+        self.cycles += cycles;
+    }
+}
@@ -0,0 +1,93 @@
+//! # Nestest Module
+//!
+//! `nestest` runs the nestest ROM in its automated mode (starting at $C000, no PPU needed) and compares the
+//! [`trace`] of every instruction against the reference log. The ROM exercises every official and most of the
+//! undocumented opcodes, so a clean run is a good sign nothing in the CPU has regressed.
+
+use crate::cpu::{CpuError, CPU};
+use crate::trace::trace;
+use std::fmt;
+
+/// Where the automated mode of nestest starts.
+const NESTEST_START : u16 = 0xC000;
+
+/// The number of matching lines shown before a divergence.
+const CONTEXT_LINES : usize = 5;
+
+/// Why a nestest run did not match the reference log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NestestError {
+    /// The ROM is not an iNES file with at least one PRG-ROM bank.
+    InvalidRom,
+    /// The CPU stopped with an error before the end of the log.
+    Cpu { line : usize, error : CpuError },
+    /// The trace differs from the log. `line` is the one based line number in the log, `context` holds the lines
+    /// before it that did match.
+    Mismatch { line : usize, expected : String, actual : String, context : Vec<String> },
+}
+
+impl fmt::Display for NestestError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NestestError::InvalidRom => write!(f, "not an iNES ROM"),
+            NestestError::Cpu { line, error } => write!(f, "line {}: {}", line, error),
+            NestestError::Mismatch { line, expected, actual, context } => {
+                writeln!(f, "trace differs from the log at line {}", line)?;
+                for previous in context {
+                    writeln!(f, "  {}", previous)?;
+                }
+                writeln!(f, "- {}", expected)?;
+                write!(f, "+ {}", actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NestestError {}
+
+/// Loads the PRG-ROM of an NROM iNES file into the CPU memory at 0x8000, a single 16KiB bank is mirrored into
+/// 0xC000 as well.
+fn load_nrom(cpu : &mut CPU, rom : &[u8]) -> Result<(), NestestError> {
+    if rom.len() < 16 || &rom[0 .. 4] != b"NES\x1a" || rom[4] == 0 {
+        return Err(NestestError::InvalidRom);
+    }
+    // Skip the header, and the trainer if there is one.
+    let start = 16 + if rom[6] & 0b100 != 0 { 512 } else { 0 };
+    let prg_len = (rom[4] as usize * 0x4000).min(0x8000);
+    let prg = rom.get(start .. start + prg_len).ok_or(NestestError::InvalidRom)?;
+
+    for addr in 0x8000u32 .. 0x10000 {
+        cpu.mem_write(addr as u16, prg[(addr as usize - 0x8000) % prg_len]);
+    }
+    Ok(())
+}
+
+/// Runs `rom` from $C000 and compares the trace of each instruction against the lines of `log`, returning the
+/// number of lines that matched. The CPU starts in the state nestest expects: after a reset, with the stack
+/// pointer at 0xFD and 7 cycles already taken.
+pub fn verify_nestest(rom : &[u8], log : &str) -> Result<usize, NestestError> {
+    let mut cpu = CPU::new();
+    load_nrom(&mut cpu, rom)?;
+    cpu.mem_write_u16(0xFFFC, NESTEST_START);
+    cpu.power_on();
+    cpu.reset();
+    cpu.stack_pointer = 0xfd;
+
+    let mut matched = 0;
+    for (index, expected) in log.lines().enumerate() {
+        let expected = expected.trim_end();
+        let actual = trace(&cpu);
+        if actual != expected {
+            let context = log.lines().skip(index.saturating_sub(CONTEXT_LINES)).take(index.min(CONTEXT_LINES));
+            return Err(NestestError::Mismatch {
+                line : index + 1,
+                expected : expected.to_string(),
+                actual,
+                context : context.map(|line| line.trim_end().to_string()).collect(),
+            });
+        }
+        cpu.step().map_err(|error| NestestError::Cpu { line : index + 1, error })?;
+        matched += 1;
+    }
+    Ok(matched)
+}
@@ -0,0 +1,264 @@
+//! # NSF Module
+//!
+//! `nsf` loads NSF files, the music of NES games ripped with just the code and data needed to play it. An NSF
+//! holds a number of tracks and two routines: INIT, called once with the track number in A to start a track, and
+//! PLAY, called at a steady rate (usually once a frame) to play it. [`NsfPlayer`] sets up the machine, calls them
+//! and lets a track be picked.
+//!
+//! The music data is loaded at the header's load address. Bankswitched NSFs instead split it into 4KiB banks,
+//! with the bank at each 4KiB of $8000-$FFFF picked by writing $5FF8-$5FFF, see [`NsfBoard`].
+//!
+//! Expansion sound chips are recorded in the header but not emulated, tracks using them play without those
+//! channels.
+
+mod player;
+
+pub use player::NsfPlayer;
+
+use crate::cartridge::Mirroring;
+use crate::mapper::state::{StateError, StateReader, StateWriter};
+use crate::mapper::Mapper;
+use crate::region::Region;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC : &[u8] = b"NESM\x1a";
+const HEADER_SIZE : usize = 0x80;
+const BANK_SIZE : usize = 0x1000;
+const BANK_REGISTERS : u16 = 0x5ff8;
+const BANK_REGISTERS_END : u16 = 0x5fff;
+const RAM : u16 = 0x6000;
+const RAM_END : u16 = 0x7fff;
+const RAM_SIZE : usize = 0x2000;
+const ROM : u16 = 0x8000;
+
+const PAL : u8 = 0b01;
+const DUAL_REGION : u8 = 0b10;
+
+/// Why an NSF could not be loaded or played.
+#[derive(Debug)]
+pub enum NsfError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file does not start with `NESM\x1a`.
+    NotNsf,
+    /// The file is too short for its header, or has no music data.
+    Truncated,
+    /// The load address is below $8000, where there is nothing to load into.
+    BadLoadAddress(u16),
+    /// Tracks are numbered from 0 up to one less than [`Nsf::total_songs`].
+    NoSuchTrack(u8),
+    /// The CPU could not run INIT or PLAY.
+    Cpu(crate::cpu::CpuError),
+    /// INIT or PLAY, at the address given, did not return.
+    RoutineDidNotReturn(u16),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::Io(error) => write!(f, "could not read NSF: {}", error),
+            NsfError::NotNsf => write!(f, "not an NSF file"),
+            NsfError::Truncated => write!(f, "NSF is truncated"),
+            NsfError::BadLoadAddress(address) => write!(f, "NSF load address {:#06x} is below $8000", address),
+            NsfError::NoSuchTrack(track) => write!(f, "there is no track {}", track),
+            NsfError::Cpu(error) => write!(f, "{}", error),
+            NsfError::RoutineDidNotReturn(address) => write!(f, "the routine at {:#06x} did not return", address),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NsfError::Io(error) => Some(error),
+            NsfError::Cpu(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NsfError {
+    fn from(error : io::Error) -> Self {
+        NsfError::Io(error)
+    }
+}
+
+impl From<crate::cpu::CpuError> for NsfError {
+    fn from(error : crate::cpu::CpuError) -> Self {
+        NsfError::Cpu(error)
+    }
+}
+
+/// A parsed NSF file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsf {
+    pub version : u8,
+    pub total_songs : u8,
+    /// The track to play first, counted from 0 (the file counts from 1).
+    pub starting_song : u8,
+    pub load_address : u16,
+    pub init_address : u16,
+    pub play_address : u16,
+    pub title : String,
+    pub artist : String,
+    pub copyright : String,
+    /// Microseconds between PLAY calls on NTSC.
+    pub ntsc_speed : u16,
+    /// Microseconds between PLAY calls on PAL.
+    pub pal_speed : u16,
+    /// The initial banks at $8000-$FFFF, all zero when the NSF is not bankswitched.
+    pub bankswitch : [u8 ; 8],
+    /// The region bits: bit 0 for PAL, bit 1 when the music plays on both.
+    pub region_flags : u8,
+    /// The expansion sound chips the music uses.
+    pub sound_chips : u8,
+    pub data : Vec<u8>,
+}
+
+impl Nsf {
+    /// Parses an NSF image.
+    ///
+    /// # Errors
+    /// [`NsfError::NotNsf`] when the magic number is wrong, [`NsfError::Truncated`] when the file ends in the
+    /// header or has no data, and [`NsfError::BadLoadAddress`].
+    pub fn from_bytes(bytes : &[u8]) -> Result<Nsf, NsfError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(NsfError::NotNsf);
+        }
+        if bytes.len() <= HEADER_SIZE {
+            return Err(NsfError::Truncated);
+        }
+        let word = |offset : usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let text = |offset : usize| {
+            let field = &bytes[offset .. offset + 32];
+            let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[.. end]).into_owned()
+        };
+        let load_address = word(0x08);
+        if load_address < ROM {
+            return Err(NsfError::BadLoadAddress(load_address));
+        }
+        Ok(Nsf {
+            version : bytes[0x05],
+            total_songs : bytes[0x06],
+            starting_song : bytes[0x07].saturating_sub(1),
+            load_address,
+            init_address : word(0x0a),
+            play_address : word(0x0c),
+            title : text(0x0e),
+            artist : text(0x2e),
+            copyright : text(0x4e),
+            ntsc_speed : word(0x6e),
+            bankswitch : bytes[0x70 .. 0x78].try_into().unwrap(),
+            pal_speed : word(0x78),
+            region_flags : bytes[0x7a],
+            sound_chips : bytes[0x7b],
+            data : bytes[HEADER_SIZE ..].to_vec(),
+        })
+    }
+
+    /// Reads and parses the NSF file at `path`.
+    pub fn from_file<P : AsRef<Path>>(path : P) -> Result<Nsf, NsfError> {
+        Nsf::from_bytes(&fs::read(path)?)
+    }
+
+    /// Whether the music is split into banks, which it is as soon as one initial bank is not zero.
+    pub fn is_bankswitched(&self) -> bool {
+        self.bankswitch.iter().any(|&bank| bank != 0)
+    }
+
+    /// The console the music was made for, NTSC for NSFs that play on both.
+    pub fn region(&self) -> Region {
+        if self.region_flags & (PAL | DUAL_REGION) == PAL {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    /// Microseconds between PLAY calls on `region`, a frame when the header leaves it 0.
+    pub fn play_period_us(&self, region : Region) -> f64 {
+        let speed = if region == Region::Ntsc { self.ntsc_speed } else { self.pal_speed };
+        if speed == 0 {
+            1_000_000.0 / region.frame_rate()
+        } else {
+            speed as f64
+        }
+    }
+}
+
+/// The cartridge an NSF plays from: 8KiB of RAM at $6000-$7FFF and the music data at $8000-$FFFF, banked 4KiB
+/// at a time through $5FF8-$5FFF for bankswitched NSFs.
+pub struct NsfBoard {
+    /// The data, after padding that puts the load address at the right place in its bank.
+    data : Vec<u8>,
+    banks : [u8 ; 8],
+    ram : Vec<u8>,
+}
+
+impl NsfBoard {
+    /// Lays out the data of `nsf`.
+    pub fn new(nsf : &Nsf) -> Self {
+        let (padding, banks) = if nsf.is_bankswitched() {
+            (nsf.load_address as usize % BANK_SIZE, nsf.bankswitch)
+        } else {
+            ((nsf.load_address - ROM) as usize, [0, 1, 2, 3, 4, 5, 6, 7])
+        };
+        let mut data = vec![0 ; padding];
+        data.extend_from_slice(&nsf.data);
+        NsfBoard { data, banks, ram : vec![0 ; RAM_SIZE] }
+    }
+}
+
+impl Mapper for NsfBoard {
+    fn cpu_peek(&self, addr : u16) -> Option<u8> {
+        match addr {
+            RAM ..= RAM_END => Some(self.ram[(addr - RAM) as usize]),
+            ROM ..= 0xffff => {
+                let bank = self.banks[(addr - ROM) as usize / BANK_SIZE] as usize;
+                // Past the end of the data reads as 0, NSFs often end well short of their last bank.
+                Some(self.data.get(bank * BANK_SIZE + (addr as usize % BANK_SIZE)).copied().unwrap_or(0))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr : u16, value : u8) {
+        match addr {
+            BANK_REGISTERS ..= BANK_REGISTERS_END => self.banks[(addr - BANK_REGISTERS) as usize] = value,
+            RAM ..= RAM_END => self.ram[(addr - RAM) as usize] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_peek(&self, _addr : u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _addr : u16, _value : u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state : &mut StateWriter) {
+        state.write(&self.banks);
+        state.write(&self.ram);
+    }
+
+    fn load_state(&mut self, state : &mut StateReader) -> Result<(), StateError> {
+        state.read(&mut self.banks)?;
+        state.read(&mut self.ram)
+    }
+}
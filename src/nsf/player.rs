@@ -0,0 +1,153 @@
+//! # Player Module
+//!
+//! `player` plays an [`Nsf`] on a [`Bus`]: the music data goes in the cartridge slot as an [`NsfBoard`], and the
+//! CPU is pointed at INIT and PLAY as a real NSF player's code would, returning to an address where nothing is
+//! mapped so the player can tell when the routine is done.
+
+use super::{Nsf, NsfBoard, NsfError};
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cpu::CPU;
+use crate::mem::Mem;
+use crate::region::Region;
+
+/// Where INIT and PLAY return to. Nothing is mapped there, the player stops the CPU on reaching it.
+const RETURN_ADDRESS : u16 = 0x4100;
+/// How long INIT or PLAY may run before the player gives up on it, about two seconds.
+const ROUTINE_CYCLE_LIMIT : u64 = 4_000_000;
+const STACK : u16 = 0x0100;
+const STACK_RESET : u8 = 0xfd;
+const RAM_END : u16 = 0x07ff;
+const PRG_RAM : u16 = 0x6000;
+const PRG_RAM_END : u16 = 0x7fff;
+const APU_REGISTERS_END : u16 = 0x4013;
+const APU_STATUS : u16 = 0x4015;
+const APU_FRAME_COUNTER : u16 = 0x4017;
+const BANK_REGISTERS : u16 = 0x5ff8;
+
+/// Plays the tracks of an NSF.
+///
+/// # Example
+/// ```no_run
+///  use nes::nsf::{Nsf, NsfPlayer};
+///
+///  let mut player = NsfPlayer::new(Nsf::from_file("music.nsf").unwrap()).unwrap();
+///  player.select_track(2).unwrap();
+///  for _ in 0 .. 60 {
+///      player.play_frame().unwrap();
+///  }
+/// ```
+pub struct NsfPlayer {
+    cpu : CPU<Bus>,
+    nsf : Nsf,
+    region : Region,
+    track : u8,
+    /// CPU cycles from one PLAY call to the next.
+    play_period : u64,
+}
+
+impl NsfPlayer {
+    /// Sets up the machine for `nsf` on the console it was made for, and starts its first track.
+    pub fn new(nsf : Nsf) -> Result<NsfPlayer, NsfError> {
+        let region = nsf.region();
+        NsfPlayer::with_region(nsf, region)
+    }
+
+    /// Like [`NsfPlayer::new`], on `region`'s console whatever the NSF was made for.
+    pub fn with_region(nsf : Nsf, region : Region) -> Result<NsfPlayer, NsfError> {
+        let mut bus = Bus::new();
+        bus.set_region(region);
+        bus.insert_cartridge(Cartridge::with_mapper(0, NsfBoard::new(&nsf)));
+        let cpu = CPU::with_memory(bus);
+        let play_period = (nsf.play_period_us(region) * region.cpu_clock_hz() as f64 / 1_000_000.0).round() as u64;
+        let track = nsf.starting_song;
+        let mut player = NsfPlayer { cpu, nsf, region, track, play_period };
+        player.select_track(track)?;
+        Ok(player)
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The track playing, counted from 0.
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    /// The machine playing the music, whose devices produce the sound.
+    pub fn cpu(&self) -> &CPU<Bus> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<Bus> {
+        &mut self.cpu
+    }
+
+    /// How many times a second [`NsfPlayer::play_frame`] should be called.
+    pub fn play_rate_hz(&self) -> f64 {
+        self.region.cpu_clock_hz() as f64 / self.play_period as f64
+    }
+
+    /// Starts track `track`, counted from 0: clears the RAM, silences the APU, puts back the initial banks and
+    /// calls INIT with the track in A and the region in X (0 for NTSC, 1 for PAL).
+    ///
+    /// # Errors
+    /// [`NsfError::NoSuchTrack`], or an error running INIT.
+    pub fn select_track(&mut self, track : u8) -> Result<(), NsfError> {
+        if track >= self.nsf.total_songs {
+            return Err(NsfError::NoSuchTrack(track));
+        }
+        let bus = self.cpu.memory_mut();
+        for addr in (0 ..= RAM_END).chain(PRG_RAM ..= PRG_RAM_END).chain(0x4000 ..= APU_REGISTERS_END) {
+            bus.write(addr, 0);
+        }
+        bus.write(APU_STATUS, 0x00);
+        bus.write(APU_STATUS, 0x0f);
+        bus.write(APU_FRAME_COUNTER, 0x40);
+        if self.nsf.is_bankswitched() {
+            for (register, &bank) in (BANK_REGISTERS ..).zip(&self.nsf.bankswitch) {
+                bus.write(register, bank);
+            }
+        }
+
+        self.cpu.register_a = track;
+        self.cpu.register_x = (self.region == Region::Pal) as u8;
+        self.cpu.register_y = 0;
+        self.cpu.stack_pointer = STACK_RESET;
+        self.track = track;
+        self.call(self.nsf.init_address)
+    }
+
+    /// Calls PLAY, then lets the machine run on until the next call is due.
+    pub fn play_frame(&mut self) -> Result<(), NsfError> {
+        let start = self.cpu.cycles;
+        self.call(self.nsf.play_address)?;
+        let idle = self.play_period.saturating_sub(self.cpu.cycles - start);
+        self.cpu.cycles += idle;
+        self.cpu.memory_mut().tick(idle);
+        Ok(())
+    }
+
+    /// Runs the routine at `address` as if called with JSR, until it returns.
+    fn call(&mut self, address : u16) -> Result<(), NsfError> {
+        for byte in (RETURN_ADDRESS - 1).to_be_bytes() {
+            let top = STACK + self.cpu.stack_pointer as u16;
+            self.cpu.memory_mut().write(top, byte);
+            self.cpu.stack_pointer = self.cpu.stack_pointer.wrapping_sub(1);
+        }
+        self.cpu.program_counter = address;
+        let start = self.cpu.cycles;
+        while self.cpu.program_counter != RETURN_ADDRESS {
+            if self.cpu.is_halted() || self.cpu.cycles - start > ROUTINE_CYCLE_LIMIT {
+                return Err(NsfError::RoutineDidNotReturn(address));
+            }
+            self.cpu.step()?;
+        }
+        Ok(())
+    }
+}
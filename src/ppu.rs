@@ -0,0 +1,902 @@
+//! # PPU Module
+//!
+//! `ppu` is the picture processing unit, the chip that draws the picture. The CPU talks to it through eight
+//! registers at $2000-$2007, mirrored up to $3FFF:
+//!
+//! | Register | Name      | Access | Purpose                                                               |
+//! |----------|-----------|--------|-----------------------------------------------------------------------|
+//! | $2000    | PPUCTRL   | Write  | NMI enable, sprite size, pattern tables, VRAM increment, nametable    |
+//! | $2001    | PPUMASK   | Write  | Greyscale, left column clipping, background and sprites on, emphasis  |
+//! | $2002    | PPUSTATUS | Read   | Vertical blank, sprite 0 hit, sprite overflow. Reading clears vblank  |
+//! | $2003    | OAMADDR   | Write  | Where OAMDATA reads and writes in OAM                                 |
+//! | $2004    | OAMDATA   | Both   | Sprite memory, writes step OAMADDR                                    |
+//! | $2005    | PPUSCROLL | Write  | X then Y scroll, two writes                                           |
+//! | $2006    | PPUADDR   | Write  | High then low byte of the VRAM address, two writes                    |
+//! | $2007    | PPUDATA   | Both   | VRAM at the VRAM address, which steps by 1 or 32 after every access   |
+//!
+//! PPUSCROLL and PPUADDR share the toggle that says which of the two writes is next, reading PPUSTATUS resets it.
+//! Both go through the PPU's internal registers: v, the current VRAM address, t, the temporary one, and fine X.
+//! PPUCTRL's nametable bits, PPUSCROLL and the first PPUADDR write set bits of t (and fine X), the second PPUADDR
+//! write copies t into v. While rendering, v is where the PPU is in the nametables, laid out as
+//! `yyy NN YYYYY XXXXX` (fine Y, nametable, coarse Y and coarse X): it steps coarse X after every tile and Y at
+//! dot 256, takes the horizontal bits from t again at dot 257 and the vertical ones during dots 280-304 of the
+//! pre-render scanline. PPUDATA accesses while rendering step both coarse X and Y rather than adding 1 or 32.
+//!
+//! Reads of PPUDATA are delayed by one: they return a buffer and then refill it from the address read. The palette
+//! is inside the PPU and answers at once, the buffer is filled with the nametable byte underneath it instead.
+//!
+//! The PPU has its own 14 bit address space: the pattern tables at $0000-$1FFF are on the cartridge, the
+//! nametables are at $2000-$2FFF (mirrored at $3000-$3EFF) and the palette at $3F00-$3FFF. The cartridge is
+//! passed in to every access that may reach it, as the [`Mapper`] of the board.
+//!
+//! The PPU has 2KiB of VRAM, room for two nametables. The cartridge decides how they fill the four nametable slots
+//! with its [`Mirroring`], four-screen boards add 2KiB of their own, and some boards supply nametables from their
+//! own memory instead (see [`Mapper::nametable_read`]).
+//!
+//! The palette is 32 entries of 6 bit colours, mirrored all the way up to $3FFF: eight palettes of four colours,
+//! four for the background then four for the sprites. Colour 0 of every palette is the backdrop, so the sprite
+//! palettes' entries 0 ($3F10, $3F14, $3F18 and $3F1C) are the same bytes as the background palettes'.
+//!
+//! The PPU is clocked by [`Ppu::tick`], one call per dot or a batch of them. A frame is 262 scanlines of 341 dots
+//! (312 on PAL and Dendy): 240 visible scanlines, then vertical blank, then the pre-render scanline. Dots 1-256 of
+//! a visible scanline each draw a pixel into [`Ppu::frame`], with the registers as they are at that dot. With
+//! rendering off the pixel is the backdrop, or the palette entry v points at when it is in the palette, which is
+//! where the colours a game writes mid-frame show up.
+//!
+//! The background comes from the nametables, attribute tables and the pattern table PPUCTRL picks. The PPU fetches
+//! a tile every 8 dots, its nametable byte, attribute byte and two pattern bytes, two tiles ahead of the one it is
+//! drawing: the first two of a scanline at dots 321-336 of the scanline before. Each tile is loaded into the low
+//! half of 16 bit shift registers, which move on a bit every dot, and fine X picks which of their top 8 bits is
+//! drawn, so the picture scrolls a pixel at a time.
+//!
+//! OAM holds 64 sprites of four bytes: Y (the sprite shows from the scanline after it), tile, attributes and X.
+//! The attributes are the palette in bits 0-1, behind the background in bit 5, and horizontal and vertical flip
+//! in bits 6 and 7. From dot 257 of a scanline the PPU fetches the patterns of the first eight sprites on the next
+//! one, which it found in OAM and copied into secondary OAM, fetching tile $FF for the slots left empty. Sprites
+//! past the eighth are not drawn. Where sprites overlap the one earlier in OAM is in front, and a sprite behind the
+//! background still hides the sprites after it.
+//!
+//! With PPUCTRL bit 5 set sprites are 8x16, two tiles one above the other. The pattern table is then bit 0 of the
+//! tile number rather than PPUCTRL bit 3, and the top tile is the even one of the pair the rest of the number
+//! picks. Flipping vertically swaps the two tiles too.
+//!
+//! Sprite 0 hit, PPUSTATUS bit 6, is set at the dot where an opaque pixel of sprite 0 is first drawn over an
+//! opaque background pixel, whatever the sprite's priority. It needs both the background and sprites shown, never
+//! happens at x 255 or in the leftmost 8 pixels while either is clipped there, and stays set until the pre-render
+//! scanline.
+//!
+//! Sprite overflow, PPUSTATUS bit 5, is meant to be set when a scanline has more than eight sprites. After finding
+//! eight the PPU goes on through OAM for a ninth, but a bug steps it to the next byte of each sprite as well as
+//! the next sprite: it compares the tile, attribute and X bytes of the sprites after the eighth as if they were Y,
+//! diagonally through OAM. So it misses ninth sprites, and sees ones that are not there. The flag is set while
+//! the PPU fetches the sprites of the next scanline, and cleared with sprite 0 hit.
+//!
+//! Vertical blank starts at dot 1 of scanline 241, setting PPUSTATUS bit 7. While the flag is set and PPUCTRL bit
+//! 7 is too, the PPU pulls the CPU's NMI line: the CPU is interrupted as vertical blank starts, or as soon as the
+//! bit is set during it. Reading PPUSTATUS clears the flag, and the pre-render scanline clears it with the others.
+
+use crate::cartridge::{Mirroring, NAMETABLE_SIZE};
+use crate::mapper::Mapper;
+use crate::region::Region;
+use std::ops::RangeInclusive;
+
+pub const PPUCTRL : u16 = 0;
+pub const PPUMASK : u16 = 1;
+pub const PPUSTATUS : u16 = 2;
+pub const OAMADDR : u16 = 3;
+pub const OAMDATA : u16 = 4;
+pub const PPUSCROLL : u16 = 5;
+pub const PPUADDR : u16 = 6;
+pub const PPUDATA : u16 = 7;
+
+/// The PPU decodes three address lines, the registers repeat every 8 bytes.
+const REGISTER_MASK : u16 = 0x0007;
+/// The PPU address space is 14 bits.
+const ADDRESS_MASK : u16 = 0x3fff;
+const PATTERN_TABLES_END : u16 = 0x1fff;
+const NAMETABLES : u16 = 0x2000;
+const NAMETABLES_END : u16 = 0x3eff;
+const PALETTE : u16 = 0x3f00;
+/// The nametable byte a palette read puts in the PPUDATA buffer is $1000 below the palette address.
+const PALETTE_SHADOW : u16 = 0x1000;
+
+/// PPUCTRL: the nametable at the top left of the screen.
+const NAMETABLE_SELECT : u8 = 0b0000_0011;
+/// PPUCTRL: the VRAM address steps by 32 (a row of tiles) rather than 1.
+const INCREMENT_32 : u8 = 0b0000_0100;
+/// PPUCTRL: the sprite tiles are in the pattern table at $1000 rather than $0000.
+const SPRITE_TABLE : u8 = 0b0000_1000;
+/// PPUCTRL: the background tiles are in the pattern table at $1000 rather than $0000.
+const BACKGROUND_TABLE : u8 = 0b0001_0000;
+/// PPUCTRL: sprites are 8x16 rather than 8x8.
+const TALL_SPRITES : u8 = 0b0010_0000;
+/// PPUCTRL: the PPU pulls the CPU's NMI line while the vblank flag is set.
+const GENERATE_NMI : u8 = 0b1000_0000;
+/// PPUMASK: colours are shown without their hue, bits 0-3.
+const GREYSCALE : u8 = 0b0000_0001;
+const GREYSCALE_BITS : u8 = 0b0011_0000;
+/// PPUMASK: the background is shown in the leftmost 8 pixels too.
+const SHOW_BACKGROUND_LEFT : u8 = 0b0000_0010;
+const SHOW_SPRITES_LEFT : u8 = 0b0000_0100;
+const SHOW_BACKGROUND : u8 = 0b0000_1000;
+const SHOW_SPRITES : u8 = 0b0001_0000;
+/// PPUSTATUS: vertical blank has started.
+pub const STATUS_VBLANK : u8 = 0b1000_0000;
+/// PPUSTATUS: sprite 0 has been drawn over the background this frame.
+pub const STATUS_SPRITE_ZERO_HIT : u8 = 0b0100_0000;
+/// PPUSTATUS: the sprite evaluation of a scanline found a ninth sprite, or thought it did.
+pub const STATUS_SPRITE_OVERFLOW : u8 = 0b0010_0000;
+/// PPUSTATUS: the bits the PPU drives, the others come from its data latch.
+const STATUS_BITS : u8 = 0b1110_0000;
+/// How many dots a bit of the PPU's data latch holds its value for when it is not refreshed, roughly 600ms.
+pub const LATCH_DECAY : u64 = 3_220_000;
+/// OAM bits 2-4 of the sprite attribute byte do not exist and read back as 0.
+const OAM_ATTRIBUTE_BITS : u8 = 0b1110_0011;
+/// Sprite attributes: the palette, of the four sprite palettes.
+const SPRITE_PALETTE : u8 = 0b0000_0011;
+const BEHIND_BACKGROUND : u8 = 0b0010_0000;
+const FLIP_HORIZONTAL : u8 = 0b0100_0000;
+const FLIP_VERTICAL : u8 = 0b1000_0000;
+
+pub const OAM_SIZE : usize = 0x100;
+/// Secondary OAM, the sprites on the next scanline.
+const SPRITES_PER_SCANLINE : usize = 8;
+const SPRITE_SIZE : usize = 4;
+/// The palette entries of the sprites come after the background's.
+const SPRITE_PALETTES : u8 = 0x10;
+/// The console's 2KiB of VRAM and the 2KiB four-screen boards add.
+const VRAM_SIZE : usize = 4 * NAMETABLE_SIZE;
+pub const PALETTE_SIZE : usize = 0x20;
+/// Palette entries are 6 bits, a read gives the top two bits of the PPU's data latch with them.
+const PALETTE_BITS : u8 = 0b0011_1111;
+
+/// The picture is 256x240 pixels.
+pub const WIDTH : usize = 256;
+pub const HEIGHT : usize = 240;
+pub const DOTS_PER_SCANLINE : u16 = 341;
+/// The dot the patterns of the next scanline's sprites are fetched at.
+const SPRITE_FETCH_DOT : u16 = 257;
+/// The dots the PPU fetches background tiles in, stepping coarse X after each: the scanline's, then the first two
+/// of the next scanline.
+const TILE_DOTS : RangeInclusive<u16> = 1 ..= 256;
+const PREFETCH_DOTS : RangeInclusive<u16> = 321 ..= 336;
+/// The dots the background shift registers move on at, and are loaded with the next tile every 8 dots.
+const SHIFT_DOTS : RangeInclusive<u16> = 2 ..= 257;
+const PREFETCH_SHIFT_DOTS : RangeInclusive<u16> = 322 ..= 337;
+/// The nametable fetches at the end of a scanline, which boards like the MMC5 count scanlines by.
+const DUMMY_NAMETABLE_DOTS : [u16 ; 2] = [337, 339];
+/// The dot v steps to the next row of pixels at.
+const INCREMENT_Y_DOT : u16 = 256;
+/// The dot v takes the horizontal bits of t at, and the dots of the pre-render scanline it takes the vertical ones.
+const COPY_HORIZONTAL_DOT : u16 = 257;
+const COPY_VERTICAL_DOTS : RangeInclusive<u16> = 280 ..= 304;
+/// The dot the pre-render scanline clears the flags of PPUSTATUS at.
+const CLEAR_FLAGS_DOT : u16 = 1;
+/// Where vertical blank starts, setting the vblank flag.
+const VBLANK_SCANLINE : u16 = 241;
+const VBLANK_DOT : u16 = 1;
+/// Sprite 0 hit never happens on the last pixel of a scanline.
+const SPRITE_ZERO_HIT_END : usize = WIDTH - 1;
+/// The dot the pre-render scanline of odd NTSC frames ends after, when rendering is on.
+const ODD_FRAME_LAST_DOT : u16 = 339;
+const TILE_SIZE : usize = 8;
+/// The attribute table is the last 64 bytes of a nametable, one byte for each 4x4 tiles.
+const ATTRIBUTE_TABLE : u16 = 0x3c0;
+
+/// The fields of v and t, `yyy NN YYYYY XXXXX`.
+const COARSE_X : u16 = 0x001f;
+const COARSE_Y : u16 = 0x03e0;
+const NAMETABLE_X : u16 = 0x0400;
+const NAMETABLE_Y : u16 = 0x0800;
+const FINE_Y : u16 = 0x7000;
+const HORIZONTAL_BITS : u16 = NAMETABLE_X | COARSE_X;
+const VERTICAL_BITS : u16 = FINE_Y | NAMETABLE_Y | COARSE_Y;
+/// Coarse Y past the last row of tiles, where it wraps to the next nametable.
+const LAST_ROW : u16 = 29;
+const FINE_X_BITS : u8 = 0b111;
+/// A tile is 16 bytes: 8 rows of low bits, then 8 rows of high bits.
+const TILE_BYTES : u16 = 16;
+/// The tile fetched for the empty slots of secondary OAM.
+const EMPTY_SPRITE_TILE : u8 = 0xff;
+/// 8x16 sprites take the pattern table from bit 0 of the tile number.
+const TALL_SPRITE_TABLE : u8 = 0b0000_0001;
+
+/// The picture processing unit.
+///
+/// # Example
+/// ```
+///  use nes::ppu::{Ppu, PPUADDR, PPUCTRL};
+///
+///  let mut ppu = Ppu::new();
+///  ppu.write_register(PPUCTRL, 0x04, None);
+///  ppu.write_register(PPUADDR, 0x21, None);
+///  ppu.write_register(PPUADDR, 0x08, None);
+///  assert_eq!(ppu.vram_addr(), 0x2108);
+/// ```
+pub struct Ppu {
+    ctrl : u8,
+    mask : u8,
+    status : u8,
+    oam_addr : u8,
+    oam : [u8 ; OAM_SIZE],
+    /// v, the current VRAM address.
+    vram_addr : u16,
+    /// t, the temporary VRAM address: where the top left of the screen is for the next frame.
+    temp_vram_addr : u16,
+    fine_x : u8,
+    /// w, the next PPUSCROLL or PPUADDR write is the second of the pair.
+    write_toggle : bool,
+    /// What the last PPUDATA read fetched, returned by the next.
+    read_buffer : u8,
+    /// The PPU's own data bus, which keeps the last value written or read through its registers. Each bit fades
+    /// to 0 on its own, see [`Ppu::latch`].
+    latch : u8,
+    /// The dot (counting [`Ppu::dots`]) each bit of the latch was last driven on.
+    latch_refreshed : [u64 ; 8],
+    dots : u64,
+    vram : [u8 ; VRAM_SIZE],
+    palette : [u8 ; PALETTE_SIZE],
+    region : Region,
+    scanline : u16,
+    dot : u16,
+    frame_count : u64,
+    /// The sprites found on the next scanline, as copied from OAM, with $FF in the empty slots.
+    secondary_oam : [u8 ; SPRITES_PER_SCANLINE * SPRITE_SIZE],
+    /// The sprites of secondary OAM with their patterns fetched, drawn on the next scanline.
+    sprites : [Sprite ; SPRITES_PER_SCANLINE],
+    sprite_count : usize,
+    /// The first sprite of `sprites` is sprite 0.
+    sprite_zero_on_scanline : bool,
+    /// The tile being fetched, loaded into the shift registers once it is whole.
+    next_tile : BackgroundTile,
+    /// The background shift registers: the two bits of the pattern, and the two bits of the palette.
+    pattern_shift : [u16 ; 2],
+    palette_shift : [u16 ; 2],
+    /// The picture, one palette colour (0-63) per pixel, row by row.
+    frame : Vec<u8>,
+    /// The NMI line has been pulled since [`Ppu::take_nmi`] last looked.
+    nmi : bool,
+}
+
+/// A background tile as fetched: its number, its palette from the attribute table, and its pattern for the row of
+/// pixels.
+#[derive(Debug, Clone, Copy, Default)]
+struct BackgroundTile {
+    tile : u8,
+    palette : u8,
+    pattern : [u8 ; 2],
+}
+
+/// A sprite ready to draw: its pattern for the scanline, already flipped horizontally if need be.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sprite {
+    x : u8,
+    attributes : u8,
+    pattern : [u8 ; 2],
+}
+
+impl Sprite {
+    /// The sprite's pixel (0-3) at `x` on the screen, 0 where it is transparent or not there.
+    fn pixel(&self, x : usize) -> u8 {
+        match x.checked_sub(self.x as usize) {
+            Some(column) if column < TILE_SIZE => {
+                let shift = 7 - column;
+                (self.pattern[0] >> shift & 1) | (self.pattern[1] >> shift & 1) << 1
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    /// The PPU as it powers on, with every register cleared.
+    pub fn new() -> Self {
+        Ppu {
+            ctrl : 0,
+            mask : 0,
+            status : 0,
+            oam_addr : 0,
+            oam : [0 ; OAM_SIZE],
+            vram_addr : 0,
+            temp_vram_addr : 0,
+            fine_x : 0,
+            write_toggle : false,
+            read_buffer : 0,
+            latch : 0,
+            latch_refreshed : [0 ; 8],
+            dots : 0,
+            vram : [0 ; VRAM_SIZE],
+            palette : [0 ; PALETTE_SIZE],
+            region : Region::default(),
+            scanline : 0,
+            dot : 0,
+            frame_count : 0,
+            secondary_oam : [0xff ; SPRITES_PER_SCANLINE * SPRITE_SIZE],
+            sprites : [Sprite::default() ; SPRITES_PER_SCANLINE],
+            sprite_count : 0,
+            sprite_zero_on_scanline : false,
+            next_tile : BackgroundTile::default(),
+            pattern_shift : [0 ; 2],
+            palette_shift : [0 ; 2],
+            frame : vec![0 ; WIDTH * HEIGHT],
+            nmi : false,
+        }
+    }
+
+    /// Runs the PPU for `dots` dots, drawing the scanlines it passes the end of.
+    pub fn tick(&mut self, dots : u64, mut mapper : Option<&mut dyn Mapper>) {
+        self.dots += dots;
+        let mut dots = dots;
+        // With rendering off every frame is the same length and the same picture: drawing the last is enough.
+        let frame_dots = self.region.scanlines_per_frame() * DOTS_PER_SCANLINE as u64;
+        let position = self.scanline as u64 * DOTS_PER_SCANLINE as u64 + self.dot as u64;
+        let to_frame_start = (frame_dots - position) % frame_dots;
+        if !self.rendering_enabled() && dots >= to_frame_start + 2 * frame_dots {
+            for _ in 0 .. to_frame_start {
+                self.step(reborrow(&mut mapper));
+            }
+            let frames = (dots - to_frame_start) / frame_dots - 1;
+            self.frame_count += frames;
+            dots -= to_frame_start + frames * frame_dots;
+        }
+
+        for _ in 0 .. dots {
+            self.step(reborrow(&mut mapper));
+        }
+    }
+
+    fn step(&mut self, mut mapper : Option<&mut dyn Mapper>) {
+        let visible = (self.scanline as usize) < HEIGHT;
+        let pre_render = self.scanline as u64 == self.region.scanlines_per_frame() - 1;
+        if self.rendering() {
+            self.fetch_background(reborrow(&mut mapper));
+        }
+        if visible && TILE_DOTS.contains(&self.dot) {
+            self.render_pixel();
+        }
+        if (visible || pre_render) && self.dot == SPRITE_FETCH_DOT {
+            self.fetch_sprites(visible, mapper);
+        }
+        if (self.scanline, self.dot) == (VBLANK_SCANLINE, VBLANK_DOT) {
+            self.status |= STATUS_VBLANK;
+            self.nmi |= self.nmi_line();
+        }
+        if pre_render && self.dot == CLEAR_FLAGS_DOT {
+            self.status &= !STATUS_BITS;
+        }
+        if self.rendering() {
+            self.update_vram_addr(pre_render);
+        }
+
+        // The NTSC PPU skips the last dot of the pre-render scanline on odd frames while rendering.
+        let skip = pre_render
+            && self.dot == ODD_FRAME_LAST_DOT
+            && self.region == Region::Ntsc
+            && self.frame_count % 2 == 1
+            && self.rendering_enabled();
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE || skip {
+            self.dot = 0;
+            self.scanline += 1;
+            if pre_render {
+                self.scanline = 0;
+                self.frame_count += 1;
+            }
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (SHOW_BACKGROUND | SHOW_SPRITES) != 0
+    }
+
+    /// Whether the PPU is drawing or getting ready to: rendering is on and it is on a visible or the pre-render
+    /// scanline.
+    fn rendering(&self) -> bool {
+        let scanline = self.scanline as u64;
+        self.rendering_enabled() && (scanline < HEIGHT as u64 || scanline == self.region.scanlines_per_frame() - 1)
+    }
+
+    /// Moves v on through the nametables as rendering does at the current dot.
+    fn update_vram_addr(&mut self, pre_render : bool) {
+        let dot = self.dot;
+        if (TILE_DOTS.contains(&dot) || PREFETCH_DOTS.contains(&dot)) && dot.is_multiple_of(TILE_SIZE as u16) {
+            self.vram_addr = increment_coarse_x(self.vram_addr);
+        }
+        if dot == INCREMENT_Y_DOT {
+            self.vram_addr = increment_y(self.vram_addr);
+        }
+        if dot == COPY_HORIZONTAL_DOT {
+            self.vram_addr = (self.vram_addr & !HORIZONTAL_BITS) | (self.temp_vram_addr & HORIZONTAL_BITS);
+        }
+        if pre_render && COPY_VERTICAL_DOTS.contains(&dot) {
+            self.vram_addr = (self.vram_addr & !VERTICAL_BITS) | (self.temp_vram_addr & VERTICAL_BITS);
+        }
+    }
+
+    /// The background fetches of the current dot, and the shift registers moving on and taking in the last tile.
+    fn fetch_background(&mut self, mapper : Option<&mut dyn Mapper>) {
+        let dot = self.dot;
+        if SHIFT_DOTS.contains(&dot) || PREFETCH_SHIFT_DOTS.contains(&dot) {
+            self.pattern_shift = self.pattern_shift.map(|bits| bits << 1);
+            self.palette_shift = self.palette_shift.map(|bits| bits << 1);
+            if dot % TILE_SIZE as u16 == 1 {
+                let tile = self.next_tile;
+                self.pattern_shift = [0, 1].map(|plane| self.pattern_shift[plane] | tile.pattern[plane] as u16);
+                // The palette is the same for all 8 pixels.
+                let palette = [0, 1].map(|bit| if tile.palette >> bit & 1 != 0 { 0xff } else { 0x00 });
+                self.palette_shift = [0, 1].map(|bit| self.palette_shift[bit] | palette[bit]);
+            }
+        }
+
+        let v = self.vram_addr;
+        let nametable_addr = NAMETABLES | (v & (NAMETABLE_X | NAMETABLE_Y | COARSE_Y | COARSE_X));
+        if DUMMY_NAMETABLE_DOTS.contains(&dot) {
+            self.read_memory(nametable_addr, mapper);
+            return;
+        }
+        if !TILE_DOTS.contains(&dot) && !PREFETCH_DOTS.contains(&dot) {
+            return;
+        }
+        let table = if self.ctrl & BACKGROUND_TABLE != 0 { 0x1000 } else { 0x0000 };
+        let pattern_addr = table + self.next_tile.tile as u16 * TILE_BYTES + (v >> 12);
+        match dot % TILE_SIZE as u16 {
+            1 => self.next_tile.tile = self.read_memory(nametable_addr, mapper),
+            3 => {
+                // One attribute byte for each 4x4 tiles, 8 to a row, two bits for each 2x2.
+                let nametable = NAMETABLES | (v & (NAMETABLE_X | NAMETABLE_Y));
+                let attribute_addr = nametable + ATTRIBUTE_TABLE + ((v >> 4) & 0x38 | (v >> 2) & 0x07);
+                let attribute = self.read_memory(attribute_addr, mapper);
+                self.next_tile.palette = attribute >> ((v >> 4) & 0b100 | v & 0b010) & 0b11;
+            }
+            5 => self.next_tile.pattern[0] = self.read_memory(pattern_addr, mapper),
+            7 => self.next_tile.pattern[1] = self.read_memory(pattern_addr + TILE_BYTES / 2, mapper),
+            _ => {}
+        }
+    }
+
+    /// Draws pixel `dot - 1` of the current scanline.
+    fn render_pixel(&mut self) {
+        let x = self.dot as usize - 1;
+        let left_column = x < TILE_SIZE;
+        let shown = |show, show_left| self.mask & show != 0 && (!left_column || self.mask & show_left != 0);
+        // The palette entry of the pixel, 0 for the backdrop.
+        let background = if shown(SHOW_BACKGROUND, SHOW_BACKGROUND_LEFT) { self.background_pixel() } else { 0 };
+        let mut entry = background;
+        if !self.rendering_enabled() && self.vram_addr & 0x3fff >= PALETTE {
+            entry = palette_index(self.vram_addr) as u8;
+        }
+        if shown(SHOW_SPRITES, SHOW_SPRITES_LEFT) {
+            // The first opaque sprite pixel wins, even when it is behind the background.
+            let sprites = self.sprites[.. self.sprite_count].iter().enumerate();
+            let mut pixels = sprites.map(|(slot, &sprite)| (slot, sprite, sprite.pixel(x)));
+            if let Some((slot, sprite, pixel)) = pixels.find(|&(_, _, pixel)| pixel != 0) {
+                let sprite_zero = slot == 0 && self.sprite_zero_on_scanline;
+                if sprite_zero && background != 0 && x < SPRITE_ZERO_HIT_END {
+                    self.status |= STATUS_SPRITE_ZERO_HIT;
+                }
+                if sprite.attributes & BEHIND_BACKGROUND == 0 || background == 0 {
+                    entry = SPRITE_PALETTES + (sprite.attributes & SPRITE_PALETTE) * 4 + pixel;
+                }
+            }
+        }
+
+        let greyscale = if self.mask & GREYSCALE != 0 { GREYSCALE_BITS } else { PALETTE_BITS };
+        self.frame[self.scanline as usize * WIDTH + x] = self.palette[entry as usize] & greyscale;
+    }
+
+    /// The palette entry of the background pixel fine X picks from the shift registers, 0 where it is transparent.
+    fn background_pixel(&self) -> u8 {
+        let bit = 15 - self.fine_x;
+        let two_bits = |bits : [u16 ; 2]| (bits[0] >> bit & 1) | (bits[1] >> bit & 1) << 1;
+        let (pixel, palette) = (two_bits(self.pattern_shift), two_bits(self.palette_shift));
+        if pixel == 0 { 0 } else { (palette * 4 + pixel) as u8 }
+    }
+
+    /// Finds the sprites on the next scanline and fetches their patterns, as the PPU does once it has drawn a scanline.
+    /// The pre-render scanline fetches too, but finds no sprites: none are drawn on the first scanline.
+    fn fetch_sprites(&mut self, visible : bool, mut mapper : Option<&mut dyn Mapper>) {
+        if !self.rendering_enabled() {
+            self.sprite_count = 0;
+            self.sprite_zero_on_scanline = false;
+            return;
+        }
+
+        self.secondary_oam = [0xff ; SPRITES_PER_SCANLINE * SPRITE_SIZE];
+        self.sprite_count = 0;
+        let y = self.scanline as usize;
+        let height = self.sprite_height();
+        let on_next_scanline = |top : u8| visible && y.wrapping_sub(top as usize) < height;
+        self.sprite_zero_on_scanline = on_next_scanline(self.oam[0]);
+        let mut n = 0;
+        while n < OAM_SIZE / SPRITE_SIZE && self.sprite_count < SPRITES_PER_SCANLINE {
+            let sprite = &self.oam[n * SPRITE_SIZE .. (n + 1) * SPRITE_SIZE];
+            if on_next_scanline(sprite[0]) {
+                let slot = self.sprite_count * SPRITE_SIZE;
+                self.secondary_oam[slot .. slot + SPRITE_SIZE].copy_from_slice(sprite);
+                self.sprite_count += 1;
+            }
+            n += 1;
+        }
+        // The search for a ninth sprite, stepping the byte within a sprite along with the sprite.
+        let mut m = 0;
+        while self.sprite_count == SPRITES_PER_SCANLINE && n < OAM_SIZE / SPRITE_SIZE {
+            if on_next_scanline(self.oam[n * SPRITE_SIZE + m]) {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % SPRITE_SIZE;
+        }
+
+        for slot in 0 .. SPRITES_PER_SCANLINE {
+            let [top, tile, attributes, x] = [0, 1, 2, 3].map(|i| self.secondary_oam[slot * SPRITE_SIZE + i]);
+            let (tile, mut row) = if slot < self.sprite_count {
+                (tile, y - top as usize)
+            } else {
+                (EMPTY_SPRITE_TILE, 0)
+            };
+            if attributes & FLIP_VERTICAL != 0 {
+                row = height - 1 - row;
+            }
+            let addr = self.sprite_tile_addr(tile, row);
+            let mut pattern = [addr, addr + TILE_BYTES / 2].map(|addr| self.read_memory(addr, reborrow(&mut mapper)));
+            if attributes & FLIP_HORIZONTAL != 0 {
+                pattern = pattern.map(u8::reverse_bits);
+            }
+            self.sprites[slot] = Sprite { x, attributes, pattern };
+        }
+    }
+
+    /// 8 or 16 pixels, from PPUCTRL.
+    fn sprite_height(&self) -> usize {
+        if self.ctrl & TALL_SPRITES != 0 { 2 * TILE_SIZE } else { TILE_SIZE }
+    }
+
+    /// The address of row `row` of sprite tile `tile`, counting down into the second tile of an 8x16 sprite.
+    fn sprite_tile_addr(&self, tile : u8, row : usize) -> u16 {
+        let (table, tile) = if self.ctrl & TALL_SPRITES != 0 {
+            let table = if tile & TALL_SPRITE_TABLE != 0 { 0x1000 } else { 0x0000 };
+            (table, (tile & !TALL_SPRITE_TABLE) + (row / TILE_SIZE) as u8)
+        } else {
+            (if self.ctrl & SPRITE_TABLE != 0 { 0x1000 } else { 0x0000 }, tile)
+        };
+        table + tile as u16 * TILE_BYTES + (row % TILE_SIZE) as u16
+    }
+
+    /// Reads register `register` (only its lowest three bits count), with the side effects the read has:
+    /// PPUSTATUS clears the vertical blank flag and the write toggle, PPUDATA moves the VRAM address on. Reads of
+    /// the write-only registers give the PPU's data latch.
+    pub fn read_register(&mut self, register : u16, mapper : Option<&mut dyn Mapper>) -> u8 {
+        // The value, and the bits of it the PPU drives rather than leaves to the latch.
+        let (value, driven) = match register & REGISTER_MASK {
+            PPUSTATUS => {
+                let value = self.peek_status();
+                self.status &= !STATUS_VBLANK;
+                self.write_toggle = false;
+                (value, STATUS_BITS)
+            }
+            OAMDATA => (self.peek_oam_data(), 0xff),
+            PPUDATA if self.data_addr() >= PALETTE => {
+                let value = self.peek_palette_data();
+                self.read_buffer = self.read_memory(self.data_addr() - PALETTE_SHADOW, mapper);
+                self.increment_vram_addr();
+                (value, PALETTE_BITS)
+            }
+            PPUDATA => {
+                let value = self.read_buffer;
+                self.read_buffer = self.read_memory(self.data_addr(), mapper);
+                self.increment_vram_addr();
+                (value, 0xff)
+            }
+            _ => (self.latch(), 0),
+        };
+        self.drive_latch(value, driven);
+        value
+    }
+
+    /// Returns what [`Ppu::read_register`] would, without any side effects.
+    pub fn peek_register(&self, register : u16, _mapper : Option<&dyn Mapper>) -> u8 {
+        match register & REGISTER_MASK {
+            PPUSTATUS => self.peek_status(),
+            OAMDATA => self.peek_oam_data(),
+            PPUDATA if self.data_addr() >= PALETTE => self.peek_palette_data(),
+            PPUDATA => self.read_buffer,
+            _ => self.latch(),
+        }
+    }
+
+    /// Writes `value` to register `register` (only its lowest three bits count). Writes to PPUSTATUS only reach
+    /// the data latch.
+    pub fn write_register(&mut self, register : u16, value : u8, mapper : Option<&mut dyn Mapper>) {
+        self.drive_latch(value, 0xff);
+        match register & REGISTER_MASK {
+            PPUCTRL => {
+                // Setting the NMI enable during vertical blank pulls the line at once.
+                let line = self.nmi_line();
+                self.ctrl = value;
+                self.nmi |= !line && self.nmi_line();
+                let nametable = ((value & NAMETABLE_SELECT) as u16) << 10;
+                self.temp_vram_addr = (self.temp_vram_addr & !(NAMETABLE_X | NAMETABLE_Y)) | nametable;
+            }
+            PPUMASK => self.mask = value,
+            OAMADDR => self.oam_addr = value,
+            OAMDATA => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            PPUSCROLL if self.write_toggle => {
+                let y = (value as u16 & 0b111) << 12 | (value as u16 >> 3) << 5;
+                self.temp_vram_addr = (self.temp_vram_addr & !(FINE_Y | COARSE_Y)) | y;
+                self.write_toggle = false;
+            }
+            PPUSCROLL => {
+                self.temp_vram_addr = (self.temp_vram_addr & !COARSE_X) | value as u16 >> 3;
+                self.fine_x = value & FINE_X_BITS;
+                self.write_toggle = true;
+            }
+            PPUADDR if self.write_toggle => {
+                self.temp_vram_addr = (self.temp_vram_addr & 0xff00) | value as u16;
+                self.vram_addr = self.temp_vram_addr;
+                self.write_toggle = false;
+            }
+            PPUADDR => {
+                // The top bit of t, fine Y's, is cleared.
+                self.temp_vram_addr = ((value as u16) << 8 | (self.temp_vram_addr & 0x00ff)) & ADDRESS_MASK;
+                self.write_toggle = true;
+            }
+            PPUDATA => {
+                self.write_memory(self.data_addr(), value, mapper);
+                self.increment_vram_addr();
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the bits of the data latch in `bits` to those of `value`, which refreshes them.
+    fn drive_latch(&mut self, value : u8, bits : u8) {
+        self.latch = (self.latch & !bits) | (value & bits);
+        for bit in 0 .. 8 {
+            if bits & 1 << bit != 0 {
+                self.latch_refreshed[bit] = self.dots;
+            }
+        }
+    }
+
+    fn peek_status(&self) -> u8 {
+        (self.status & STATUS_BITS) | (self.latch() & !STATUS_BITS)
+    }
+
+    fn peek_oam_data(&self) -> u8 {
+        let value = self.oam[self.oam_addr as usize];
+        if self.oam_addr & 0b11 == 2 {
+            value & OAM_ATTRIBUTE_BITS
+        } else {
+            value
+        }
+    }
+
+    /// What a PPUDATA read of the palette gives.
+    fn peek_palette_data(&self) -> u8 {
+        let colour = self.palette[palette_index(self.data_addr())];
+        let colour = if self.mask & GREYSCALE != 0 { colour & GREYSCALE_BITS } else { colour };
+        colour | (self.latch() & !PALETTE_BITS)
+    }
+
+    /// The address PPUDATA accesses, v without fine Y's top bit.
+    fn data_addr(&self) -> u16 {
+        self.vram_addr & ADDRESS_MASK
+    }
+
+    /// Steps v after a PPUDATA access. While rendering the access is taken for a tile fetch, and moves v on to the
+    /// next tile and the next row at once.
+    fn increment_vram_addr(&mut self) {
+        if self.rendering() {
+            self.vram_addr = increment_y(increment_coarse_x(self.vram_addr));
+        } else {
+            let step = if self.ctrl & INCREMENT_32 != 0 { 32 } else { 1 };
+            self.vram_addr = self.vram_addr.wrapping_add(step) & ADDRESS_MASK;
+        }
+    }
+
+    /// Reads the PPU address space at `addr`.
+    fn read_memory(&self, addr : u16, mapper : Option<&mut dyn Mapper>) -> u8 {
+        match (addr & ADDRESS_MASK, mapper) {
+            (addr @ 0 ..= PATTERN_TABLES_END, Some(mapper)) => mapper.ppu_read(addr),
+            (addr @ NAMETABLES ..= NAMETABLES_END, Some(mapper)) => match mapper.nametable_read(addr) {
+                Some(value) => value,
+                None => self.vram[mapper.mirroring().vram_offset(addr)],
+            },
+            (addr @ NAMETABLES ..= NAMETABLES_END, None) => self.vram[Mirroring::Horizontal.vram_offset(addr)],
+            (addr, _) if addr >= PALETTE => self.palette[palette_index(addr)],
+            _ => 0,
+        }
+    }
+
+    /// Returns the byte at `addr` in the PPU address space without any side effects, as a debugger would look at
+    /// it.
+    pub fn peek_memory(&self, addr : u16, mapper : Option<&dyn Mapper>) -> u8 {
+        match (addr & ADDRESS_MASK, mapper) {
+            (addr @ 0 ..= PATTERN_TABLES_END, Some(mapper)) => mapper.ppu_peek(addr),
+            (addr @ NAMETABLES ..= NAMETABLES_END, Some(mapper)) => match mapper.nametable_peek(addr) {
+                Some(value) => value,
+                None => self.vram[mapper.mirroring().vram_offset(addr)],
+            },
+            (addr @ NAMETABLES ..= NAMETABLES_END, None) => self.vram[Mirroring::Horizontal.vram_offset(addr)],
+            (addr, _) if addr >= PALETTE => self.palette[palette_index(addr)],
+            _ => 0,
+        }
+    }
+
+    /// Writes the PPU address space at `addr`.
+    fn write_memory(&mut self, addr : u16, value : u8, mapper : Option<&mut dyn Mapper>) {
+        match (addr & ADDRESS_MASK, mapper) {
+            (addr @ 0 ..= PATTERN_TABLES_END, Some(mapper)) => mapper.ppu_write(addr, value),
+            (addr @ NAMETABLES ..= NAMETABLES_END, Some(mapper)) => {
+                if mapper.nametable_write(addr, value) {
+                    return;
+                }
+                self.vram[mapper.mirroring().vram_offset(addr)] = value;
+            }
+            (addr @ NAMETABLES ..= NAMETABLES_END, None) => self.vram[Mirroring::Horizontal.vram_offset(addr)] = value,
+            (addr, _) if addr >= PALETTE => self.palette[palette_index(addr)] = value & PALETTE_BITS,
+            _ => {}
+        }
+    }
+
+    /// PPUCTRL.
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    /// PPUMASK.
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    /// PPUSTATUS, only the three bits the PPU drives.
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    /// The PPU's data latch, what reads of its write-only registers give. Writes to any register set all of it,
+    /// reads only the bits they drive, and a bit that is not set again for [`LATCH_DECAY`] dots fades to 0.
+    pub fn latch(&self) -> u8 {
+        (0 .. 8)
+            .filter(|&bit| self.dots - self.latch_refreshed[bit] < LATCH_DECAY)
+            .fold(0, |latch, bit| latch | (self.latch & 1 << bit))
+    }
+
+    /// The number of dots the PPU has run for.
+    pub fn dots(&self) -> u64 {
+        self.dots
+    }
+
+    /// OAMADDR.
+    pub fn oam_addr(&self) -> u8 {
+        self.oam_addr
+    }
+
+    /// The 64 sprites, four bytes each: Y, tile, attributes and X.
+    pub fn oam(&self) -> &[u8 ; OAM_SIZE] {
+        &self.oam
+    }
+
+    /// v, the address PPUDATA accesses next, and where rendering is while it is on.
+    pub fn vram_addr(&self) -> u16 {
+        self.vram_addr
+    }
+
+    /// t, the address the next frame starts rendering from, as set by PPUCTRL, PPUSCROLL and PPUADDR.
+    pub fn temp_vram_addr(&self) -> u16 {
+        self.temp_vram_addr
+    }
+
+    /// The pixel within a tile the screen starts at, the low three bits of the first PPUSCROLL write.
+    pub fn fine_x(&self) -> u8 {
+        self.fine_x
+    }
+
+    /// The nametable VRAM: the console's 2KiB, then the 2KiB only four-screen boards use.
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    /// The picture drawn so far, 256x240 pixels row by row, each a colour of the NES's 64 colour palette. The
+    /// colour emphasis bits of PPUMASK are left for the frontend to apply.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Whether the PPU is pulling the CPU's NMI line: the vblank flag and PPUCTRL bit 7 are both set.
+    pub fn nmi_line(&self) -> bool {
+        self.status & STATUS_VBLANK != 0 && self.ctrl & GENERATE_NMI != 0
+    }
+
+    /// Returns true if the NMI line has been pulled since the last call. The CPU's NMI input is edge triggered, it
+    /// takes one interrupt each time the line is pulled however long it is held.
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    /// The frames started since power on. It goes up when the PPU leaves the pre-render scanline, once
+    /// [`Ppu::frame`] holds a whole picture.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// The scanline being drawn: 0-239 visible, 261 (311 on PAL) the pre-render scanline.
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// The dot of the scanline, 0-340.
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Changes the timing to `region`'s, which decides the number of scanlines.
+    pub fn set_region(&mut self, region : Region) {
+        self.region = region;
+        self.scanline = self.scanline.min(region.scanlines_per_frame() as u16 - 1);
+    }
+
+    /// The 32 palette entries, $3F00-$3F1F. The mirrored backdrop entries at $3F10, $3F14, $3F18 and $3F1C are
+    /// kept at $3F00, $3F04, $3F08 and $3F0C, their own bytes stay 0.
+    pub fn palette(&self) -> &[u8 ; PALETTE_SIZE] {
+        &self.palette
+    }
+
+    /// Whether the next PPUSCROLL or PPUADDR write is the second of its pair.
+    pub fn write_toggle(&self) -> bool {
+        self.write_toggle
+    }
+}
+
+/// Steps the coarse X of v to the next tile, wrapping into the next nametable across.
+fn increment_coarse_x(addr : u16) -> u16 {
+    if addr & COARSE_X == COARSE_X {
+        (addr & !COARSE_X) ^ NAMETABLE_X
+    } else {
+        addr + 1
+    }
+}
+
+/// Steps v to the next row of pixels: fine Y, then coarse Y, which wraps into the next nametable down after row 29.
+/// Rows 30 and 31, in the attribute table, wrap back to 0 in the same nametable.
+fn increment_y(addr : u16) -> u16 {
+    if addr & FINE_Y != FINE_Y {
+        return addr + (1 << 12);
+    }
+    let addr = addr & !FINE_Y;
+    match (addr & COARSE_Y) >> 5 {
+        LAST_ROW => (addr & !COARSE_Y) ^ NAMETABLE_Y,
+        row if row == COARSE_Y >> 5 => addr & !COARSE_Y,
+        _ => addr + (1 << 5),
+    }
+}
+
+/// Lends `mapper` out for one call, so it can be passed on again after.
+fn reborrow<'a>(mapper : &'a mut Option<&mut dyn Mapper>) -> Option<&'a mut dyn Mapper> {
+    mapper.as_mut().map(|mapper| &mut **mapper as &mut dyn Mapper)
+}
+
+/// The palette entry `addr` ($3F00-$3FFF) selects: 32 entries mirrored every 32 bytes, with colour 0 of the sprite
+/// palettes folded onto colour 0 of the background palettes.
+fn palette_index(addr : u16) -> usize {
+    let index = addr as usize % PALETTE_SIZE;
+    if index & 0b1_0011 == 0b1_0000 {
+        index & 0b0_1111
+    } else {
+        index
+    }
+}
@@ -0,0 +1,71 @@
+//! # Region Module
+//!
+//! `region` describes the timing differences between the NTSC, PAL and Dendy consoles. The CPU and PPU run from
+//! the same master clock divided differently, so the CPU speed, the number of PPU dots per CPU cycle and the
+//! frame rate all depend on which one is being emulated.
+
+/// The console timing to emulate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    /// North America and Japan, the 2A03 CPU and 2C02 PPU.
+    #[default]
+    Ntsc,
+    /// Europe and Australia, the 2A07 CPU and 2C07 PPU.
+    Pal,
+    /// The Dendy and other famiclones, a PAL frame with NTSC-like CPU timing.
+    Dendy,
+}
+
+impl Region {
+    /// The master clock crystal frequency in Hz.
+    pub const fn master_clock_hz(&self) -> u32 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal | Region::Dendy => 26_601_712,
+        }
+    }
+
+    /// The CPU clock is the master clock divided by 12 (NTSC), 16 (PAL) or 15 (Dendy).
+    pub const fn cpu_clock_hz(&self) -> u32 {
+        let divider = match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        };
+        self.master_clock_hz() / divider
+    }
+
+    /// The number of PPU dots per CPU cycle as a (numerator, denominator) fraction: 3 everywhere except PAL,
+    /// where it is 3.2.
+    pub const fn ppu_dots_per_cpu_cycle(&self) -> (u64, u64) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    /// The number of scanlines in a frame, including vertical blank.
+    pub const fn scanlines_per_frame(&self) -> u64 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Frames per second. Every scanline is 341 dots, apart from the one dot the NTSC PPU skips on odd frames
+    /// with rendering enabled, which is averaged in here.
+    pub fn frame_rate(&self) -> f64 {
+        let ppu_clock = match self {
+            Region::Ntsc => self.master_clock_hz() as f64 / 4.0,
+            Region::Pal | Region::Dendy => self.master_clock_hz() as f64 / 5.0,
+        };
+        let dots = (341 * self.scanlines_per_frame()) as f64 - if *self == Region::Ntsc { 0.5 } else { 0.0 };
+        ppu_clock / dots
+    }
+
+    /// Converts a CPU cycle count into the number of PPU dots elapsed over the same time.
+    pub const fn cpu_cycles_to_ppu_dots(&self, cycles : u64) -> u64 {
+        let (numerator, denominator) = self.ppu_dots_per_cpu_cycle();
+        cycles * numerator / denominator
+    }
+}
@@ -0,0 +1,113 @@
+//! # Trace Module
+//!
+//! `trace` formats the CPU state in the same layout as the nestest reference log, one line per instruction, so
+//! a run of this emulator can be diffed against the log of a known good one.
+
+use crate::cpu::{AddressingMode, CPU};
+use crate::disasm;
+use crate::mem::Mem;
+use crate::opcodes;
+
+/// Every scanline is 341 dots long.
+const DOTS_PER_SCANLINE : u64 = 341;
+
+/// Returns the nestest log line for the instruction at the program counter, describing the CPU before it runs:
+///
+/// ```text
+/// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+/// ```
+///
+/// Memory operands show the value currently stored at the address they refer to, and undocumented opcodes are
+/// marked with a `*` in front of the mnemonic. There is no PPU yet, so its scanline and dot are worked out from
+/// the CPU cycle count and [`CPU::region`]. Memory is read with [`CPU::peek`], so taking a trace has no side
+/// effects.
+pub fn trace<M : Mem>(cpu : &CPU<M>) -> String {
+    let pc = cpu.program_counter;
+    let code = cpu.peek(pc);
+
+    let (bytes, asm) = match opcodes::lookup(code) {
+        Some(opcode) => {
+            let bytes : Vec<u8> = (0..opcode.bytes as u16).map(|i| cpu.peek(pc.wrapping_add(i))).collect();
+            let marker = if opcode.unofficial { "*" } else { " " };
+            let operand = disassemble_operand(cpu, code, &opcode.addressing_mode, &bytes);
+            let asm = format!("{}{} {}", marker, opcode.name, operand);
+            (bytes, asm.trim_end().to_string())
+        }
+        None => (vec![code], " ???".to_string()),
+    };
+
+    let hex : Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let dots = cpu.region().cpu_cycles_to_ppu_dots(cpu.cycles);
+    // The pushed copy of the status is what nestest prints: bit 5 set and the break flag clear.
+    let status = (cpu.status.bits() | 0b0010_0000) & !0b0001_0000;
+
+    format!(
+        "{:04X}  {:8} {:33}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+        pc,
+        hex.join(" "),
+        asm,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        status,
+        cpu.stack_pointer,
+        (dots / DOTS_PER_SCANLINE) % cpu.region().scanlines_per_frame(),
+        dots % DOTS_PER_SCANLINE,
+        cpu.cycles,
+    )
+}
+
+/// Formats the operand of an instruction the way nestest does: the operand as [`disasm::format_operand`] writes it,
+/// followed by the address it resolves to and the value stored there.
+fn disassemble_operand<M : Mem>(cpu : &CPU<M>, code : u8, mode : &AddressingMode, bytes : &[u8]) -> String {
+    let operand = disasm::format_operand(code, mode, &bytes[1 ..], cpu.program_counter);
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or(0)]);
+
+    match mode {
+        AddressingMode::Immediate | AddressingMode::Relative | AddressingMode::NoneAddressing => operand,
+        AddressingMode::ZeroPage => format!("{} = {:02X}", operand, cpu.peek(byte as u16)),
+        AddressingMode::ZeroPage_X => {
+            let addr = byte.wrapping_add(cpu.register_x);
+            format!("{} @ {:02X} = {:02X}", operand, addr, cpu.peek(addr as u16))
+        }
+        AddressingMode::ZeroPage_Y => {
+            let addr = byte.wrapping_add(cpu.register_y);
+            format!("{} @ {:02X} = {:02X}", operand, addr, cpu.peek(addr as u16))
+        }
+        // JMP and JSR do not access the target, so there is no value to show.
+        AddressingMode::Absolute if code == 0x4c || code == 0x20 => operand,
+        AddressingMode::Absolute => format!("{} = {:02X}", operand, cpu.peek(word)),
+        AddressingMode::Absolute_X => {
+            let addr = word.wrapping_add(cpu.register_x as u16);
+            format!("{} @ {:04X} = {:02X}", operand, addr, cpu.peek(addr))
+        }
+        AddressingMode::Absolute_Y => {
+            let addr = word.wrapping_add(cpu.register_y as u16);
+            format!("{} @ {:04X} = {:02X}", operand, addr, cpu.peek(addr))
+        }
+        AddressingMode::Indirect => {
+            // Same page wrap bug as the CPU.
+            let lo = cpu.peek(word) as u16;
+            let hi = cpu.peek((word & 0xff00) | (word.wrapping_add(1) & 0x00ff)) as u16;
+            format!("{} = {:04X}", operand, (hi << 8) | lo)
+        }
+        AddressingMode::Indirect_X => {
+            let pointer = byte.wrapping_add(cpu.register_x);
+            let addr = read_zero_page_pointer(cpu, pointer);
+            format!("{} @ {:02X} = {:04X} = {:02X}", operand, pointer, addr, cpu.peek(addr))
+        }
+        AddressingMode::Indirect_Y => {
+            let base = read_zero_page_pointer(cpu, byte);
+            let addr = base.wrapping_add(cpu.register_y as u16);
+            format!("{} = {:04X} @ {:04X} = {:02X}", operand, base, addr, cpu.peek(addr))
+        }
+    }
+}
+
+/// Reads a pointer from the zero page, wrapping from 0xFF to 0x00 like the CPU does.
+fn read_zero_page_pointer<M : Mem>(cpu : &CPU<M>, address : u8) -> u16 {
+    let lo = cpu.peek(address as u16) as u16;
+    let hi = cpu.peek(address.wrapping_add(1) as u16) as u16;
+    (hi << 8) | lo
+}
@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod asm_tests {
+    use nes::asm::{assemble, assemble_at, AsmError};
+    use nes::cpu::AddressingMode;
+    use nes::disasm::decode;
+    use nes::opcodes::CPU_OPS_CODES;
+
+    #[test]
+    fn test_addressing_mode_syntax() {
+        let program = assemble(
+            "
+            LDA #$05
+            LDA $10
+            LDA $10,X
+            LDX $10,Y
+            LDA $1234
+            LDA $1234,X
+            LDA $1234,Y
+            JMP ($1234)
+            LDA ($10,X)
+            LDA ($10),Y
+            ASL A
+            ASL
+            INX
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            program,
+            vec![
+                0xa9, 0x05, 0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x34, 0x12, 0xbd, 0x34, 0x12, 0xb9, 0x34, 0x12,
+                0x6c, 0x34, 0x12, 0xa1, 0x10, 0xb1, 0x10, 0x0a, 0x0a, 0xe8,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_number_formats_and_operand_width() {
+        assert_eq!(assemble("LDA #%1010").unwrap(), vec![0xa9, 0x0a]);
+        assert_eq!(assemble("LDA #200").unwrap(), vec![0xa9, 200]);
+        // Four hex digits force absolute addressing, as does a value that does not fit in a byte.
+        assert_eq!(assemble("LDA $0010").unwrap(), vec![0xad, 0x10, 0x00]);
+        assert_eq!(assemble("LDA 256").unwrap(), vec![0xad, 0x00, 0x01]);
+        // STX only has a zero page,Y form.
+        assert_eq!(assemble("STX $10,Y").unwrap(), vec![0x96, 0x10]);
+        assert_eq!(assemble("lda #$01 ; lower case").unwrap(), vec![0xa9, 0x01]);
+    }
+
+    #[test]
+    fn test_labels_and_branches() {
+        let program = assemble_at(
+            "
+            start:  JSR sub
+                    JMP start
+            sub:    LDX #$03
+            loop:   DEX
+                    BNE loop
+                    BEQ done
+                    NOP
+            done:   RTS
+            table:  .word start, $1234
+                    .byte $01, %10, 3
+            ",
+            0xc000,
+        )
+        .unwrap();
+
+        assert_eq!(
+            program,
+            vec![
+                0x20, 0x06, 0xc0, 0x4c, 0x00, 0xc0, 0xa2, 0x03, 0xca, 0xd0, 0xfd, 0xf0, 0x01, 0xea, 0x60, 0x00, 0xc0,
+                0x34, 0x12, 0x01, 0x02, 0x03,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(assemble("\nFOO #$01"), Err(AsmError::UnknownMnemonic { line : 2, mnemonic : "FOO".to_string() }));
+        assert_eq!(assemble("LAX $10"), Err(AsmError::UnknownMnemonic { line : 1, mnemonic : "LAX".to_string() }));
+        assert_eq!(assemble("LDA #$1g"), Err(AsmError::InvalidOperand { line : 1, operand : "#$1g".to_string() }));
+        assert_eq!(
+            assemble("STA #$01"),
+            Err(AsmError::UnsupportedAddressingMode { line : 1, mnemonic : "STA".to_string() })
+        );
+        assert_eq!(assemble("JMP nowhere"), Err(AsmError::UndefinedLabel { line : 1, label : "nowhere".to_string() }));
+        assert_eq!(assemble("a: NOP\na: NOP"), Err(AsmError::DuplicateLabel { line : 2, label : "a".to_string() }));
+        assert_eq!(assemble("LDA #$100"), Err(AsmError::ValueOutOfRange { line : 1, value : 0x100 }));
+        assert_eq!(
+            assemble("BNE far\n.byte 0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0\nfar: RTS"),
+            Err(AsmError::BranchOutOfRange { line : 1, offset : 128 })
+        );
+    }
+
+    #[test]
+    fn test_disassembly_of_every_official_opcode_reassembles() {
+        for opcode in CPU_OPS_CODES.iter().filter(|opcode| !opcode.unofficial) {
+            let mut bytes = vec![opcode.code];
+            match opcode.addressing_mode {
+                AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect => {
+                    bytes.extend([0x34, 0x12])
+                }
+                AddressingMode::NoneAddressing => {}
+                _ => bytes.push(0x10),
+            }
+            let assembly = decode(&bytes, 0x8000).assembly();
+
+            assert_eq!(assemble(&assembly), Ok(bytes), "{}", assembly);
+        }
+    }
+}
@@ -0,0 +1,620 @@
+#[cfg(test)]
+mod bus_tests {
+    use nes::asm::assemble_at;
+    use nes::cartridge::{Cartridge, Mirroring, Rom};
+    use nes::mapper::{Mapper, StateError, StateReader, StateWriter};
+    use nes::bus::{AccessLog, Bus, BusAccess, BusDevice, Ram, RamInit, DEFAULT_OPEN_BUS_DECAY};
+    use nes::cpu::{Access, ExecutionMode};
+    use nes::region::Region;
+    use nes::cpu::CPU;
+    use nes::mem::Mem;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_ram_is_mirrored_four_times() {
+        let mut bus = Bus::new();
+        bus.write(0x1fff, 0xab);
+
+        for addr in [0x07ff, 0x0fff, 0x17ff, 0x1fff] {
+            assert_eq!(bus.read(addr), 0xab);
+        }
+        bus.write(0x0800, 0x01);
+        assert_eq!(bus.read(0x0000), 0x01);
+    }
+
+    #[test]
+    fn test_cpu_runs_from_mirrored_ram() {
+        let mut cpu = CPU::with_memory(Bus::new());
+        // Writing through a mirror lands in the same RAM the program reads back.
+        let program = assemble_at("LDA #$42\nSTA $0810\nLDX $10\nBRK", 0x0600).unwrap();
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, *byte);
+        }
+        cpu.program_counter = 0x0600;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_x, 0x42);
+        assert_eq!(cpu.memory().peek(0x1810), 0x42);
+    }
+
+    #[test]
+    fn test_registers_are_routed_to_their_device() {
+        let mut bus = Bus::new();
+        bus.write(0x2000, 0x21);
+        bus.write(0x4016, 0x01);
+        bus.write(0x6000, 0x5a);
+
+        assert_eq!(bus.ppu().ctrl(), 0x21);
+        assert_eq!(bus.apu_io().register(0x16), 0x01);
+        assert_eq!(bus.cartridge_stub().register(0x6000 - 0x4020), 0x5a);
+        assert_eq!(bus.read(0x6000), 0x5a);
+        // None of them is RAM.
+        assert_eq!(bus.read(0x0006), 0x00);
+    }
+
+    #[test]
+    fn test_apu_test_registers_are_disconnected() {
+        let mut bus = Bus::new();
+        bus.write(0x4018, 0xff);
+        bus.write(0x0000, 0x12);
+        // Nothing stores the write, the read sees the open bus.
+        assert_eq!(bus.read(0x4018), 0x12);
+    }
+
+    #[test]
+    fn test_cpu_loads_program_into_cartridge_space() {
+        let mut cpu = CPU::with_memory(Bus::new());
+        // LDA #$80; STA $2000; BRK
+        cpu.load_and_run(vec![0xa9, 0x80, 0x8d, 0x00, 0x20, 0x00]).unwrap();
+
+        assert_eq!(cpu.memory().ppu().ctrl(), 0x80);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mirrored_every_8_bytes() {
+        let mut bus = Bus::new();
+        // PPUADDR ($2006) through some of its aliases.
+        let pairs = [[(0x200e, 0x01), (0x3456, 0x02)], [(0x3ffe, 0x03), (0x2006, 0x04)]];
+        for [(high_addr, high), (low_addr, low)] in pairs {
+            bus.write(high_addr, high);
+            bus.write(low_addr, low);
+            assert_eq!(bus.ppu().vram_addr(), (high as u16) << 8 | low as u16, "${:04X}", high_addr);
+        }
+        // PPUCTRL written at its last alias reads back at the first.
+        bus.write(0x3ff8, 0x80);
+        assert_eq!(bus.read(0x2000), 0x80);
+        assert_eq!(bus.read(0x2008), 0x80);
+    }
+
+    #[test]
+    fn test_16k_prg_rom_is_mirrored() {
+        let mut prg_rom = vec![0 ; 0x4000];
+        prg_rom[0x0000] = 0x11;
+        prg_rom[0x3ffc] = 0x22;
+        let mut bus = Bus::new();
+        bus.attach_prg_rom(prg_rom);
+
+        assert_eq!(bus.read(0x8000), 0x11);
+        assert_eq!(bus.read(0xc000), 0x11);
+        assert_eq!(bus.read(0xbffc), 0x22);
+        assert_eq!(bus.read(0xfffc), 0x22);
+    }
+
+    #[test]
+    fn test_32k_prg_rom_is_not_mirrored() {
+        let mut prg_rom = vec![0 ; 0x8000];
+        prg_rom[0x0000] = 0x11;
+        prg_rom[0x4000] = 0x22;
+        let mut bus = Bus::new();
+        bus.attach_prg_rom(prg_rom);
+
+        assert_eq!(bus.read(0x8000), 0x11);
+        assert_eq!(bus.read(0xc000), 0x22);
+    }
+
+    #[test]
+    fn test_prg_rom_ignores_writes() {
+        let mut bus = Bus::new();
+        bus.attach_prg_rom(vec![0x33 ; 0x4000]);
+        bus.write(0x8000, 0x00);
+        bus.write(0x6000, 0x44);
+
+        assert_eq!(bus.read(0x8000), 0x33);
+        // NROM has nothing below $8000, so the read gives the open bus: the $33 the last read left on it. The stub
+        // no longer answers there either.
+        assert_eq!(bus.read(0x6000), 0x33);
+        assert_eq!(bus.cartridge_stub().register(0x6000 - 0x4020), 0x00);
+    }
+
+    #[test]
+    fn test_cpu_runs_from_prg_rom() {
+        let mut prg_rom = assemble_at("LDX #$07\nSTX $0200\nBRK", 0xc000).unwrap();
+        prg_rom.resize(0x4000, 0);
+        // Reset vector at $FFFC, the BRK vector stays $0000 so the BRK ends the run.
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0xc0;
+        let mut bus = Bus::new();
+        bus.attach_prg_rom(prg_rom);
+        let mut cpu = CPU::with_memory(bus);
+        cpu.power_on();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x0200), 0x07);
+    }
+
+    #[test]
+    fn test_ram_init_patterns() {
+        assert!((0 .. 0x800).all(|addr| Bus::new().peek(addr) == 0x00));
+        assert!((0 .. 0x800).all(|addr| Bus::with_ram_init(RamInit::AllFF).peek(addr) == 0xff));
+
+        let bus = Bus::with_ram_init(RamInit::Alternating);
+        let start : Vec<u8> = (0 .. 10).map(|addr| bus.peek(addr)).collect();
+        assert_eq!(start, vec![0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00]);
+        // Mirrors show the same pattern.
+        assert_eq!(bus.peek(0x0804), 0xff);
+    }
+
+    #[test]
+    fn test_random_ram_init_is_reproducible() {
+        let dump = |bus : &Bus| (0 .. 0x800).map(|addr| bus.peek(addr)).collect::<Vec<u8>>();
+        let a = dump(&Bus::with_ram_init(RamInit::Random(1)));
+        let b = dump(&Bus::with_ram_init(RamInit::Random(1)));
+        let c = dump(&Bus::with_ram_init(RamInit::Random(2)));
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(a.iter().any(|&byte| byte != a[0]));
+        assert!(dump(&Bus::with_ram_init(RamInit::Random(0))).iter().any(|&byte| byte != 0));
+    }
+
+    #[test]
+    fn test_power_on_refills_ram() {
+        let mut bus = Bus::with_ram_init(RamInit::AllFF);
+        bus.write(0x0010, 0x12);
+        bus.write(0x6000, 0x34);
+        bus.power_on();
+
+        assert_eq!(bus.read(0x0010), 0xff);
+        assert_eq!(bus.read(0x6000), 0x34);
+
+        bus.set_ram_init(RamInit::AllZero);
+        bus.power_on();
+        assert_eq!(bus.ram_init(), RamInit::AllZero);
+        assert_eq!(bus.read(0x0010), 0x00);
+    }
+
+    #[test]
+    fn test_write_only_registers_read_open_bus() {
+        let mut bus = Bus::new();
+        bus.write(0x2002, 0x80);
+        bus.write(0x2000, 0x11);
+        bus.write(0x4000, 0x22);
+        bus.write(0x0010, 0x33);
+
+        assert_eq!(bus.read(0x4000), 0x33);
+        assert_eq!(bus.read(0x4014), 0x33);
+        // PPUSTATUS can be read, so it answers itself: nothing set yet, its low bits are the PPU's data latch.
+        assert_eq!(bus.read(0x200a), 0x11);
+        assert_eq!(bus.read(0x2000), 0x11);
+        // The devices still saw the writes, and PPUSTATUS cannot be written.
+        assert_eq!(bus.ppu().ctrl(), 0x11);
+        assert_eq!(bus.ppu().status(), 0x00);
+        assert_eq!(bus.apu_io().register(0), 0x22);
+    }
+
+    #[test]
+    fn test_write_only_ppu_registers_read_the_ppu_latch() {
+        let mut bus = Bus::new();
+        bus.write(0x2000, 0x11);
+        assert_eq!(bus.read(0x2000), 0x11);
+
+        // Not the CPU's open bus: the PPU keeps the last value that went through its registers.
+        bus.write(0x0010, 0x33);
+        assert_eq!((bus.read(0x2000), bus.read(0x3ffd), bus.peek(0x2006)), (0x11, 0x11, 0x11));
+        bus.write(0x2006, 0x44);
+        assert_eq!(bus.read(0x2001), 0x44);
+
+        // It fades after a while, as the CPU's open bus does.
+        bus.tick(5_000_000);
+        assert_eq!(bus.read(0x2000), 0x00);
+    }
+
+    #[test]
+    fn test_open_bus_decays() {
+        let mut bus = Bus::new();
+        bus.write(0x0010, 0x5a);
+        bus.tick(DEFAULT_OPEN_BUS_DECAY - 1);
+        assert_eq!(bus.read(0x4018), 0x5a);
+
+        // The read drove the bus again, with the value it returned.
+        bus.tick(DEFAULT_OPEN_BUS_DECAY - 1);
+        assert_eq!(bus.open_bus(), 0x5a);
+        bus.tick(1);
+        assert_eq!(bus.open_bus(), 0x00);
+        assert_eq!(bus.peek(0x4018), 0x00);
+    }
+
+    #[test]
+    fn test_open_bus_decay_is_configurable() {
+        let mut bus = Bus::new();
+        bus.set_open_bus_decay(Some(10));
+        bus.write(0x0010, 0x5a);
+        bus.tick(9);
+        assert_eq!(bus.peek(0x4006), 0x5a);
+        bus.tick(1);
+        assert_eq!(bus.peek(0x4006), 0x00);
+
+        bus.set_open_bus_decay(None);
+        bus.write(0x0010, 0x5a);
+        bus.tick(u32::MAX as u64);
+        assert_eq!(bus.peek(0x4006), 0x5a);
+    }
+
+    #[test]
+    fn test_access_log_records_cpu_accesses() {
+        let mut cpu = CPU::with_memory(Bus::new());
+        cpu.memory_mut().log_accesses(AccessLog::new(64).filter(0x0000 ..= 0x1fff));
+        // LDA #$42; STA $0810; LDX $0010; BRK
+        cpu.load_and_run(vec![0xa9, 0x42, 0x8d, 0x10, 0x08, 0xae, 0x10, 0x00, 0x00]).unwrap();
+
+        let log : Vec<(u16, u8, Access)> = cpu.memory().access_log().unwrap()
+            .entries()
+            .filter(|entry| entry.address == 0x0810 || entry.address == 0x0010)
+            .map(|entry| (entry.address, entry.value, entry.access))
+            .collect();
+        assert_eq!(log, vec![(0x0810, 0x42, Access::Write), (0x0010, 0x42, Access::Read)]);
+    }
+
+    #[test]
+    fn test_access_log_is_a_ring_buffer() {
+        let mut bus = Bus::new();
+        bus.log_accesses(AccessLog::new(2));
+        bus.write(0x0001, 0x01);
+        bus.tick(1);
+        bus.write(0x0002, 0x02);
+        bus.peek(0x0002);
+        bus.tick(1);
+        bus.read(0x0003);
+
+        let log : Vec<BusAccess> = bus.access_log().unwrap().entries().collect();
+        assert_eq!(log, vec![
+            BusAccess { cycle : 1, address : 0x0002, value : 0x02, access : Access::Write },
+            BusAccess { cycle : 2, address : 0x0003, value : 0x00, access : Access::Read },
+        ]);
+
+        bus.access_log_mut().unwrap().clear();
+        assert_eq!(bus.access_log().unwrap().entries().count(), 0);
+        assert!(bus.take_access_log().is_some());
+        assert!(bus.access_log().is_none());
+    }
+
+    #[test]
+    fn test_access_log_cycles_are_exact_per_cycle() {
+        for (mode, cycle) in [(ExecutionMode::PerCycle, 3), (ExecutionMode::PerInstruction, 0)] {
+            let mut cpu = CPU::with_memory(Bus::new());
+            cpu.execution_mode = mode;
+            cpu.memory_mut().log_accesses(AccessLog::new(1).filter(0x2002 ..= 0x2002));
+            // LDA $2002, the register is read on the instruction's last cycle.
+            for (offset, byte) in [0xad, 0x02, 0x20].into_iter().enumerate() {
+                cpu.mem_write(0x0600 + offset as u16, byte);
+            }
+            cpu.program_counter = 0x0600;
+            cpu.step().unwrap();
+
+            let log : Vec<BusAccess> = cpu.memory().access_log().unwrap().entries().collect();
+            assert_eq!(log[0].cycle, cycle, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_access_log_sink() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&seen);
+        let mut bus = Bus::new();
+        bus.log_accesses(AccessLog::new(0).filter(0x2000 ..= 0x2007).filter(0x4014 ..= 0x4014).sink(move |access| {
+            sink.borrow_mut().push(access.address);
+        }));
+        for addr in [0x2000, 0x0000, 0x4014, 0x2008] {
+            bus.write(addr, 0);
+        }
+
+        assert_eq!(*seen.borrow(), vec![0x2000, 0x4014]);
+        assert_eq!(bus.access_log().unwrap().entries().count(), 0);
+    }
+
+    #[test]
+    fn test_registered_ram_device() {
+        let mut bus = Bus::new();
+        bus.register_device(0x6000 ..= 0x7fff, Ram::new(0x1000));
+        bus.write(0x6010, 0x12);
+
+        assert_eq!(bus.read(0x6010), 0x12);
+        // 4KiB mounted over 8KiB is mirrored.
+        assert_eq!(bus.read(0x7010), 0x12);
+        // The cartridge stub no longer sees the addresses.
+        assert_eq!(bus.cartridge_stub().register(0x6010 - 0x4020), 0x00);
+    }
+
+    #[test]
+    fn test_registered_device_overrides_memory_map() {
+        let mut bus = Bus::new();
+        bus.attach_prg_rom(vec![0xea ; 0x4000]);
+        bus.register_device(0x0100 ..= 0x01ff, Ram::new(0x100));
+        bus.register_device(0xfffc ..= 0xfffd, Ram::new(2));
+        bus.register_device(0x0180 ..= 0x0180, Ram::new(1));
+        bus.write(0x0100, 0x01);
+        bus.write(0x0180, 0x02);
+        bus.write(0xfffc, 0x03);
+
+        assert_eq!(bus.read(0x0100), 0x01);
+        assert_eq!(bus.read(0x0180), 0x02);
+        assert_eq!(bus.peek(0xfffc), 0x03);
+        // The RAM mirrors and the rest of the ROM are untouched.
+        assert_eq!(bus.read(0x0900), 0x00);
+        assert_eq!(bus.read(0xfffe), 0xea);
+
+        bus.clear_devices();
+        assert_eq!(bus.read(0xfffc), 0xea);
+    }
+
+    #[test]
+    fn test_debug_device_receives_offsets() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        /// Collects what a test ROM writes to its result port.
+        struct ResultPort(Rc<RefCell<Vec<(u16, u8)>>>);
+
+        impl BusDevice for ResultPort {
+            fn read(&mut self, offset : u16) -> u8 {
+                self.peek(offset)
+            }
+
+            fn write(&mut self, offset : u16, value : u8) {
+                self.0.borrow_mut().push((offset, value));
+            }
+
+            fn peek(&self, _offset : u16) -> u8 {
+                0x80
+            }
+        }
+
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::with_memory(Bus::new());
+        cpu.memory_mut().register_device(0x5000 ..= 0x500f, ResultPort(Rc::clone(&writes)));
+        // LDA #$01; STA $5000; LDA $5004; STA $5003; BRK
+        cpu.load_and_run(vec![0xa9, 0x01, 0x8d, 0x00, 0x50, 0xad, 0x04, 0x50, 0x8d, 0x03, 0x50, 0x00]).unwrap();
+
+        assert_eq!(*writes.borrow(), vec![(0x0000, 0x01), (0x0003, 0x80)]);
+    }
+
+    #[test]
+    fn test_tick_clocks_ppu_three_dots_per_cpu_cycle() {
+        let mut bus = Bus::new();
+        bus.tick(1);
+        bus.tick(10);
+
+        assert_eq!(bus.cycles(), 11);
+        assert_eq!(bus.ppu_dots(), 33);
+    }
+
+    #[test]
+    fn test_tick_clocks_pal_ppu_3_2_dots_per_cpu_cycle() {
+        let mut bus = Bus::new();
+        bus.set_region(Region::Pal);
+        let mut dots = Vec::new();
+        for _ in 0 .. 5 {
+            bus.tick(1);
+            dots.push(bus.ppu_dots());
+        }
+
+        // The fifth of a dot left over each cycle adds up to an extra dot every five cycles.
+        assert_eq!(dots, vec![3, 6, 9, 12, 16]);
+        assert_eq!(bus.region(), Region::Pal);
+    }
+
+    #[test]
+    fn test_cpu_clocks_the_bus_for_every_cycle() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        /// Counts the CPU cycles it is clocked for.
+        struct Clocked(Rc<Cell<u64>>);
+
+        impl BusDevice for Clocked {
+            fn read(&mut self, _offset : u16) -> u8 {
+                0
+            }
+
+            fn write(&mut self, _offset : u16, _value : u8) {}
+
+            fn peek(&self, _offset : u16) -> u8 {
+                0
+            }
+
+            fn tick(&mut self, cpu_cycles : u64) {
+                self.0.set(self.0.get() + cpu_cycles);
+            }
+        }
+
+        for mode in [ExecutionMode::PerInstruction, ExecutionMode::PerCycle] {
+            let clocked = Rc::new(Cell::new(0));
+            let mut cpu = CPU::with_memory(Bus::new());
+            cpu.execution_mode = mode;
+            cpu.memory_mut().register_device(0x5000 ..= 0x5000, Clocked(Rc::clone(&clocked)));
+            // LDX #$05; DEX; BNE -3; STA $0200,X; BRK
+            cpu.load_and_run(vec![0xa2, 0x05, 0xca, 0xd0, 0xfd, 0x9d, 0x00, 0x02, 0x00]).unwrap();
+            cpu.reset();
+
+            assert_eq!(cpu.memory().cycles(), cpu.cycles, "{:?}", mode);
+            assert_eq!(clocked.get(), cpu.cycles, "{:?}", mode);
+            assert_eq!(cpu.memory().ppu_dots(), cpu.cycles * 3, "{:?}", mode);
+        }
+    }
+
+    /// What the bus did with a [`Probe`].
+    #[derive(Default)]
+    struct ProbeLog {
+        reads : Vec<u16>,
+        writes : Vec<(u16, u8)>,
+        snooped : Vec<(u16, u8)>,
+        cycles : u64,
+    }
+
+    /// A board that records what the bus does with it, and raises its IRQ after 10 CPU cycles.
+    struct Probe(Rc<RefCell<ProbeLog>>);
+
+    impl Mapper for Probe {
+        fn cpu_read(&mut self, addr : u16) -> Option<u8> {
+            self.0.borrow_mut().reads.push(addr);
+            self.cpu_peek(addr)
+        }
+
+        fn cpu_peek(&self, addr : u16) -> Option<u8> {
+            Some((addr >> 8) as u8)
+        }
+
+        fn cpu_write(&mut self, addr : u16, value : u8) {
+            self.0.borrow_mut().writes.push((addr, value));
+        }
+
+        fn ppu_peek(&self, _addr : u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _addr : u16, _value : u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Vertical
+        }
+
+        fn save_state(&self, _state : &mut StateWriter) {}
+
+        fn load_state(&mut self, _state : &mut StateReader) -> Result<(), StateError> {
+            Ok(())
+        }
+
+        fn snoop_cpu_write(&mut self, addr : u16, value : u8) {
+            self.0.borrow_mut().snooped.push((addr, value));
+        }
+
+        fn tick(&mut self, cpu_cycles : u64) {
+            self.0.borrow_mut().cycles += cpu_cycles;
+        }
+
+        fn irq_pending(&self) -> bool {
+            self.0.borrow().cycles >= 10
+        }
+    }
+
+    #[test]
+    fn test_cartridge_answers_cartridge_space() {
+        let log = Rc::new(RefCell::new(ProbeLog::default()));
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::with_mapper(1000, Probe(log.clone())));
+        bus.write(0x4020, 0x01);
+        bus.write(0x8000, 0x02);
+        bus.write(0x2001, 0x03);
+        bus.write(0x0000, 0x04);
+
+        assert_eq!(bus.read(0x5000), 0x50);
+        assert_eq!(bus.peek(0xff00), 0xff);
+        assert_eq!(bus.read(0x0000), 0x04);
+
+        let log = log.borrow();
+        assert_eq!(log.reads, [0x5000], "peeks and RAM reads do not reach the board");
+        assert_eq!(log.writes, [(0x4020, 0x01), (0x8000, 0x02)]);
+        assert_eq!(log.snooped, [(0x4020, 0x01), (0x8000, 0x02), (0x2001, 0x03), (0x0000, 0x04)]);
+    }
+
+    #[test]
+    fn test_cartridge_is_clocked_and_raises_irq() {
+        let log = Rc::new(RefCell::new(ProbeLog::default()));
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::with_mapper(1000, Probe(log.clone())));
+        bus.tick(9);
+        assert!(!bus.irq_pending());
+        bus.tick(1);
+        assert!(bus.irq_pending());
+        assert_eq!(log.borrow().cycles, 10);
+
+        let cartridge = bus.remove_cartridge().unwrap();
+        assert_eq!(cartridge.mapper_number(), 1000);
+        assert!(bus.cartridge().is_none());
+        assert!(!bus.irq_pending());
+        // Back to the stub.
+        bus.write(0x8000, 0x12);
+        assert_eq!(bus.read(0x8000), 0x12);
+    }
+
+    #[test]
+    fn test_cartridge_from_rom_switches_banks_through_bus() {
+        // UxROM, 4 banks of 16KiB.
+        let mut image = vec![b'N', b'E', b'S', 0x1a, 4, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for bank in 0 .. 4 {
+            image.extend(vec![bank ; 0x4000]);
+        }
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::new(Rom::from_bytes(&image).unwrap()).unwrap());
+        bus.write(0x8000, 0x02);
+        assert_eq!(bus.read(0x8000), 0x02);
+        assert_eq!(bus.read(0xc000), 0x03);
+    }
+
+    /// Runs `source` from $C000 on an MMC1 board, whose first 16KiB bank is all $01 and whose 8KiB CHR banks hold
+    /// their index, and returns the first byte of the pattern tables.
+    fn run_on_mmc1(source : &str, mode : ExecutionMode) -> u8 {
+        let mut program = assemble_at(source, 0xc000).unwrap();
+        program.resize(0x4000, 0);
+        program[0x3ffc] = 0x00;
+        program[0x3ffd] = 0xc0;
+        let mut image = vec![b'N', b'E', b'S', 0x1a, 2, 4, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        image.extend(vec![0x01 ; 0x4000]);
+        image.extend(program);
+        for bank in 0 .. 4 {
+            image.extend(vec![bank ; 0x2000]);
+        }
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::new(Rom::from_bytes(&image).unwrap()).unwrap());
+        let mut cpu = CPU::with_memory(bus);
+        cpu.execution_mode = mode;
+        cpu.power_on();
+        cpu.run().unwrap();
+        cpu.memory().cartridge().unwrap().mapper().ppu_peek(0x0000)
+    }
+
+    #[test]
+    fn test_mmc1_counts_one_write_of_a_read_modify_write() {
+        for mode in [ExecutionMode::PerInstruction, ExecutionMode::PerCycle] {
+            // INC writes the $01 it read and then $02, the MMC1 only takes the first: the register ends up $1F, the
+            // last 8KiB of CHR.
+            let rmw = run_on_mmc1(&"INC $A000\n".repeat(5), mode);
+            assert_eq!(rmw, 3, "{:?}", mode);
+            // The same two values stored by separate instructions both count, loading $15 then $0A: 8KiB bank 1.
+            let stores = run_on_mmc1(&"LDA #$01\nSTA $A000\nLDA #$02\nSTA $A000\n".repeat(5), mode);
+            assert_eq!(stores, 1, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_reset_returns_multicart_to_menu() {
+        // Mapper 58, 4 banks of 16KiB.
+        let mut image = vec![b'N', b'E', b'S', 0x1a, 4, 0, 0xa0, 0x30, 0, 0, 0, 0, 0, 0, 0, 0];
+        for bank in 0 .. 4 {
+            image.extend(vec![bank ; 0x4000]);
+        }
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::new(Rom::from_bytes(&image).unwrap()).unwrap());
+        bus.write(0x0000, 0x42);
+        bus.write(0x8042, 0x00);
+        assert_eq!(bus.read(0x8000), 0x02);
+
+        bus.reset();
+        assert_eq!(bus.read(0x8000), 0x00);
+        assert_eq!(bus.read(0x0000), 0x42);
+    }
+}
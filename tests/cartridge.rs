@@ -0,0 +1,620 @@
+#[cfg(test)]
+mod cartridge_tests {
+    use nes::cartridge::{
+        crc32, sha1, unif_board_mapper, Cartridge, DatabaseError, GameDatabase, LoadWarning, Mirroring, Rom, RomError,
+    };
+    use nes::mapper::{Nrom, StateError};
+    use nes::region::Region;
+
+    /// An iNES image with the given header flags, PRG ROM banks filled with 0x01, 0x02, ... and CHR ROM banks
+    /// filled with 0x81, 0x82, ...
+    fn image(prg_banks : u8, chr_banks : u8, flags6 : u8, flags7 : u8) -> Vec<u8> {
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, prg_banks, chr_banks, flags6, flags7, 0, 0, 0, 0, 0, 0, 0, 0];
+        if flags6 & 0x04 != 0 {
+            bytes.extend([0xee ; 512]);
+        }
+        for bank in 0 .. prg_banks {
+            bytes.extend(vec![bank + 1 ; 0x4000]);
+        }
+        for bank in 0 .. chr_banks {
+            bytes.extend(vec![0x81 + bank ; 0x2000]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parses_banks() {
+        let rom = Rom::from_bytes(&image(2, 1, 0x00, 0x00)).unwrap();
+
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!((rom.prg_rom[0], rom.prg_rom[0x4000]), (0x01, 0x02));
+        assert_eq!(rom.chr_rom, vec![0x81 ; 0x2000]);
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+        assert!(!rom.battery);
+        assert_eq!(rom.trainer, None);
+    }
+
+    #[test]
+    fn test_chr_ram_has_no_chr_rom() {
+        let rom = Rom::from_bytes(&image(1, 0, 0x00, 0x00)).unwrap();
+        assert!(rom.chr_rom.is_empty());
+    }
+
+    #[test]
+    fn test_parses_flags() {
+        // Mapper 0x14 (MMC1 is 1, this is made up), vertical mirroring, battery.
+        let rom = Rom::from_bytes(&image(1, 1, 0x43, 0x10)).unwrap();
+        assert_eq!(rom.mapper, 0x14);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+
+        let rom = Rom::from_bytes(&image(1, 1, 0x09, 0x00)).unwrap();
+        assert_eq!(rom.mirroring, Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_skips_the_trainer() {
+        let rom = Rom::from_bytes(&image(1, 1, 0x04, 0x00)).unwrap();
+
+        assert_eq!(rom.trainer, Some(vec![0xee ; 512]));
+        assert_eq!(rom.prg_rom, vec![0x01 ; 0x4000]);
+        assert_eq!(rom.chr_rom, vec![0x81 ; 0x2000]);
+    }
+
+    #[test]
+    fn test_ignores_mapper_high_nibble_of_dirty_header() {
+        let mut bytes = image(1, 1, 0x10, 0x40);
+        bytes[7 .. 16].copy_from_slice(b"DiskDude!");
+        bytes.truncate(16);
+        bytes.extend(image(1, 1, 0, 0)[16 ..].iter());
+
+        assert_eq!(Rom::from_bytes(&bytes).unwrap().mapper, 1);
+    }
+
+    #[test]
+    fn test_rejects_malformed_files() {
+        assert!(matches!(Rom::from_bytes(b"NES"), Err(RomError::NotINes)));
+        assert!(matches!(Rom::from_bytes(&[0 ; 0x4010]), Err(RomError::NotINes)));
+        assert!(matches!(Rom::from_bytes(b"NES\x1a\x01"), Err(RomError::Truncated { expected : 16, actual : 5 })));
+        assert!(matches!(Rom::from_bytes(&image(0, 1, 0, 0)), Err(RomError::NoPrgRom)));
+
+        let mut bytes = image(2, 1, 0, 0);
+        bytes.pop();
+        match Rom::from_bytes(&bytes) {
+            Err(error @ RomError::Truncated { expected : 0xa010, actual : 0xa00f }) => {
+                assert_eq!(error.to_string(), "ROM is truncated, the header needs 40976 bytes but there are 40975");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("nes-cartridge-test-{}.nes", std::process::id()));
+        std::fs::write(&path, image(1, 1, 0x01, 0x00)).unwrap();
+        let rom = Rom::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rom.unwrap().mirroring, Mirroring::Vertical);
+        assert!(matches!(Rom::from_file(&path), Err(RomError::Io(_))));
+    }
+
+    #[test]
+    fn test_ines_implies_usual_ram_sizes() {
+        let rom = Rom::from_bytes(&image(1, 0, 0x00, 0x00)).unwrap();
+        assert!(!rom.nes2);
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size, rom.chr_ram_size), (0x2000, 0, 0x2000));
+        assert_eq!(rom.region, None);
+
+        let rom = Rom::from_bytes(&image(1, 1, 0x02, 0x00)).unwrap();
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size, rom.chr_ram_size), (0, 0x2000, 0));
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut bytes = image(2, 0, 0x12, 0x48);
+        bytes[8] = 0x31; // Submapper 3, mapper bits 8-11 = 1.
+        bytes[10] = 0x07; // 8KiB PRG RAM, no NVRAM.
+        bytes[11] = 0x97; // 8KiB CHR RAM, 32KiB CHR NVRAM.
+        bytes[12] = 0x01; // PAL.
+        let rom = Rom::from_bytes(&bytes).unwrap();
+
+        assert!(rom.nes2);
+        assert_eq!(rom.mapper, 0x141);
+        assert_eq!(rom.submapper, 3);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0x2000, 0));
+        assert_eq!((rom.chr_ram_size, rom.chr_nvram_size), (0x2000, 0x8000));
+        assert_eq!(rom.region, Some(Region::Pal));
+    }
+
+    #[test]
+    fn test_nes2_regions() {
+        for (timing, region) in [(0, Some(Region::Ntsc)), (1, Some(Region::Pal)), (2, None), (3, Some(Region::Dendy))] {
+            let mut bytes = image(1, 1, 0x00, 0x08);
+            bytes[12] = timing;
+            assert_eq!(Rom::from_bytes(&bytes).unwrap().region, region);
+        }
+    }
+
+    #[test]
+    fn test_nes2_extended_rom_sizes() {
+        // A high nibble of 1 adds 256 banks to the PRG ROM count.
+        let mut bytes = image(1, 0, 0x00, 0x08);
+        bytes[9] = 0x01;
+        bytes.resize(16 + 257 * 0x4000, 0);
+        assert_eq!(Rom::from_bytes(&bytes).unwrap().prg_rom.len(), 257 * 0x4000);
+
+        // Exponent-multiplier form: 2^13 * (1 * 2 + 1) = 24KiB of PRG ROM and 2^10 * 1 = 1KiB of CHR ROM.
+        let mut bytes = image(1, 0, 0x00, 0x08);
+        bytes[4] = (13 << 2) | 1;
+        bytes[5] = 10 << 2;
+        bytes[9] = 0xff;
+        bytes.truncate(16);
+        bytes.extend(vec![0x01 ; 0x6000]);
+        bytes.extend(vec![0x02 ; 0x400]);
+        let rom = Rom::from_bytes(&bytes).unwrap();
+        assert_eq!(rom.prg_rom, vec![0x01 ; 0x6000]);
+        assert_eq!(rom.chr_rom, vec![0x02 ; 0x400]);
+
+        // A size no file could have is reported as truncation.
+        bytes[4] = 63 << 2 | 3;
+        assert!(matches!(Rom::from_bytes(&bytes), Err(RomError::Truncated { .. })));
+    }
+
+    #[test]
+    fn test_nes2_prg_rom_smaller_than_a_bank() {
+        // 2^13 = 8KiB of PRG ROM in exponent-multiplier form, less than the banks these boards fix at the end of
+        // the ROM. It is mirrored through them.
+        for mapper in [1u8, 2, 4, 9, 24, 69] {
+            let mut bytes = image(0, 1, mapper << 4, 0x08 | (mapper & 0xf0));
+            bytes[4] = 13 << 2;
+            bytes[9] = 0x0f;
+            bytes.splice(16 .. 16, (0 .. 0x2000).map(|offset| (offset >> 8) as u8));
+            let cartridge = Cartridge::new(Rom::from_bytes(&bytes).unwrap()).unwrap();
+            let reads = [0x8100, 0xa200, 0xc300, 0xfffc].map(|addr| cartridge.mapper().cpu_peek(addr));
+            assert_eq!(reads, [Some(0x01), Some(0x02), Some(0x03), Some(0x1f)], "mapper {}", mapper);
+        }
+    }
+
+    #[test]
+    fn test_nes2_header_is_not_treated_as_dirty() {
+        let mut bytes = image(1, 1, 0x10, 0x28);
+        bytes[12] = 0x01;
+        assert_eq!(Rom::from_bytes(&bytes).unwrap().mapper, 0x21);
+    }
+
+    #[test]
+    fn test_cartridge_picks_board_by_mapper_number() {
+        // UxROM, 4 banks of 16KiB.
+        let mut cartridge = Cartridge::new(Rom::from_bytes(&image(4, 0, 0x20, 0x00)).unwrap()).unwrap();
+        assert_eq!(cartridge.mapper_number(), 2);
+        assert_eq!(cartridge.mapper().cpu_peek(0xc000), Some(0x04));
+        cartridge.mapper_mut().cpu_write(0x8000, 2);
+        assert_eq!(cartridge.mapper().cpu_peek(0x8000), Some(0x03));
+    }
+
+    #[test]
+    fn test_cartridge_rejects_unsupported_mapper() {
+        let rom = Rom::from_bytes(&image(1, 1, 0xf0, 0xf0)).unwrap();
+        match Cartridge::new(rom) {
+            Err(error @ RomError::UnsupportedMapper(255)) => assert_eq!(error.to_string(), "mapper 255 is not supported"),
+            _ => panic!("mapper 255 should not be supported"),
+        }
+    }
+
+    #[test]
+    fn test_cartridge_with_own_mapper() {
+        let nrom = Nrom::new(Rom::from_bytes(&image(1, 1, 0x01, 0x00)).unwrap());
+        let cartridge = Cartridge::with_mapper(1000, nrom);
+        assert_eq!(cartridge.mapper_number(), 1000);
+        assert_eq!(cartridge.mapper().mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_save_ram_needs_battery() {
+        // MMC1 with and without a battery.
+        let mut cartridge = Cartridge::new(Rom::from_bytes(&image(2, 1, 0x12, 0x00)).unwrap()).unwrap();
+        assert!(cartridge.has_battery());
+        cartridge.mapper_mut().cpu_write(0x6000, 0x42);
+        cartridge.mapper_mut().cpu_write(0x7fff, 0x43);
+        let saved = cartridge.save_ram().unwrap().to_vec();
+        assert_eq!((saved.len(), saved[0], saved[0x1fff]), (0x2000, 0x42, 0x43));
+
+        let mut restored = Cartridge::new(Rom::from_bytes(&image(2, 1, 0x12, 0x00)).unwrap()).unwrap();
+        restored.load_ram(&saved);
+        assert_eq!(restored.mapper().cpu_peek(0x6000), Some(0x42));
+        // A short save only fills the start.
+        restored.load_ram(&[0x01]);
+        assert_eq!((restored.mapper().cpu_peek(0x6000), restored.mapper().cpu_peek(0x7fff)), (Some(0x01), Some(0x43)));
+
+        let cartridge = Cartridge::new(Rom::from_bytes(&image(2, 1, 0x10, 0x00)).unwrap()).unwrap();
+        assert!(!cartridge.has_battery());
+        assert_eq!(cartridge.save_ram(), None);
+        // NROM has no PRG RAM at all.
+        let cartridge = Cartridge::new(Rom::from_bytes(&image(1, 1, 0x02, 0x00)).unwrap()).unwrap();
+        assert_eq!(cartridge.save_ram(), None);
+    }
+
+    #[test]
+    fn test_battery_ram_persists_to_sav_file() {
+        let path = std::env::temp_dir().join(format!("nes-cartridge-save-test-{}.nes", std::process::id()));
+        let sav = path.with_extension("sav");
+        std::fs::write(&path, image(2, 1, 0x12, 0x00)).unwrap();
+
+        let mut cartridge = Cartridge::from_file(&path).unwrap();
+        assert_eq!(cartridge.save_path(), Some(sav.as_path()));
+        assert_eq!(cartridge.mapper().cpu_peek(0x6000), Some(0x00));
+        cartridge.mapper_mut().cpu_write(0x6000, 0x42);
+        // Dropping the cartridge saves.
+        drop(cartridge);
+        assert_eq!(std::fs::read(&sav).unwrap()[0], 0x42);
+
+        let mut cartridge = Cartridge::from_file(&path).unwrap();
+        assert_eq!(cartridge.mapper().cpu_peek(0x6000), Some(0x42));
+        cartridge.set_save_path(None);
+        cartridge.mapper_mut().cpu_write(0x6000, 0x00);
+        assert!(!cartridge.load_from_disk().unwrap());
+        drop(cartridge);
+        assert_eq!(std::fs::read(&sav).unwrap()[0], 0x42);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sav).unwrap();
+    }
+
+    #[test]
+    fn test_trainer_is_loaded_at_7000() {
+        // MMC3 with a trainer: the PRG ROM still starts after it.
+        let cartridge = Cartridge::new(Rom::from_bytes(&image(2, 1, 0x44, 0x00)).unwrap()).unwrap();
+        assert_eq!(cartridge.mapper().cpu_peek(0x7000), Some(0xee));
+        assert_eq!(cartridge.mapper().cpu_peek(0x71ff), Some(0xee));
+        assert_eq!(cartridge.mapper().cpu_peek(0x7200), Some(0x00));
+        assert_eq!(cartridge.mapper().cpu_peek(0x6fff), Some(0x00));
+        assert_eq!(cartridge.mapper().cpu_peek(0x8000), Some(0x01));
+
+        // NROM has nowhere to put it, the cartridge still loads.
+        let cartridge = Cartridge::new(Rom::from_bytes(&image(1, 1, 0x04, 0x00)).unwrap()).unwrap();
+        assert_eq!(cartridge.mapper().cpu_peek(0x8000), Some(0x01));
+    }
+
+    #[test]
+    fn test_mirroring_vram_translation() {
+        let slots = |mirroring : Mirroring| [0x2000, 0x2400, 0x2800, 0x2c00].map(|addr| mirroring.vram_offset(addr + 0x15));
+        assert_eq!(slots(Mirroring::Horizontal), [0x015, 0x015, 0x415, 0x415]);
+        assert_eq!(slots(Mirroring::Vertical), [0x015, 0x415, 0x015, 0x415]);
+        assert_eq!(slots(Mirroring::SingleScreenLower), [0x015 ; 4]);
+        assert_eq!(slots(Mirroring::SingleScreenUpper), [0x415 ; 4]);
+        assert_eq!(slots(Mirroring::FourScreen), [0x015, 0x415, 0x815, 0xc15]);
+
+        // $3000-$3EFF mirrors $2000-$2EFF.
+        assert_eq!(Mirroring::Vertical.vram_offset(0x3400), Mirroring::Vertical.vram_offset(0x2400));
+        assert_eq!(Mirroring::Vertical.vram_size(), 0x800);
+        assert_eq!(Mirroring::FourScreen.vram_size(), 0x1000);
+    }
+
+    #[test]
+    fn test_cartridge_mirroring_follows_mapper() {
+        // AxROM switches between its single-screen nametables.
+        let mut cartridge = Cartridge::new(Rom::from_bytes(&image(2, 0, 0x70, 0x00)).unwrap()).unwrap();
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+        cartridge.mapper_mut().cpu_write(0x8000, 0x10);
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+
+        let cartridge = Cartridge::new(Rom::from_bytes(&image(1, 1, 0x09, 0x00)).unwrap()).unwrap();
+        assert_eq!(cartridge.mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let hex = |digest : [u8 ; 20]| digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            hex(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(hex(sha1(&[b'a' ; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn test_rom_hashes_skip_header() {
+        let bytes = image(1, 1, 0x00, 0x00);
+        let rom = Rom::from_bytes(&bytes).unwrap();
+        let hashes = rom.hashes();
+        assert_eq!(hashes.crc32, crc32(&bytes[16 ..]));
+        assert_eq!(hashes.prg_crc32, crc32(&bytes[16 .. 16 + 0x4000]));
+        assert_eq!(hashes.chr_crc32, crc32(&bytes[16 + 0x4000 ..]));
+        assert_eq!(hashes.sha1, sha1(&bytes[16 ..]));
+
+        // The same game with different header garbage hashes the same.
+        let mut other = bytes.clone();
+        other[15] = 0x44;
+        assert_eq!(Rom::from_bytes(&other).unwrap().hashes(), hashes);
+    }
+
+    #[test]
+    fn test_game_database_parse() {
+        let database = GameDatabase::parse(
+            "# comment\n\n0000abcd;1;H;B;PAL;A Game\n0000abce;4;;-;;Another; with a semicolon\n",
+        )
+        .unwrap();
+        assert_eq!(database.len(), 2);
+        let game = database.lookup(0xabcd).unwrap();
+        assert_eq!(game.title, "A Game");
+        assert_eq!((game.mapper, game.mirroring, game.battery), (1, Some(Mirroring::Horizontal), Some(true)));
+        assert_eq!(game.region, Some(Region::Pal));
+        let game = database.lookup(0xabce).unwrap();
+        assert_eq!((game.title.as_str(), game.mirroring, game.battery), ("Another; with a semicolon", None, Some(false)));
+        assert!(database.lookup(0x1234).is_none());
+
+        match GameDatabase::parse("# ok\nzz;0;;;;Title") {
+            Err(error @ DatabaseError::Syntax { line : 2, .. }) => {
+                assert_eq!(error.to_string(), "game database line 2: bad CRC32")
+            }
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+        assert!(GameDatabase::parse("0;0;X;;;Title").is_err());
+        assert!(GameDatabase::parse("0;0;;").is_err());
+        assert!(!GameDatabase::builtin().is_empty());
+    }
+
+    #[test]
+    fn test_cartridge_applies_database_overrides() {
+        // Claims to be NROM with horizontal mirroring, the database knows it is UxROM with vertical mirroring.
+        let rom = Rom::from_bytes(&image(4, 0, 0x00, 0x00)).unwrap();
+        let crc = rom.hashes().crc32;
+        let database = GameDatabase::parse(&format!("{:08x};2;V;;NTSC;Fixed Game", crc)).unwrap();
+        let mut cartridge = Cartridge::with_database(rom, &database).unwrap();
+
+        assert_eq!(cartridge.game().unwrap().title, "Fixed Game");
+        assert_eq!(cartridge.hashes().unwrap().crc32, crc);
+        assert_eq!(cartridge.mapper_number(), 2);
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+        cartridge.mapper_mut().cpu_write(0x8000, 1);
+        assert_eq!(cartridge.mapper().cpu_peek(0x8000), Some(0x02));
+
+        let cartridge = Cartridge::new(Rom::from_bytes(&image(4, 0, 0x00, 0x00)).unwrap()).unwrap();
+        assert!(cartridge.game().is_none());
+        assert_eq!(cartridge.mapper_number(), 0);
+    }
+
+    #[test]
+    fn test_tolerant_load_of_clean_file() {
+        let (rom, report) = Rom::from_bytes_tolerant(&image(2, 1, 0x00, 0x00)).unwrap();
+
+        assert_eq!(rom, Rom::from_bytes(&image(2, 1, 0x00, 0x00)).unwrap());
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn test_tolerant_load_ignores_overdump() {
+        let mut bytes = image(1, 1, 0x00, 0x00);
+        bytes.extend([0xff ; 100]);
+        let (rom, report) = Rom::from_bytes_tolerant(&bytes).unwrap();
+
+        assert_eq!(rom.chr_rom, vec![0x81 ; 0x2000]);
+        assert_eq!(report.warnings, [LoadWarning::TrailingData { bytes : 100 }]);
+        assert_eq!(report.warnings[0].to_string(), "ignored 100 bytes after the end of the ROM");
+    }
+
+    #[test]
+    fn test_tolerant_load_pads_short_roms() {
+        let mut bytes = image(2, 1, 0x00, 0x00);
+        bytes.truncate(16 + 0x8000 + 0x800);
+        let (rom, report) = Rom::from_bytes_tolerant(&bytes).unwrap();
+
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert_eq!((rom.chr_rom[0x7ff], rom.chr_rom[0x800]), (0x81, 0x00));
+        assert_eq!(report.warnings, [LoadWarning::ChrRomTruncated { expected : 0x2000, actual : 0x800 }]);
+
+        bytes.truncate(16 + 0x6000);
+        let (rom, report) = Rom::from_bytes_tolerant(&bytes).unwrap();
+
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!((rom.prg_rom[0x5fff], rom.prg_rom[0x6000]), (0x02, 0x00));
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(report.warnings, [
+            LoadWarning::PrgRomTruncated { expected : 0x8000, actual : 0x6000 },
+            LoadWarning::ChrRomMissing { expected : 0x2000 },
+        ]);
+    }
+
+    #[test]
+    fn test_tolerant_load_drops_missing_trainer() {
+        let bytes = image(1, 0, 0x04, 0x00);
+        let (rom, report) = Rom::from_bytes_tolerant(&bytes[.. 16 + 0x100]).unwrap();
+
+        assert!(rom.trainer.is_none());
+        assert_eq!(rom.prg_rom[0], 0xee);
+        assert_eq!(report.warnings[0], LoadWarning::TrainerMissing);
+    }
+
+    #[test]
+    fn test_tolerant_load_still_rejects_unusable_files() {
+        assert!(matches!(Rom::from_bytes_tolerant(b"NES"), Err(RomError::NotINes)));
+        assert!(matches!(Rom::from_bytes_tolerant(&image(1, 1, 0, 0)[.. 16]), Err(RomError::Truncated { .. })));
+        assert!(matches!(Rom::from_bytes_tolerant(&image(0, 1, 0, 0)), Err(RomError::NoPrgRom)));
+    }
+
+    #[test]
+    fn test_cartridge_from_file_reports_fixes() {
+        let path = std::env::temp_dir().join(format!("nes-cartridge-report-test-{}.nes", std::process::id()));
+        let mut bytes = image(1, 1, 0x00, 0x00);
+        bytes.truncate(16 + 0x4000);
+        std::fs::write(&path, bytes).unwrap();
+        let cartridge = Cartridge::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut cartridge = cartridge.unwrap();
+        assert_eq!(cartridge.load_report().warnings, [LoadWarning::ChrRomMissing { expected : 0x2000 }]);
+        cartridge.mapper_mut().ppu_write(0x0010, 0x42);
+        assert_eq!(cartridge.mapper_mut().ppu_read(0x0010), 0x42);
+        assert!(Cartridge::new(Rom::from_bytes(&image(1, 1, 0, 0)).unwrap()).unwrap().load_report().is_clean());
+    }
+
+    /// A UNIF image made of `chunks`.
+    fn unif(chunks : &[(&[u8 ; 4], &[u8])]) -> Vec<u8> {
+        let mut bytes = b"UNIF".to_vec();
+        bytes.extend(7u32.to_le_bytes());
+        bytes.resize(32, 0);
+        for (id, data) in chunks {
+            bytes.extend(*id);
+            bytes.extend((data.len() as u32).to_le_bytes());
+            bytes.extend(*data);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parses_unif() {
+        let prg0 = vec![1 ; 0x4000];
+        let prg1 = vec![2 ; 0x4000];
+        let chr0 = vec![0x81 ; 0x2000];
+        let bytes = unif(&[
+            (b"MAPR", b"NES-SNROM\0"),
+            (b"NAME", b"Some Game\0"),
+            (b"PRG1", &prg1),
+            (b"PRG0", &prg0),
+            (b"CHR0", &chr0),
+            (b"MIRR", &[1]),
+            (b"BATR", &[1]),
+            (b"TVCI", &[1]),
+        ]);
+        let rom = Rom::from_bytes(&bytes).unwrap();
+
+        assert_eq!(rom.mapper, 1);
+        assert_eq!((rom.prg_rom.len(), rom.prg_rom[0], rom.prg_rom[0x4000]), (0x8000, 1, 2));
+        assert_eq!(rom.chr_rom, chr0);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.region, Some(Region::Pal));
+        assert_eq!(rom.prg_ram_size, 0x2000);
+        assert_eq!(Cartridge::new(rom).unwrap().mapper_number(), 1);
+    }
+
+    #[test]
+    fn test_unif_without_chr_rom_has_chr_ram() {
+        let rom = Rom::from_bytes(&unif(&[(b"MAPR", b"HVC-UNROM"), (b"PRG0", &[0 ; 0x20000])])).unwrap();
+
+        assert_eq!(rom.mapper, 2);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_rejects_bad_unif() {
+        let prg = [0 ; 0x4000];
+        assert!(matches!(Rom::from_bytes(&unif(&[(b"PRG0", &prg)])), Err(RomError::MissingBoard)));
+        assert!(matches!(Rom::from_bytes(&unif(&[(b"MAPR", b"NES-NROM-128")])), Err(RomError::NoPrgRom)));
+        match Rom::from_bytes(&unif(&[(b"MAPR", b"UNL-MYSTERY\0"), (b"PRG0", &prg)])) {
+            Err(error @ RomError::UnknownBoard(_)) => assert_eq!(error.to_string(), "UNIF board UNL-MYSTERY is not supported"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut bytes = unif(&[(b"MAPR", b"NES-NROM-128"), (b"PRG0", &prg)]);
+        bytes.pop();
+        assert!(matches!(Rom::from_bytes(&bytes), Err(RomError::Truncated { .. })));
+        assert!(matches!(Rom::from_bytes(b"UNIF"), Err(RomError::Truncated { expected : 32, actual : 4 })));
+    }
+
+    #[test]
+    fn test_unif_board_names() {
+        assert_eq!(unif_board_mapper("NES-NROM-256"), Some(0));
+        assert_eq!(unif_board_mapper("NROM-256"), Some(0));
+        assert_eq!(unif_board_mapper("NES-CNROM"), Some(3));
+        assert_eq!(unif_board_mapper("NES-ELROM"), Some(5));
+        assert_eq!(unif_board_mapper("NES-AOROM"), Some(7));
+        assert_eq!(unif_board_mapper("NES-PNROM"), Some(9));
+        assert_eq!(unif_board_mapper("NES-JLROM"), Some(69));
+        assert_eq!(unif_board_mapper("NES-MHROM"), Some(66));
+        assert_eq!(unif_board_mapper("NES-SNROM-EXTRA"), None);
+    }
+
+    #[test]
+    fn test_bus_conflicts_from_header() {
+        // UxROM, PRG banks filled with 1, 2, 3, 4: the byte at $8000 is 1, so with conflicts writing 3 selects bank 1.
+        let mut uxrom = Cartridge::new(Rom::from_bytes(&image(4, 0, 0x20, 0x00)).unwrap()).unwrap();
+        assert!(!uxrom.bus_conflicts());
+        uxrom.cpu_write(0x8000, 3);
+        assert_eq!(uxrom.mapper().cpu_peek(0x8000), Some(0x04));
+
+        let mut bytes = image(4, 0, 0x20, 0x08);
+        bytes[8] = 0x20; // NES 2.0 submapper 2, AND bus conflicts.
+        let mut uxrom = Cartridge::new(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        assert!(uxrom.bus_conflicts());
+        uxrom.cpu_write(0x8000, 3);
+        assert_eq!(uxrom.mapper().cpu_peek(0x8000), Some(0x02));
+
+        // The conflicts can be turned off for games the header gets wrong.
+        uxrom.set_bus_conflicts(false);
+        uxrom.cpu_write(0x8000, 3);
+        assert_eq!(uxrom.mapper().cpu_peek(0x8000), Some(0x04));
+
+        bytes[8] = 0x10; // Submapper 1 takes CNROM's conflicts away.
+        bytes[6] = 0x30;
+        assert!(!Cartridge::new(Rom::from_bytes(&bytes).unwrap()).unwrap().bus_conflicts());
+        assert!(Cartridge::new(Rom::from_bytes(&image(2, 1, 0x30, 0x00)).unwrap()).unwrap().bus_conflicts());
+    }
+
+    #[test]
+    fn test_bus_conflicts_from_database() {
+        let rom = Rom::from_bytes(&image(4, 0, 0x20, 0x00)).unwrap();
+        let crc = rom.hashes().crc32;
+        let database = GameDatabase::parse(&format!("{:08x};2.2;;;;Conflicted Game", crc)).unwrap();
+        assert_eq!(database.lookup(crc).unwrap().submapper, Some(2));
+        let mut cartridge = Cartridge::with_database(rom, &database).unwrap();
+
+        assert!(cartridge.bus_conflicts());
+        cartridge.cpu_write(0x8000, 3);
+        assert_eq!(cartridge.mapper().cpu_peek(0x8000), Some(0x02));
+
+        assert_eq!(GameDatabase::parse("0;2;;;;Title").unwrap().lookup(0).unwrap().submapper, None);
+        assert!(GameDatabase::parse("0;2.x;;;;Title").is_err());
+    }
+
+    #[test]
+    fn test_cartridge_save_state() {
+        let mut cartridge = Cartridge::new(Rom::from_bytes(&image(4, 0, 0x20, 0x00)).unwrap()).unwrap();
+        cartridge.cpu_write(0x8000, 2);
+        cartridge.mapper_mut().ppu_write(0x0000, 0x42);
+        let state = cartridge.save_state();
+
+        cartridge.cpu_write(0x8000, 0);
+        cartridge.mapper_mut().ppu_write(0x0000, 0);
+        cartridge.load_state(&state).unwrap();
+        assert_eq!((cartridge.mapper().cpu_peek(0x8000), cartridge.mapper().ppu_peek(0x0000)), (Some(0x03), 0x42));
+    }
+
+    #[test]
+    fn test_cartridge_rejects_foreign_states() {
+        let mut cartridge = Cartridge::new(Rom::from_bytes(&image(4, 0, 0x20, 0x00)).unwrap()).unwrap();
+        let state = cartridge.save_state();
+
+        assert_eq!(cartridge.load_state(b"NES\x1a"), Err(StateError::NotAState));
+        let nrom = Cartridge::new(Rom::from_bytes(&image(1, 1, 0x00, 0x00)).unwrap()).unwrap();
+        let error = cartridge.load_state(&nrom.save_state()).unwrap_err();
+        assert_eq!(error, StateError::WrongMapper { expected : 2, found : 0 });
+        assert_eq!(error.to_string(), "save state is for mapper 0, the cartridge is mapper 2");
+
+        let mut newer = state.clone();
+        newer[6] = 2;
+        assert_eq!(cartridge.load_state(&newer), Err(StateError::UnsupportedVersion { mapper : 2, version : 2 }));
+        assert_eq!(cartridge.load_state(&state[.. state.len() - 1]), Err(StateError::Truncated));
+        let mut longer = state.clone();
+        longer.push(0);
+        assert_eq!(cartridge.load_state(&longer), Err(StateError::TrailingData));
+
+        // Same board, but 32KiB of CHR RAM instead of 8KiB.
+        let mut bytes = image(4, 0, 0x20, 0x08);
+        bytes[11] = 0x09;
+        let mut bigger = Cartridge::new(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        let error = bigger.load_state(&state).unwrap_err();
+        assert_eq!(error, StateError::SizeMismatch { expected : 0x8000, found : 0x2000 });
+        cartridge.load_state(&state).unwrap();
+    }
+}
@@ -1,28 +1,29 @@
 #[cfg(test)]
 mod cpu_tests {
-    use nes::cpu::CPU;
+    use nes::cpu::{Access, CpuError, CpuQuirks, CpuState, ExecutionMode, Interrupt, Stopped, WatchHit, CPU};
+    use nes::mem::TestBus;
 
     #[test]
     fn test_0xa9_lda_immediate_load_data() {
         let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0x05, 0x00]);
+        cpu.load_and_run(vec![0xa9, 0x05, 0x00]).unwrap();
         assert_eq!(cpu.register_a, 0x05);
-        assert!(cpu.status & 0b0000_0010 == 0b00);
-        assert!(cpu.status & 0b1000_0000 == 0);
+        assert!(!cpu.status.zero());
+        assert!(!cpu.status.negative());
     }
  
      #[test]
      fn test_0xa9_lda_zero_flag() {
          let mut cpu = CPU::new();
-         cpu.load_and_run(vec![0xa9, 0x00, 0x00]);
-         assert!(cpu.status & 0b0000_0010 == 0b10);
+         cpu.load_and_run(vec![0xa9, 0x00, 0x00]).unwrap();
+         assert!(cpu.status.zero());
      } 
 
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
         let mut cpu = CPU::new();
         cpu.register_a = 10;
-        cpu.load_and_run(vec![0xa9, 10, 0xaa, 0x00]);
+        cpu.load_and_run(vec![0xa9, 10, 0xaa, 0x00]).unwrap();
     
         assert_eq!(cpu.register_x, 10)
     }
@@ -31,15 +32,15 @@ mod cpu_tests {
     fn test_0xe8_inx_increment() {
         let mut cpu = CPU::new();
         cpu.register_x = 0b0111_1111;
-        cpu.load_and_run(vec![0xa9, 0b0111_1111, 0xaa, 0xe8, 0x00]);
+        cpu.load_and_run(vec![0xa9, 0b0111_1111, 0xaa, 0xe8, 0x00]).unwrap();
         assert_eq!(cpu.register_x, 0b1000_0000);
-        assert_eq!(cpu.status, 0b1000_0000);
+        assert_eq!(cpu.status.bits(), 0b1000_0000);
     }
     
     #[test]
     fn test_5_ops_working_together() {
         let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]).unwrap();
   
         assert_eq!(cpu.register_x, 0xc1)
     }
@@ -47,8 +48,2410 @@ mod cpu_tests {
      #[test]
      fn test_inx_overflow() {
          let mut cpu = CPU::new();
-         cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00]);
+         cpu.load_and_run(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00]).unwrap();
  
          assert_eq!(cpu.register_x, 1)
      }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_0xa5_lda_from_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+        cpu.load_and_run(vec![0xa5, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_0xb5_lda_zero_page_x_wraps() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0f, 0x42);
+        cpu.load_and_run(vec![0xa2, 0x10, 0xb5, 0xff, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_0xbd_lda_absolute_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x1234, 0x99);
+        cpu.load_and_run(vec![0xa2, 0x04, 0xbd, 0x30, 0x12, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x99);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0xb1_lda_indirect_y() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x20, 0x0300);
+        cpu.mem_write(0x0305, 0x07);
+        cpu.load_and_run(vec![0xa0, 0x05, 0xb1, 0x20, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x07);
+    }
+
+    #[test]
+    fn test_0x85_sta_zero_page() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x33, 0x85, 0x40, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x40), 0x33);
+    }
+
+    #[test]
+    fn test_0x69_adc_sets_carry() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0xff, 0x69, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0xe9_sbc_with_carry_set() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x10, 0x38, 0xe9, 0x01, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x0f);
+        assert!(cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0x29_and_0x09_ora_0x49_eor() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0b1100_1100, 0x29, 0b1010_1010, 0x09, 0b0000_0001, 0x49, 0b1000_1001, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0b0000_0000);
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x0a_asl_accumulator() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0b1000_0001, 0x0a, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0b0000_0010);
+        assert!(cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0x66_ror_memory_through_carry() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b0000_0010);
+        cpu.load_and_run(vec![0x38, 0x66, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b1000_0001);
+        assert!(!cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0xe6_inc_and_0xc6_dec_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0xff);
+        cpu.mem_write(0x11, 0x01);
+        cpu.load_and_run(vec![0xe6, 0x10, 0xc6, 0x11, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x00);
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0xc9_cmp_sets_carry_and_zero() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x40, 0xc9, 0x40, 0x00]).unwrap();
+
+        assert!(cpu.status.carry() && cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0xd0_bne_loop() {
+        let mut cpu = CPU::new();
+        // LDX #$05; loop: INY; DEX; BNE loop; BRK
+        cpu.load_and_run(vec![0xa2, 0x05, 0xc8, 0xca, 0xd0, 0xfc, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x00);
+        assert_eq!(cpu.register_y, 0x05);
+    }
+
+    #[test]
+    fn test_0x4c_jmp_absolute() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0x4c, 0x05, 0x80, 0xa9, 0x01, 0xa9, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x02);
+    }
+
+    #[test]
+    fn test_0x20_jsr_0x60_rts() {
+        let mut cpu = CPU::new();
+        // JSR sub; LDX #$01; BRK; sub: LDA #$07; RTS
+        cpu.load_and_run(vec![0x20, 0x06, 0x80, 0xa2, 0x01, 0x00, 0xa9, 0x07, 0x60]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x07);
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+    }
+
+    #[test]
+    fn test_0x48_pha_0x68_pla() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x80, 0x48, 0xa9, 0x00, 0x68, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_transfers_and_flags() {
+        let mut cpu = CPU::new();
+        // LDY #$3C; TYA; TAX; SEC; SED; SEI; CLD
+        cpu.load_and_run(vec![0xa0, 0x3c, 0x98, 0xaa, 0x38, 0xf8, 0x78, 0xd8, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x3c);
+        assert_eq!(cpu.register_x, 0x3c);
+        assert_eq!(cpu.status.bits(), 0b0000_0101);
+    }
+
+    #[test]
+    fn test_0x24_bit() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b1100_0000);
+        cpu.load_and_run(vec![0xa9, 0x01, 0x24, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.status.bits(), 0b1100_0010);
+    }
+
+    /// Runs `LDA #a; CLC/SEC; ADC #m; BRK` and returns the A register with the carry and overflow flags.
+    fn run_adc(a : u8, m : u8, carry : bool) -> (u8, bool, bool) {
+        let mut cpu = CPU::new();
+        let set_carry = if carry { 0x38 } else { 0x18 };
+        cpu.load_and_run(vec![0xa9, a, set_carry, 0x69, m, 0x00]).unwrap();
+        (cpu.register_a, cpu.status.carry(), cpu.status.overflow())
+    }
+
+    #[test]
+    fn test_0x69_adc_overflow_truth_table() {
+        // (A, M, result, carry out, overflow)
+        let table = [
+            (0x50, 0x10, 0x60, false, false),
+            (0x50, 0x50, 0xa0, false, true),
+            (0x50, 0x90, 0xe0, false, false),
+            (0x50, 0xd0, 0x20, true, false),
+            (0xd0, 0x10, 0xe0, false, false),
+            (0xd0, 0x50, 0x20, true, false),
+            (0xd0, 0x90, 0x60, true, true),
+            (0xd0, 0xd0, 0xa0, true, false),
+        ];
+
+        for (a, m, result, carry, overflow) in table {
+            assert_eq!(run_adc(a, m, false), (result, carry, overflow), "{:#04x} + {:#04x}", a, m);
+        }
+    }
+
+    #[test]
+    fn test_0x69_adc_overflow_edge_cases() {
+        assert_eq!(run_adc(0x7f, 0x01, false), (0x80, false, true));
+        assert_eq!(run_adc(0x80, 0xff, false), (0x7f, true, true));
+        assert_eq!(run_adc(0xff, 0x01, false), (0x00, true, false));
+        assert_eq!(run_adc(0x80, 0x80, false), (0x00, true, true));
+    }
+
+    #[test]
+    fn test_0x69_adc_carry_in() {
+        assert_eq!(run_adc(0x7f, 0x00, true), (0x80, false, true));
+        assert_eq!(run_adc(0xff, 0x00, true), (0x00, true, false));
+        assert_eq!(run_adc(0x01, 0x01, true), (0x03, false, false));
+    }
+
+    #[test]
+    fn test_adc_memory_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x01);
+        cpu.mem_write(0x15, 0x02);
+        cpu.mem_write(0x0200, 0x04);
+        cpu.mem_write(0x0205, 0x08);
+        cpu.mem_write_u16(0x20, 0x0200);
+        cpu.mem_write_u16(0x30, 0x0300);
+        cpu.mem_write(0x0305, 0x20);
+        cpu.load(vec![
+            0x65, 0x10,       // ADC $10
+            0x75, 0x10,       // ADC $10,X
+            0x6d, 0x00, 0x02, // ADC $0200
+            0x7d, 0x00, 0x02, // ADC $0200,X
+            0x79, 0x00, 0x02, // ADC $0200,Y
+            0x61, 0x1b,       // ADC ($1B,X)
+            0x71, 0x30,       // ADC ($30),Y
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x05;
+        cpu.register_y = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0x01 + 0x02 + 0x04 + 0x08 + 0x08 + 0x04 + 0x20);
+    }
+
+    /// Runs `LDA #a; CLC/SEC; SBC #m; BRK` and returns the A register with the carry and overflow flags.
+    fn run_sbc(a : u8, m : u8, carry : bool) -> (u8, bool, bool) {
+        let mut cpu = CPU::new();
+        let set_carry = if carry { 0x38 } else { 0x18 };
+        cpu.load_and_run(vec![0xa9, a, set_carry, 0xe9, m, 0x00]).unwrap();
+        (cpu.register_a, cpu.status.carry(), cpu.status.overflow())
+    }
+
+    #[test]
+    fn test_0xe9_sbc_overflow_truth_table() {
+        // (A, M, result, carry out, overflow) with the carry set going in (no borrow).
+        let table = [
+            (0x50, 0xf0, 0x60, false, false),
+            (0x50, 0xb0, 0xa0, false, true),
+            (0x50, 0x70, 0xe0, false, false),
+            (0x50, 0x30, 0x20, true, false),
+            (0xd0, 0xf0, 0xe0, false, false),
+            (0xd0, 0xb0, 0x20, true, false),
+            (0xd0, 0x70, 0x60, true, true),
+            (0xd0, 0x30, 0xa0, true, false),
+        ];
+
+        for (a, m, result, carry, overflow) in table {
+            assert_eq!(run_sbc(a, m, true), (result, carry, overflow), "{:#04x} - {:#04x}", a, m);
+        }
+    }
+
+    #[test]
+    fn test_0xe9_sbc_borrow_is_inverted_carry() {
+        // Carry clear means an extra one is borrowed.
+        assert_eq!(run_sbc(0x10, 0x01, false), (0x0e, true, false));
+        // Borrowing out of bit 7 clears the carry.
+        assert_eq!(run_sbc(0x00, 0x01, true), (0xff, false, false));
+        assert_eq!(run_sbc(0x00, 0x00, false), (0xff, false, false));
+        assert_eq!(run_sbc(0x80, 0x01, true), (0x7f, true, true));
+    }
+
+    #[test]
+    fn test_sbc_memory_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x01);
+        cpu.mem_write(0x15, 0x02);
+        cpu.mem_write(0x0200, 0x04);
+        cpu.mem_write(0x0205, 0x08);
+        cpu.mem_write_u16(0x20, 0x0200);
+        cpu.mem_write_u16(0x30, 0x0300);
+        cpu.mem_write(0x0305, 0x20);
+        cpu.load(vec![
+            0xa9, 0xff,       // LDA #$FF
+            0x38,             // SEC
+            0xe5, 0x10,       // SBC $10
+            0xf5, 0x10,       // SBC $10,X
+            0xed, 0x00, 0x02, // SBC $0200
+            0xfd, 0x00, 0x02, // SBC $0200,X
+            0xf9, 0x00, 0x02, // SBC $0200,Y
+            0xe1, 0x1b,       // SBC ($1B,X)
+            0xf1, 0x30,       // SBC ($30),Y
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x05;
+        cpu.register_y = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0xff - (0x01 + 0x02 + 0x04 + 0x08 + 0x08 + 0x04 + 0x20));
+        assert!(cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0x29_and_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0xf0, 0x29, 0x0f, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.zero());
+
+        cpu.load_and_run(vec![0xa9, 0xf0, 0x29, 0x80, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(!cpu.status.zero());
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x09_ora_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x00, 0x09, 0x00, 0x00]).unwrap();
+        assert!(cpu.status.zero());
+
+        cpu.load_and_run(vec![0xa9, 0x01, 0x09, 0x80, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x81);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x49_eor_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0xff, 0x49, 0xff, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.zero());
+
+        cpu.load_and_run(vec![0xa9, 0x0f, 0x49, 0xf0, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0xff);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_logical_memory_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b0000_0001);
+        cpu.mem_write(0x15, 0b0000_0010);
+        cpu.mem_write(0x0200, 0b0000_0100);
+        cpu.mem_write(0x0205, 0b0000_1000);
+        cpu.mem_write_u16(0x20, 0x0200);
+        cpu.mem_write_u16(0x30, 0x0300);
+        cpu.mem_write(0x0305, 0b0001_0000);
+        cpu.mem_write(0x0306, 0b1111_1110);
+        cpu.mem_write(0x0307, 0b1000_0000);
+        cpu.load(vec![
+            0x05, 0x10,       // ORA $10
+            0x15, 0x10,       // ORA $10,X
+            0x0d, 0x00, 0x02, // ORA $0200
+            0x1d, 0x00, 0x02, // ORA $0200,X
+            0x01, 0x1b,       // ORA ($1B,X)
+            0x11, 0x30,       // ORA ($30),Y
+            0x2d, 0x06, 0x03, // AND $0306
+            0x4d, 0x07, 0x03, // EOR $0307
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x05;
+        cpu.register_y = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_a, 0b1001_1110);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x4a_lsr_accumulator_sets_carry_and_zero() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x01, 0x4a, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.carry());
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x2a_rol_accumulator_through_carry() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0x38, 0xa9, 0b0100_0000, 0x2a, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0b1000_0001);
+        assert!(!cpu.status.carry());
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x6a_ror_accumulator_through_carry() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0x18, 0xa9, 0b0000_0001, 0x6a, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.carry());
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_shift_memory_forms_leave_accumulator_alone() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b1000_0001);
+        cpu.mem_write(0x15, 0b1000_0001);
+        cpu.mem_write(0x0200, 0b0100_0000);
+        cpu.mem_write(0x0205, 0b0000_0011);
+        cpu.load(vec![
+            0x06, 0x10,       // ASL $10
+            0x56, 0x10,       // LSR $10,X
+            0x2e, 0x00, 0x02, // ROL $0200
+            0x7e, 0x00, 0x02, // ROR $0200,X
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_a = 0x5a;
+        cpu.register_x = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b0000_0010);
+        assert_eq!(cpu.mem_read(0x15), 0b0100_0000);
+        assert_eq!(cpu.mem_read(0x0200), 0b1000_0001);
+        assert_eq!(cpu.mem_read(0x0205), 0b0000_0001);
+        assert_eq!(cpu.register_a, 0x5a);
+        assert!(cpu.status.carry());
+        assert!(!cpu.status.negative());
+    }
+
+    #[test]
+    fn test_branch_forward_skips_instructions() {
+        let mut cpu = CPU::new();
+        // LDA #$00; BEQ +2; LDX #$01; LDY #$02; BRK
+        cpu.load_and_run(vec![0xa9, 0x00, 0xf0, 0x02, 0xa2, 0x01, 0xa0, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x00);
+        assert_eq!(cpu.register_y, 0x02);
+    }
+
+    #[test]
+    fn test_branch_not_taken_falls_through() {
+        let mut cpu = CPU::new();
+        // LDA #$01; BEQ +2; LDX #$01; LDY #$02; BRK
+        cpu.load_and_run(vec![0xa9, 0x01, 0xf0, 0x02, 0xa2, 0x01, 0xa0, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.register_y, 0x02);
+    }
+
+    #[test]
+    fn test_branch_backward() {
+        let mut cpu = CPU::new();
+        // LDX #$08; loop: DEX; BPL loop; BRK
+        cpu.load_and_run(vec![0xa2, 0x08, 0xca, 0x10, 0xfd, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0xff);
+        assert_eq!(cpu.program_counter, 0x8006);
+    }
+
+    #[test]
+    fn test_branch_across_page_boundaries() {
+        let mut cpu = CPU::new();
+        // $8000: JMP $80F0
+        // $80F0: CLC; BCC +$0F -> $8102
+        // $8102: INX; SEC; BCS -$80 -> $8086
+        // $8086: INY; BRK
+        cpu.mem_write(0x80f0, 0x18);
+        cpu.mem_write(0x80f1, 0x90);
+        cpu.mem_write(0x80f2, 0x0f);
+        cpu.mem_write(0x8102, 0xe8);
+        cpu.mem_write(0x8103, 0x38);
+        cpu.mem_write(0x8104, 0xb0);
+        cpu.mem_write(0x8105, 0x80);
+        cpu.mem_write(0x8086, 0xc8);
+        cpu.load_and_run(vec![0x4c, 0xf0, 0x80]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.register_y, 0x01);
+        assert_eq!(cpu.program_counter, 0x8088);
+    }
+
+    #[test]
+    fn test_branch_conditions() {
+        // (setup, taken branch, untaken branch)
+        let cases = [
+            (vec![0x18], 0x90, 0xb0),       // CLC: BCC taken, BCS not
+            (vec![0x38], 0xb0, 0x90),       // SEC: BCS taken, BCC not
+            (vec![0xa9, 0x00], 0xf0, 0xd0), // Z set: BEQ taken, BNE not
+            (vec![0xa9, 0x01], 0xd0, 0xf0), // Z clear: BNE taken, BEQ not
+            (vec![0xa9, 0x80], 0x30, 0x10), // N set: BMI taken, BPL not
+            (vec![0xa9, 0x7f], 0x10, 0x30), // N clear: BPL taken, BMI not
+            (vec![0xa9, 0x7f, 0x69, 0x01], 0x70, 0x50), // V set: BVS taken, BVC not
+            (vec![0xb8], 0x50, 0x70),       // CLV: BVC taken, BVS not
+        ];
+
+        for (setup, taken, untaken) in cases {
+            let mut program = setup.clone();
+            // untaken +2; taken +2; LDX #$01; BRK; LDY #$01; BRK
+            program.extend([untaken, 0x02, taken, 0x03, 0xa2, 0x01, 0x00, 0xa0, 0x01, 0x00]);
+            let mut cpu = CPU::new();
+            cpu.load_and_run(program).unwrap();
+
+            assert_eq!((cpu.register_x, cpu.register_y), (0x00, 0x01), "branch {:#04x}", taken);
+        }
+    }
+
+    #[test]
+    fn test_0x6c_jmp_indirect() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x0120, 0x8010);
+        cpu.mem_write(0x8010, 0xa9);
+        cpu.mem_write(0x8011, 0x2a);
+        cpu.load_and_run(vec![0x6c, 0x20, 0x01, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x2a);
+    }
+
+    #[test]
+    fn test_0x6c_jmp_indirect_page_wrap_bug() {
+        let mut cpu = CPU::new();
+        // The pointer at $30FF takes its high byte from $3000, not $3100.
+        cpu.mem_write(0x30ff, 0x10);
+        cpu.mem_write(0x3000, 0x80);
+        cpu.mem_write(0x3100, 0x90);
+        cpu.mem_write(0x8010, 0xa9);
+        cpu.mem_write(0x8011, 0x01);
+        cpu.mem_write(0x9010, 0xa9);
+        cpu.mem_write(0x9011, 0x02);
+        cpu.load_and_run(vec![0x6c, 0xff, 0x30, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x01);
+    }
+
+    #[test]
+    fn test_0x4c_jmp_to_next_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0x4c, 0x03, 0x80, 0xe8, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x01);
+    }
+
+    #[test]
+    fn test_0x20_jsr_pushes_return_address_minus_one() {
+        let mut cpu = CPU::new();
+        // $8000: JSR $8010; $8010: BRK
+        cpu.load_and_run(vec![0x20, 0x10, 0x80]).unwrap();
+
+        assert_eq!(cpu.stack_pointer, 0xfb);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x8002);
+    }
+
+    #[test]
+    fn test_nested_subroutines() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0x20, 0x07, 0x80, // $8000: JSR outer
+            0xa0, 0x03,       // $8003: LDY #$03
+            0x00,             // $8005: BRK
+            0x00,
+            0xa2, 0x01,       // $8007: outer: LDX #$01
+            0x20, 0x0d, 0x80, // $8009: JSR inner
+            0x60,             // $800C: RTS
+            0xa9, 0x02,       // $800D: inner: LDA #$02
+            0x60,             // $800F: RTS
+        ]).unwrap();
+
+        assert_eq!((cpu.register_a, cpu.register_x, cpu.register_y), (0x02, 0x01, 0x03));
+        assert_eq!(cpu.stack_pointer, 0xfd);
+        assert_eq!(cpu.program_counter, 0x8006);
+    }
+
+    #[test]
+    fn test_reset_initialises_stack_pointer() {
+        let mut cpu = CPU::new();
+        cpu.stack_pointer = 0x12;
+        cpu.load(vec![0x00]).unwrap();
+        cpu.power_on();
+
+        assert_eq!(cpu.stack_pointer, 0xfd);
+    }
+
+    #[test]
+    fn test_0x48_pha_writes_to_page_one() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x11, 0x48, 0xa9, 0x22, 0x48, 0x00]).unwrap();
+
+        assert_eq!(cpu.stack_pointer, 0xfb);
+        assert_eq!(cpu.mem_read(0x01fd), 0x11);
+        assert_eq!(cpu.mem_read(0x01fc), 0x22);
+    }
+
+    #[test]
+    fn test_0x08_php_pushes_break_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0x38, 0x08, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x01fd), 0b0011_0001);
+        assert_eq!(cpu.status.bits(), 0b0000_0001);
+    }
+
+    #[test]
+    fn test_0x28_plp_ignores_break_flags() {
+        let mut cpu = CPU::new();
+        // LDA #$FF; PHA; PLP
+        cpu.load_and_run(vec![0xa9, 0xff, 0x48, 0x28, 0x00]).unwrap();
+
+        assert_eq!(cpu.status.bits(), 0b1100_1111);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+    }
+
+    #[test]
+    fn test_0x68_pla_sets_zero_flag() {
+        let mut cpu = CPU::new();
+        // LDA #$00; PHA; LDA #$01; PLA
+        cpu.load_and_run(vec![0xa9, 0x00, 0x48, 0xa9, 0x01, 0x68, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x00_brk_without_handler_stops() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xe8, 0x00, 0xe8]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+        assert_eq!(cpu.program_counter, 0x8002);
+    }
+
+    #[test]
+    fn test_0x00_brk_dispatches_through_vector() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffe, 0x9000);
+        // The handler uninstalls itself so the BRK after the return ends the program.
+        // $9000: LDX #$01; LDA #$00; STA $FFFE; STA $FFFF; RTI
+        for (i, byte) in [0xa2, 0x01, 0xa9, 0x00, 0x8d, 0xfe, 0xff, 0x8d, 0xff, 0xff, 0x40].iter().enumerate() {
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        // SEC; BRK; (padding); LDY #$02; BRK
+        cpu.load_and_run(vec![0x38, 0x00, 0xff, 0xa0, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.register_y, 0x02);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+        // The return address skips the padding byte and the status was pushed with the break flags set.
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x8003);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0011_0001);
+        // RTI restored the status from before the interrupt, so the I flag set by BRK is gone.
+        assert!(cpu.status.carry() && !cpu.status.interrupt_disable());
+    }
+
+    #[test]
+    fn test_0x00_brk_sets_interrupt_disable() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffe, 0x9000);
+        // $9000: PHP; PLA; STA $10; LDA #$00; STA $FFFE; STA $FFFF; BRK
+        for (i, byte) in [0x08, 0x68, 0x85, 0x10, 0xa9, 0x00, 0x8d, 0xfe, 0xff, 0x8d, 0xff, 0xff, 0x00].iter().enumerate() {
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        cpu.load_and_run(vec![0x00]).unwrap();
+
+        assert!(cpu.mem_read(0x10) & 0b0000_0100 != 0);
+    }
+
+    #[test]
+    fn test_compare_flag_table() {
+        // (register, operand, carry, zero, negative)
+        let table = [
+            (0x40, 0x40, true, true, false),
+            (0x41, 0x40, true, false, false),
+            (0x40, 0x41, false, false, true),
+            (0xff, 0x00, true, false, true),
+            (0x00, 0xff, false, false, false),
+            (0x80, 0x01, true, false, false),
+        ];
+
+        for (register, operand, carry, zero, negative) in table {
+            // CMP #, CPX #, CPY # respectively
+            for program in [vec![0xa9, register, 0xc9, operand, 0x00],
+                            vec![0xa2, register, 0xe0, operand, 0x00],
+                            vec![0xa0, register, 0xc0, operand, 0x00]] {
+                let mut cpu = CPU::new();
+                cpu.load_and_run(program).unwrap();
+
+                assert_eq!(cpu.status.carry(), carry, "{:#04x} cmp {:#04x}", register, operand);
+                assert_eq!(cpu.status.zero(), zero, "{:#04x} cmp {:#04x}", register, operand);
+                assert_eq!(cpu.status.negative(), negative, "{:#04x} cmp {:#04x}", register, operand);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compare_does_not_modify_registers() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x10, 0xa2, 0x20, 0xa0, 0x30, 0xc9, 0x01, 0xe0, 0x02, 0xc0, 0x03, 0x00]).unwrap();
+
+        assert_eq!((cpu.register_a, cpu.register_x, cpu.register_y), (0x10, 0x20, 0x30));
+    }
+
+    #[test]
+    fn test_compare_memory_addressing_modes() {
+        // Each program loads 5 into A, X and Y and compares it against a 5 stored through a different addressing mode.
+        let programs : [&[u8]; 11] = [
+            &[0xc5, 0x10],       // CMP $10
+            &[0xd5, 0x0b],       // CMP $0B,X
+            &[0xcd, 0x00, 0x02], // CMP $0200
+            &[0xdd, 0xfb, 0x01], // CMP $01FB,X
+            &[0xd9, 0xfb, 0x01], // CMP $01FB,Y
+            &[0xc1, 0x2b],       // CMP ($2B,X)
+            &[0xd1, 0x20],       // CMP ($20),Y
+            &[0xe4, 0x10],       // CPX $10
+            &[0xec, 0x00, 0x02], // CPX $0200
+            &[0xc4, 0x10],       // CPY $10
+            &[0xcc, 0x00, 0x02], // CPY $0200
+        ];
+
+        for compare in programs {
+            let mut cpu = CPU::new();
+            cpu.mem_write(0x10, 0x05);
+            cpu.mem_write(0x0200, 0x05);
+            cpu.mem_write_u16(0x20, 0x01fb);
+            cpu.mem_write_u16(0x30, 0x0200);
+            let mut program = vec![0xa9, 0x05, 0xa2, 0x05, 0xa0, 0x05];
+            program.extend_from_slice(compare);
+            program.push(0x00);
+            cpu.load_and_run(program).unwrap();
+
+            assert!(cpu.status.carry() && cpu.status.zero(), "compare {:#04x}", compare[0]);
+        }
+    }
+
+    #[test]
+    fn test_0xc8_iny_wraps_to_zero() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa0, 0xff, 0xc8, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_y, 0x00);
+        assert!(cpu.status.zero());
+        assert!(!cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x88_dey_wraps_to_negative() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa0, 0x00, 0x88, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_y, 0xff);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0xca_dex_to_zero() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa2, 0x01, 0xca, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x00);
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_inc_dec_memory_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x7f);
+        cpu.mem_write(0x15, 0x00);
+        cpu.mem_write(0x0200, 0x10);
+        cpu.mem_write(0x0205, 0x80);
+        cpu.load(vec![
+            0xe6, 0x10,       // INC $10
+            0xd6, 0x10,       // DEC $10,X
+            0xee, 0x00, 0x02, // INC $0200
+            0xde, 0x00, 0x02, // DEC $0200,X
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x80);
+        assert_eq!(cpu.mem_read(0x15), 0xff);
+        assert_eq!(cpu.mem_read(0x0200), 0x11);
+        assert_eq!(cpu.mem_read(0x0205), 0x7f);
+        assert!(!cpu.status.negative());
+        assert!(!cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x85_sta_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x20, 0x0300);
+        cpu.mem_write_u16(0x30, 0x0400);
+        cpu.load(vec![
+            0xa9, 0x01, 0x85, 0x10,       // STA $10
+            0xa9, 0x02, 0x95, 0x10,       // STA $10,X
+            0xa9, 0x03, 0x8d, 0x00, 0x02, // STA $0200
+            0xa9, 0x04, 0x9d, 0x00, 0x02, // STA $0200,X
+            0xa9, 0x05, 0x99, 0x00, 0x02, // STA $0200,Y
+            0xa9, 0x06, 0x81, 0x1e,       // STA ($1E,X)
+            0xa9, 0x07, 0x91, 0x30,       // STA ($30),Y
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x02;
+        cpu.register_y = 0x04;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.mem_read(0x12), 0x02);
+        assert_eq!(cpu.mem_read(0x0200), 0x03);
+        assert_eq!(cpu.mem_read(0x0202), 0x04);
+        assert_eq!(cpu.mem_read(0x0204), 0x05);
+        assert_eq!(cpu.mem_read(0x0300), 0x06);
+        assert_eq!(cpu.mem_read(0x0404), 0x07);
+    }
+
+    #[test]
+    fn test_0x86_stx_and_0x84_sty_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![
+            0x86, 0x10,       // STX $10
+            0x96, 0x10,       // STX $10,Y
+            0x8e, 0x00, 0x02, // STX $0200
+            0x84, 0x20,       // STY $20
+            0x94, 0x20,       // STY $20,X
+            0x8c, 0x01, 0x02, // STY $0201
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x03;
+        cpu.register_y = 0x04;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x03);
+        assert_eq!(cpu.mem_read(0x14), 0x03);
+        assert_eq!(cpu.mem_read(0x0200), 0x03);
+        assert_eq!(cpu.mem_read(0x20), 0x04);
+        assert_eq!(cpu.mem_read(0x23), 0x04);
+        assert_eq!(cpu.mem_read(0x0201), 0x04);
+    }
+
+    #[test]
+    fn test_stores_do_not_affect_flags() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x85, 0x10, 0x86, 0x11, 0x84, 0x12, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.register_a = 0x00;
+        cpu.register_x = 0x80;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.status.bits(), 0);
+        assert_eq!(cpu.mem_read(0x11), 0x80);
+    }
+
+    #[test]
+    fn test_ldx_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x01);
+        cpu.mem_write(0x14, 0x02);
+        cpu.mem_write(0x0200, 0x03);
+        cpu.mem_write(0x0204, 0x84);
+        // Each load is stored to $30 + n so every mode can be checked.
+        cpu.load(vec![
+            0xa2, 0x00, 0x86, 0x30,       // LDX #$00
+            0xa6, 0x10, 0x86, 0x31,       // LDX $10
+            0xb6, 0x10, 0x86, 0x32,       // LDX $10,Y
+            0xae, 0x00, 0x02, 0x86, 0x33, // LDX $0200
+            0xbe, 0x00, 0x02,             // LDX $0200,Y
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_y = 0x04;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x30), 0x00);
+        assert_eq!(cpu.mem_read(0x31), 0x01);
+        assert_eq!(cpu.mem_read(0x32), 0x02);
+        assert_eq!(cpu.mem_read(0x33), 0x03);
+        assert_eq!(cpu.register_x, 0x84);
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_ldy_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x01);
+        cpu.mem_write(0x14, 0x02);
+        cpu.mem_write(0x0200, 0x03);
+        cpu.mem_write(0x0204, 0x00);
+        cpu.load(vec![
+            0xa0, 0x80, 0x84, 0x30,       // LDY #$80
+            0xa4, 0x10, 0x84, 0x31,       // LDY $10
+            0xb4, 0x10, 0x84, 0x32,       // LDY $10,X
+            0xac, 0x00, 0x02, 0x84, 0x33, // LDY $0200
+            0xbc, 0x00, 0x02,             // LDY $0200,X
+            0x00,
+        ]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x04;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x30), 0x80);
+        assert_eq!(cpu.mem_read(0x31), 0x01);
+        assert_eq!(cpu.mem_read(0x32), 0x02);
+        assert_eq!(cpu.mem_read(0x33), 0x03);
+        assert_eq!(cpu.register_y, 0x00);
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0xb6_ldx_zero_page_y_wraps() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x02, 0x66);
+        cpu.load(vec![0xb6, 0xff, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.register_y = 0x03;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_x, 0x66);
+    }
+
+    #[test]
+    fn test_0xa8_tay_and_0x98_tya() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x80, 0xa8, 0x00]).unwrap();
+        assert_eq!(cpu.register_y, 0x80);
+        assert!(cpu.status.negative());
+
+        cpu.load_and_run(vec![0xa0, 0x00, 0xa9, 0x01, 0x98, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x8a_txa() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa2, 0x7f, 0x8a, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x7f);
+        assert!(!cpu.status.negative() && !cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x9a_txs_does_not_affect_flags() {
+        let mut cpu = CPU::new();
+        // LDX #$00 sets Z, LDA #$01 clears it, TXS must leave it clear.
+        cpu.load_and_run(vec![0xa2, 0x00, 0xa9, 0x01, 0x9a, 0x00]).unwrap();
+
+        assert_eq!(cpu.stack_pointer, 0x00);
+        assert!(!cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0xba_tsx_sets_flags() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xba, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0xfd);
+        assert!(cpu.status.negative());
+
+        // PHA three times brings the stack pointer to $FA; TXS/TSX round trips through X.
+        cpu.load_and_run(vec![0x48, 0x48, 0x48, 0xba, 0xe8, 0x9a, 0xba, 0x00]).unwrap();
+        assert_eq!(cpu.stack_pointer, 0xfb);
+        assert_eq!(cpu.register_x, 0xfb);
+    }
+
+    #[test]
+    fn test_flag_set_and_clear_instructions() {
+        // (program, expected status)
+        let cases : [(&[u8], u8); 7] = [
+            (&[0x38], 0b0000_0001),       // SEC
+            (&[0x38, 0x18], 0b0000_0000), // SEC; CLC
+            (&[0x78], 0b0000_0100),       // SEI
+            (&[0x78, 0x58], 0b0000_0000), // SEI; CLI
+            (&[0xf8], 0b0000_1000),       // SED
+            (&[0xf8, 0xd8], 0b0000_0000), // SED; CLD
+            (&[0xa9, 0x7f, 0x69, 0x01, 0xb8, 0xa9, 0x01], 0b0000_0000), // overflow then CLV
+        ];
+
+        for (program, status) in cases {
+            let mut cpu = CPU::new();
+            let mut program = program.to_vec();
+            program.push(0x00);
+            cpu.load_and_run(program.clone()).unwrap();
+
+            assert_eq!(cpu.status.bits(), status, "{:02x?}", program);
+        }
+    }
+
+    #[test]
+    fn test_0xf8_sed_does_not_enable_decimal_arithmetic() {
+        let mut cpu = CPU::new();
+        // SED; LDA #$09; ADC #$01 is $0A on the NES, not the BCD result $10.
+        cpu.load_and_run(vec![0xf8, 0xa9, 0x09, 0x69, 0x01, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x0a);
+        assert!(cpu.status.decimal_mode());
+    }
+
+    #[test]
+    fn test_0x2c_bit_absolute_flags_come_from_operand() {
+        let mut cpu = CPU::new();
+        // A & M == 0 sets Z, yet N and V still come from bits 7 and 6 of M.
+        cpu.mem_write(0x2002, 0b1100_0000);
+        cpu.load_and_run(vec![0xa9, 0x3f, 0x2c, 0x02, 0x20, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x3f);
+        assert_eq!(cpu.status.bits(), 0b1100_0010);
+    }
+
+    #[test]
+    fn test_0x24_bit_clears_flags() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b0000_0001);
+        // LDA #$7F; ADC #$01 sets N and V, BIT then clears both as well as Z.
+        cpu.load_and_run(vec![0xa9, 0x7f, 0x69, 0x01, 0xa9, 0x01, 0x24, 0x10, 0x00]).unwrap();
+
+        assert!(!cpu.status.negative() && !cpu.status.overflow() && !cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0xea_nop() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xea, 0xea, 0xe8, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    #[test]
+    fn test_undocumented_nops_consume_operands() {
+        // Every NOP is followed by operand bytes that would be INX if they were executed.
+        let nops : [&[u8]; 8] = [
+            &[0x1a],             // NOP (implied)
+            &[0x80, 0xe8],       // NOP #i
+            &[0x04, 0xe8],       // NOP d
+            &[0x14, 0xe8],       // NOP d,X
+            &[0x0c, 0xe8, 0xe8], // NOP a
+            &[0x1c, 0xe8, 0xe8], // NOP a,X
+            &[0xfa],
+            &[0xfc, 0xe8, 0xe8],
+        ];
+
+        for nop in nops {
+            let mut cpu = CPU::new();
+            let mut program = nop.to_vec();
+            program.extend([0xc8, 0x00]);
+            cpu.load_and_run(program).unwrap();
+
+            assert_eq!(cpu.register_x, 0x00, "nop {:#04x}", nop[0]);
+            assert_eq!(cpu.register_y, 0x01, "nop {:#04x}", nop[0]);
+            assert_eq!(cpu.status.bits(), 0x00, "nop {:#04x}", nop[0]);
+        }
+    }
+
+    #[test]
+    fn test_0xa7_lax_loads_a_and_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x80);
+        cpu.load_and_run(vec![0xa7, 0x10, 0x00]).unwrap();
+
+        assert_eq!((cpu.register_a, cpu.register_x), (0x80, 0x80));
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x87_sax_stores_a_and_x() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0b1100_1100, 0xa2, 0b1010_1010, 0x87, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b1000_1000);
+        assert!(cpu.status.negative(), "flags are left as set by LDX");
+    }
+
+    #[test]
+    fn test_0xc7_dcp() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x41);
+        cpu.load_and_run(vec![0xa9, 0x40, 0xc7, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x40);
+        assert!(cpu.status.carry() && cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0xe7_isb() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x0f);
+        cpu.load_and_run(vec![0x38, 0xa9, 0x20, 0xe7, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x10);
+        assert_eq!(cpu.register_a, 0x10);
+        assert!(cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0x07_slo() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b1000_0001);
+        cpu.load_and_run(vec![0xa9, 0b0000_0100, 0x07, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b0000_0010);
+        assert_eq!(cpu.register_a, 0b0000_0110);
+        assert!(cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0x27_rla() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b0100_0000);
+        cpu.load_and_run(vec![0x38, 0xa9, 0xff, 0x27, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b1000_0001);
+        assert_eq!(cpu.register_a, 0b1000_0001);
+        assert!(cpu.status.negative() && !cpu.status.carry());
+    }
+
+    #[test]
+    fn test_0x47_sre() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b0000_0011);
+        cpu.load_and_run(vec![0xa9, 0b0000_0001, 0x47, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b0000_0001);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.carry() && cpu.status.zero());
+    }
+
+    #[test]
+    fn test_0x67_rra_uses_carry_from_rotate() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0b0000_0011);
+        // ROR leaves $01 in memory with the carry set, ADC then computes $10 + $01 + 1.
+        cpu.load_and_run(vec![0x18, 0xa9, 0x10, 0x67, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.register_a, 0x12);
+    }
+
+    #[test]
+    fn test_unofficial_opcodes_are_marked() {
+        use nes::opcodes::{lookup, OPCODES};
+
+        assert!(!lookup(0xa9).unwrap().unofficial);
+        assert!(!lookup(0xea).unwrap().unofficial);
+        for code in [0x04, 0x1a, 0xa7, 0x87, 0xeb, 0xc7, 0xe7, 0x07, 0x27, 0x47, 0x67] {
+            assert!(lookup(code).unwrap().unofficial, "{:#04x}", code);
+        }
+        assert_eq!(OPCODES.iter().flatten().filter(|op| !op.unofficial).count(), 151);
+    }
+
+    #[test]
+    fn test_jam_halts_cpu() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xe8, 0x02, 0xe8, 0x00]).unwrap();
+
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.register_x, 0x01);
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        // Running again does nothing until the CPU is reset.
+        cpu.run().unwrap();
+        assert_eq!(cpu.register_x, 0x01);
+
+        cpu.power_on();
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_every_jam_opcode_halts() {
+        for code in [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2] {
+            let mut cpu = CPU::new();
+            cpu.load_and_run(vec![code, 0x00]).unwrap();
+
+            assert!(cpu.is_halted(), "{:#04x}", code);
+        }
+    }
+
+    #[test]
+    fn test_0x8b_xaa_magic_constants() {
+        // LDA #$01; LDX #$FF; XAA #$FF
+        let program = vec![0xa9, 0x01, 0xa2, 0xff, 0x8b, 0xff, 0x00];
+        for (magic, result) in [(0xee, 0xef), (0xff, 0xff), (0x00, 0x01)] {
+            let mut cpu = CPU::new();
+            cpu.quirks = CpuQuirks { xaa_magic : magic, ..CpuQuirks::default() };
+            cpu.load_and_run(program.clone()).unwrap();
+
+            assert_eq!(cpu.register_a, result, "magic {:#04x}", magic);
+        }
+    }
+
+    #[test]
+    fn test_0xbb_las() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0205, 0b1111_0000);
+        // SP is $FD after reset, so the result is $F0 & $FD = $F0.
+        cpu.load(vec![0xbb, 0x00, 0x02, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.register_y = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!((cpu.register_a, cpu.register_x, cpu.stack_pointer), (0xf0, 0xf0, 0xf0));
+        assert!(cpu.status.negative());
+    }
+
+    #[test]
+    fn test_0x9f_ahx_stores_a_and_x_and_high_byte() {
+        let mut cpu = CPU::new();
+        // A & X = $FF, H + 1 = $03.
+        cpu.load(vec![0x9f, 0x00, 0x02, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.register_a = 0xff;
+        cpu.register_x = 0xff;
+        cpu.register_y = 0x10;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x0210), 0x03);
+    }
+
+    #[test]
+    fn test_0x9b_tas_page_cross_quirk() {
+        // $02F0,Y with Y = $20 crosses into page 3. The stored value is ($07 & $05) & $03 = $01, which
+        // replaces the high byte of the address when the quirk is on.
+        for (page_cross, address) in [(true, 0x0110), (false, 0x0310)] {
+            let mut cpu = CPU::new();
+            cpu.quirks.unstable_store_page_cross = page_cross;
+            cpu.load(vec![0x9b, 0xf0, 0x02, 0x00]).unwrap();
+            cpu.power_on();
+            cpu.register_a = 0x07;
+            cpu.register_x = 0x05;
+            cpu.register_y = 0x20;
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.stack_pointer, 0x05);
+            assert_eq!(cpu.mem_read(address), 0x01, "page cross {}", page_cross);
+        }
+    }
+
+    #[test]
+    fn test_stack_push_wraps_within_page_one() {
+        let mut cpu = CPU::new();
+        // LDX #$00; TXS; LDA #$11; PHA; LDA #$22; PHA
+        cpu.load_and_run(vec![0xa2, 0x00, 0x9a, 0xa9, 0x11, 0x48, 0xa9, 0x22, 0x48, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x0100), 0x11);
+        assert_eq!(cpu.mem_read(0x01ff), 0x22);
+        assert_eq!(cpu.mem_read(0x00ff), 0x00);
+        assert_eq!(cpu.stack_pointer, 0xfe);
+    }
+
+    #[test]
+    fn test_stack_pull_wraps_within_page_one() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0100, 0x33);
+        cpu.mem_write(0x0200, 0x44);
+        // LDX #$FF; TXS; PLA
+        cpu.load_and_run(vec![0xa2, 0xff, 0x9a, 0x68, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x33);
+        assert_eq!(cpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn test_subroutine_across_stack_wrap() {
+        let mut cpu = CPU::new();
+        // LDX #$00; TXS; JSR sub; LDY #$01; BRK; sub: RTS
+        cpu.load_and_run(vec![0xa2, 0x00, 0x9a, 0x20, 0x09, 0x80, 0xa0, 0x01, 0x00, 0x60]).unwrap();
+
+        // The high byte went to $0100 and the low byte wrapped to $01FF.
+        assert_eq!(cpu.mem_read(0x0100), 0x80);
+        assert_eq!(cpu.mem_read(0x01ff), 0x05);
+        assert_eq!(cpu.register_y, 0x01);
+        assert_eq!(cpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn test_cycles_accumulate_per_instruction() {
+        let mut cpu = CPU::new();
+        // LDA #$01 (2); TAX (2); INX (2)
+        cpu.load_and_run(vec![0xa9, 0x01, 0xaa, 0xe8, 0x00]).unwrap();
+
+        assert_eq!(cpu.cycles, 6);
+    }
+
+    #[test]
+    fn test_cycles_for_memory_and_stack_instructions() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xad, 0x00, 0x02, // LDA $0200 (4)
+            0x85, 0x10,       // STA $10 (3)
+            0xfe, 0x00, 0x02, // INC $0200,X (7)
+            0x48,             // PHA (3)
+            0x68,             // PLA (4)
+            0x20, 0x0e, 0x80, // JSR $800E (6)
+            0x00,
+            0x60,             // RTS (6)
+        ]).unwrap();
+
+        assert_eq!(cpu.cycles, 4 + 3 + 7 + 3 + 4 + 6 + 6);
+    }
+
+    /// Runs `program` with X and Y set to `index` and returns the cycles it took.
+    fn cycles_with_index(program : Vec<u8>, index : u8) -> u64 {
+        let mut cpu = CPU::new();
+        cpu.load(program).unwrap();
+        cpu.power_on();
+        cpu.register_x = index;
+        cpu.register_y = index;
+        cpu.run().unwrap();
+        cpu.cycles
+    }
+
+    #[test]
+    fn test_page_cross_penalty_for_indexed_reads() {
+        // LDA $02F0,X
+        assert_eq!(cycles_with_index(vec![0xbd, 0xf0, 0x02, 0x00], 0x0f), 4);
+        assert_eq!(cycles_with_index(vec![0xbd, 0xf0, 0x02, 0x00], 0x10), 5);
+        // LDX $02F0,Y
+        assert_eq!(cycles_with_index(vec![0xbe, 0xf0, 0x02, 0x00], 0x10), 5);
+        // CMP $02F0,Y
+        assert_eq!(cycles_with_index(vec![0xd9, 0xf0, 0x02, 0x00], 0x10), 5);
+        // NOP $02F0,X
+        assert_eq!(cycles_with_index(vec![0x1c, 0xf0, 0x02, 0x00], 0x10), 5);
+    }
+
+    #[test]
+    fn test_page_cross_penalty_for_indirect_y() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x20, 0x02f0);
+        cpu.load(vec![0xb1, 0x20, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.register_y = 0x10;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.cycles, 6);
+    }
+
+    #[test]
+    fn test_no_page_cross_penalty_for_stores_and_read_modify_write() {
+        // STA $02F0,X always takes 5 and INC $02F0,X always takes 7.
+        assert_eq!(cycles_with_index(vec![0x9d, 0xf0, 0x02, 0x00], 0x10), 5);
+        assert_eq!(cycles_with_index(vec![0xfe, 0xf0, 0x02, 0x00], 0x10), 7);
+        // Zero page indexing wraps within the page rather than crossing it.
+        assert_eq!(cycles_with_index(vec![0xb5, 0xf0, 0x00], 0x20), 4);
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut cpu = CPU::new();
+        // Not taken: LDA #$01 (2); BEQ (2)
+        cpu.load_and_run(vec![0xa9, 0x01, 0xf0, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.cycles, 2 + 2);
+
+        // Taken on the same page: LDA #$00 (2); BEQ (3)
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x00, 0xf0, 0x00, 0x00]).unwrap();
+        assert_eq!(cpu.cycles, 2 + 3);
+
+        // Taken across a page: JMP $80FA (3); $80FA: LDA #$00 (2); BEQ +$10 to $810E (4)
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x80fa, 0xa9);
+        cpu.mem_write(0x80fb, 0x00);
+        cpu.mem_write(0x80fc, 0xf0);
+        cpu.mem_write(0x80fd, 0x10);
+        cpu.load_and_run(vec![0x4c, 0xfa, 0x80]).unwrap();
+        assert_eq!(cpu.program_counter, 0x810f);
+        assert_eq!(cpu.cycles, 3 + 2 + 4);
+    }
+
+    #[test]
+    fn test_backward_branch_across_page_cycles() {
+        let mut cpu = CPU::new();
+        // $8000: JMP $8100; $8100: SEC (2); BCS -$10 to $80F3 (4)
+        cpu.mem_write(0x8100, 0x38);
+        cpu.mem_write(0x8101, 0xb0);
+        cpu.mem_write(0x8102, 0xf0);
+        cpu.load_and_run(vec![0x4c, 0x00, 0x81]).unwrap();
+
+        assert_eq!(cpu.program_counter, 0x80f4);
+        assert_eq!(cpu.cycles, 3 + 2 + 4);
+    }
+
+    #[test]
+    fn test_nmi_is_serviced_before_next_instruction() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        // $9000: LDX #$42; RTI
+        cpu.mem_write(0x9000, 0xa2);
+        cpu.mem_write(0x9001, 0x42);
+        cpu.mem_write(0x9002, 0x40);
+        // SEI; INY; BRK
+        cpu.load(vec![0x78, 0xc8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.trigger_nmi();
+        cpu.run().unwrap();
+
+        // The NMI is taken even with interrupts disabled, then the main program runs.
+        assert_eq!(cpu.register_x, 0x42);
+        assert_eq!(cpu.register_y, 0x01);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+        assert_eq!(cpu.cycles, 7 + 2 + 6 + 2 + 2);
+    }
+
+    #[test]
+    fn test_nmi_pushes_return_address_and_status_without_break() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        cpu.load(vec![0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_carry(true);
+        cpu.trigger_nmi();
+        cpu.run().unwrap();
+
+        // The handler at $9000 is BRK with no IRQ handler installed, which ends the run.
+        assert_eq!(cpu.program_counter, 0x9001);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x8000);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0010_0001);
+        assert!(cpu.status.interrupt_disable());
+    }
+
+    #[test]
+    fn test_irq_is_ignored_while_interrupts_are_disabled() {
+        let mut cpu = CPU::new();
+        // INX; INX; BRK
+        cpu.load(vec![0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_interrupt_disable(true);
+        cpu.set_irq(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+    }
+
+    #[test]
+    fn test_irq_is_serviced_once_interrupts_are_enabled() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffe, 0x9000);
+        // $9000: PLA; ORA #$04; PHA (return with interrupts disabled, as the line stays asserted); INY;
+        // LDA #$00; STA $FFFE; STA $FFFF (uninstall the handler); RTI
+        let handler = [0x68, 0x09, 0x04, 0x48, 0xc8, 0xa9, 0x00, 0x8d, 0xfe, 0xff, 0x8d, 0xff, 0xff, 0x40];
+        for (i, byte) in handler.iter().enumerate() {
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        // INX; CLI; INX; BRK
+        cpu.load(vec![0xe8, 0x58, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_interrupt_disable(true);
+        cpu.set_irq(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(cpu.register_y, 1);
+        assert!(cpu.status.interrupt_disable());
+        assert!(!cpu.status.contains(0b0001_0000));
+        assert_eq!(cpu.stack_pointer, 0xfd);
+    }
+
+    /// Installs an interrupt handler at 0x9000 that stores X to $10, returns with interrupts disabled (the IRQ line
+    /// stays asserted) and uninstalls itself, so the BRK ending the test program is an exit again.
+    fn install_recording_handler(cpu : &mut CPU, vector : u16) {
+        // STX $10; PLA; ORA #$04; PHA; LDA #$00; STA $FFFE; STA $FFFF; RTI
+        let handler = [0x86, 0x10, 0x68, 0x09, 0x04, 0x48, 0xa9, 0x00, 0x8d, 0xfe, 0xff, 0x8d, 0xff, 0xff, 0x40];
+        for (i, byte) in handler.iter().enumerate() {
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        cpu.mem_write_u16(vector, 0x9000);
+        cpu.mem_write(0x10, 0xff);
+    }
+
+    #[test]
+    fn test_irq_after_cli_waits_one_instruction() {
+        let mut cpu = CPU::new();
+        install_recording_handler(&mut cpu, 0xfffe);
+        // CLI; INX; INX; BRK
+        cpu.load(vec![0x58, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_interrupt_disable(true);
+        cpu.set_irq(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 1);
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_irq_still_taken_after_sei() {
+        let mut cpu = CPU::new();
+        install_recording_handler(&mut cpu, 0xfffe);
+        // CLI; SEI; INX; BRK
+        cpu.load(vec![0x58, 0x78, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_interrupt_disable(true);
+        cpu.set_irq(true);
+        cpu.run().unwrap();
+
+        // The IRQ is seen by SEI's poll, even though the status pushed has I set.
+        assert_eq!(cpu.mem_read(0x10), 0);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_irq_after_plp_waits_one_instruction() {
+        let mut cpu = CPU::new();
+        install_recording_handler(&mut cpu, 0xfffe);
+        // LDA #$00; PHA; PLP; INX; INX; BRK
+        cpu.load(vec![0xa9, 0x00, 0x48, 0x28, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_interrupt_disable(true);
+        cpu.set_irq(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 1);
+    }
+
+    #[test]
+    fn test_nmi_after_taken_branch_waits_one_instruction() {
+        // BNE +0 (taken, same page) or NOP; NOP
+        for (first, expected) in [([0xd0, 0x00], 1), ([0xea, 0xea], 0)] {
+            let mut cpu = CPU::new();
+            // ...; BRK; INX; INX; BRK
+            cpu.load(vec![first[0], first[1], 0x00, 0xe8, 0xe8, 0x00]).unwrap();
+            cpu.power_on();
+            cpu.run().unwrap();
+
+            install_recording_handler(&mut cpu, 0xfffa);
+            cpu.trigger_nmi();
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.mem_read(0x10), expected);
+            assert_eq!(cpu.register_x, 2);
+        }
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut cpu = CPU::new();
+        // BNE +0; BRK (exit); BRK; padding
+        cpu.load(vec![0xd0, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.run().unwrap();
+        assert_eq!(cpu.program_counter, 0x8003);
+
+        // The branch polled early, so the NMI is only noticed while the BRK is running.
+        cpu.mem_write_u16(0xfffe, 0x9100);
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        // $9000: LDA #$00; STA $FFFE; STA $FFFF; BRK
+        for (i, byte) in [0xa9, 0x00, 0x8d, 0xfe, 0xff, 0x8d, 0xff, 0xff, 0x00].iter().enumerate() {
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        cpu.trigger_nmi();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x9009);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x8005);
+        assert!(cpu.mem_read(0x01fb) & 0b0001_0000 != 0);
+    }
+
+    #[test]
+    fn test_reset_keeps_registers_and_memory() {
+        let mut cpu = CPU::new();
+        // LDA #$01; LDX #$02; LDY #$03; STA $10; BRK
+        cpu.load_and_run(vec![0xa9, 0x01, 0xa2, 0x02, 0xa0, 0x03, 0x85, 0x10, 0x00]).unwrap();
+        let cycles = cpu.cycles;
+        cpu.reset();
+
+        assert_eq!(cpu.register_a, 0x01);
+        assert_eq!(cpu.register_x, 0x02);
+        assert_eq!(cpu.register_y, 0x03);
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.stack_pointer, 0xfa);
+        assert!(cpu.status.interrupt_disable());
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.cycles, cycles + 7);
+    }
+
+    #[test]
+    fn test_reset_stack_pointer_wraps() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x00]).unwrap();
+        cpu.power_on();
+        cpu.stack_pointer = 0x01;
+        cpu.reset();
+
+        assert_eq!(cpu.stack_pointer, 0xfe);
+    }
+
+    #[test]
+    fn test_reset_recovers_jammed_cpu() {
+        let mut cpu = CPU::new();
+        // JAM
+        cpu.load_and_run(vec![0x02]).unwrap();
+        assert!(cpu.is_halted());
+
+        cpu.mem_write(0x8000, 0xe8);
+        cpu.mem_write(0x8001, 0x00);
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_power_on_clears_registers() {
+        let mut cpu = CPU::new();
+        // LDA #$ff; SEC; PHA; BRK
+        cpu.load_and_run(vec![0xa9, 0xff, 0x38, 0x48, 0x00]).unwrap();
+        cpu.power_on();
+
+        assert_eq!(cpu.register_a, 0);
+        assert_eq!(cpu.status.bits(), 0);
+        assert_eq!(cpu.stack_pointer, 0xfd);
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    /// Runs `SED; LDA #a; ADC #m` (or SBC) with the given carry and BCD support, returning the CPU.
+    fn run_decimal(opcode : u8, a : u8, m : u8, carry : bool, bcd_enabled : bool) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.bcd_enabled = bcd_enabled;
+        let carry_op = if carry { 0x38 } else { 0x18 };
+        cpu.load_and_run(vec![0xf8, carry_op, 0xa9, a, opcode, m, 0x00]).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_decimal_flag_ignored_by_default() {
+        let cpu = run_decimal(0x69, 0x09, 0x01, false, false);
+        assert_eq!(cpu.register_a, 0x0a);
+        assert!(cpu.status.decimal_mode());
+
+        let cpu = run_decimal(0xe9, 0x10, 0x01, true, false);
+        assert_eq!(cpu.register_a, 0x0f);
+    }
+
+    #[test]
+    fn test_decimal_adc() {
+        // (a, m, carry in, result, carry out)
+        let cases = [
+            (0x09, 0x01, false, 0x10, false),
+            (0x58, 0x46, true, 0x05, true),
+            (0x12, 0x34, false, 0x46, false),
+            (0x81, 0x92, false, 0x73, true),
+            (0x99, 0x00, true, 0x00, true),
+        ];
+        for (a, m, carry, result, carry_out) in cases {
+            let cpu = run_decimal(0x69, a, m, carry, true);
+            assert_eq!(cpu.register_a, result, "{:#04x} + {:#04x} + {}", a, m, carry);
+            assert_eq!(cpu.status.carry(), carry_out, "{:#04x} + {:#04x} + {}", a, m, carry);
+        }
+    }
+
+    #[test]
+    fn test_decimal_adc_zero_flag_is_binary() {
+        // 0x99 + 0x01 is 0x00 in decimal, but 0x9a in binary, so Z stays clear.
+        let cpu = run_decimal(0x69, 0x99, 0x01, false, true);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.carry());
+        assert!(!cpu.status.zero());
+    }
+
+    #[test]
+    fn test_decimal_sbc() {
+        // (a, m, carry in, result, carry out)
+        let cases = [
+            (0x46, 0x12, true, 0x34, true),
+            (0x40, 0x13, true, 0x27, true),
+            (0x32, 0x02, false, 0x29, true),
+            (0x00, 0x01, true, 0x99, false),
+            (0x12, 0x21, true, 0x91, false),
+        ];
+        for (a, m, carry, result, carry_out) in cases {
+            let cpu = run_decimal(0xe9, a, m, carry, true);
+            assert_eq!(cpu.register_a, result, "{:#04x} - {:#04x} - {}", a, m, !carry);
+            assert_eq!(cpu.status.carry(), carry_out, "{:#04x} - {:#04x} - {}", a, m, !carry);
+        }
+    }
+
+    #[test]
+    fn test_decimal_mode_off_with_cld() {
+        let mut cpu = CPU::new();
+        cpu.bcd_enabled = true;
+        // SED; CLD; LDA #$09; ADC #$01; BRK
+        cpu.load_and_run(vec![0xf8, 0xd8, 0xa9, 0x09, 0x69, 0x01, 0x00]).unwrap();
+        assert_eq!(cpu.register_a, 0x0a);
+    }
+
+    #[test]
+    fn test_indirect_x_pointer_at_ff_wraps_to_zero_page() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xff, 0x34);
+        cpu.mem_write(0x00, 0x12);
+        cpu.mem_write(0x100, 0x56);
+        cpu.mem_write(0x1234, 0x42);
+        cpu.mem_write(0x5634, 0x99);
+        // LDX #$0f; LDA ($f0,X); BRK
+        cpu.load_and_run(vec![0xa2, 0x0f, 0xa1, 0xf0, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_indirect_x_index_wraps_to_zero_page() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x00);
+        cpu.mem_write(0x11, 0x30);
+        cpu.mem_write(0x110, 0x00);
+        cpu.mem_write(0x111, 0x40);
+        // LDX #$20; LDA #$77; STA ($f0,X); BRK
+        cpu.load_and_run(vec![0xa2, 0x20, 0xa9, 0x77, 0x81, 0xf0, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x3000), 0x77);
+        assert_eq!(cpu.mem_read(0x4000), 0x00);
+    }
+
+    #[test]
+    fn test_indirect_y_pointer_at_ff_wraps_to_zero_page() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xff, 0x30);
+        cpu.mem_write(0x00, 0x12);
+        cpu.mem_write(0x100, 0x56);
+        cpu.mem_write(0x1234, 0x42);
+        cpu.mem_write(0x5634, 0x99);
+        // LDY #$04; LDA ($ff),Y; BRK
+        cpu.load_and_run(vec![0xa0, 0x04, 0xb1, 0xff, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_run_with_callback_called_before_each_instruction() {
+        let mut cpu = CPU::new();
+        // LDA #$01; INX; INX; BRK
+        cpu.load(vec![0xa9, 0x01, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+
+        let mut trace = vec![];
+        cpu.run_with_callback(|cpu| trace.push((cpu.program_counter, cpu.register_x))).unwrap();
+
+        // The final call is for the BRK that ends the run.
+        assert_eq!(trace, vec![(0x8000, 0), (0x8002, 0), (0x8003, 1), (0x8004, 2)]);
+    }
+
+    #[test]
+    fn test_run_with_callback_can_modify_cpu() {
+        let mut cpu = CPU::new();
+        // INX; INX; INX; BRK
+        cpu.load(vec![0xe8, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+
+        cpu.run_with_callback(|cpu| {
+            if cpu.program_counter == 0x8001 {
+                cpu.program_counter = 0x8003;
+            }
+        }).unwrap();
+
+        assert_eq!(cpu.register_x, 1);
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    #[test]
+    fn test_run_with_callback_nmi_raised_during_taken_branch() {
+        let mut cpu = CPU::new();
+        install_recording_handler(&mut cpu, 0xfffa);
+        // BNE +0; INX; INX; BRK
+        cpu.load(vec![0xd0, 0x00, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+
+        // Raised once the branch has run: the branch polled before its last cycle, so one more instruction runs.
+        cpu.run_with_callback(|cpu| {
+            if cpu.program_counter == 0x8002 && cpu.register_x == 0 && cpu.mem_read(0x10) == 0xff {
+                cpu.trigger_nmi();
+            }
+        }).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 1);
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_step_executes_one_instruction() {
+        let mut cpu = CPU::new();
+        // LDA #$05; STA $0200,X; BRK
+        cpu.load(vec![0xa9, 0x05, 0x9d, 0x00, 0x02, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.register_x = 0x10;
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.address, 0x8000);
+        assert_eq!(step.opcode, 0xa9);
+        assert_eq!(step.mnemonic, "LDA");
+        assert_eq!(step.operands, vec![0x05]);
+        assert_eq!(step.effective_address, None);
+        assert_eq!(step.cycles, 2);
+        assert_eq!(cpu.register_a, 0x05);
+        assert_eq!(cpu.program_counter, 0x8002);
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.mnemonic, "STA");
+        assert_eq!(step.operands, vec![0x00, 0x02]);
+        assert_eq!(step.effective_address, Some(0x0210));
+        assert_eq!(step.cycles, 5);
+        assert_eq!(cpu.mem_read(0x0210), 0x05);
+    }
+
+    #[test]
+    fn test_step_reports_branch_target_and_cycles() {
+        let mut cpu = CPU::new();
+        // BNE -2 (to itself)
+        cpu.load(vec![0xd0, 0xfe]).unwrap();
+        cpu.power_on();
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.effective_address, Some(0x8000));
+        assert_eq!(step.cycles, 3);
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn test_step_executes_brk_when_stop_on_brk_is_set() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x00]).unwrap();
+        cpu.power_on();
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.mnemonic, "BRK");
+        assert_eq!(step.operands, Vec::<u8>::new());
+        assert_eq!(step.cycles, 7);
+        assert_eq!(cpu.stack_pointer, 0xfa);
+    }
+
+    #[test]
+    fn test_step_enters_pending_interrupt() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        cpu.mem_write(0x9000, 0xe8);
+        cpu.load(vec![0xea]).unwrap();
+        cpu.power_on();
+        cpu.trigger_nmi();
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(step.address, 0x9000);
+        assert_eq!(step.mnemonic, "INX");
+        assert_eq!(step.cycles, 7 + 2);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_step_on_jammed_cpu_does_nothing() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0x02]).unwrap();
+        let cycles = cpu.cycles;
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.address, 0x8000);
+        assert_eq!(step.opcode, 0x02);
+        assert_eq!(step.cycles, 0);
+        assert_eq!(cpu.cycles, cycles);
+    }
+
+    #[test]
+    fn test_unknown_opcode_returns_error() {
+        let mut cpu = CPU::new();
+        // Nothing is assigned to 0xab yet.
+        let result = cpu.load_and_run(vec![0xe8, 0xab, 0x00]);
+
+        assert_eq!(result, Err(CpuError::UnknownOpcode { opcode : 0xab, address : 0x8001 }));
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_eq!(cpu.register_x, 1);
+
+        // The caller can patch things up and carry on.
+        cpu.mem_write(0x8001, 0xea);
+        cpu.run().unwrap();
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+
+    #[test]
+    fn test_step_unknown_opcode_returns_error() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xab]).unwrap();
+        cpu.power_on();
+
+        assert_eq!(cpu.step(), Err(CpuError::UnknownOpcode { opcode : 0xab, address : 0x8000 }));
+        assert_eq!(cpu.cycles, 0);
+    }
+
+    #[test]
+    fn test_load_rejects_oversized_program() {
+        let mut cpu = CPU::new();
+        assert_eq!(
+            cpu.load(vec![0xea; 0x8001]),
+            Err(CpuError::ProgramTooLarge { size : 0x8001, max : 0x8000 })
+        );
+        assert!(cpu.load(vec![0xea; 0x8000]).is_ok());
+    }
+
+    #[test]
+    fn test_cpu_error_display() {
+        let error = CpuError::UnknownOpcode { opcode : 0xab, address : 0x8001 };
+        assert_eq!(error.to_string(), "unknown opcode 0xab at 0x8001");
+    }
+
+    #[test]
+    fn test_indexed_store_across_page_leaves_unfixed_address_alone() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0201, 0x11);
+        cpu.mem_write(0x0301, 0x00);
+        // LDX #$02; LDA #$42; STA $02FF,X; INC $02FF,X; BRK
+        cpu.load_and_run(vec![0xa2, 0x02, 0xa9, 0x42, 0x9d, 0xff, 0x02, 0xfe, 0xff, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x0301), 0x43);
+        assert_eq!(cpu.mem_read(0x0201), 0x11);
+        assert_eq!(cpu.cycles, 2 + 2 + 5 + 7);
+    }
+
+    #[test]
+    fn test_unmapped_read_returns_open_bus() {
+        let mut cpu = CPU::new();
+        cpu.unmap(0x5000 ..= 0x5fff);
+        // LDA $5123; BRK
+        cpu.load_and_run(vec![0xad, 0x23, 0x51, 0x00]).unwrap();
+
+        // The last byte on the bus was the high byte of the operand.
+        assert_eq!(cpu.register_a, 0x51);
+    }
+
+    #[test]
+    fn test_unmapped_write_is_dropped() {
+        let mut cpu = CPU::new();
+        cpu.unmap(0x5000 ..= 0x5fff);
+        // LDA #$42; STA $5000; LDX #$00; LDY $5000,X; BRK
+        cpu.load_and_run(vec![0xa9, 0x42, 0x8d, 0x00, 0x50, 0xa2, 0x00, 0xbc, 0x00, 0x50, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_y, 0x50);
+    }
+
+    #[test]
+    fn test_unmapped_indirect_read_returns_pointer_high_byte() {
+        let mut cpu = CPU::new();
+        cpu.unmap(0x5000 ..= 0x5fff);
+        cpu.mem_write_u16(0x10, 0x5080);
+        // LDY #$04; LDA ($10),Y; BRK
+        cpu.load_and_run(vec![0xa0, 0x04, 0xb1, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a, 0x50);
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_cpu() {
+        // LDA #$02; STA $4014; NOP; BRK, the write is on an even cycle (2 + 4).
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x02, 0x8d, 0x14, 0x40, 0xea, 0x00]).unwrap();
+        assert_eq!(cpu.cycles, 2 + 4 + 513 + 2);
+
+        // BNE +0 first puts the write on an odd cycle (3 + 2 + 4), an extra cycle is needed to line up.
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xd0, 0x00, 0xa9, 0x02, 0x8d, 0x14, 0x40, 0xea, 0x00]).unwrap();
+        assert_eq!(cpu.cycles, 3 + 2 + 4 + 514 + 2);
+    }
+
+    #[test]
+    fn test_stall_counted_by_next_step() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xea, 0xea]).unwrap();
+        cpu.power_on();
+        cpu.stall(10);
+
+        assert_eq!(cpu.step().unwrap().cycles, 12);
+        assert_eq!(cpu.step().unwrap().cycles, 2);
+    }
+
+    #[test]
+    fn test_dmc_dma_fetches_sample_and_stalls() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0xc000, 0x5a);
+        cpu.load(vec![0xea, 0xea]).unwrap();
+        cpu.power_on();
+
+        assert_eq!(cpu.dmc_dma(0xc000), 0x5a);
+        assert_eq!(cpu.step().unwrap().cycles, 4 + 2);
+    }
+
+    #[test]
+    fn test_dmc_dma_during_oam_dma_steals_fewer_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xea]).unwrap();
+        cpu.power_on();
+        // The write is on cycle 0, so the OAM DMA takes 513 cycles.
+        cpu.mem_write(0x4014, 0x02);
+        cpu.dmc_dma(0xc000);
+
+        assert_eq!(cpu.step().unwrap().cycles, 513 + 2 + 2);
+    }
+
+    #[test]
+    fn test_dmc_dma_after_another_steals_all_its_cycles() {
+        for mode in [ExecutionMode::PerInstruction, ExecutionMode::PerCycle] {
+            let mut cpu = CPU::new();
+            cpu.execution_mode = mode;
+            cpu.load(vec![0xea, 0xea]).unwrap();
+            cpu.power_on();
+            // The stall the first fetch leaves is not OAM DMA, the second halts the CPU for 4 cycles as well.
+            cpu.dmc_dma(0xc000);
+            cpu.dmc_dma(0xc000);
+            assert_eq!(cpu.step().unwrap().cycles, 4 + 4 + 2, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_dmc_dma_repeats_register_reads() {
+        for (address, reads) in [(0x2007, 2), (0x200f, 2), (0x3fff, 2), (0x4016, 2), (0x2006, 1), (0x0200, 1)] {
+            let mut bus = TestBus::new();
+            let [low, high] = u16::to_le_bytes(address);
+            bus.load(0x0600, &[0xad, low, high]); // LDA address
+            let mut cpu = CPU::with_memory(bus);
+            cpu.program_counter = 0x0600;
+            cpu.step().unwrap();
+            cpu.dmc_dma(0xc000);
+
+            assert_eq!(cpu.memory().reads(address), reads, "{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn test_dmc_dma_drives_data_bus() {
+        let mut cpu = CPU::new();
+        cpu.unmap(0x5000 ..= 0x5fff);
+        cpu.mem_write(0xc000, 0x77);
+        // LDA $5000; BRK
+        cpu.load(vec![0xad, 0x00, 0x50, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.dmc_dma(0xc000);
+        cpu.step().unwrap();
+
+        // The instruction's own operand fetch comes after the DMA, so it is what the open bus holds.
+        assert_eq!(cpu.register_a, 0x50);
+    }
+
+    /// Sets up a CPU with registers and the first 2KiB of memory filled from `seed`, and `code` at the program
+    /// counter, for comparing the two execution modes.
+    fn scrambled_cpu(seed : u64, code : u8, mode : ExecutionMode) -> CPU {
+        let mut state = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as u8
+        };
+
+        let mut cpu = CPU::new();
+        cpu.execution_mode = mode;
+        for addr in 0x0000 .. 0x0800 {
+            cpu.mem_write(addr, next());
+        }
+        cpu.mem_write_u16(0xfffe, 0x0300);
+        cpu.register_a = next();
+        cpu.register_x = next();
+        cpu.register_y = next();
+        cpu.stack_pointer = next();
+        cpu.status = (next() & !0b0011_0000).into();
+        cpu.program_counter = 0x0400 + next() as u16;
+        cpu.mem_write(cpu.program_counter, code);
+        cpu
+    }
+
+    #[test]
+    fn test_per_cycle_matches_per_instruction() {
+        for code in 0..=0xffu8 {
+            for seed in 0..16 {
+                let mut fast = scrambled_cpu(seed, code, ExecutionMode::PerInstruction);
+                let mut ticked = scrambled_cpu(seed, code, ExecutionMode::PerCycle);
+                let expected = match fast.step() {
+                    Ok(result) => result,
+                    Err(_) => continue,
+                };
+
+                assert_eq!(ticked.step().unwrap(), expected, "opcode {:02X}, seed {}", code, seed);
+                let registers = |cpu : &CPU| {
+                    (cpu.register_a, cpu.register_x, cpu.register_y, cpu.status.bits(), cpu.stack_pointer,
+                     cpu.program_counter, cpu.cycles, cpu.is_halted())
+                };
+                assert_eq!(registers(&ticked), registers(&fast), "opcode {:02X}, seed {}", code, seed);
+                // Everything but the stores to the effective address lands in the scrambled 2KiB.
+                for addr in (0x0000 .. 0x0800).chain(expected.effective_address) {
+                    assert_eq!(ticked.mem_read(addr), fast.mem_read(addr), "opcode {:02X}, seed {}, ${:04X}", code, seed, addr);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tick_writes_read_modify_write_result_on_last_cycle() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x41);
+        // INC $10
+        cpu.load(vec![0xe6, 0x10]).unwrap();
+        cpu.power_on();
+
+        for _ in 0..4 {
+            assert!(!cpu.tick().unwrap());
+            assert_eq!(cpu.mem_read(0x10), 0x41);
+        }
+        assert!(cpu.tick().unwrap());
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+        assert_eq!(cpu.cycles, 5);
+        assert_eq!(cpu.program_counter, 0x8002);
+    }
+
+    #[test]
+    fn test_tick_takes_extra_cycle_on_page_cross() {
+        for (x, ticks) in [(0x00, 4), (0x01, 5)] {
+            let mut cpu = CPU::new();
+            // LDA $10FF,X
+            cpu.load(vec![0xbd, 0xff, 0x10]).unwrap();
+            cpu.power_on();
+            cpu.register_x = x;
+
+            let mut count = 1;
+            while !cpu.tick().unwrap() {
+                count += 1;
+            }
+            assert_eq!(count, ticks);
+        }
+    }
+
+    #[test]
+    fn test_tick_unknown_opcode_does_not_advance() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xab]).unwrap();
+        cpu.power_on();
+
+        assert_eq!(cpu.tick(), Err(CpuError::UnknownOpcode { opcode : 0xab, address : 0x8000 }));
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.cycles, 0);
+    }
+
+    #[test]
+    fn test_per_cycle_nmi_after_final_poll_waits_one_instruction() {
+        // The NMI is raised before the last cycle of the NOP (seen by its poll) or after it (too late).
+        for (ticks_before, expected) in [(1, 0), (2, 1)] {
+            let mut cpu = CPU::new();
+            install_recording_handler(&mut cpu, 0xfffa);
+            // NOP; INX; INX; BRK
+            cpu.load(vec![0xea, 0xe8, 0xe8, 0x00]).unwrap();
+            cpu.power_on();
+            cpu.execution_mode = ExecutionMode::PerCycle;
+            for _ in 0..ticks_before {
+                cpu.tick().unwrap();
+            }
+            cpu.trigger_nmi();
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.mem_read(0x10), expected);
+            assert_eq!(cpu.register_x, 2);
+        }
+    }
+
+    #[test]
+    fn test_per_cycle_irq_after_cli_waits_one_instruction() {
+        let mut cpu = CPU::new();
+        cpu.execution_mode = ExecutionMode::PerCycle;
+        install_recording_handler(&mut cpu, 0xfffe);
+        // CLI; INX; INX; BRK
+        cpu.load(vec![0x58, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.status.set_interrupt_disable(true);
+        cpu.set_irq(true);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 1);
+        assert_eq!(cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_opcode_table_is_indexed_by_code() {
+        use nes::opcodes::{lookup, CPU_OPS_CODES, OPCODES};
+
+        for (code, opcode) in OPCODES.iter().enumerate() {
+            if let Some(opcode) = opcode {
+                assert_eq!(opcode.code as usize, code);
+            }
+        }
+        assert_eq!(OPCODES.iter().flatten().count(), CPU_OPS_CODES.len());
+        assert_eq!(lookup(0xab), None);
+    }
+
+    #[test]
+    fn test_cpu_accesses_go_through_memory() {
+        let mut cpu = CPU::with_memory(TestBus::new());
+        // INC $10
+        cpu.load(vec![0xe6, 0x10]).unwrap();
+        cpu.power_on();
+        cpu.memory_mut().clear_accesses();
+        cpu.step().unwrap();
+
+        let accesses : Vec<(u16, Access)> =
+            cpu.memory().accesses().iter().map(|access| (access.address, access.access)).collect();
+        assert_eq!(
+            accesses,
+            vec![
+                (0x8000, Access::Read),
+                (0x8001, Access::Read),
+                (0x0010, Access::Read),
+                (0x0010, Access::Write),
+                (0x0010, Access::Write)
+            ]
+        );
+        assert_eq!(cpu.memory().writes(0x0010), vec![0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut cpu = CPU::with_memory(TestBus::new());
+        cpu.load(vec![0xea]).unwrap();
+        cpu.memory_mut().clear_accesses();
+
+        assert_eq!(cpu.peek(0x8000), 0xea);
+        assert!(cpu.memory().accesses().is_empty());
+    }
+
+    #[test]
+    fn test_restore_replays_the_same_run() {
+        let mut cpu = CPU::new();
+        install_recording_handler(&mut cpu, 0xfffa);
+        // LDX #$05; DEX; BNE -3; BRK
+        cpu.load(vec![0xa2, 0x05, 0xca, 0xd0, 0xfd, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.step().unwrap();
+        cpu.trigger_nmi();
+        let state : CpuState = cpu.snapshot();
+        assert!(state.nmi_pending);
+
+        cpu.run().unwrap();
+        let first = cpu.snapshot();
+        cpu.mem_write(0x10, 0xff);
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        cpu.restore(&state);
+        assert_eq!(cpu.snapshot(), state);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.snapshot(), first);
+        assert_eq!(cpu.mem_read(0x10), 5);
+    }
+
+    #[test]
+    fn test_restore_part_way_through_an_instruction() {
+        let mut cpu = CPU::new();
+        // LDA $1234; BRK
+        cpu.mem_write(0x1234, 0x42);
+        cpu.load(vec![0xad, 0x34, 0x12, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        let state = cpu.snapshot();
+
+        while !cpu.tick().unwrap() {}
+        cpu.register_a = 0;
+        cpu.restore(&state);
+        assert!(!cpu.tick().unwrap());
+        assert!(cpu.tick().unwrap());
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn test_run_reports_why_it_stopped() {
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.load_and_run(vec![0xe8, 0x00]).unwrap(), Stopped::Exit);
+        assert_eq!(cpu.load_and_run(vec![0xe8, 0x02]).unwrap(), Stopped::Halted);
+    }
+
+    #[test]
+    fn test_run_stops_at_breakpoint_and_resumes() {
+        let mut cpu = CPU::new();
+        // LDX #$03; DEX; BNE -3; BRK
+        cpu.load(vec![0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.add_breakpoint(0x8002);
+
+        for x in [3, 2, 1] {
+            assert_eq!(cpu.run().unwrap(), Stopped::Breakpoint(0x8002));
+            assert_eq!(cpu.register_x, x);
+        }
+        assert!(cpu.remove_breakpoint(0x8002));
+        assert!(!cpu.remove_breakpoint(0x8002));
+        assert_eq!(cpu.run().unwrap(), Stopped::Exit);
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_breakpoint_in_interrupt_handler() {
+        let mut cpu = CPU::new();
+        install_recording_handler(&mut cpu, 0xfffa);
+        // INX; INX; BRK
+        cpu.load(vec![0xe8, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+        cpu.add_breakpoint(0x9000);
+        cpu.trigger_nmi();
+
+        assert_eq!(cpu.run().unwrap(), Stopped::Breakpoint(0x9000));
+        assert_eq!(cpu.mem_read(0x10), 0xff);
+        cpu.clear_breakpoints();
+        assert_eq!(cpu.run().unwrap(), Stopped::Exit);
+        assert_eq!(cpu.mem_read(0x10), 0);
+    }
+
+    #[test]
+    fn test_step_reports_breakpoint_ahead() {
+        let mut cpu = CPU::new();
+        // INX; INX; JAM
+        cpu.load(vec![0xe8, 0xe8, 0x02]).unwrap();
+        cpu.power_on();
+        cpu.add_breakpoint(0x8001);
+
+        assert_eq!(cpu.step().unwrap().stopped, Some(Stopped::Breakpoint(0x8001)));
+        assert_eq!(cpu.step().unwrap().stopped, None);
+        assert_eq!(cpu.step().unwrap().stopped, Some(Stopped::Halted));
+    }
+
+    #[test]
+    fn test_watch_write_stops_after_the_instruction() {
+        let mut cpu = CPU::new();
+        cpu.watch_write(0x0200 ..= 0x02ff);
+        // LDA #$07; STA $10; STA $0234; INX; BRK
+        cpu.load(vec![0xa9, 0x07, 0x85, 0x10, 0x8d, 0x34, 0x02, 0xe8, 0x00]).unwrap();
+        cpu.power_on();
+
+        let hit = WatchHit { pc : 0x8004, access : Access::Write, address : 0x0234, value : 0x07 };
+        assert_eq!(cpu.run().unwrap(), Stopped::Watchpoint(hit));
+        assert_eq!(cpu.program_counter, 0x8007);
+        assert_eq!(cpu.run().unwrap(), Stopped::Exit);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_watch_read_sees_dummy_reads() {
+        for mode in [ExecutionMode::PerInstruction, ExecutionMode::PerCycle] {
+            let mut cpu = CPU::new();
+            cpu.execution_mode = mode;
+            cpu.watch_read(0x10ff ..= 0x10ff);
+            // LDX #$01; LDA $10FF,X; BRK
+            cpu.load(vec![0xa2, 0x01, 0xbd, 0xff, 0x10, 0x00]).unwrap();
+            cpu.power_on();
+
+            // The page crossing first reads $1000, not $10FF.
+            assert_eq!(cpu.run().unwrap(), Stopped::Exit);
+
+            cpu.clear_watchpoints();
+            cpu.watch_read(0x1000 ..= 0x1000);
+            cpu.program_counter = 0x8002;
+            let hit = WatchHit { pc : 0x8002, access : Access::Read, address : 0x1000, value : 0x00 };
+            assert_eq!(cpu.run().unwrap(), Stopped::Watchpoint(hit));
+        }
+    }
+
+    #[test]
+    fn test_watch_read_sees_unmapped_reads() {
+        let mut cpu = CPU::new();
+        cpu.unmap(0x5000 ..= 0x5fff);
+        cpu.watch_read(0x5000 ..= 0x5000);
+        // LDA $5000; BRK
+        cpu.load(vec![0xad, 0x00, 0x50, 0x00]).unwrap();
+        cpu.power_on();
+
+        // Nothing answers, the open bus still holds the operand's high byte.
+        let hit = WatchHit { pc : 0x8000, access : Access::Read, address : 0x5000, value : 0x50 };
+        assert_eq!(cpu.run().unwrap(), Stopped::Watchpoint(hit));
+    }
+
+    #[test]
+    fn test_watch_handler_is_called_instead_of_stopping() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new();
+        cpu.watch_write(0x10 ..= 0x10);
+        let recorded = hits.clone();
+        cpu.on_watch(move |hit| recorded.borrow_mut().push(*hit));
+        // LDX #$02; STX $10; DEX; BNE -5; BRK
+        cpu.load_and_run(vec![0xa2, 0x02, 0x86, 0x10, 0xca, 0xd0, 0xfb, 0x00]).unwrap();
+
+        let values : Vec<u8> = hits.borrow().iter().map(|hit| hit.value).collect();
+        assert_eq!(values, vec![2, 1]);
+        assert!(cpu.take_watch_hit().is_none());
+    }
+
+    #[test]
+    fn test_direct_memory_access_is_not_watched() {
+        let mut cpu = CPU::new();
+        cpu.watch_write(0x10 ..= 0x10);
+        cpu.mem_write(0x10, 0x01);
+        cpu.load(vec![0xea, 0x00]).unwrap();
+        cpu.power_on();
+
+        assert_eq!(cpu.run().unwrap(), Stopped::Exit);
+        assert!(cpu.take_watch_hit().is_none());
+    }
+}
@@ -0,0 +1,78 @@
+#[cfg(test)]
+mod disasm_tests {
+    use nes::cpu::CPU;
+    use nes::disasm::{decode, disassemble, disassemble_memory, listing};
+
+    #[test]
+    fn test_operand_syntax_for_each_addressing_mode() {
+        let cases : [(&[u8], &str); 12] = [
+            (&[0xa9, 0x05], "LDA #$05"),
+            (&[0xa5, 0x10], "LDA $10"),
+            (&[0xb5, 0x10], "LDA $10,X"),
+            (&[0xb6, 0x10], "LDX $10,Y"),
+            (&[0xad, 0x34, 0x12], "LDA $1234"),
+            (&[0xbd, 0x34, 0x12], "LDA $1234,X"),
+            (&[0xb9, 0x34, 0x12], "LDA $1234,Y"),
+            (&[0x6c, 0x34, 0x12], "JMP ($1234)"),
+            (&[0xa1, 0x10], "LDA ($10,X)"),
+            (&[0xb1, 0x10], "LDA ($10),Y"),
+            (&[0x0a], "ASL A"),
+            (&[0xe8], "INX"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(decode(bytes, 0x8000).assembly(), expected);
+        }
+    }
+
+    #[test]
+    fn test_branch_target_is_relative_to_next_instruction() {
+        let instruction = decode(&[0xd0, 0xfe], 0x8000);
+        assert_eq!(instruction.target(), Some(0x8000));
+        assert_eq!(instruction.assembly(), "BNE $8000");
+        assert_eq!(decode(&[0x10, 0x7f], 0x80f0).target(), Some(0x8171));
+    }
+
+    #[test]
+    fn test_unknown_and_truncated_bytes_are_data() {
+        let code = disassemble(&[0xab, 0xea, 0xad, 0x00], 0x8000);
+        let lines : Vec<String> = code.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "8000  AB        .byte $AB",
+                "8001  EA        NOP",
+                "8002  AD        .byte $AD",
+                "8003  00        BRK",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listing_labels_targets_inside_the_code() {
+        // JSR $8006; JMP $9000; BRK; LDX #$03; DEX; BNE $8008; RTS
+        let code = [0x20, 0x06, 0x80, 0x4c, 0x00, 0x90, 0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x60];
+        assert_eq!(
+            listing(&disassemble(&code, 0x8000)),
+            "\
+8000  20 06 80  JSR L_8006
+8003  4C 00 90  JMP $9000
+L_8006:
+8006  A2 03     LDX #$03
+L_8008:
+8008  CA        DEX
+8009  D0 FD     BNE L_8008
+800B  60        RTS
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_memory() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa9, 0x01, 0x8d, 0x00, 0x02]).unwrap();
+
+        let code = disassemble_memory(cpu.memory(), 0x8000 ..= 0x8004);
+        assert_eq!(code.len(), 2);
+        assert_eq!(code[1].to_string(), "8002  8D 00 02  STA $0200");
+    }
+}
@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod emulator_tests {
+    use nes::asm::assemble_at;
+    use nes::cartridge::{Cartridge, Rom};
+    use nes::emulator::Emulator;
+    use nes::mem::Mem;
+    use nes::ppu::{PPUADDR, PPUDATA};
+    use nes::region::Region;
+
+    /// An NROM cartridge running `source` from $C000, where the reset vector points.
+    fn cartridge(source : &str) -> Cartridge {
+        cartridge_with_nmi(source, 0x0000)
+    }
+
+    /// An NROM cartridge running `source` from $C000, with its NMI handler at `nmi`.
+    fn cartridge_with_nmi(source : &str, nmi : u16) -> Cartridge {
+        Cartridge::new(rom(source, nmi)).unwrap()
+    }
+
+    /// The ROM of [`cartridge_with_nmi`].
+    fn rom(source : &str, nmi : u16) -> Rom {
+        let mut prg_rom = assemble_at(source, 0xc000).unwrap();
+        prg_rom.resize(0x4000, 0);
+        prg_rom[0x3ffa .. 0x3ffc].copy_from_slice(&nmi.to_le_bytes());
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0xc0;
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend(prg_rom);
+        bytes.extend([0 ; 0x2000]);
+        Rom::from_bytes(&bytes).unwrap()
+    }
+
+    fn run(emulator : &mut Emulator, instructions : usize) {
+        for _ in 0 .. instructions {
+            emulator.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_insert_starts_the_game() {
+        let mut emulator = Emulator::with_cartridge(cartridge("LDA #$11\nSTA $00\nloop: JMP loop"));
+        assert_eq!(emulator.cpu().program_counter, 0xc000);
+        run(&mut emulator, 3);
+        assert_eq!(emulator.cpu().memory().peek(0x0000), 0x11);
+    }
+
+    #[test]
+    fn test_hot_swap() {
+        let mut emulator = Emulator::with_cartridge(cartridge("LDA #$11\nSTA $00\nloop: JMP loop"));
+        run(&mut emulator, 3);
+
+        let first = emulator.insert(cartridge("LDA #$22\nSTA $01\nloop: JMP loop"));
+        assert_eq!(first.unwrap().mapper().cpu_peek(0xc001), Some(0x11));
+        // The console was powered on again: the new game starts from its reset vector with fresh RAM.
+        assert_eq!(emulator.cpu().program_counter, 0xc000);
+        assert_eq!(emulator.cpu().memory().peek(0x0000), 0x00);
+        run(&mut emulator, 3);
+        assert_eq!(emulator.cpu().memory().peek(0x0001), 0x22);
+        assert_eq!(emulator.cartridge().unwrap().mapper().cpu_peek(0xc001), Some(0x22));
+    }
+
+    #[test]
+    fn test_insert_switches_to_the_cartridge_region() {
+        let mut emulator = Emulator::with_cartridge(cartridge("loop: JMP loop"));
+        assert_eq!(emulator.region(), Region::Ntsc);
+
+        let mut rom = rom("loop: JMP loop", 0x0000);
+        rom.region = Some(Region::Pal);
+        emulator.insert(Cartridge::new(rom).unwrap());
+        assert_eq!((emulator.region(), emulator.cpu().region()), (Region::Pal, Region::Pal));
+        assert_eq!(emulator.cpu().memory().ppu().region(), Region::Pal);
+
+        // A cartridge that does not say keeps the console as it is.
+        emulator.insert(cartridge("loop: JMP loop"));
+        assert_eq!(emulator.region(), Region::Pal);
+        emulator.set_region(Region::Dendy);
+        assert_eq!(emulator.cpu().region(), Region::Dendy);
+    }
+
+    #[test]
+    fn test_eject_empties_the_slot() {
+        let mut emulator = Emulator::new();
+        assert!(emulator.eject().is_none());
+
+        emulator.insert(cartridge("loop: JMP loop"));
+        assert!(emulator.eject().is_some());
+        assert!(emulator.cartridge().is_none());
+        assert!(emulator.eject().is_none());
+    }
+
+    #[test]
+    fn test_reset_keeps_ram() {
+        let mut emulator = Emulator::with_cartridge(cartridge("INC $00\nloop: JMP loop"));
+        run(&mut emulator, 2);
+        emulator.reset();
+        assert_eq!(emulator.cpu().program_counter, 0xc000);
+        run(&mut emulator, 1);
+        assert_eq!(emulator.cpu().memory().peek(0x0000), 2);
+    }
+
+    #[test]
+    fn test_register_writes_reach_the_ppu_on_their_cycle() {
+        let program = "LDA #$3F\nSTA $2006\nLDA #$01\nSTA $2006\nloop: JMP loop";
+        let mut emulator = Emulator::with_cartridge(cartridge(program));
+        let ppu = emulator.cpu_mut().memory_mut().ppu_mut();
+        // Backdrop $0F and entry 1 $16, leaving v at $0000.
+        let writes = [(PPUADDR, 0x3f), (PPUADDR, 0x00), (PPUDATA, 0x0f), (PPUDATA, 0x16)];
+        for (register, value) in writes.into_iter().chain([(PPUADDR, 0x00), (PPUADDR, 0x00)]) {
+            ppu.write_register(register, value, None);
+        }
+        run(&mut emulator, 100);
+
+        // Pointing v at palette entry 1 with rendering off shows it from the dot of the second PPUADDR write: the
+        // last cycle of the second STA, after 11 cycles or 33 dots.
+        let frame = emulator.cpu().memory().ppu().frame();
+        assert_eq!((frame[31], frame[32]), (0x0f, 0x16));
+    }
+
+    #[test]
+    fn test_vblank_nmi_runs_the_handler_every_frame() {
+        // The handler, at $C003, counts the frames the main loop waits through.
+        let program = "JMP main\nINC $00\nRTI\nmain: LDA #$80\nSTA $2000\nloop: JMP loop";
+        let mut emulator = Emulator::with_cartridge(cartridge_with_nmi(program, 0xc003));
+        // A frame is about 29780 cycles, the loop 3.
+        run(&mut emulator, 25000);
+        assert_eq!(emulator.cpu().memory().ppu().frame_count(), 2);
+        assert_eq!(emulator.cpu().memory().peek(0x0000), 2);
+    }
+
+    #[test]
+    fn test_vblank_nmi_during_an_instruction_is_taken_after_it() {
+        // LDA $0200 takes 4 cycles, the NMI handler is at $C008.
+        let program = "LDA $0200\nNOP\nNOP\nloop: JMP loop\nINC $00\nRTI";
+        let mut emulator = Emulator::with_cartridge(cartridge_with_nmi(program, 0xc008));
+        let bus = emulator.cpu_mut().memory_mut();
+        bus.write(0x2000, 0x80);
+        // Vertical blank starts at dot 1 of scanline 241, in the LDA's second cycle.
+        let position = bus.ppu().scanline() as u64 * 341 + bus.ppu().dot() as u64;
+        bus.ppu_mut().tick(241 * 341 + 1 - 4 - position, None);
+
+        run(&mut emulator, 1);
+        assert_ne!(emulator.cpu().memory().ppu().status() & 0x80, 0);
+        // The interrupt comes before the NOP, the step runs it and the handler's INC.
+        run(&mut emulator, 1);
+        assert_eq!(emulator.cpu().program_counter, 0xc00a);
+        assert_eq!(emulator.cpu().memory().peek(0x0000), 1);
+    }
+}
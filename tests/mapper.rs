@@ -0,0 +1,1408 @@
+#[cfg(test)]
+mod mapper_tests {
+    use nes::cartridge::{Cartridge, Mirroring, Rom};
+    use nes::mapper::fds::{FdsDisk, FdsError, SIDE_SIZE};
+    use nes::mapper::{
+        Axrom, Chr, Cnrom, ColorDreams, Fds, Fme7, Gxrom, Mapper, Mmc1, Mmc2, Mmc3, Mmc5, Multicart225, Multicart58, Nrom, Nwc, StateError, StateReader, StateWriter, Uxrom, Vrc6,
+    };
+
+    /// A ROM for `mapper` with `prg_banks` 16KiB PRG banks and `chr_banks` 8KiB CHR banks. Every 8KiB of PRG ROM is
+    /// filled with its index, and every 1KiB of CHR ROM with its index, so reads show which bank is mapped.
+    fn rom(mapper : u8, prg_banks : u8, chr_banks : u8, flags6 : u8) -> Rom {
+        let mut bytes =
+            vec![b'N', b'E', b'S', 0x1a, prg_banks, chr_banks, (mapper << 4) | flags6, mapper & 0xf0, 0, 0, 0, 0, 0, 0, 0, 0];
+        for bank in 0 .. prg_banks as usize * 2 {
+            bytes.extend(vec![bank as u8 ; 0x2000]);
+        }
+        for bank in 0 .. chr_banks as usize * 8 {
+            bytes.extend(vec![bank as u8 ; 0x400]);
+        }
+        Rom::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_nrom_128_mirrors_prg_rom() {
+        let nrom = Nrom::new(rom(0, 1, 1, 0));
+
+        assert_eq!(nrom.cpu_peek(0x8000), Some(0));
+        assert_eq!(nrom.cpu_peek(0xa000), Some(1));
+        assert_eq!(nrom.cpu_peek(0xc000), Some(0));
+        assert_eq!(nrom.cpu_peek(0xffff), Some(1));
+    }
+
+    #[test]
+    fn test_nrom_256_maps_all_prg_rom() {
+        let mut nrom = Nrom::new(rom(0, 2, 1, 0));
+
+        assert_eq!(nrom.cpu_read(0x8000), Some(0));
+        assert_eq!(nrom.cpu_read(0xc000), Some(2));
+        assert_eq!(nrom.cpu_read(0xe000), Some(3));
+    }
+
+    #[test]
+    fn test_nrom_is_read_only() {
+        let mut nrom = Nrom::new(rom(0, 1, 1, 0));
+        nrom.cpu_write(0x8000, 0xff);
+        nrom.ppu_write(0x0000, 0xff);
+
+        assert_eq!(nrom.cpu_read(0x8000), Some(0));
+        assert_eq!(nrom.ppu_read(0x0000), 0);
+    }
+
+    #[test]
+    fn test_nrom_chr_and_mirroring() {
+        let nrom = Nrom::new(rom(0, 1, 1, 0x01));
+
+        assert_eq!(nrom.ppu_peek(0x0000), 0);
+        assert_eq!(nrom.ppu_peek(0x0400), 1);
+        assert_eq!(nrom.ppu_peek(0x1fff), 7);
+        assert_eq!(nrom.mirroring(), Mirroring::Vertical);
+        assert_eq!(Nrom::new(rom(0, 1, 1, 0)).mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_nrom_without_chr_rom_has_chr_ram() {
+        let mut nrom = Nrom::new(rom(0, 1, 0, 0));
+        nrom.ppu_write(0x0000, 0x12);
+        nrom.ppu_write(0x1fff, 0x34);
+
+        assert_eq!(nrom.ppu_read(0x0000), 0x12);
+        assert_eq!(nrom.ppu_read(0x1fff), 0x34);
+    }
+
+    #[test]
+    fn test_chr_ram_size_from_nes2_header() {
+        // MMC1 with 32KiB of CHR RAM (NES 2.0 shift 9), in 4KiB CHR mode.
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, 2, 0, 0x10, 0x08, 0, 0, 0, 0x09, 0, 0, 0, 0];
+        bytes.resize(16 + 0x8000, 0);
+        let mut mmc1 = Mmc1::new(Rom::from_bytes(&bytes).unwrap());
+        mmc1_write(&mut mmc1, 0x8000, 0x1c);
+        mmc1_write(&mut mmc1, 0xa000, 7);
+        mmc1.ppu_write(0x0000, 0x77);
+        mmc1_write(&mut mmc1, 0xa000, 0);
+        assert_eq!(mmc1.ppu_peek(0x0000), 0x00);
+        mmc1_write(&mut mmc1, 0xa000, 7);
+        assert_eq!(mmc1.ppu_peek(0x0000), 0x77, "bank 7 of 4KiB is only there with 32KiB");
+    }
+
+    #[test]
+    fn test_chr_defaults_to_8k_of_ram() {
+        let chr = Chr::new(Vec::new(), 0);
+        assert!(chr.is_ram());
+        assert_eq!(chr.len(), 0x2000);
+        assert_eq!(Chr::new(Vec::new(), 0x8000).len(), 0x8000);
+        assert!(!Chr::new(vec![0 ; 0x2000], 0x8000).is_ram());
+    }
+
+    /// Loads an MMC1 register through the serial port, as a game would with five STA instructions.
+    fn mmc1_write(mmc1 : &mut impl Mapper, addr : u16, value : u8) {
+        for bit in 0 .. 5 {
+            mmc1.cpu_write(addr, value >> bit);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_powers_on_with_last_bank_fixed() {
+        // 8 banks of 16KiB.
+        let mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_peek(0xc000), Some(14));
+        assert_eq!(mmc1.cpu_peek(0xffff), Some(15));
+    }
+
+    #[test]
+    fn test_mmc1_prg_banking_modes() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        mmc1_write(&mut mmc1, 0xe000, 5);
+        // Last bank fixed at $C000, bank 5 at $8000.
+        assert_eq!((mmc1.cpu_peek(0x8000), mmc1.cpu_peek(0xc000)), (Some(10), Some(14)));
+
+        // First bank fixed at $8000, bank 5 at $C000.
+        mmc1_write(&mut mmc1, 0x8000, 0b0_1000);
+        assert_eq!((mmc1.cpu_peek(0x8000), mmc1.cpu_peek(0xc000)), (Some(0), Some(10)));
+
+        // 32KiB: bank 5 is 4 and 5.
+        mmc1_write(&mut mmc1, 0x8000, 0b0_0000);
+        assert_eq!((mmc1.cpu_peek(0x8000), mmc1.cpu_peek(0xc000)), (Some(8), Some(10)));
+    }
+
+    #[test]
+    fn test_mmc1_chr_banking_modes() {
+        // 4 banks of 8KiB, so 8 of 4KiB.
+        let mut mmc1 = Mmc1::new(rom(1, 2, 4, 0));
+        mmc1_write(&mut mmc1, 0xa000, 3);
+        mmc1_write(&mut mmc1, 0xc000, 6);
+        // 8KiB mode uses the first register without its low bit.
+        assert_eq!((mmc1.ppu_peek(0x0000), mmc1.ppu_peek(0x1000)), (2 * 4, 3 * 4));
+
+        mmc1_write(&mut mmc1, 0x8000, 0b1_1100);
+        assert_eq!((mmc1.ppu_peek(0x0000), mmc1.ppu_peek(0x1000)), (3 * 4, 6 * 4));
+        assert_eq!(mmc1.ppu_peek(0x1400), 6 * 4 + 1);
+    }
+
+    #[test]
+    fn test_mmc1_mirroring() {
+        let mut mmc1 = Mmc1::new(rom(1, 2, 1, 0));
+        for (control, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            mmc1_write(&mut mmc1, 0x9fff, 0b0_1100 | control);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_reset_bit_clears_shift_register() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        mmc1_write(&mut mmc1, 0x8000, 0b0_0000);
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 0x80);
+        mmc1_write(&mut mmc1, 0xe000, 2);
+
+        // Back to the last bank fixed at $C000, and only the writes after the reset were used.
+        assert_eq!((mmc1.cpu_peek(0x8000), mmc1.cpu_peek(0xc000)), (Some(4), Some(14)));
+    }
+
+    #[test]
+    fn test_mmc1_ignores_consecutive_writes() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        // A read-modify-write writes twice on back to back cycles, only the first write counts.
+        for (cycle, bit) in (0 ..).step_by(6).zip([0, 1, 0, 0, 0]) {
+            mmc1.cpu_write_on_cycle(0xe000, bit, cycle);
+            mmc1.cpu_write_on_cycle(0xe000, bit ^ 1, cycle + 1);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(4));
+
+        // On the same cycle (the whole instruction at once), the second write is ignored as well.
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        for (cycle, bit) in (0 ..).step_by(6).zip([1, 1, 0, 0, 0]) {
+            mmc1.cpu_write_on_cycle(0xe000, bit, cycle);
+            mmc1.cpu_write_on_cycle(0xe000, bit ^ 1, cycle);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(6));
+    }
+
+    #[test]
+    fn test_mmc1_writes_without_a_cycle_all_count() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        for bit in [1, 0, 1, 0, 0] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(10));
+    }
+
+    #[test]
+    fn test_mmc1_loads_version_1_states() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        mmc1.cpu_write_on_cycle(0xe000, 1, 10);
+        let state = save_state(&mmc1);
+        // Version 1 had the board's own cycle count before the last write.
+        let mut old = state[.. state.len() - 9].to_vec();
+        old.extend(20u64.to_le_bytes());
+        old.extend(&state[state.len() - 9 ..]);
+
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        let mut reader = StateReader::new(&old, 1);
+        mmc1.load_state(&mut reader).unwrap();
+        reader.finish().unwrap();
+        // The shift register came back, and the last write is forgotten: the next one counts.
+        mmc1.cpu_write_on_cycle(0xe000, 1, 11);
+        for bit in [0, 0, 0] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(6));
+    }
+
+    #[test]
+    fn test_mmc1_prg_ram() {
+        let mut mmc1 = Mmc1::new(rom(1, 2, 1, 0));
+        mmc1.cpu_write(0x6000, 0x12);
+        mmc1.cpu_write(0x7fff, 0x34);
+        assert_eq!((mmc1.cpu_peek(0x6000), mmc1.cpu_peek(0x7fff)), (Some(0x12), Some(0x34)));
+
+        mmc1_write(&mut mmc1, 0xe000, 0b1_0000);
+        mmc1.cpu_write(0x6000, 0x56);
+        assert_eq!(mmc1.cpu_peek(0x6000), None);
+        mmc1_write(&mut mmc1, 0xe000, 0b0_0000);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x12));
+    }
+
+    #[test]
+    fn test_mmc1_chr_ram() {
+        let mut mmc1 = Mmc1::new(rom(1, 2, 0, 0));
+        mmc1.ppu_write(0x1234, 0x56);
+        assert_eq!(mmc1.ppu_peek(0x1234), 0x56);
+
+        let mut mmc1 = Mmc1::new(rom(1, 2, 1, 0));
+        mmc1.ppu_write(0x1234, 0x56);
+        assert_eq!(mmc1.ppu_peek(0x1234), 4);
+    }
+
+    #[test]
+    fn test_uxrom_switches_the_bank_at_8000() {
+        let mut uxrom = Uxrom::new(rom(2, 8, 0, 0));
+        assert_eq!((uxrom.cpu_peek(0x8000), uxrom.cpu_peek(0xc000)), (Some(0), Some(14)));
+
+        uxrom.cpu_write(0xc123, 5);
+        assert_eq!((uxrom.cpu_peek(0x8000), uxrom.cpu_peek(0xa000)), (Some(10), Some(11)));
+        assert_eq!((uxrom.cpu_peek(0xc000), uxrom.cpu_peek(0xe000)), (Some(14), Some(15)));
+
+        // Bank numbers wrap around the ROM.
+        uxrom.cpu_write(0x8000, 9);
+        assert_eq!(uxrom.cpu_peek(0x8000), Some(2));
+    }
+
+    #[test]
+    fn test_uxrom_chr_ram() {
+        let mut uxrom = Uxrom::new(rom(2, 4, 0, 0x01));
+        uxrom.ppu_write(0x0010, 0xaa);
+        uxrom.ppu_write(0x1fff, 0xbb);
+
+        assert_eq!((uxrom.ppu_peek(0x0010), uxrom.ppu_peek(0x1fff)), (0xaa, 0xbb));
+        assert_eq!(uxrom.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_cnrom_switches_chr_bank() {
+        let mut image = rom(3, 2, 4, 0);
+        // A byte in the ROM that lets every bit through.
+        image.prg_rom[0x7fff] = 0xff;
+        let mut cnrom = Cnrom::new(image);
+        assert_eq!(cnrom.ppu_peek(0x0400), 1);
+
+        cnrom.cpu_write(0xffff, 2);
+        assert_eq!((cnrom.ppu_peek(0x0000), cnrom.ppu_peek(0x1c00)), (16, 23));
+        assert_eq!((cnrom.cpu_peek(0x8000), cnrom.cpu_peek(0xc000)), (Some(0), Some(2)));
+    }
+
+    #[test]
+    fn test_cnrom_bus_conflict() {
+        // The byte at $A000 is 1 (the second 8KiB of PRG ROM), so only bit 0 of the write survives.
+        let mut cnrom = Cartridge::new(rom(3, 1, 4, 0)).unwrap();
+        assert!(cnrom.bus_conflicts());
+        cnrom.cpu_write(0xa000, 3);
+        assert_eq!(cnrom.mapper().ppu_peek(0x0000), 8);
+
+        // Writing over a zero byte always selects bank 0.
+        cnrom.cpu_write(0x8000, 3);
+        assert_eq!(cnrom.mapper().ppu_peek(0x0000), 0);
+    }
+
+    /// Sets MMC3 bank register `register` to `bank`.
+    fn mmc3_bank(mmc3 : &mut Mmc3, bank_select : u8, register : u8, bank : u8) {
+        mmc3.cpu_write(0x8000, bank_select | register);
+        mmc3.cpu_write(0x8001, bank);
+    }
+
+    /// The pattern table fetches of one scanline: background from $0000, then sprites from $1000.
+    fn mmc3_scanline(mmc3 : &mut Mmc3) {
+        mmc3.ppu_read(0x0000);
+        mmc3.ppu_read(0x1000);
+    }
+
+    #[test]
+    fn test_mmc3_prg_banking() {
+        // 32 banks of 8KiB.
+        let mut mmc3 = Mmc3::new(rom(4, 16, 8, 0));
+        mmc3_bank(&mut mmc3, 0, 6, 3);
+        mmc3_bank(&mut mmc3, 0, 7, 4);
+        let banks = |mmc3 : &Mmc3| [0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mmc3.cpu_peek(addr).unwrap());
+        assert_eq!(banks(&mmc3), [3, 4, 30, 31]);
+
+        mmc3.cpu_write(0x8000, 0x40);
+        assert_eq!(banks(&mmc3), [30, 4, 3, 31]);
+    }
+
+    #[test]
+    fn test_mmc3_chr_banking() {
+        // 64 banks of 1KiB.
+        let mut mmc3 = Mmc3::new(rom(4, 2, 8, 0));
+        for (register, bank) in [(0, 9), (1, 20), (2, 30), (3, 31), (4, 40), (5, 50)] {
+            mmc3_bank(&mut mmc3, 0, register, bank);
+        }
+        let banks = |mmc3 : &Mmc3| (0 .. 8).map(|slot| mmc3.ppu_peek(slot * 0x400)).collect::<Vec<u8>>();
+        assert_eq!(banks(&mmc3), vec![8, 9, 20, 21, 30, 31, 40, 50]);
+
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(banks(&mmc3), vec![30, 31, 40, 50, 8, 9, 20, 21]);
+    }
+
+    #[test]
+    fn test_mmc3_mirroring() {
+        let mut mmc3 = Mmc3::new(rom(4, 2, 1, 0));
+        mmc3.cpu_write(0xa000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+        mmc3.cpu_write(0xbffe, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+
+        let mut mmc3 = Mmc3::new(rom(4, 2, 1, 0x08));
+        mmc3.cpu_write(0xa000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_mmc3_prg_ram_protect() {
+        let mut mmc3 = Mmc3::new(rom(4, 2, 1, 0));
+        mmc3.cpu_write(0x6000, 0x12);
+        assert_eq!(mmc3.cpu_peek(0x6000), Some(0x12));
+
+        // Write protected.
+        mmc3.cpu_write(0xa001, 0xc0);
+        mmc3.cpu_write(0x6000, 0x34);
+        assert_eq!(mmc3.cpu_peek(0x6000), Some(0x12));
+
+        // Disabled.
+        mmc3.cpu_write(0xa001, 0x00);
+        assert_eq!(mmc3.cpu_peek(0x6000), None);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let mut mmc3 = Mmc3::new(rom(4, 2, 1, 0));
+        mmc3.cpu_write(0xc000, 2);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        // The first clock reloads 2, then it counts down to 0 and fires.
+        let mut fired = Vec::new();
+        for _ in 0 .. 3 {
+            mmc3_scanline(&mut mmc3);
+            fired.push(mmc3.irq_pending());
+        }
+        assert_eq!(fired, vec![false, false, true]);
+
+        // Acknowledging also disables it, so it stays quiet until enabled again.
+        mmc3.cpu_write(0xe000, 0);
+        assert!(!mmc3.irq_pending());
+        for _ in 0 .. 3 {
+            mmc3_scanline(&mut mmc3);
+        }
+        assert!(!mmc3.irq_pending());
+        mmc3.cpu_write(0xe001, 0);
+        for _ in 0 .. 3 {
+            mmc3_scanline(&mut mmc3);
+        }
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_mmc3_only_rising_a12_clocks_the_counter() {
+        let mut mmc3 = Mmc3::new(rom(4, 2, 1, 0));
+        mmc3.cpu_write(0xc000, 1);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+        // Eight sprite fetches in a row are one rise.
+        mmc3.ppu_read(0x0000);
+        for sprite in 0 .. 8 {
+            mmc3.ppu_read(0x1000 + sprite * 16);
+        }
+        assert!(!mmc3.irq_pending());
+        mmc3_scanline(&mut mmc3);
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_mmc3_latch_of_zero_fires_every_scanline() {
+        let mut mmc3 = Mmc3::new(rom(4, 2, 1, 0));
+        mmc3.cpu_write(0xc000, 0);
+        mmc3.cpu_write(0xe001, 0);
+        for _ in 0 .. 3 {
+            mmc3_scanline(&mut mmc3);
+            assert!(mmc3.irq_pending());
+            mmc3.cpu_write(0xe000, 0);
+            mmc3.cpu_write(0xe001, 0);
+        }
+    }
+
+    #[test]
+    fn test_axrom_switches_32k_banks() {
+        // 4 banks of 32KiB.
+        let mut axrom = Axrom::new(rom(7, 8, 0, 0));
+        assert_eq!((axrom.cpu_peek(0x8000), axrom.cpu_peek(0xe000)), (Some(0), Some(3)));
+
+        axrom.cpu_write(0x8000, 2);
+        assert_eq!((axrom.cpu_peek(0x8000), axrom.cpu_peek(0xe000)), (Some(8), Some(11)));
+        // Bit 3 is not part of the bank number.
+        axrom.cpu_write(0x8000, 0x0b);
+        assert_eq!(axrom.cpu_peek(0x8000), Some(12));
+    }
+
+    #[test]
+    fn test_axrom_mirrors_16k_prg_rom() {
+        let mut axrom = Axrom::new(rom(7, 1, 0, 0));
+        assert_eq!([0x8000, 0xa000, 0xc000, 0xfffc].map(|addr| axrom.cpu_peek(addr).unwrap()), [0, 1, 0, 1]);
+        axrom.cpu_write(0x8000, 1);
+        assert_eq!((axrom.cpu_peek(0x8000), axrom.cpu_peek(0xfffc)), (Some(0), Some(1)));
+    }
+
+    #[test]
+    fn test_axrom_single_screen_mirroring() {
+        let mut axrom = Axrom::new(rom(7, 8, 0, 0x01));
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+        axrom.cpu_write(0xffff, 0x10);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+        axrom.cpu_write(0xffff, 0x01);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+        axrom.ppu_write(0x0123, 0x45);
+        assert_eq!(axrom.ppu_peek(0x0123), 0x45);
+    }
+
+    #[test]
+    fn test_color_dreams_switches_prg_and_chr() {
+        // 4 banks of 32KiB PRG ROM and 16 banks of 8KiB CHR ROM, vertical mirroring.
+        let mut board = ColorDreams::new(rom(11, 8, 16, 0x01));
+        assert_eq!((board.cpu_peek(0x8000), board.ppu_peek(0x0000)), (Some(0), 0));
+        assert_eq!(board.mirroring(), Mirroring::Vertical);
+
+        board.cpu_write(0x8000, 0xf1);
+        assert_eq!((board.cpu_peek(0x8000), board.cpu_peek(0xe000)), (Some(4), Some(7)));
+        assert_eq!((board.ppu_peek(0x0000), board.ppu_peek(0x1c00)), (120, 127));
+        // Bits 2 and 3 drive the lockout defeat, not the banks.
+        board.cpu_write(0xc000, 0x2e);
+        assert_eq!((board.cpu_peek(0x8000), board.ppu_peek(0x0000)), (Some(8), 16));
+        assert_eq!(board.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_color_dreams_mirrors_16k_prg_rom() {
+        let mut board = ColorDreams::new(rom(11, 1, 2, 0));
+        assert_eq!([0x8000, 0xa000, 0xc000, 0xfffc].map(|addr| board.cpu_peek(addr).unwrap()), [0, 1, 0, 1]);
+        board.cpu_write(0x8000, 0x13);
+        assert_eq!((board.cpu_peek(0x8000), board.cpu_peek(0xfffc), board.ppu_peek(0x0000)), (Some(0), Some(1), 8));
+    }
+
+    #[test]
+    fn test_color_dreams_has_bus_conflicts() {
+        let mut cartridge = Cartridge::new(rom(11, 8, 16, 0)).unwrap();
+        assert!(cartridge.bus_conflicts());
+        // The byte at $8000 is 0, so the write cannot select anything but bank 0.
+        cartridge.cpu_write(0x8000, 0xff);
+        assert_eq!((cartridge.mapper().cpu_peek(0x8000), cartridge.mapper().ppu_peek(0x0000)), (Some(0), 0));
+    }
+
+    #[test]
+    fn test_gxrom_switches_prg_and_chr() {
+        // 4 banks of 32KiB PRG ROM and 4 banks of 8KiB CHR ROM, horizontal mirroring.
+        let mut gxrom = Gxrom::new(rom(66, 8, 4, 0));
+        assert_eq!((gxrom.cpu_peek(0x8000), gxrom.ppu_peek(0x0000)), (Some(0), 0));
+        assert_eq!(gxrom.mirroring(), Mirroring::Horizontal);
+
+        gxrom.cpu_write(0x8000, 0x21);
+        assert_eq!((gxrom.cpu_peek(0x8000), gxrom.cpu_peek(0xe000)), (Some(8), Some(11)));
+        assert_eq!((gxrom.ppu_peek(0x0000), gxrom.ppu_peek(0x1c00)), (8, 15));
+        // Bits 2, 3, 6 and 7 are not connected.
+        gxrom.cpu_write(0xffff, 0xdf);
+        assert_eq!((gxrom.cpu_peek(0x8000), gxrom.ppu_peek(0x0000)), (Some(4), 24));
+    }
+
+    #[test]
+    fn test_gxrom_mirrors_16k_prg_rom() {
+        let mut gxrom = Gxrom::new(rom(66, 1, 2, 0));
+        assert_eq!([0x8000, 0xa000, 0xc000, 0xfffc].map(|addr| gxrom.cpu_peek(addr).unwrap()), [0, 1, 0, 1]);
+        gxrom.cpu_write(0x8000, 0x11);
+        assert_eq!((gxrom.cpu_peek(0x8000), gxrom.cpu_peek(0xfffc), gxrom.ppu_peek(0x0000)), (Some(0), Some(1), 8));
+    }
+
+    #[test]
+    fn test_gxrom_has_bus_conflicts() {
+        let mut cartridge = Cartridge::new(rom(66, 8, 4, 0)).unwrap();
+        assert!(cartridge.bus_conflicts());
+        // The byte at $A000 is 1: only CHR bank 1 survives a write of $33.
+        cartridge.cpu_write(0xa000, 0x33);
+        assert_eq!((cartridge.mapper().cpu_peek(0x8000), cartridge.mapper().ppu_peek(0x0000)), (Some(0), 8));
+    }
+
+    #[test]
+    fn test_mmc2_prg_banking() {
+        // 16 banks of 8KiB.
+        let mut mmc2 = Mmc2::new(rom(9, 8, 16, 0));
+        mmc2.cpu_write(0xa000, 5);
+
+        assert_eq!([0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mmc2.cpu_peek(addr).unwrap()), [5, 13, 14, 15]);
+    }
+
+    #[test]
+    fn test_mmc2_chr_latches() {
+        // 32 banks of 4KiB, each 4KiB holds 1KiB banks 4n to 4n+3.
+        let mut mmc2 = Mmc2::new(rom(9, 8, 16, 0));
+        for (addr, bank) in [(0xb000, 5), (0xc000, 2), (0xd000, 3), (0xe000, 4)] {
+            mmc2.cpu_write(addr, bank);
+        }
+        assert_eq!((mmc2.ppu_peek(0x0000), mmc2.ppu_peek(0x1000)), (2 * 4, 4 * 4));
+
+        // The fetch that flips the latch still comes from the old bank.
+        assert_eq!(mmc2.ppu_read(0x0fd8), 2 * 4 + 3);
+        assert_eq!((mmc2.ppu_peek(0x0000), mmc2.ppu_peek(0x1000)), (5 * 4, 4 * 4));
+
+        // The upper half latches on any byte of tiles $FD and $FE, the lower only on the first.
+        mmc2.ppu_read(0x0fe9);
+        mmc2.ppu_read(0x1fdc);
+        assert_eq!((mmc2.ppu_peek(0x0000), mmc2.ppu_peek(0x1000)), (5 * 4, 3 * 4));
+        mmc2.ppu_read(0x0fe8);
+        mmc2.ppu_read(0x1fef);
+        assert_eq!((mmc2.ppu_peek(0x0000), mmc2.ppu_peek(0x1000)), (2 * 4, 4 * 4));
+    }
+
+    #[test]
+    fn test_mmc2_mirroring() {
+        let mut mmc2 = Mmc2::new(rom(9, 8, 16, 0));
+        mmc2.cpu_write(0xf000, 0);
+        assert_eq!(mmc2.mirroring(), Mirroring::Vertical);
+        mmc2.cpu_write(0xffff, 1);
+        assert_eq!(mmc2.mirroring(), Mirroring::Horizontal);
+    }
+
+    /// The PPU's fetches for one rendered scanline as the MMC5 sees them: 32 background tiles, 8 sprites with their
+    /// garbage nametable fetches, the first two tiles of the next line and the two dummy nametable fetches that end
+    /// the line. Returns the nametable and pattern bytes of the background tiles.
+    fn mmc5_scanline(mmc5 : &mut Mmc5) -> Vec<(Option<u8>, Option<u8>, u8)> {
+        let mut tiles = Vec::new();
+        let tile = |mmc5 : &mut Mmc5, pattern : u16| {
+            let name = mmc5.nametable_read(0x2000);
+            let attribute = mmc5.nametable_read(0x23c0);
+            let low = mmc5.ppu_read(pattern);
+            mmc5.ppu_read(pattern + 8);
+            (name, attribute, low)
+        };
+        for _ in 0 .. 32 {
+            tiles.push(tile(mmc5, 0x0000));
+        }
+        for _ in 0 .. 8 {
+            tile(mmc5, 0x1000);
+        }
+        for _ in 0 .. 2 {
+            tiles.push(tile(mmc5, 0x0000));
+        }
+        mmc5.nametable_read(0x2000);
+        mmc5.nametable_read(0x2000);
+        tiles
+    }
+
+    #[test]
+    fn test_mmc5_prg_modes() {
+        // 16 banks of 8KiB.
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        let banks = |mmc5 : &Mmc5| [0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mmc5.cpu_peek(addr).unwrap());
+        assert_eq!(banks(&mmc5)[3], 15);
+
+        for (addr, bank) in [(0x5114, 0x83), (0x5115, 0x85), (0x5116, 0x88), (0x5117, 0x8b)] {
+            mmc5.cpu_write(addr, bank);
+        }
+        assert_eq!(banks(&mmc5), [3, 5, 8, 11]);
+        mmc5.cpu_write(0x5100, 2);
+        assert_eq!(banks(&mmc5), [4, 5, 8, 11]);
+        mmc5.cpu_write(0x5100, 1);
+        assert_eq!(banks(&mmc5), [4, 5, 10, 11]);
+        mmc5.cpu_write(0x5100, 0);
+        assert_eq!(banks(&mmc5), [8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn test_mmc5_prg_ram_banks_and_protect() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_peek(0x6000), Some(0), "PRG RAM is protected at power on");
+
+        mmc5.cpu_write(0x5102, 2);
+        mmc5.cpu_write(0x5103, 1);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_peek(0x6000), Some(0x42));
+
+        // RAM bank 0 at $8000 instead of ROM.
+        mmc5.cpu_write(0x5114, 0x00);
+        assert_eq!(mmc5.cpu_peek(0x8000), Some(0x42));
+        mmc5.cpu_write(0x8001, 0x43);
+        assert_eq!(mmc5.cpu_peek(0x6001), Some(0x43));
+    }
+
+    #[test]
+    fn test_mmc5_chr_modes() {
+        // 64 banks of 1KiB.
+        let mut mmc5 = Mmc5::new(rom(5, 8, 8, 0));
+        for register in 0 .. 8 {
+            mmc5.cpu_write(0x5120 + register, 16 + register as u8);
+        }
+        let banks = |mmc5 : &Mmc5| (0 .. 8).map(|slot| mmc5.ppu_peek(slot * 0x400)).collect::<Vec<_>>();
+
+        mmc5.cpu_write(0x5101, 3);
+        assert_eq!(banks(&mmc5), [16, 17, 18, 19, 20, 21, 22, 23]);
+        mmc5.cpu_write(0x5101, 2);
+        assert_eq!(banks(&mmc5), [34, 35, 38, 39, 42, 43, 46, 47]);
+        mmc5.cpu_write(0x5101, 1);
+        assert_eq!(banks(&mmc5)[.. 4], [(19 * 4) % 64, (19 * 4 + 1) % 64, (19 * 4 + 2) % 64, (19 * 4 + 3) % 64]);
+        mmc5.cpu_write(0x5101, 0);
+        mmc5.cpu_write(0x5127, 3);
+        assert_eq!(banks(&mmc5), [24, 25, 26, 27, 28, 29, 30, 31]);
+
+        // Writing the background set switches to it outside of 8x16 sprites.
+        mmc5.cpu_write(0x512b, 1);
+        assert_eq!(banks(&mmc5), [8, 9, 10, 11, 12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn test_mmc5_multiplier() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        assert_eq!((mmc5.cpu_peek(0x5205), mmc5.cpu_peek(0x5206)), (Some(0x01), Some(0xfe)));
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_peek(0x5205).unwrap() as u16 | (mmc5.cpu_peek(0x5206).unwrap() as u16) << 8, 20000);
+    }
+
+    #[test]
+    fn test_mmc5_exram_modes() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5.cpu_write(0x5c00, 0x42);
+        assert_eq!(mmc5.cpu_peek(0x5c00), None, "the CPU cannot read ExRAM while it is a nametable");
+
+        mmc5.cpu_write(0x5104, 2);
+        assert_eq!(mmc5.cpu_peek(0x5c00), Some(0x42));
+        mmc5.cpu_write(0x5fff, 0x43);
+        assert_eq!(mmc5.cpu_peek(0x5fff), Some(0x43));
+
+        mmc5.cpu_write(0x5104, 3);
+        mmc5.cpu_write(0x5fff, 0x44);
+        assert_eq!(mmc5.cpu_peek(0x5fff), Some(0x43), "ExRAM is read only in mode 3");
+    }
+
+    #[test]
+    fn test_mmc5_nametable_mapping() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5.cpu_write(0x5105, 0b01_00_01_00);
+        assert_eq!(mmc5.mirroring(), Mirroring::Vertical);
+        assert_eq!(mmc5.nametable_peek(0x2000), None);
+        mmc5.cpu_write(0x5105, 0b01_01_00_00);
+        assert_eq!(mmc5.mirroring(), Mirroring::Horizontal);
+
+        // ExRAM in the second slot, fill mode in the fourth.
+        mmc5.cpu_write(0x5105, 0b11_01_10_00);
+        mmc5.cpu_write(0x5106, 0x24);
+        mmc5.cpu_write(0x5107, 0x02);
+        assert!(mmc5.nametable_write(0x2405, 0x31));
+        assert!(!mmc5.nametable_write(0x2005, 0x31));
+        assert_eq!(mmc5.nametable_peek(0x2405), Some(0x31));
+        assert_eq!(mmc5.nametable_peek(0x2c05), Some(0x24));
+        assert_eq!(mmc5.nametable_peek(0x2fc5), Some(0xaa));
+        assert_eq!(mmc5.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc5_scanline_irq() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5.cpu_write(0x5203, 3);
+        mmc5.cpu_write(0x5204, 0x80);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x00));
+
+        // The pre-render line starts the frame, the IRQ fires at the end of line 3.
+        mmc5_scanline(&mut mmc5);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x40));
+        for _ in 0 .. 2 {
+            mmc5_scanline(&mut mmc5);
+        }
+        assert!(!mmc5.irq_pending());
+        mmc5_scanline(&mut mmc5);
+        assert!(mmc5.irq_pending());
+
+        // Reading the status acknowledges.
+        assert_eq!(mmc5.cpu_read(0x5204), Some(0xc0));
+        assert!(!mmc5.irq_pending());
+
+        // No fetches for a few CPU cycles, rendering has stopped.
+        mmc5.tick(1);
+        mmc5.tick(3);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn test_mmc5_rendering_off_leaves_frame() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5_scanline(&mut mmc5);
+        mmc5.snoop_cpu_write(0x2001, 0x1e);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x40));
+        mmc5.snoop_cpu_write(0x2001, 0x00);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn test_mmc5_8x16_sprites_use_separate_chr_banks() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 8, 0));
+        mmc5.cpu_write(0x5101, 0);
+        mmc5.cpu_write(0x5127, 1);
+        mmc5.cpu_write(0x512b, 2);
+        mmc5.snoop_cpu_write(0x2000, 0x20);
+        mmc5_scanline(&mut mmc5);
+
+        let tiles = mmc5_scanline(&mut mmc5);
+        assert!(tiles.iter().all(|&(_, _, pattern)| pattern == 16), "backgrounds use $512B");
+        // The sprite fetches come after the first 64 pattern fetches.
+        for _ in 0 .. 32 {
+            mmc5.nametable_read(0x2000);
+            mmc5.nametable_read(0x23c0);
+            mmc5.ppu_read(0x0000);
+            mmc5.ppu_read(0x0008);
+        }
+        mmc5.nametable_read(0x2000);
+        assert_eq!(mmc5.ppu_read(0x1000), 8 + 4, "sprites use $5127");
+    }
+
+    #[test]
+    fn test_mmc5_extended_attributes() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 8, 0));
+        mmc5.cpu_write(0x5104, 1);
+        // The tile at $2000 gets 4KiB CHR bank 3 and palette 2, the ExRAM byte at the same offset.
+        mmc5.cpu_write(0x5c00, 0x83);
+        mmc5_scanline(&mut mmc5);
+
+        let tiles = mmc5_scanline(&mut mmc5);
+        assert_eq!(tiles[0], (None, Some(0xaa), 12));
+
+        mmc5.cpu_write(0x5104, 0);
+        let tiles = mmc5_scanline(&mut mmc5);
+        assert_eq!(tiles[0], (None, None, 0));
+    }
+
+    #[test]
+    fn test_mmc5_vertical_split() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 8, 0));
+        mmc5.cpu_write(0x5104, 0);
+        mmc5.cpu_write(0x5105, 0b10_10_10_10);
+        // The left four columns come from ExRAM, scrolled down 8 lines, with patterns from 4KiB bank 2.
+        mmc5.cpu_write(0x5200, 0x84);
+        mmc5.cpu_write(0x5201, 8);
+        mmc5.cpu_write(0x5202, 2);
+        for column in 0 .. 32 {
+            mmc5.nametable_write(0x2000 + 32 + column, 0x80 + column as u8);
+        }
+        mmc5.nametable_write(0x2000, 0x11);
+        mmc5.nametable_write(0x23c0, 0b1110_0100);
+        mmc5_scanline(&mut mmc5);
+
+        let tiles = mmc5_scanline(&mut mmc5);
+        // Tiles are fetched from column 2: 2 and 3 are split, 4 onwards are not.
+        assert_eq!(tiles[0], (Some(0x82), Some(0x55), 8));
+        assert_eq!(tiles[1], (Some(0x83), Some(0x55), 8));
+        assert_eq!(tiles[2], (Some(0x11), Some(0xe4), 0));
+        // The next line's first two tiles are prefetched at the end.
+        assert_eq!(tiles[32], (Some(0x80), Some(0x00), 8));
+    }
+
+    #[test]
+    fn test_mmc5_pcm() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5.cpu_write(0x5011, 0x80);
+        assert!(mmc5.audio_sample() > 0.0);
+
+        // In read mode the sample comes from reads of $8000-$BFFF, and a zero raises the IRQ.
+        mmc5.cpu_write(0x5010, 0x81);
+        mmc5.cpu_write(0x5114, 0x80);
+        mmc5.cpu_read(0x8000);
+        assert!(mmc5.irq_pending());
+        assert_eq!(mmc5.cpu_read(0x5010), Some(0x81));
+        assert!(!mmc5.irq_pending());
+    }
+
+    #[test]
+    fn test_mmc5_pulse_length_status() {
+        let mut mmc5 = Mmc5::new(rom(5, 8, 1, 0));
+        mmc5.cpu_write(0x5003, 0x08);
+        assert_eq!(mmc5.cpu_peek(0x5015), Some(0), "disabled channels do not load their length");
+
+        mmc5.cpu_write(0x5015, 0x03);
+        mmc5.cpu_write(0x5000, 0xbf);
+        mmc5.cpu_write(0x5002, 0x40);
+        mmc5.cpu_write(0x5003, 0x08);
+        mmc5.cpu_write(0x5007, 0x18);
+        assert_eq!(mmc5.cpu_peek(0x5015), Some(0x03));
+
+        // Length 254 and 2: the second channel is silent after two frame clocks.
+        mmc5.tick(7457 * 2);
+        assert_eq!(mmc5.cpu_peek(0x5015), Some(0x01));
+        mmc5.tick(100);
+        assert!(mmc5.audio_sample() > 0.0);
+    }
+
+    #[test]
+    fn test_vrc6_prg_banking() {
+        // 16 banks of 8KiB.
+        let mut vrc6 = Vrc6::new(rom(24, 8, 1, 0));
+        vrc6.cpu_write(0x8000, 3);
+        vrc6.cpu_write(0xc000, 9);
+        let banks = |vrc6 : &Vrc6| [0x8000, 0xa000, 0xc000, 0xe000].map(|addr| vrc6.cpu_peek(addr).unwrap());
+        assert_eq!(banks(&vrc6), [6, 7, 9, 15]);
+    }
+
+    #[test]
+    fn test_vrc6_chr_banking_and_mirroring() {
+        // 64 banks of 1KiB.
+        let mut vrc6 = Vrc6::new(rom(24, 8, 8, 0));
+        for (index, addr) in [0xd000, 0xd001, 0xd002, 0xd003, 0xe000, 0xe001, 0xe002, 0xe003].into_iter().enumerate() {
+            vrc6.cpu_write(addr, 10 + index as u8);
+        }
+        let banks = |vrc6 : &Vrc6| (0 .. 8).map(|slot| vrc6.ppu_peek(slot * 0x400)).collect::<Vec<_>>();
+        vrc6.cpu_write(0xb003, 0x20);
+        assert_eq!(banks(&vrc6), [10, 11, 12, 13, 14, 15, 16, 17]);
+        assert_eq!(vrc6.mirroring(), Mirroring::Vertical);
+
+        // 2KiB banks R0-R3, their low bit from PPU A10.
+        vrc6.cpu_write(0xb003, 0x05);
+        assert_eq!(banks(&vrc6), [10, 11, 10, 11, 12, 13, 12, 13]);
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+
+        vrc6.cpu_write(0xb003, 0x2e);
+        assert_eq!(banks(&vrc6), [10, 11, 12, 13, 14, 14, 15, 15]);
+        assert_eq!(vrc6.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_vrc6_mapper_26_swaps_address_lines() {
+        let mut vrc6 = Vrc6::new(rom(26, 8, 8, 0));
+        // $D001 on mapper 26 is R2.
+        vrc6.cpu_write(0xd001, 7);
+        assert_eq!(vrc6.ppu_peek(0x0800), 7);
+        // $B003 is the same either way, $B001 would be the sawtooth.
+        vrc6.cpu_write(0xb003, 0x84);
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_peek(0x6000), Some(0x42));
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_vrc6_prg_ram_enable() {
+        let mut vrc6 = Vrc6::new(rom(24, 8, 1, 0));
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_peek(0x6000), None);
+        vrc6.cpu_write(0xb003, 0x80);
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn test_vrc6_irq_cycle_mode() {
+        let mut vrc6 = Vrc6::new(rom(24, 8, 1, 0));
+        vrc6.cpu_write(0xf000, 0xfb);
+        vrc6.cpu_write(0xf001, 0b111);
+        vrc6.tick(4);
+        assert!(!vrc6.irq_pending());
+        vrc6.tick(1);
+        assert!(vrc6.irq_pending());
+
+        // Acknowledging copies the enable-after-acknowledge bit to enable, and the counter was reloaded.
+        vrc6.cpu_write(0xf002, 0);
+        assert!(!vrc6.irq_pending());
+        vrc6.tick(5);
+        assert!(vrc6.irq_pending());
+    }
+
+    #[test]
+    fn test_vrc6_irq_scanline_mode() {
+        let mut vrc6 = Vrc6::new(rom(24, 8, 1, 0));
+        vrc6.cpu_write(0xf000, 0xfe);
+        vrc6.cpu_write(0xf001, 0b010);
+        // Two scanlines of 341 dots, three to a CPU cycle: 114 cycles each, with the extra dots carried over.
+        vrc6.tick(114 * 2 - 1);
+        assert!(!vrc6.irq_pending());
+        vrc6.tick(1);
+        assert!(vrc6.irq_pending());
+
+        vrc6.cpu_write(0xf002, 0);
+        vrc6.tick(1000);
+        assert!(!vrc6.irq_pending(), "the IRQ stays disabled after an acknowledge without bit 0");
+    }
+
+    #[test]
+    fn test_vrc6_audio() {
+        let mut vrc6 = Vrc6::new(rom(24, 8, 1, 0));
+        assert_eq!(vrc6.audio_sample(), 0.0);
+
+        // Pulse 1 in digitized mode outputs its volume.
+        vrc6.cpu_write(0x9000, 0x8f);
+        vrc6.cpu_write(0x9002, 0x80);
+        let pulse = vrc6.audio_sample();
+        assert!(pulse > 0.0);
+
+        // The sawtooth climbs by its rate every other clock.
+        vrc6.cpu_write(0xb000, 0x20);
+        vrc6.cpu_write(0xb001, 0x00);
+        vrc6.cpu_write(0xb002, 0x80);
+        vrc6.tick(4);
+        assert!(vrc6.audio_sample() > pulse);
+
+        // $9003 bit 0 halts every channel.
+        vrc6.cpu_write(0x9003, 0x01);
+        let halted = vrc6.audio_sample();
+        vrc6.tick(100);
+        assert_eq!(vrc6.audio_sample(), halted);
+    }
+
+    fn fme7_command(fme7 : &mut Fme7, command : u8, parameter : u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xa000, parameter);
+    }
+
+    #[test]
+    fn test_fme7_prg_banking() {
+        // 16 banks of 8KiB.
+        let mut fme7 = Fme7::new(rom(69, 8, 1, 0));
+        for (command, bank) in [(0x8, 2), (0x9, 4), (0xa, 5), (0xb, 0x46)] {
+            fme7_command(&mut fme7, command, bank);
+        }
+        let banks = |fme7 : &Fme7| [0x6000, 0x8000, 0xa000, 0xc000, 0xe000].map(|addr| fme7.cpu_peek(addr).unwrap());
+        assert_eq!(banks(&fme7), [2, 4, 5, 6, 15]);
+    }
+
+    #[test]
+    fn test_fme7_prg_ram() {
+        let mut fme7 = Fme7::new(rom(69, 8, 1, 0));
+        // RAM selected but not enabled.
+        fme7_command(&mut fme7, 0x8, 0x40);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_peek(0x6000), None);
+
+        fme7_command(&mut fme7, 0x8, 0xc0);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(0x42));
+
+        // Back to ROM, the RAM keeps its contents.
+        fme7_command(&mut fme7, 0x8, 0x03);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(3));
+        fme7_command(&mut fme7, 0x8, 0xc0);
+        assert_eq!(fme7.cpu_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn test_fme7_chr_banking_and_mirroring() {
+        let mut fme7 = Fme7::new(rom(69, 8, 8, 0));
+        for slot in 0 .. 8 {
+            fme7_command(&mut fme7, slot, 40 + slot);
+        }
+        let banks = (0 .. 8).map(|slot| fme7.ppu_peek(slot * 0x400)).collect::<Vec<_>>();
+        assert_eq!(banks, [40, 41, 42, 43, 44, 45, 46, 47]);
+
+        for (value, mirroring) in [
+            (0, Mirroring::Vertical),
+            (1, Mirroring::Horizontal),
+            (2, Mirroring::SingleScreenLower),
+            (3, Mirroring::SingleScreenUpper),
+        ] {
+            fme7_command(&mut fme7, 0xc, value);
+            assert_eq!(fme7.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_fme7_irq_counts_cpu_cycles() {
+        let mut fme7 = Fme7::new(rom(69, 8, 1, 0));
+        fme7_command(&mut fme7, 0xe, 0x00);
+        fme7_command(&mut fme7, 0xf, 0x01);
+        fme7_command(&mut fme7, 0xd, 0x81);
+
+        // From $0100 the counter wraps on the 257th cycle.
+        fme7.tick(256);
+        assert!(!fme7.irq_pending());
+        fme7.tick(1);
+        assert!(fme7.irq_pending());
+
+        // Writing the control register acknowledges, the counter keeps going without raising it again.
+        fme7_command(&mut fme7, 0xd, 0x80);
+        assert!(!fme7.irq_pending());
+        fme7.tick(0x10000);
+        assert!(!fme7.irq_pending());
+    }
+
+    /// A .fds disk side holding one 4 byte file, $de $ad $be $ef.
+    fn fds_side() -> Vec<u8> {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0);
+        side.extend([2, 1]);
+        side.extend([3, 0, 0]);
+        side.extend(b"FILENAME");
+        side.extend([0x00, 0x60, 4, 0, 0]);
+        side.extend([4, 0xde, 0xad, 0xbe, 0xef]);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    fn fds(sides : usize) -> Fds {
+        let disk = FdsDisk::from_bytes(&fds_side().repeat(sides)).unwrap();
+        let mut bios = vec![0xea ; 0x2000];
+        bios[0x1ffc] = 0x24;
+        Fds::new(bios, disk).unwrap()
+    }
+
+    /// Runs the drive until it raises the disk IRQ and returns the byte it read.
+    fn fds_read_byte(fds : &mut Fds) -> u8 {
+        for _ in 0 .. 1_000_000 {
+            fds.tick(1);
+            if fds.irq_pending() {
+                return fds.cpu_read(0x4031).unwrap();
+            }
+        }
+        panic!("the drive never read a byte");
+    }
+
+    #[test]
+    fn test_fds_disk_image() {
+        let side = fds_side();
+        let mut with_header = vec![b'F', b'D', b'S', 0x1a, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        with_header.extend(side.repeat(2));
+
+        assert_eq!(FdsDisk::from_bytes(&with_header).unwrap().side_count(), 2);
+        assert_eq!(FdsDisk::from_bytes(&side).unwrap().side_count(), 1);
+        assert!(matches!(FdsDisk::from_bytes(&side[.. 1000]), Err(FdsError::NotFds)));
+        assert!(matches!(FdsDisk::from_bytes(&vec![0 ; SIDE_SIZE]), Err(FdsError::NotFds)));
+
+        // The blocks are laid out with gaps, start marks and CRCs.
+        let disk = FdsDisk::from_bytes(&side).unwrap();
+        let raw = disk.side(0);
+        let start = raw.iter().position(|&byte| byte != 0).unwrap();
+        assert_eq!(raw[start], 0x80);
+        assert_eq!(&raw[start + 1 .. start + 16], b"\x01*NINTENDO-HVC*");
+        assert!(raw.windows(5).any(|window| window == [4, 0xde, 0xad, 0xbe, 0xef]));
+    }
+
+    #[test]
+    fn test_fds_bios_and_ram() {
+        let mut fds = fds(1);
+
+        assert_eq!(fds.cpu_peek(0xe000), Some(0xea));
+        assert_eq!(fds.cpu_peek(0xfffc), Some(0x24));
+        fds.cpu_write(0xe000, 0);
+        assert_eq!(fds.cpu_peek(0xe000), Some(0xea));
+        fds.cpu_write(0x6000, 1);
+        fds.cpu_write(0xdfff, 2);
+        assert_eq!((fds.cpu_peek(0x6000), fds.cpu_peek(0xdfff)), (Some(1), Some(2)));
+        assert!(matches!(Fds::new(vec![0 ; 0x1000], fds.disk().clone()), Err(FdsError::BiosSize(0x1000))));
+
+        fds.ppu_write(0x1234, 0x56);
+        assert_eq!(fds.ppu_peek(0x1234), 0x56);
+        assert_eq!(fds.mirroring(), Mirroring::Vertical);
+        fds.cpu_write(0x4025, 0x2e);
+        assert_eq!(fds.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_fds_timer_irq() {
+        let mut fds = fds(1);
+        fds.cpu_write(0x4020, 10);
+        fds.cpu_write(0x4021, 0);
+        fds.cpu_write(0x4022, 0x02);
+
+        fds.tick(10);
+        assert!(!fds.irq_pending());
+        fds.tick(1);
+        assert!(fds.irq_pending());
+        assert_eq!(fds.cpu_read(0x4030).unwrap() & 0x01, 0x01);
+        assert!(!fds.irq_pending());
+
+        // Without repeat the timer stops after one IRQ.
+        fds.tick(100);
+        assert!(!fds.irq_pending());
+
+        fds.cpu_write(0x4022, 0x03);
+        fds.tick(11);
+        assert!(fds.irq_pending());
+        fds.cpu_read(0x4030);
+        fds.tick(11);
+        assert!(fds.irq_pending());
+
+        // Disabling the disk registers stops the timer and acknowledges it.
+        fds.cpu_write(0x4023, 0x00);
+        assert!(!fds.irq_pending());
+        fds.tick(100);
+        assert!(!fds.irq_pending());
+    }
+
+    #[test]
+    fn test_fds_reads_disk() {
+        let mut fds = fds(1);
+        assert_eq!(fds.cpu_peek(0x4032).unwrap() & 0b011, 0b010);
+
+        // Motor on, read mode, ready, disk IRQ.
+        fds.cpu_write(0x4025, 0xe5);
+        let header : Vec<u8> = (0 .. 15).map(|_| fds_read_byte(&mut fds)).collect();
+        assert_eq!(header, b"\x01*NINTENDO-HVC*");
+        assert_eq!(fds.cpu_peek(0x4032).unwrap() & 0b011, 0b000);
+        assert_eq!(fds.cpu_peek(0x4030).unwrap() & 0x40, 0);
+    }
+
+    #[test]
+    fn test_fds_writes_disk() {
+        let mut fds = fds(1);
+        // Motor on, write mode, ready, disk IRQ.
+        fds.cpu_write(0x4025, 0xe1);
+        for value in [0x11, 0x22, 0x33, 0x44, 0x55] {
+            fds.cpu_write(0x4024, value);
+            fds_read_byte(&mut fds);
+        }
+
+        // Each byte lands two bytes behind the head.
+        assert_eq!(&fds.disk().side(0)[.. 4], [0x33, 0x44, 0x55, 0x00]);
+    }
+
+    #[test]
+    fn test_fds_disk_switching() {
+        let mut fds = fds(2);
+
+        assert_eq!(fds.disk_side(), Some(0));
+        fds.eject_disk();
+        assert_eq!(fds.disk_side(), None);
+        assert_eq!(fds.cpu_peek(0x4032).unwrap() & 0b111, 0b111);
+        assert!(!fds.insert_disk(2));
+        assert!(fds.insert_disk(1));
+        assert_eq!(fds.disk_side(), Some(1));
+        assert_eq!(fds.cpu_peek(0x4032).unwrap() & 0b101, 0b000);
+    }
+
+    #[test]
+    fn test_fds_wave_channel() {
+        let mut fds = fds(1);
+        assert_eq!(fds.audio_sample(), 0.0);
+
+        // Write a square wave while the wavetable is writable.
+        fds.cpu_write(0x4089, 0x80);
+        for step in 0 .. 64 {
+            fds.cpu_write(0x4040 + step, if step < 32 { 63 } else { 0 });
+        }
+        fds.cpu_write(0x4089, 0x00);
+        assert_eq!(fds.cpu_peek(0x4040), Some(63));
+        assert_eq!(fds.cpu_peek(0x407f), Some(0));
+
+        // Full volume set directly, then start the wave.
+        fds.cpu_write(0x4080, 0x80 | 32);
+        assert_eq!(fds.cpu_peek(0x4090), Some(0x40 | 32));
+        fds.cpu_write(0x4082, 0x00);
+        fds.cpu_write(0x4083, 0x04);
+        fds.tick(1);
+        let high = fds.audio_sample();
+        assert!(high > 0.3 && high < 0.4, "{}", high);
+
+        // A frequency of $400 steps through the wave every 64 cycles, half of it is silent.
+        fds.tick(32 * 64);
+        assert_eq!(fds.audio_sample(), 0.0);
+        fds.tick(32 * 64);
+        assert_eq!(fds.audio_sample(), high);
+
+        // Halting the wave resets it to the first step.
+        fds.tick(32 * 64);
+        fds.cpu_write(0x4083, 0x84);
+        fds.tick(1);
+        assert_eq!(fds.audio_sample(), high);
+    }
+
+    #[test]
+    fn test_multicart_58_latches_address() {
+        let mut cart = Multicart58::new(rom(58, 8, 8, 0));
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(0), Some(2)));
+
+        // Horizontal mirroring, 16KiB mode, CHR bank 3 and PRG bank 5, whatever the value written.
+        cart.cpu_write(0x8000 | 0x80 | 0x40 | (3 << 3) | 5, 0xff);
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(10), Some(10)));
+        assert_eq!(cart.ppu_peek(0x0000), 24);
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+
+        // 32KiB mode pairs banks 4 and 5.
+        cart.cpu_write(0x8005, 0);
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(8), Some(10)));
+        assert_eq!(cart.mirroring(), Mirroring::Vertical);
+
+        cart.reset();
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(0), Some(2)));
+    }
+
+    #[test]
+    fn test_multicart_225_latches_address() {
+        // 2MiB of PRG ROM, 128KiB of CHR ROM.
+        let mut cart = Multicart225::new(rom(225, 128, 16, 0));
+
+        // High bit, 16KiB mode, PRG bank 3 (67 with the high bit) and CHR bank 5.
+        cart.cpu_write(0x8000 | 0x4000 | 0x1000 | (3 << 6) | 5, 0);
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(134), Some(134)));
+        assert_eq!(cart.ppu_peek(0x0000), 40);
+        assert_eq!(cart.mirroring(), Mirroring::Vertical);
+
+        cart.cpu_write(0x8000 | 0x2000 | (3 << 6), 0);
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(4), Some(6)));
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+
+        cart.reset();
+        assert_eq!((cart.cpu_peek(0x8000), cart.cpu_peek(0xc000)), (Some(0), Some(2)));
+    }
+
+    #[test]
+    fn test_multicart_225_nibble_ram_survives_reset() {
+        let mut cart = Multicart225::new(rom(225, 8, 1, 0));
+        cart.cpu_write(0x5800, 0xff);
+        cart.cpu_write(0x5803, 0x07);
+
+        assert_eq!(cart.cpu_peek(0x5800), Some(0x0f));
+        assert_eq!(cart.cpu_peek(0x5fff), Some(0x07));
+        cart.reset();
+        assert_eq!(cart.cpu_peek(0x5c00), Some(0x0f));
+    }
+
+    #[test]
+    fn test_nwc_prg_locked_until_initialised() {
+        // Two 128KiB chips.
+        let mut nwc = Nwc::new(rom(105, 16, 0, 0));
+        assert_eq!((nwc.cpu_peek(0x8000), nwc.cpu_peek(0xc000)), (Some(0), Some(2)));
+
+        mmc1_write(&mut nwc, 0xa000, 0b0_0010);
+        assert_eq!(nwc.cpu_peek(0x8000), Some(0));
+        mmc1_write(&mut nwc, 0xa000, 0b1_0000);
+        mmc1_write(&mut nwc, 0xa000, 0b0_0010);
+        // The second 32KiB of the first chip.
+        assert_eq!((nwc.cpu_peek(0x8000), nwc.cpu_peek(0xc000)), (Some(4), Some(6)));
+
+        // The second chip, banked like an MMC1 with the last bank fixed.
+        mmc1_write(&mut nwc, 0xa000, 0b0_1000);
+        mmc1_write(&mut nwc, 0xe000, 2);
+        assert_eq!((nwc.cpu_peek(0x8000), nwc.cpu_peek(0xc000)), (Some(20), Some(30)));
+
+        nwc.ppu_write(0x1fff, 0x42);
+        assert_eq!(nwc.ppu_peek(0x1fff), 0x42);
+    }
+
+    #[test]
+    fn test_nwc_timer() {
+        let mut nwc = Nwc::with_dip_switches(rom(105, 16, 0, 0), 0);
+        assert_eq!(nwc.timer_length(), 0x2000_0000);
+        nwc.set_dip_switches(4);
+        assert_eq!(nwc.timer_length(), 0x2800_0000);
+        assert_eq!(Nwc::new(rom(105, 16, 0, 0)).dip_switches(), 4);
+
+        // Held until the board is initialised.
+        nwc.tick(0x3000_0000);
+        assert!(!nwc.irq_pending());
+        mmc1_write(&mut nwc, 0xa000, 0b1_0000);
+        mmc1_write(&mut nwc, 0xa000, 0b0_0000);
+
+        nwc.tick(0x2800_0000 - 1 - nwc.timer());
+        assert!(!nwc.irq_pending());
+        nwc.tick(1);
+        assert!(nwc.irq_pending());
+
+        // Setting bit 4 stops and clears the timer and acknowledges the IRQ.
+        mmc1_write(&mut nwc, 0xa000, 0b1_0000);
+        assert!(!nwc.irq_pending());
+        assert_eq!(nwc.timer(), 0);
+        nwc.tick(0x3000_0000);
+        assert!(!nwc.irq_pending());
+    }
+
+    fn save_state(mapper : &impl Mapper) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        mapper.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    fn load_state(mapper : &mut impl Mapper, bytes : &[u8]) {
+        let mut state = StateReader::new(bytes, mapper.state_version());
+        mapper.load_state(&mut state).unwrap();
+        state.finish().unwrap();
+    }
+
+    #[test]
+    fn test_mmc1_state_keeps_the_shift_register() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        for bit in [1, 1] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        let state = save_state(&mmc1);
+
+        for bit in [0, 0, 0] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(6));
+
+        load_state(&mut mmc1, &state);
+        for bit in [1, 0, 0] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        assert_eq!(mmc1.cpu_peek(0x8000), Some(14));
+    }
+
+    #[test]
+    fn test_mmc1_rejects_a_full_shift_register() {
+        let mut mmc1 = Mmc1::new(rom(1, 8, 1, 0));
+        for bit in [1, 1] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        let mut state = save_state(&mmc1);
+        // The count is followed by the four registers and the last write, none without a cycle.
+        let count = state.len() - 6;
+        assert_eq!(state[count], 2);
+        state[count] = 200;
+
+        let mut reader = StateReader::new(&state, mmc1.state_version());
+        assert_eq!(mmc1.load_state(&mut reader), Err(StateError::BadValue));
+    }
+
+    #[test]
+    fn test_vrc6_state_restores_banks_ram_and_irq() {
+        let mut vrc6 = Vrc6::new(rom(24, 8, 1, 0));
+        vrc6.cpu_write(0x8000, 3);
+        vrc6.cpu_write(0xd000, 5);
+        vrc6.cpu_write(0xb003, 0x80);
+        vrc6.cpu_write(0x6000, 0x42);
+        vrc6.cpu_write(0xf000, 0xfb);
+        vrc6.cpu_write(0xf001, 0b111);
+        vrc6.tick(3);
+        let state = save_state(&vrc6);
+
+        vrc6.cpu_write(0x8000, 0);
+        vrc6.cpu_write(0xd000, 0);
+        vrc6.cpu_write(0x6000, 0);
+        vrc6.tick(10);
+        assert!(vrc6.irq_pending());
+
+        load_state(&mut vrc6, &state);
+        assert_eq!((vrc6.cpu_peek(0x8000), vrc6.ppu_peek(0x0000), vrc6.cpu_peek(0x6000)), (Some(6), 5, Some(0x42)));
+        assert!(!vrc6.irq_pending());
+        vrc6.tick(1);
+        assert!(!vrc6.irq_pending());
+        vrc6.tick(1);
+        assert!(vrc6.irq_pending());
+    }
+
+    #[test]
+    fn test_fds_state_includes_the_disk() {
+        let mut other = fds(1);
+        let mut fds = fds(2);
+        fds.insert_disk(1);
+        fds.cpu_write(0x6000, 0x11);
+        fds.ppu_write(0x0000, 0x22);
+        let state = save_state(&fds);
+
+        fds.eject_disk();
+        fds.cpu_write(0x6000, 0);
+        fds.ppu_write(0x0000, 0);
+        load_state(&mut fds, &state);
+        assert_eq!(fds.disk_side(), Some(1));
+        assert_eq!((fds.cpu_peek(0x6000), fds.ppu_peek(0x0000)), (Some(0x11), 0x22));
+
+        // A state only loads into a board built for the same sizes.
+        let mut reader = StateReader::new(&state, other.state_version());
+        assert!(other.load_state(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_fds_rejects_a_side_past_the_disk() {
+        let mut fds = fds(2);
+        fds.insert_disk(0);
+        let first = save_state(&fds);
+        fds.insert_disk(1);
+        let mut state = save_state(&fds);
+        // Only the side differs between the two states.
+        let side = (0 .. state.len()).find(|&index| state[index] != first[index]).unwrap();
+        assert_eq!(state[side], 1);
+        state[side] = 2;
+
+        let mut reader = StateReader::new(&state, fds.state_version());
+        assert_eq!(fds.load_state(&mut reader), Err(StateError::BadValue));
+    }
+}
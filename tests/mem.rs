@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod mem_tests {
+    use nes::bus::BusAccess;
+    use nes::cpu::Access;
+    use nes::mem::{FlatMemory, Mem, TestBus};
+
+    #[test]
+    fn test_flat_memory_reads_back_writes() {
+        let mut memory = FlatMemory::new();
+        memory.write(0x0000, 0x12);
+        memory.write(0xffff, 0x34);
+
+        assert_eq!(memory.read(0x0000), 0x12);
+        assert_eq!(memory.read(0xffff), 0x34);
+        assert_eq!(memory.peek(0xffff), 0x34);
+        assert_eq!(memory.bytes().len(), 0x10000);
+    }
+
+    #[test]
+    fn test_flat_memory_has_no_mirroring() {
+        let mut memory = FlatMemory::new();
+        memory.write(0x0001, 0xaa);
+
+        assert_eq!(memory.read(0x0801), 0x00);
+        assert_eq!(memory.read(0x2001), 0x00);
+    }
+
+    #[test]
+    fn test_test_bus_preloads_regions() {
+        let mut bus = TestBus::new();
+        bus.load(0x1234, &[0x01, 0x02]);
+        bus.load(0xffff, &[0x03, 0x04]);
+
+        assert_eq!(&bus.bytes()[0x1234 ..= 0x1235], &[0x01, 0x02]);
+        assert_eq!(bus.peek(0xffff), 0x03);
+        assert_eq!(bus.peek(0x0000), 0x04);
+        // Neither loading nor peeking is an access.
+        assert!(bus.accesses().is_empty());
+    }
+
+    #[test]
+    fn test_test_bus_records_accesses() {
+        let mut bus = TestBus::new();
+        bus.write(0x0010, 0x12);
+        bus.tick(2);
+        bus.read(0x0010);
+        bus.write(0x0010, 0x34);
+
+        assert_eq!(bus.accesses(), &[
+            BusAccess { cycle : 0, address : 0x0010, value : 0x12, access : Access::Write },
+            BusAccess { cycle : 2, address : 0x0010, value : 0x12, access : Access::Read },
+            BusAccess { cycle : 2, address : 0x0010, value : 0x34, access : Access::Write },
+        ]);
+        assert_eq!(bus.reads(0x0010), 1);
+        assert_eq!(bus.writes(0x0010), vec![0x12, 0x34]);
+        assert_eq!(bus.reads(0x0011), 0);
+
+        bus.clear_accesses();
+        assert!(bus.accesses().is_empty());
+        assert_eq!(bus.read(0x0010), 0x34);
+    }
+}
@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod nestest_tests {
+    use nes::cpu::CpuError;
+    use nes::nestest::{verify_nestest, NestestError};
+
+    /// Builds an NROM image with one 16KiB PRG bank holding `program` at $C000.
+    fn nrom(program : &[u8]) -> Vec<u8> {
+        let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0; 0x4000];
+        prg[.. program.len()].copy_from_slice(program);
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        rom
+    }
+
+    const LOG : &str = "\
+C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C003  86 10     STX $10 = 00                    A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+C005  4C 00 C0  JMP $C000                       A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14
+C000  A2 05     LDX #$05                        A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 51 CYC:17
+";
+
+    #[test]
+    fn test_matching_log() {
+        let rom = nrom(&[0xa2, 0x05, 0xe8, 0x86, 0x10, 0x4c, 0x00, 0xc0]);
+        assert_eq!(verify_nestest(&rom, LOG), Ok(5));
+    }
+
+    #[test]
+    fn test_first_divergence_is_reported() {
+        // INY instead of INX
+        let rom = nrom(&[0xa2, 0x05, 0xc8, 0x86, 0x10, 0x4c, 0x00, 0xc0]);
+        match verify_nestest(&rom, LOG) {
+            Err(NestestError::Mismatch { line, expected, actual, context }) => {
+                assert_eq!(line, 2);
+                assert!(expected.contains("INX"));
+                assert!(actual.contains("INY"));
+                assert_eq!(context, vec![LOG.lines().next().unwrap().to_string()]);
+            }
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cpu_error_is_reported() {
+        let rom = nrom(&[0xa2, 0x05, 0xab]);
+        assert_eq!(
+            verify_nestest(&rom, LOG),
+            Err(NestestError::Mismatch {
+                line : 2,
+                expected : LOG.lines().nth(1).unwrap().to_string(),
+                actual : "C002  AB        ???                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9"
+                    .to_string(),
+                context : vec![LOG.lines().next().unwrap().to_string()],
+            })
+        );
+
+        let log = "C000  AB        ???                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+        assert_eq!(
+            verify_nestest(&nrom(&[0xab]), log),
+            Err(NestestError::Cpu { line : 1, error : CpuError::UnknownOpcode { opcode : 0xab, address : 0xc000 } })
+        );
+    }
+
+    #[test]
+    fn test_invalid_rom() {
+        assert_eq!(verify_nestest(&[0; 16], LOG), Err(NestestError::InvalidRom));
+    }
+
+    /// Needs `nestest.nes` and `nestest.log` (from the nes-test-roms collection) in `tests/roms`, which are not
+    /// distributed with this crate. Run with `cargo test -- --ignored` once they are in place.
+    #[test]
+    #[ignore]
+    fn test_nestest_rom() {
+        let rom = std::fs::read("tests/roms/nestest.nes").expect("tests/roms/nestest.nes is missing");
+        let log = std::fs::read_to_string("tests/roms/nestest.log").expect("tests/roms/nestest.log is missing");
+
+        if let Err(error) = verify_nestest(&rom, &log) {
+            panic!("{}", error);
+        }
+    }
+}
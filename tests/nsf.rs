@@ -0,0 +1,119 @@
+#[cfg(test)]
+mod nsf_tests {
+    use nes::mapper::Mapper;
+    use nes::nsf::{Nsf, NsfBoard, NsfError, NsfPlayer};
+    use nes::region::Region;
+
+    /// An NSF with 3 tracks whose data, loaded at `load`, is `data`.
+    fn image(load : u16, init : u16, play : u16, bankswitch : [u8 ; 8], data : &[u8]) -> Vec<u8> {
+        let mut bytes = b"NESM\x1a\x01\x03\x02".to_vec();
+        for word in [load, init, play] {
+            bytes.extend(word.to_le_bytes());
+        }
+        for text in [&b"Title"[..], b"Artist", b"2024 Someone"] {
+            let mut field = text.to_vec();
+            field.resize(32, 0);
+            bytes.extend(field);
+        }
+        bytes.extend(16639u16.to_le_bytes());
+        bytes.extend(bankswitch);
+        bytes.extend(19997u16.to_le_bytes());
+        bytes.extend([0, 0, 0, 0, 0, 0]);
+        bytes.extend(data);
+        bytes
+    }
+
+    /// INIT at $8000 stores the track at $00 and the region at $02, PLAY at $8005 counts its calls at $01.
+    fn player_image() -> Vec<u8> {
+        image(0x8000, 0x8000, 0x8005, [0 ; 8], &[0x85, 0x00, 0x86, 0x02, 0x60, 0xe6, 0x01, 0x60])
+    }
+
+    #[test]
+    fn test_parses_header() {
+        let nsf = Nsf::from_bytes(&player_image()).unwrap();
+
+        assert_eq!((nsf.version, nsf.total_songs, nsf.starting_song), (1, 3, 1));
+        assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8000, 0x8005));
+        assert_eq!((nsf.title.as_str(), nsf.artist.as_str(), nsf.copyright.as_str()), ("Title", "Artist", "2024 Someone"));
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (16639, 19997));
+        assert_eq!(nsf.region(), Region::Ntsc);
+        assert!(!nsf.is_bankswitched());
+        assert_eq!(nsf.data.len(), 8);
+    }
+
+    #[test]
+    fn test_rejects_malformed_files() {
+        assert!(matches!(Nsf::from_bytes(b"NES\x1a"), Err(NsfError::NotNsf)));
+        assert!(matches!(Nsf::from_bytes(&player_image()[.. 0x80]), Err(NsfError::Truncated)));
+        let low = image(0x6000, 0x6000, 0x6000, [0 ; 8], &[0x60]);
+        match Nsf::from_bytes(&low) {
+            Err(error @ NsfError::BadLoadAddress(0x6000)) => {
+                assert_eq!(error.to_string(), "NSF load address 0x6000 is below $8000");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_board_loads_at_load_address() {
+        let nsf = Nsf::from_bytes(&image(0x8010, 0x8010, 0x8010, [0 ; 8], &[1, 2, 3])).unwrap();
+        let mut board = NsfBoard::new(&nsf);
+
+        assert_eq!(board.cpu_peek(0x800f), Some(0));
+        assert_eq!((board.cpu_peek(0x8010), board.cpu_peek(0x8012)), (Some(1), Some(3)));
+        board.cpu_write(0x6000, 0x42);
+        assert_eq!(board.cpu_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    fn test_board_bankswitching() {
+        // Three 4KiB banks filled with 0x10, 0x11 and 0x12, the data starting $200 into its first bank.
+        let mut data = vec![0x10 ; 0x1000 - 0x200];
+        data.extend(vec![0x11 ; 0x1000]);
+        data.extend(vec![0x12 ; 0x1000]);
+        let nsf = Nsf::from_bytes(&image(0x8200, 0x8200, 0x8200, [0, 2, 0, 0, 0, 0, 0, 1], &data)).unwrap();
+        let mut board = NsfBoard::new(&nsf);
+
+        assert!(nsf.is_bankswitched());
+        assert_eq!((board.cpu_peek(0x8000), board.cpu_peek(0x8200)), (Some(0), Some(0x10)));
+        assert_eq!((board.cpu_peek(0x9000), board.cpu_peek(0xf000)), (Some(0x12), Some(0x11)));
+        board.cpu_write(0x5ff9, 1);
+        assert_eq!(board.cpu_peek(0x9fff), Some(0x11));
+    }
+
+    #[test]
+    fn test_player_runs_init_and_play() {
+        let mut player = NsfPlayer::new(Nsf::from_bytes(&player_image()).unwrap()).unwrap();
+
+        assert_eq!(player.track(), 1);
+        assert_eq!(player.cpu().peek(0x0000), 1);
+        for _ in 0 .. 3 {
+            player.play_frame().unwrap();
+        }
+        assert_eq!(player.cpu().peek(0x0001), 3);
+        // Three PLAY periods of 16639us at the NTSC CPU clock, after INIT.
+        let cycles = player.cpu().cycles;
+        assert!((3 * 29780 .. 3 * 29780 + 100).contains(&cycles), "{}", cycles);
+        assert!((player.play_rate_hz() - 60.1).abs() < 0.1);
+
+        player.select_track(2).unwrap();
+        assert_eq!((player.cpu().peek(0x0000), player.cpu().peek(0x0001)), (2, 0));
+        assert!(matches!(player.select_track(3), Err(NsfError::NoSuchTrack(3))));
+    }
+
+    #[test]
+    fn test_player_region() {
+        let nsf = Nsf::from_bytes(&player_image()).unwrap();
+        let player = NsfPlayer::with_region(nsf, Region::Pal).unwrap();
+
+        assert_eq!(player.cpu().peek(0x0002), 1);
+        assert!((player.play_rate_hz() - 50.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_player_gives_up_on_routines_that_never_return() {
+        let nsf = Nsf::from_bytes(&image(0x8000, 0x8000, 0x8000, [0 ; 8], &[0x4c, 0x00, 0x80])).unwrap();
+
+        assert!(matches!(NsfPlayer::new(nsf), Err(NsfError::RoutineDidNotReturn(0x8000))));
+    }
+}
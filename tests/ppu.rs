@@ -0,0 +1,943 @@
+#[cfg(test)]
+mod ppu_tests {
+    use nes::asm::assemble_at;
+    use nes::bus::Bus;
+    use nes::cartridge::{Cartridge, Mirroring, Rom};
+    use nes::cpu::CPU;
+    use nes::mapper::{Mapper, Mmc3, Nrom, StateError, StateReader, StateWriter};
+    use nes::mem::Mem;
+    use nes::ppu::{
+        Ppu, LATCH_DECAY, OAMADDR, OAMDATA, PPUADDR, PPUCTRL, PPUDATA, PPUMASK, PPUSCROLL, PPUSTATUS,
+        STATUS_SPRITE_OVERFLOW, STATUS_SPRITE_ZERO_HIT, STATUS_VBLANK, WIDTH,
+    };
+    use nes::region::Region;
+
+    /// An NROM ROM whose CHR ROM holds its own address, low byte, and has `flags6` for mirroring.
+    fn rom(flags6 : u8) -> Rom {
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, 1, 1, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend([0 ; 0x4000]);
+        bytes.extend((0 .. 0x2000).map(|addr| addr as u8));
+        Rom::from_bytes(&bytes).unwrap()
+    }
+
+    fn set_vram_addr(ppu : &mut Ppu, addr : u16) {
+        ppu.write_register(PPUADDR, (addr >> 8) as u8, None);
+        ppu.write_register(PPUADDR, addr as u8, None);
+    }
+
+    #[test]
+    fn test_scroll_and_addr_share_the_write_toggle() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(PPUSCROLL, 0x12, None);
+        assert!(ppu.write_toggle());
+        // The second write of the pair goes to PPUADDR's low byte, over the coarse X of the first.
+        ppu.write_register(PPUADDR, 0x34, None);
+        assert_eq!((ppu.temp_vram_addr(), ppu.fine_x(), ppu.vram_addr()), (0x0034, 0x02, 0x0034));
+        assert!(!ppu.write_toggle());
+
+        // Reading PPUSTATUS starts a new pair.
+        ppu.write_register(PPUSCROLL, 0x56, None);
+        ppu.read_register(PPUSTATUS, None);
+        ppu.write_register(PPUSCROLL, 0x78, None);
+        ppu.write_register(PPUSCROLL, 0x9a, None);
+        // Fine Y 2, coarse Y 19, coarse X 15.
+        assert_eq!((ppu.temp_vram_addr(), ppu.fine_x()), (0x226f, 0x00));
+    }
+
+    #[test]
+    fn test_vram_addr_is_14_bits() {
+        let mut ppu = Ppu::new();
+        set_vram_addr(&mut ppu, 0xff20);
+        assert_eq!(ppu.vram_addr(), 0x3f20);
+
+        ppu.write_register(PPUCTRL, 0x04, None);
+        set_vram_addr(&mut ppu, 0x3ff0);
+        ppu.write_register(PPUDATA, 0, None);
+        assert_eq!(ppu.vram_addr(), 0x0010);
+    }
+
+    #[test]
+    fn test_ppudata_reads_are_buffered() {
+        let mut nrom = Nrom::new(rom(0));
+        let mut ppu = Ppu::new();
+        set_vram_addr(&mut ppu, 0x0123);
+
+        // The first read returns the stale buffer, each read after it the byte before.
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut nrom)), 0x00);
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut nrom)), 0x23);
+        assert_eq!(ppu.peek_register(PPUDATA, Some(&nrom)), 0x24);
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut nrom)), 0x24);
+        assert_eq!(ppu.vram_addr(), 0x0126);
+
+        // PPUCTRL bit 2 steps a row of 32 tiles.
+        ppu.write_register(PPUCTRL, 0x04, None);
+        ppu.read_register(PPUDATA, Some(&mut nrom));
+        assert_eq!(ppu.vram_addr(), 0x0146);
+    }
+
+    #[test]
+    fn test_oam_data() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(OAMADDR, 0xff, None);
+        for value in [0x11, 0x22, 0x33, 0xff] {
+            ppu.write_register(OAMDATA, value, None);
+        }
+        // Writes step OAMADDR, wrapping around.
+        assert_eq!(ppu.oam_addr(), 0x03);
+        assert_eq!((ppu.oam()[0xff], ppu.oam()[0x00], ppu.oam()[0x01]), (0x11, 0x22, 0x33));
+        assert_eq!(ppu.read_register(OAMDATA, None), 0x00);
+
+        // Reads do not, and the three missing bits of a sprite's attributes read as 0.
+        ppu.write_register(OAMADDR, 0x02, None);
+        assert_eq!(ppu.read_register(OAMDATA, None), 0xe3);
+        assert_eq!(ppu.read_register(OAMDATA, None), 0xe3);
+        assert_eq!(ppu.oam()[0x02], 0xff);
+    }
+
+    #[test]
+    fn test_status_low_bits_are_the_data_latch() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(PPUCTRL, 0xff, None);
+        assert_eq!(ppu.read_register(PPUSTATUS, None), 0x1f);
+        // Write-only registers read back the latch too.
+        assert_eq!(ppu.read_register(PPUSCROLL, None), 0x1f);
+    }
+
+    #[test]
+    fn test_data_latch_decays_bit_by_bit() {
+        let mut ppu = Ppu::new();
+        set_vram_addr(&mut ppu, 0x3f00);
+        ppu.write_register(PPUDATA, 0x15, None);
+        set_vram_addr(&mut ppu, 0x3f00);
+        ppu.write_register(PPUMASK, 0xc0, None);
+        ppu.tick(LATCH_DECAY - 1, None);
+        // A palette read only drives the low six bits, the top two are the latch's and keep fading.
+        assert_eq!(ppu.read_register(PPUDATA, None), 0xd5);
+        assert_eq!(ppu.latch(), 0xd5);
+        ppu.tick(1, None);
+        assert_eq!(ppu.read_register(PPUSCROLL, None), 0x15);
+        ppu.tick(LATCH_DECAY, None);
+        assert_eq!((ppu.latch(), ppu.peek_register(PPUCTRL, None)), (0x00, 0x00));
+    }
+
+    #[test]
+    fn test_ppu_registers_through_the_bus() {
+        let mut bus = Bus::new();
+        // CHR RAM: an NROM ROM without CHR ROM.
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend([0 ; 0x4000]);
+        bus.insert_cartridge(Cartridge::new(Rom::from_bytes(&bytes).unwrap()).unwrap());
+
+        bus.write(0x2006, 0x10);
+        bus.write(0x2006, 0x00);
+        bus.write(0x2007, 0xab);
+        bus.write(0x2007, 0xcd);
+        assert_eq!(bus.cartridge().unwrap().mapper().ppu_peek(0x1001), 0xcd);
+
+        bus.write(0x3ffe, 0x10);
+        bus.write(0x3ffe, 0x00);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x200f), 0xab);
+        assert_eq!(bus.peek(0x2007), 0xcd);
+    }
+
+    fn write_vram(ppu : &mut Ppu, mapper : &mut dyn Mapper, addr : u16, value : u8) {
+        set_vram_addr(ppu, addr);
+        ppu.write_register(PPUDATA, value, Some(mapper));
+    }
+
+    /// The byte at offset 5 of each of the four nametable slots.
+    fn slots(ppu : &Ppu, mapper : &dyn Mapper) -> [u8 ; 4] {
+        [0x2005, 0x2405, 0x2805, 0x2c05].map(|addr| ppu.peek_memory(addr, Some(mapper)))
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        let mut ppu = Ppu::new();
+        let mut vertical = Nrom::new(rom(0x01));
+        write_vram(&mut ppu, &mut vertical, 0x2005, 0x11);
+        write_vram(&mut ppu, &mut vertical, 0x2c05, 0x22);
+        assert_eq!(slots(&ppu, &vertical), [0x11, 0x22, 0x11, 0x22]);
+        assert_eq!((ppu.vram()[0x005], ppu.vram()[0x405]), (0x11, 0x22));
+
+        let horizontal = Nrom::new(rom(0x00));
+        assert_eq!(slots(&ppu, &horizontal), [0x11, 0x11, 0x22, 0x22]);
+
+        // $3000-$3EFF mirrors the nametables, and reads through PPUDATA see them.
+        let mut horizontal = horizontal;
+        set_vram_addr(&mut ppu, 0x3805);
+        ppu.read_register(PPUDATA, Some(&mut horizontal));
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut horizontal)), 0x22);
+    }
+
+    #[test]
+    fn test_four_screen_nametables() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0x08));
+        assert_eq!(nrom.mirroring(), Mirroring::FourScreen);
+        for (i, addr) in [0x2005, 0x2405, 0x2805, 0x2c05].into_iter().enumerate() {
+            write_vram(&mut ppu, &mut nrom, addr, i as u8 + 1);
+        }
+        assert_eq!(slots(&ppu, &nrom), [1, 2, 3, 4]);
+        assert_eq!(ppu.vram()[0xc05], 4);
+    }
+
+    /// A board with 1KiB of its own that it puts in the last nametable slot, as the MMC5 does with ExRAM.
+    struct ExtraNametable {
+        nrom : Nrom,
+        ram : [u8 ; 0x400],
+    }
+
+    impl Mapper for ExtraNametable {
+        fn cpu_peek(&self, addr : u16) -> Option<u8> {
+            self.nrom.cpu_peek(addr)
+        }
+
+        fn cpu_write(&mut self, _addr : u16, _value : u8) {}
+
+        fn ppu_peek(&self, addr : u16) -> u8 {
+            self.nrom.ppu_peek(addr)
+        }
+
+        fn ppu_write(&mut self, _addr : u16, _value : u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Vertical
+        }
+
+        fn nametable_peek(&self, addr : u16) -> Option<u8> {
+            (addr & 0x0c00 == 0x0c00).then(|| self.ram[addr as usize & 0x3ff])
+        }
+
+        fn nametable_write(&mut self, addr : u16, value : u8) -> bool {
+            if addr & 0x0c00 == 0x0c00 {
+                self.ram[addr as usize & 0x3ff] = value;
+            }
+            addr & 0x0c00 == 0x0c00
+        }
+
+        fn save_state(&self, _state : &mut StateWriter) {}
+
+        fn load_state(&mut self, _state : &mut StateReader) -> Result<(), StateError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_board_supplied_nametable() {
+        let mut ppu = Ppu::new();
+        let mut board = ExtraNametable { nrom : Nrom::new(rom(0)), ram : [0 ; 0x400] };
+        write_vram(&mut ppu, &mut board, 0x2405, 0x11);
+        write_vram(&mut ppu, &mut board, 0x2c05, 0x22);
+
+        assert_eq!(slots(&ppu, &board), [0x00, 0x11, 0x00, 0x22]);
+        assert_eq!(board.ram[5], 0x22);
+        // The console's VRAM behind the slot is untouched.
+        assert_eq!(ppu.vram()[0x405], 0x11);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0));
+        for (addr, value) in [(0x3f10, 0x01), (0x3f14, 0x02), (0x3f18, 0x03), (0x3f1c, 0x04), (0x3f01, 0x05)] {
+            write_vram(&mut ppu, &mut nrom, addr, value);
+        }
+        write_vram(&mut ppu, &mut nrom, 0x3f31, 0x06);
+
+        // The sprite palettes' backdrop entries are the background palettes'.
+        let peek = |ppu : &Ppu, addr| ppu.peek_memory(addr, Some(&nrom));
+        assert_eq!([0x3f00, 0x3f04, 0x3f08, 0x3f0c].map(|addr| peek(&ppu, addr)), [1, 2, 3, 4]);
+        assert_eq!(&ppu.palette()[.. 0x0d], &[1, 5, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4]);
+        // The others are not, and the 32 entries repeat up to $3FFF.
+        assert_eq!((peek(&ppu, 0x3f01), peek(&ppu, 0x3f11)), (0x05, 0x06));
+        assert_eq!((peek(&ppu, 0x3fe0), peek(&ppu, 0x3ff1)), (0x01, 0x06));
+    }
+
+    #[test]
+    fn test_palette_reads_through_ppudata() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0));
+        write_vram(&mut ppu, &mut nrom, 0x2f05, 0x77);
+        write_vram(&mut ppu, &mut nrom, 0x3f05, 0xff);
+        assert_eq!(ppu.palette()[5], 0x3f);
+
+        // Palette reads are not delayed, the top two bits are the data latch, and the buffer gets the nametable byte
+        // underneath.
+        set_vram_addr(&mut ppu, 0x3f05);
+        ppu.write_register(PPUCTRL, 0x80, None);
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut nrom)), 0xbf);
+        set_vram_addr(&mut ppu, 0x0000);
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut nrom)), 0x77);
+
+        // Greyscale drops the hue.
+        ppu.write_register(PPUMASK, 0x01, None);
+        set_vram_addr(&mut ppu, 0x3f05);
+        assert_eq!(ppu.read_register(PPUDATA, Some(&mut nrom)) & 0x3f, 0x30);
+    }
+
+    /// An NROM board with CHR RAM, `flags6` for mirroring, and the palette: backdrop $0F, background palette 2 of
+    /// $11, $12 and $13.
+    fn chr_ram_board(ppu : &mut Ppu, flags6 : u8) -> Nrom {
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, 1, 0, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend([0 ; 0x4000]);
+        let mut nrom = Nrom::new(Rom::from_bytes(&bytes).unwrap());
+        for (addr, value) in [(0x3f00, 0x0f), (0x3f09, 0x11), (0x3f0a, 0x12), (0x3f0b, 0x13)] {
+            write_vram(ppu, &mut nrom, addr, value);
+        }
+        nrom
+    }
+
+    /// Fills the 8 rows of the tile at `addr` with the planes `low` and `high`.
+    fn write_tile(ppu : &mut Ppu, mapper : &mut dyn Mapper, addr : u16, low : u8, high : u8) {
+        for row in 0 .. 8 {
+            write_vram(ppu, mapper, addr + row, low);
+            write_vram(ppu, mapper, addr + row + 8, high);
+        }
+    }
+
+    /// Runs the PPU through the next pre-render scanline, which sets v up from t, and the frame after it, to the
+    /// start of vertical blank.
+    fn run_frame(ppu : &mut Ppu, mapper : &mut dyn Mapper) {
+        while ppu.scanline() != 261 {
+            ppu.tick(1, Some(mapper));
+        }
+        while ppu.scanline() != 240 {
+            ppu.tick(1, Some(mapper));
+        }
+    }
+
+    fn pixels(ppu : &Ppu, x : usize, y : usize) -> &[u8] {
+        &ppu.frame()[y * WIDTH + x .. y * WIDTH + x + 8]
+    }
+
+    #[test]
+    fn test_background_rendering() {
+        let mut ppu = Ppu::new();
+        let mut nrom = chr_ram_board(&mut ppu, 0);
+        // Tile 1 in both pattern tables: pixels 3, 3, 1, 1, 2, 2, 0, 0 at $0000, all 1 at $1000.
+        write_tile(&mut ppu, &mut nrom, 0x0010, 0xf0, 0xcc);
+        write_tile(&mut ppu, &mut nrom, 0x1010, 0xff, 0x00);
+        // At column 2, row 1, in the top right quarter of the attribute byte's 4x4 tiles, given palette 2.
+        write_vram(&mut ppu, &mut nrom, 0x2022, 0x01);
+        write_vram(&mut ppu, &mut nrom, 0x23c0, 0b0000_1000);
+        set_vram_addr(&mut ppu, 0x0000);
+
+        ppu.write_register(PPUMASK, 0x0a, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(ppu.frame_count(), 1);
+        assert_eq!(pixels(&ppu, 16, 8), &[0x13, 0x13, 0x11, 0x11, 0x12, 0x12, 0x0f, 0x0f]);
+        assert_eq!(pixels(&ppu, 16, 15), pixels(&ppu, 16, 8));
+        assert_eq!((pixels(&ppu, 8, 8), pixels(&ppu, 16, 16)), (&[0x0f ; 8][..], &[0x0f ; 8][..]));
+
+        // PPUCTRL bit 4 picks the pattern table at $1000.
+        ppu.write_register(PPUCTRL, 0x10, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(pixels(&ppu, 16, 8), &[0x11 ; 8]);
+
+        // Greyscale drops the hue.
+        ppu.write_register(PPUMASK, 0x0b, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(pixels(&ppu, 16, 8), &[0x10 ; 8]);
+
+        // With the background hidden the screen is the backdrop.
+        ppu.write_register(PPUMASK, 0x00, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert!(ppu.frame().iter().all(|&colour| colour == 0x0f));
+    }
+
+    #[test]
+    fn test_background_scrolling() {
+        let mut ppu = Ppu::new();
+        let mut nrom = chr_ram_board(&mut ppu, 0x01);
+        write_tile(&mut ppu, &mut nrom, 0x0010, 0xff, 0xff);
+        // Palette 0 colour 3.
+        write_vram(&mut ppu, &mut nrom, 0x3f03, 0x16);
+        // The top left tile of the first two nametables.
+        write_vram(&mut ppu, &mut nrom, 0x2000, 0x01);
+        write_vram(&mut ppu, &mut nrom, 0x2400, 0x01);
+        set_vram_addr(&mut ppu, 0x0000);
+
+        // The leftmost 8 pixels are clipped unless PPUMASK bit 1 shows them.
+        ppu.write_register(PPUMASK, 0x08, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(pixels(&ppu, 0, 0), &[0x0f ; 8]);
+        ppu.write_register(PPUMASK, 0x0a, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(pixels(&ppu, 0, 0), &[0x16 ; 8]);
+
+        // Scrolled 4 pixels left and 2 up, the tile of the second nametable comes in from the right edge.
+        ppu.write_register(PPUSCROLL, 4, None);
+        ppu.write_register(PPUSCROLL, 2, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(pixels(&ppu, 0, 0), &[0x16, 0x16, 0x16, 0x16, 0x0f, 0x0f, 0x0f, 0x0f]);
+        assert_eq!(pixels(&ppu, 0, 5), &[0x16, 0x16, 0x16, 0x16, 0x0f, 0x0f, 0x0f, 0x0f]);
+        assert_eq!(pixels(&ppu, 0, 6), &[0x0f ; 8]);
+        assert_eq!(pixels(&ppu, 248, 0), &[0x0f, 0x0f, 0x0f, 0x0f, 0x16, 0x16, 0x16, 0x16]);
+
+        // PPUCTRL's nametable select puts the second nametable at the top left.
+        write_vram(&mut ppu, &mut nrom, 0x2400, 0x00);
+        ppu.write_register(PPUCTRL, 0x01, None);
+        ppu.write_register(PPUSCROLL, 0, None);
+        ppu.write_register(PPUSCROLL, 0, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(pixels(&ppu, 0, 0), &[0x0f ; 8]);
+    }
+
+    #[test]
+    fn test_frame_timing() {
+        let mut ppu = Ppu::new();
+        ppu.tick(341 + 5, None);
+        assert_eq!((ppu.scanline(), ppu.dot()), (1, 5));
+
+        // Odd NTSC frames are a dot shorter while rendering.
+        ppu.tick(341 * 262 - 346, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (1, 0, 0));
+        ppu.write_register(PPUMASK, 0x08, None);
+        ppu.tick(341 * 262 - 1, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (2, 0, 0));
+        // Not with rendering off.
+        ppu.tick(341 * 262, None);
+        ppu.write_register(PPUMASK, 0x00, None);
+        ppu.tick(341 * 262 - 1, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (3, 261, 340));
+
+        // PAL frames are 312 scanlines, without the skipped dot.
+        let mut ppu = Ppu::new();
+        ppu.set_region(Region::Pal);
+        ppu.write_register(PPUMASK, 0x08, None);
+        ppu.tick(341 * 312 * 2, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (2, 0, 0));
+
+        // Long runs with rendering off keep the count.
+        ppu.write_register(PPUMASK, 0x00, None);
+        ppu.tick(341 * 312 * 1000 + 7, None);
+        assert_eq!((ppu.frame_count(), ppu.scanline(), ppu.dot()), (1002, 0, 7));
+    }
+
+    #[test]
+    fn test_bus_clocks_the_ppu() {
+        let mut bus = Bus::new();
+        bus.tick(114);
+        assert_eq!((bus.ppu().scanline(), bus.ppu().dot()), (1, 1));
+
+        bus.set_region(Region::Pal);
+        assert_eq!(bus.ppu().region(), Region::Pal);
+        bus.tick(5);
+        assert_eq!(bus.ppu().dot(), 17);
+    }
+
+    /// Writes sprite `index` to OAM: Y, tile, attributes and X.
+    fn write_sprite(ppu : &mut Ppu, index : u8, sprite : [u8 ; 4]) {
+        ppu.write_register(OAMADDR, index * 4, None);
+        for value in sprite {
+            ppu.write_register(OAMDATA, value, None);
+        }
+    }
+
+    /// The board of [`chr_ram_board`] with sprite palettes 0 and 1 and three tiles: 1 of colour 1 and 3 of colour
+    /// 3 all over, and 2 a diagonal line of colour 1 from the top left. OAM is cleared to $FF, below the screen.
+    fn sprite_board(ppu : &mut Ppu) -> Nrom {
+        let mut nrom = chr_ram_board(ppu, 0);
+        for index in 0 .. 64 {
+            write_sprite(ppu, index, [0xff ; 4]);
+        }
+        for (addr, value) in [(0x3f01, 0x01), (0x3f13, 0x30), (0x3f15, 0x21), (0x3f17, 0x23)] {
+            write_vram(ppu, &mut nrom, addr, value);
+        }
+        write_tile(ppu, &mut nrom, 0x0010, 0xff, 0x00);
+        write_tile(ppu, &mut nrom, 0x0030, 0xff, 0xff);
+        for row in 0 .. 8 {
+            write_vram(ppu, &mut nrom, 0x0020 + row, 0x80 >> row);
+        }
+        set_vram_addr(ppu, 0x0000);
+        nrom
+    }
+
+    /// The pixels of scanline `y` that are not the backdrop, with their x.
+    fn sprite_pixels(ppu : &Ppu, y : usize) -> Vec<(usize, u8)> {
+        let row = &ppu.frame()[y * WIDTH .. (y + 1) * WIDTH];
+        row.iter().enumerate().filter(|&(_, &colour)| colour != 0x0f).map(|(x, &colour)| (x, colour)).collect()
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        // Shown from the scanline after their Y: as they are, flipped horizontally, and flipped vertically.
+        write_sprite(&mut ppu, 0, [9, 2, 0x01, 20]);
+        write_sprite(&mut ppu, 1, [29, 2, 0x41, 20]);
+        write_sprite(&mut ppu, 2, [49, 2, 0x81, 20]);
+        // Sprites are 8 pixels high, and none are drawn on the first scanline.
+        write_sprite(&mut ppu, 3, [0xff, 3, 0x00, 60]);
+
+        ppu.write_register(PPUMASK, 0x14, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(sprite_pixels(&ppu, 9), vec![]);
+        assert_eq!(sprite_pixels(&ppu, 10), vec![(20, 0x21)]);
+        assert_eq!(sprite_pixels(&ppu, 13), vec![(23, 0x21)]);
+        assert_eq!(sprite_pixels(&ppu, 18), vec![]);
+        assert_eq!((sprite_pixels(&ppu, 30), sprite_pixels(&ppu, 33)), (vec![(27, 0x21)], vec![(24, 0x21)]));
+        assert_eq!((sprite_pixels(&ppu, 50), sprite_pixels(&ppu, 57)), (vec![(27, 0x21)], vec![(20, 0x21)]));
+        assert_eq!(sprite_pixels(&ppu, 0), vec![]);
+
+        // PPUCTRL bit 3 picks the pattern table at $1000, which is empty.
+        ppu.write_register(PPUCTRL, 0x08, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(sprite_pixels(&ppu, 10), vec![]);
+
+        // Hidden sprites.
+        ppu.write_register(PPUCTRL, 0x00, None);
+        ppu.write_register(PPUMASK, 0x08, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(sprite_pixels(&ppu, 10), vec![]);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut prg_rom = assemble_at("LDA #$02\nSTA $4014\nBRK", 0xc000).unwrap();
+        prg_rom.resize(0x4000, 0);
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0xc0;
+        let mut bytes = vec![b'N', b'E', b'S', 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend(prg_rom);
+        let mut bus = Bus::new();
+        bus.insert_cartridge(Cartridge::new(Rom::from_bytes(&bytes).unwrap()).unwrap());
+        // Tile 1 of colour 1 all over, in sprite palette 0.
+        let vram = [(0x3f00, 0x0f), (0x3f11, 0x21)].into_iter().chain((0x0010 .. 0x0018).map(|addr| (addr, 0xff)));
+        for (addr, value) in vram {
+            bus.write(0x2006, (addr >> 8) as u8);
+            bus.write(0x2006, addr as u8);
+            bus.write(0x2007, value);
+        }
+        // Page 2 holds sprite 0 at (20, 9), the rest below the screen.
+        for addr in 0x0200 .. 0x0300 {
+            bus.write(addr, 0xff);
+        }
+        for (addr, value) in (0x0200 ..).zip([9, 1, 0x00, 20]) {
+            bus.write(addr, value);
+        }
+
+        let mut cpu = CPU::with_memory(bus);
+        cpu.power_on();
+        cpu.run().unwrap();
+        assert_eq!(cpu.memory().ppu().oam()[.. 5], [9, 1, 0x00, 20, 0xff]);
+
+        cpu.memory_mut().write(0x2001, 0x14);
+        cpu.memory_mut().tick(2 * 29781);
+        assert_eq!(sprite_pixels(cpu.memory().ppu(), 10), (20 .. 28).map(|x| (x, 0x21)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        // Background at x 24-31 on scanlines 8-15.
+        write_vram(&mut ppu, &mut nrom, 0x2023, 0x01);
+        set_vram_addr(&mut ppu, 0x0000);
+        // Sprite 0 behind the background, sprite 1 in front of it but behind sprite 0.
+        write_sprite(&mut ppu, 0, [7, 3, 0x20, 20]);
+        write_sprite(&mut ppu, 1, [7, 3, 0x01, 24]);
+
+        ppu.write_register(PPUMASK, 0x1e, None);
+        run_frame(&mut ppu, &mut nrom);
+        let expected = [[0x30 ; 4], [0x01 ; 4], [0x23 ; 4], [0x0f ; 4]].concat();
+        assert_eq!(&ppu.frame()[8 * WIDTH + 20 .. 8 * WIDTH + 36], &expected[..]);
+    }
+
+    #[test]
+    fn test_eight_sprites_per_scanline() {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        for index in 0 .. 9 {
+            write_sprite(&mut ppu, index, [19, 2, 0x01, index * 16]);
+        }
+
+        ppu.write_register(PPUMASK, 0x14, None);
+        run_frame(&mut ppu, &mut nrom);
+        let drawn = (0 .. 8).map(|index| (index * 16, 0x21)).collect::<Vec<_>>();
+        assert_eq!(sprite_pixels(&ppu, 20), drawn);
+
+        // The left column is clipped unless PPUMASK bit 2 shows it.
+        ppu.write_register(PPUMASK, 0x10, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(sprite_pixels(&ppu, 20), drawn[1 ..]);
+    }
+
+    #[test]
+    fn test_tall_sprites() {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        write_tile(&mut ppu, &mut nrom, 0x1020, 0xff, 0x00);
+        set_vram_addr(&mut ppu, 0x0000);
+        // Tiles 2 and 3 from $0000, flipped vertically, and tiles 2 and 3 from $1000.
+        write_sprite(&mut ppu, 0, [9, 2, 0x01, 20]);
+        write_sprite(&mut ppu, 1, [39, 2, 0x81, 20]);
+        write_sprite(&mut ppu, 2, [69, 3, 0x01, 20]);
+
+        // PPUCTRL bit 3 no longer picks the pattern table.
+        ppu.write_register(PPUCTRL, 0x28, None);
+        ppu.write_register(PPUMASK, 0x14, None);
+        run_frame(&mut ppu, &mut nrom);
+        let solid = |colour| (20 .. 28).map(|x| (x, colour)).collect::<Vec<_>>();
+        assert_eq!((sprite_pixels(&ppu, 10), sprite_pixels(&ppu, 17)), (vec![(20, 0x21)], vec![(27, 0x21)]));
+        assert_eq!((sprite_pixels(&ppu, 18), sprite_pixels(&ppu, 25)), (solid(0x23), solid(0x23)));
+        assert_eq!(sprite_pixels(&ppu, 26), vec![]);
+
+        assert_eq!((sprite_pixels(&ppu, 40), sprite_pixels(&ppu, 47)), (solid(0x23), solid(0x23)));
+        assert_eq!((sprite_pixels(&ppu, 48), sprite_pixels(&ppu, 55)), (vec![(27, 0x21)], vec![(20, 0x21)]));
+
+        assert_eq!((sprite_pixels(&ppu, 70), sprite_pixels(&ppu, 78)), (solid(0x21), vec![]));
+
+        // Back to 8x8.
+        ppu.write_register(PPUCTRL, 0x00, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!((sprite_pixels(&ppu, 17), sprite_pixels(&ppu, 18)), (vec![(27, 0x21)], vec![]));
+    }
+
+    #[test]
+    fn test_sprite_fetches_clock_the_mmc3() {
+        let mut ppu = Ppu::new();
+        let mut mmc3 = Mmc3::new(rom(0x40));
+        mmc3.cpu_write(0xc000, 10);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        // Background from $0000 and sprites from $1000: A12 rises once a scanline, at the sprite fetches. The rise on
+        // the first scanline reloads the counter, the one on scanline 10 takes it to 0.
+        ppu.write_register(PPUCTRL, 0x08, None);
+        ppu.write_register(PPUMASK, 0x18, None);
+        ppu.tick(341 * 9 + 258, Some(&mut mmc3));
+        assert!(!mmc3.irq_pending());
+        ppu.tick(341, Some(&mut mmc3));
+        assert!(mmc3.irq_pending());
+    }
+
+    fn sprite_zero_hit(ppu : &Ppu) -> bool {
+        ppu.status() & STATUS_SPRITE_ZERO_HIT != 0
+    }
+
+    /// Runs a frame with sprite 0 at `sprite` over a solid background tile at x 24-31 on scanlines 8-15 and at the
+    /// left and right edges of scanlines 16-23, and says whether it hit.
+    fn sprite_zero_frame(mask : u8, sprite : [u8 ; 4]) -> bool {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        // Tile 4 is just its rightmost column.
+        write_tile(&mut ppu, &mut nrom, 0x0040, 0x01, 0x00);
+        for addr in [0x2023, 0x2040, 0x205f] {
+            write_vram(&mut ppu, &mut nrom, addr, 0x01);
+        }
+        set_vram_addr(&mut ppu, 0x0000);
+        write_sprite(&mut ppu, 0, sprite);
+        ppu.write_register(PPUMASK, mask, None);
+        run_frame(&mut ppu, &mut nrom);
+        sprite_zero_hit(&ppu)
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        write_vram(&mut ppu, &mut nrom, 0x2023, 0x01);
+        set_vram_addr(&mut ppu, 0x0000);
+        // Behind the background, the first pixel over it is x 24 on scanline 8.
+        write_sprite(&mut ppu, 0, [7, 3, 0x20, 20]);
+        ppu.write_register(PPUMASK, 0x1e, None);
+
+        // Pixel x of a scanline is drawn at dot x + 1.
+        ppu.tick(341 * 8 + 25, Some(&mut nrom));
+        assert!(!sprite_zero_hit(&ppu));
+        ppu.tick(1, Some(&mut nrom));
+        assert!(sprite_zero_hit(&ppu));
+
+        // Reading PPUSTATUS leaves it set, the pre-render scanline clears it at dot 1.
+        assert_eq!(ppu.read_register(PPUSTATUS, None) & STATUS_SPRITE_ZERO_HIT, STATUS_SPRITE_ZERO_HIT);
+        ppu.tick(341 * 253 - 25, Some(&mut nrom));
+        assert_eq!((ppu.scanline(), ppu.dot()), (261, 1));
+        assert!(sprite_zero_hit(&ppu));
+        ppu.tick(1, Some(&mut nrom));
+        assert!(!sprite_zero_hit(&ppu));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_needs_opaque_pixels() {
+        assert!(sprite_zero_frame(0x1e, [7, 3, 0x00, 20]));
+        // Only sprite 0 counts.
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        write_vram(&mut ppu, &mut nrom, 0x2023, 0x01);
+        set_vram_addr(&mut ppu, 0x0000);
+        write_sprite(&mut ppu, 1, [7, 3, 0x00, 20]);
+        ppu.write_register(PPUMASK, 0x1e, None);
+        run_frame(&mut ppu, &mut nrom);
+        assert!(!sprite_zero_hit(&ppu));
+
+        // Over the transparent background, and with the background or the sprites hidden.
+        assert!(!sprite_zero_frame(0x1e, [39, 3, 0x00, 20]));
+        assert!(!sprite_zero_frame(0x16, [7, 3, 0x00, 20]));
+        assert!(!sprite_zero_frame(0x0e, [7, 3, 0x00, 20]));
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_at_the_edges() {
+        // In the leftmost 8 pixels only when neither the background nor the sprites are clipped there.
+        assert!(sprite_zero_frame(0x1e, [15, 3, 0x00, 0]));
+        assert!(!sprite_zero_frame(0x1c, [15, 3, 0x00, 0]));
+        assert!(!sprite_zero_frame(0x1a, [15, 3, 0x00, 0]));
+
+        // Never at x 255.
+        assert!(!sprite_zero_frame(0x1e, [15, 4, 0x00, 248]));
+        assert!(sprite_zero_frame(0x1e, [15, 4, 0x00, 247]));
+    }
+
+    /// Runs a frame with `sprites` written to OAM from sprite 0, and says whether sprite overflow was set.
+    fn sprite_overflow_frame(sprites : &[[u8 ; 4]]) -> bool {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        for (index, &sprite) in sprites.iter().enumerate() {
+            write_sprite(&mut ppu, index as u8, sprite);
+        }
+        ppu.write_register(PPUMASK, 0x14, None);
+        run_frame(&mut ppu, &mut nrom);
+        ppu.status() & STATUS_SPRITE_OVERFLOW != 0
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = Ppu::new();
+        let mut nrom = sprite_board(&mut ppu);
+        for index in 0 .. 9 {
+            write_sprite(&mut ppu, index, [19, 2, 0x00, index * 8]);
+        }
+        ppu.write_register(PPUMASK, 0x14, None);
+
+        // Set when the sprites of scanline 20 are fetched, cleared at dot 1 of the pre-render scanline.
+        ppu.tick(341 * 19 + 257, Some(&mut nrom));
+        assert_eq!(ppu.status() & STATUS_SPRITE_OVERFLOW, 0);
+        ppu.tick(1, Some(&mut nrom));
+        assert_eq!(ppu.read_register(PPUSTATUS, None) & STATUS_SPRITE_OVERFLOW, STATUS_SPRITE_OVERFLOW);
+        ppu.tick(341 * 242 - 257, Some(&mut nrom));
+        assert_eq!((ppu.scanline(), ppu.dot()), (261, 1));
+        assert_eq!(ppu.status() & STATUS_SPRITE_OVERFLOW, STATUS_SPRITE_OVERFLOW);
+        ppu.tick(1, Some(&mut nrom));
+        assert_eq!(ppu.status() & STATUS_SPRITE_OVERFLOW, 0);
+
+        let eight = [[19, 2, 0x00, 0x00] ; 8];
+        assert!(!sprite_overflow_frame(&eight));
+        assert!(sprite_overflow_frame(&[&eight[..], &[[19, 2, 0x00, 0x00]]].concat()));
+    }
+
+    #[test]
+    fn test_sprite_overflow_scans_oam_diagonally() {
+        let eight = [[19, 2, 0x00, 0x00] ; 8];
+        // A ninth sprite after one off the scanline is missed: the PPU compares its tile number instead of its Y.
+        let missed = [&eight[..], &[[0xff ; 4], [19, 2, 0x00, 0x00]]].concat();
+        assert!(!sprite_overflow_frame(&missed));
+        // And a tile number that would be on the scanline as a Y counts, with no ninth sprite.
+        let phantom = [&eight[..], &[[0xff ; 4], [0xff, 15, 0xff, 0xff]]].concat();
+        assert!(sprite_overflow_frame(&phantom));
+        // The third byte of the sprite after that, then the fourth of the next.
+        let phantom = [&eight[..], &[[0xff ; 4], [0xff ; 4], [0xff, 0xff, 12, 0xff], [0xff, 0xff, 0xff, 19]]].concat();
+        assert!(sprite_overflow_frame(&phantom[.. 11]));
+        assert!(sprite_overflow_frame(&[&phantom[.. 10], &[[0xff ; 4], phantom[11]]].concat()));
+    }
+
+    /// Runs the PPU until it is about to draw dot `dot` of scanline `scanline`.
+    fn run_to(ppu : &mut Ppu, mapper : &mut dyn Mapper, scanline : u16, dot : u16) {
+        while (ppu.scanline(), ppu.dot()) != (scanline, dot) {
+            ppu.tick(1, Some(mapper));
+        }
+    }
+
+    #[test]
+    fn test_scroll_writes_set_t() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(PPUCTRL, 0x03, None);
+        assert_eq!(ppu.temp_vram_addr(), 0x0c00);
+        ppu.write_register(PPUSCROLL, 0xff, None);
+        ppu.write_register(PPUSCROLL, 0xff, None);
+        assert_eq!((ppu.temp_vram_addr(), ppu.fine_x()), (0x7fff, 0x07));
+        // Nothing reaches v until the second PPUADDR write, the first clears the top bit of t.
+        ppu.write_register(PPUCTRL, 0x00, None);
+        ppu.write_register(PPUADDR, 0xff, None);
+        assert_eq!((ppu.temp_vram_addr(), ppu.vram_addr()), (0x3fff, 0x0000));
+        ppu.write_register(PPUADDR, 0x12, None);
+        assert_eq!((ppu.temp_vram_addr(), ppu.vram_addr()), (0x3f12, 0x3f12));
+    }
+
+    #[test]
+    fn test_rendering_moves_v() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0));
+        // Nametable 1, coarse X 3, fine Y 3, coarse Y 1.
+        ppu.write_register(PPUCTRL, 0x01, None);
+        ppu.write_register(PPUSCROLL, 0x18, None);
+        ppu.write_register(PPUSCROLL, 0x0b, None);
+        assert_eq!(ppu.temp_vram_addr(), 0x3423);
+        ppu.write_register(PPUMASK, 0x08, None);
+
+        // The pre-render scanline copies all of t, then fetches the first two tiles.
+        run_to(&mut ppu, &mut nrom, 261, 305);
+        assert_eq!(ppu.vram_addr(), 0x3423);
+        run_to(&mut ppu, &mut nrom, 0, 0);
+        assert_eq!(ppu.vram_addr(), 0x3425);
+        // 32 tiles take coarse X round into the other nametable, then Y steps.
+        run_to(&mut ppu, &mut nrom, 0, 257);
+        assert_eq!(ppu.vram_addr(), 0x4025);
+        run_to(&mut ppu, &mut nrom, 0, 258);
+        assert_eq!(ppu.vram_addr(), 0x4423);
+
+        // Coarse Y wraps into the next nametable down after row 29: 241 rows on from row 11 is row 12 of it.
+        run_to(&mut ppu, &mut nrom, 229, 258);
+        assert_eq!(ppu.vram_addr(), 0x1c03);
+
+        // With rendering off v stays put.
+        ppu.write_register(PPUMASK, 0x00, None);
+        run_to(&mut ppu, &mut nrom, 1, 0);
+        assert_eq!(ppu.vram_addr(), 0x1c03);
+    }
+
+    #[test]
+    fn test_ppudata_while_rendering_moves_the_scroll() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0));
+        ppu.write_register(PPUMASK, 0x08, None);
+        run_to(&mut ppu, &mut nrom, 10, 100);
+        let v = ppu.vram_addr();
+        ppu.read_register(PPUDATA, Some(&mut nrom));
+        assert_eq!(ppu.vram_addr(), v + 0x1001);
+    }
+
+    #[test]
+    fn test_ppuaddr_split() {
+        let mut ppu = Ppu::new();
+        let mut nrom = chr_ram_board(&mut ppu, 0x01);
+        write_tile(&mut ppu, &mut nrom, 0x0010, 0xff, 0xff);
+        write_vram(&mut ppu, &mut nrom, 0x3f03, 0x16);
+        // Only the second nametable has a tile, at its top left.
+        write_vram(&mut ppu, &mut nrom, 0x2400, 0x01);
+        set_vram_addr(&mut ppu, 0x0000);
+        ppu.write_register(PPUMASK, 0x0a, None);
+        run_frame(&mut ppu, &mut nrom);
+
+        // Pointing v at the second nametable in the horizontal blank of scanline 100 moves the rest of the frame.
+        // The top bits of the address are fine Y, $2400 starts at the third row of pixels.
+        run_to(&mut ppu, &mut nrom, 100, 300);
+        set_vram_addr(&mut ppu, 0x2400);
+        run_to(&mut ppu, &mut nrom, 240, 0);
+        assert_eq!(pixels(&ppu, 0, 100), &[0x0f ; 8]);
+        assert_eq!((pixels(&ppu, 0, 101), pixels(&ppu, 0, 106)), (&[0x16 ; 8][..], &[0x16 ; 8][..]));
+        assert_eq!((pixels(&ppu, 0, 0), pixels(&ppu, 0, 107)), (&[0x0f ; 8][..], &[0x0f ; 8][..]));
+        // The next frame starts from t again, which the write left on the second nametable too.
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!((pixels(&ppu, 0, 0), pixels(&ppu, 0, 101)), (&[0x16 ; 8][..], &[0x0f ; 8][..]));
+    }
+
+    #[test]
+    fn test_fine_x_scroll() {
+        let mut ppu = Ppu::new();
+        let mut nrom = chr_ram_board(&mut ppu, 0);
+        write_tile(&mut ppu, &mut nrom, 0x0010, 0xff, 0xff);
+        write_vram(&mut ppu, &mut nrom, 0x3f03, 0x16);
+        write_vram(&mut ppu, &mut nrom, 0x2001, 0x01);
+        ppu.write_register(PPUSCROLL, 3, None);
+        ppu.write_register(PPUSCROLL, 0, None);
+        ppu.write_register(PPUMASK, 0x0a, None);
+        run_frame(&mut ppu, &mut nrom);
+
+        // The tile at pixels 8-15 is drawn 3 to the left, straddling the two tiles of the screen.
+        assert_eq!(pixels(&ppu, 0, 0), &[0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x16, 0x16, 0x16]);
+        assert_eq!(pixels(&ppu, 8, 0), &[0x16, 0x16, 0x16, 0x16, 0x16, 0x0f, 0x0f, 0x0f]);
+    }
+
+    #[test]
+    fn test_mask_writes_take_effect_mid_scanline() {
+        let mut ppu = Ppu::new();
+        let mut nrom = chr_ram_board(&mut ppu, 0);
+        write_tile(&mut ppu, &mut nrom, 0x0010, 0xff, 0xff);
+        write_vram(&mut ppu, &mut nrom, 0x3f03, 0x16);
+        for addr in 0x2000 .. 0x2020 {
+            write_vram(&mut ppu, &mut nrom, addr, 0x01);
+        }
+        set_vram_addr(&mut ppu, 0x0000);
+        ppu.write_register(PPUMASK, 0x0a, None);
+        run_frame(&mut ppu, &mut nrom);
+
+        // Hiding the background as the PPU is about to draw pixel 128 leaves the left half of the line drawn.
+        run_to(&mut ppu, &mut nrom, 3, 129);
+        ppu.write_register(PPUMASK, 0x00, None);
+        run_to(&mut ppu, &mut nrom, 240, 0);
+        assert_eq!((pixels(&ppu, 120, 3), pixels(&ppu, 128, 3)), (&[0x16 ; 8][..], &[0x0f ; 8][..]));
+        assert_eq!((pixels(&ppu, 128, 2), pixels(&ppu, 0, 4)), (&[0x16 ; 8][..], &[0x0f ; 8][..]));
+    }
+
+    #[test]
+    fn test_palette_shows_through_with_rendering_off() {
+        let mut ppu = Ppu::new();
+        let mut nrom = chr_ram_board(&mut ppu, 0);
+        write_vram(&mut ppu, &mut nrom, 0x3f15, 0x2a);
+
+        // With v in the palette the PPU draws the entry it points at rather than the backdrop.
+        set_vram_addr(&mut ppu, 0x3f15);
+        run_frame(&mut ppu, &mut nrom);
+        assert!(ppu.frame().iter().all(|&colour| colour == 0x2a));
+        // $3F10 is the backdrop itself.
+        set_vram_addr(&mut ppu, 0x3f10);
+        run_frame(&mut ppu, &mut nrom);
+        assert!(ppu.frame().iter().all(|&colour| colour == 0x0f));
+    }
+
+    #[test]
+    fn test_vblank_flag() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0));
+        run_to(&mut ppu, &mut nrom, 241, 1);
+        assert_eq!(ppu.status(), 0);
+        ppu.tick(1, None);
+        assert_eq!(ppu.status(), STATUS_VBLANK);
+        // With PPUCTRL bit 7 clear the NMI line is left alone.
+        assert!(!ppu.take_nmi());
+
+        // Reading PPUSTATUS returns the flag and clears it.
+        assert_eq!(ppu.read_register(PPUSTATUS, None) & STATUS_VBLANK, STATUS_VBLANK);
+        assert_eq!(ppu.status(), 0);
+        run_frame(&mut ppu, &mut nrom);
+        run_to(&mut ppu, &mut nrom, 241, 2);
+        assert_eq!(ppu.status(), STATUS_VBLANK);
+        // The pre-render scanline clears it.
+        run_to(&mut ppu, &mut nrom, 261, 2);
+        assert_eq!(ppu.status(), 0);
+    }
+
+    #[test]
+    fn test_vblank_nmi() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(rom(0));
+        ppu.write_register(PPUCTRL, 0x80, None);
+        run_to(&mut ppu, &mut nrom, 241, 2);
+        assert!(ppu.nmi_line());
+        // The edge is taken once however long the line stays pulled.
+        assert!(ppu.take_nmi());
+        assert!(!ppu.take_nmi());
+        ppu.write_register(PPUCTRL, 0x80, None);
+        assert!(!ppu.take_nmi());
+
+        // Setting the enable again during vertical blank pulls the line again.
+        ppu.write_register(PPUCTRL, 0x00, None);
+        assert!(!ppu.nmi_line());
+        ppu.write_register(PPUCTRL, 0x80, None);
+        assert!(ppu.take_nmi());
+        // Once the flag has been read there is nothing to signal.
+        ppu.read_register(PPUSTATUS, None);
+        ppu.write_register(PPUCTRL, 0x00, None);
+        ppu.write_register(PPUCTRL, 0x80, None);
+        assert!(!ppu.nmi_line() && !ppu.take_nmi());
+
+        // The next vertical blank pulls it again, until the pre-render scanline lets go.
+        run_frame(&mut ppu, &mut nrom);
+        run_to(&mut ppu, &mut nrom, 241, 2);
+        assert!(ppu.take_nmi());
+        run_to(&mut ppu, &mut nrom, 261, 2);
+        assert!(!ppu.nmi_line());
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod region_tests {
+    use nes::bus::Bus;
+    use nes::cpu::CPU;
+    use nes::region::Region;
+    use nes::trace::trace;
+
+    #[test]
+    fn test_cpu_clock() {
+        assert_eq!(Region::Ntsc.cpu_clock_hz(), 1_789_772);
+        assert_eq!(Region::Pal.cpu_clock_hz(), 1_662_607);
+        assert_eq!(Region::Dendy.cpu_clock_hz(), 1_773_447);
+    }
+
+    #[test]
+    fn test_frame_rate() {
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.001);
+        assert!((Region::Dendy.frame_rate() - 50.0070).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_ppu_dots() {
+        assert_eq!(Region::Ntsc.cpu_cycles_to_ppu_dots(10), 30);
+        assert_eq!(Region::Dendy.cpu_cycles_to_ppu_dots(10), 30);
+        assert_eq!(Region::Pal.cpu_cycles_to_ppu_dots(10), 32);
+        assert_eq!(Region::Pal.cpu_cycles_to_ppu_dots(7), 22);
+    }
+
+    #[test]
+    fn test_default_region() {
+        assert_eq!(CPU::new().region(), Region::Ntsc);
+    }
+
+    #[test]
+    fn test_trace_uses_region() {
+        let mut cpu = CPU::with_memory(Bus::new());
+        cpu.cycles = 29_000;
+        assert!(trace(&cpu).ends_with("PPU:255, 45 CYC:29000"));
+
+        // The CPU takes the region the bus is clocked with.
+        cpu.memory_mut().set_region(Region::Pal);
+        assert!(trace(&cpu).ends_with("PPU:272, 48 CYC:29000"));
+    }
+}
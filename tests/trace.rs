@@ -0,0 +1,108 @@
+#[cfg(test)]
+mod trace_tests {
+    use nes::cpu::CPU;
+    use nes::trace::trace;
+
+    #[test]
+    fn test_format_trace() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x64, 0xa2);
+        cpu.mem_write(0x65, 0x01);
+        cpu.mem_write(0x66, 0xca);
+        cpu.mem_write(0x67, 0x88);
+        cpu.mem_write(0x68, 0x00);
+        cpu.program_counter = 0x64;
+        cpu.register_a = 1;
+        cpu.register_x = 2;
+        cpu.register_y = 3;
+        cpu.status.set_interrupt_disable(true);
+
+        let mut result = vec![];
+        cpu.run_with_callback(|cpu| result.push(trace(cpu))).unwrap();
+
+        assert_eq!(
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0,  0 CYC:0",
+            result[0]
+        );
+        assert_eq!(
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0,  6 CYC:2",
+            result[1]
+        );
+        assert_eq!(
+            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 12 CYC:4",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn test_format_mem_access() {
+        let mut cpu = CPU::new();
+        // ORA ($33),Y
+        cpu.mem_write(0x64, 0x11);
+        cpu.mem_write(0x65, 0x33);
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+        cpu.mem_write(0x400, 0xaa);
+        cpu.program_counter = 0x64;
+        cpu.status.set_interrupt_disable(true);
+
+        assert_eq!(
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_nestest_start() {
+        let mut cpu = CPU::new();
+        // JMP $C5F5 at $C000, as the first line of the nestest log.
+        cpu.mem_write(0xc000, 0x4c);
+        cpu.mem_write_u16(0xc001, 0xc5f5);
+        cpu.mem_write_u16(0xfffc, 0xc000);
+        cpu.reset();
+        cpu.stack_pointer = 0xfd;
+
+        assert_eq!(
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_format_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.register_x = 0x02;
+        cpu.status.set_interrupt_disable(true);
+        cpu.mem_write(0x82, 0x00);
+        cpu.mem_write(0x83, 0x02);
+        cpu.mem_write(0x200, 0x5a);
+
+        let cases : [(&[u8], &str); 6] = [
+            (&[0xa1, 0x80], "0600  A1 80     LDA ($80,X) @ 82 = 0200 = 5A    "),
+            (&[0xb5, 0x80], "0600  B5 80     LDA $80,X @ 82 = 00             "),
+            (&[0xbd, 0xfe, 0x01], "0600  BD FE 01  LDA $01FE,X @ 0200 = 5A        "),
+            (&[0x6c, 0x82, 0x00], "0600  6C 82 00  JMP ($0082) = 0200              "),
+            (&[0xd0, 0xfe], "0600  D0 FE     BNE $0600                       "),
+            (&[0x4a], "0600  4A        LSR A                           "),
+        ];
+        for (program, expected) in cases {
+            for (i, byte) in program.iter().enumerate() {
+                cpu.mem_write(0x600 + i as u16, *byte);
+            }
+            cpu.program_counter = 0x600;
+            assert_eq!(&trace(&cpu)[.. expected.len()], expected);
+        }
+    }
+
+    #[test]
+    fn test_unofficial_opcodes_are_marked() {
+        let mut cpu = CPU::new();
+        // LAX $10
+        cpu.mem_write(0x600, 0xa7);
+        cpu.mem_write(0x601, 0x10);
+        cpu.mem_write(0x10, 0x55);
+        cpu.program_counter = 0x600;
+
+        assert!(trace(&cpu).starts_with("0600  A7 10    *LAX $10 = 55                    A:00"));
+    }
+}